@@ -0,0 +1,309 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    io::Write,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use typed_path::Utf8NativePath;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{
+    analysis::{
+        cfa::{read_traceback_table, AnalyzerState, FunctionInfo, SectionAddress},
+        tracker::{DataKind, Relocation, Tracker},
+    },
+    obj::{ObjInfo, ObjSymbolKind},
+    util::{config::is_auto_symbol, file::buf_writer},
+};
+
+/// File name of the analysis cache, within a module's output directory.
+pub const ANALYSIS_CACHE_FILE: &str = "analysis.json";
+
+/// Bumped whenever the cached data or the analysis producing it changes.
+const CACHE_VERSION: u32 = 4;
+
+/// Analysis results of a single module, reused between `dol split` runs.
+///
+/// Each stage is keyed by a hash of everything it reads from the object,
+/// so only the stages affected by a change are recomputed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnalysisCache {
+    #[serde(default)]
+    pub functions: Option<FunctionsCache>,
+    #[serde(default)]
+    pub tracker: Option<TrackerCache>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// Function bounds found by [`AnalyzerState::detect_functions`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionsCache {
+    pub key: u64,
+    /// Start, end and whether a traceback table follows the function.
+    pub functions: Vec<(SectionAddress, SectionAddress, bool)>,
+    pub jump_tables: Vec<(SectionAddress, u32)>,
+}
+
+/// Results of [`Tracker::process`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackerCache {
+    pub key: u64,
+    pub relocations: Vec<(SectionAddress, Relocation)>,
+    pub data_types: Vec<(SectionAddress, DataKind)>,
+    pub array_strides: Vec<(SectionAddress, u32)>,
+    pub known_relocations: Vec<SectionAddress>,
+    pub stores_to: Vec<SectionAddress>,
+    pub sda_to: Vec<SectionAddress>,
+    pub hal_to: Vec<SectionAddress>,
+}
+
+impl AnalysisCache {
+    /// Reads the cache, starting over if it's missing or unreadable.
+    pub fn read(path: &Utf8NativePath) -> Self {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log::warn!("Failed to read analysis cache {}: {}", path, e);
+                return Self::default();
+            }
+        };
+        serde_json::from_slice(&data).unwrap_or_else(|e| {
+            log::warn!("Discarding invalid analysis cache {}: {}", path, e);
+            Self::default()
+        })
+    }
+
+    /// Writes the cache if any stage was recomputed.
+    pub fn write(&self, path: &Utf8NativePath) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut w = buf_writer(path)?;
+        serde_json::to_writer(&mut w, self)
+            .with_context(|| format!("Failed to write analysis cache {}", path))?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+impl FunctionsCache {
+    fn new(obj: &ObjInfo, state: &AnalyzerState) -> Self {
+        let functions = state
+            .functions
+            .iter()
+            .filter_map(|(&start, info)| Some((start, info.end?, info.tbtab.is_some())))
+            .collect::<Vec<_>>();
+        Self {
+            key: functions_key(obj, &functions),
+            functions,
+            jump_tables: state.jump_tables.iter().map(|(&addr, &size)| (addr, size)).collect(),
+        }
+    }
+
+    fn restore(&self, obj: &ObjInfo, state: &mut AnalyzerState) {
+        for &(start, end, has_tbtab) in &self.functions {
+            let tbtab = if has_tbtab {
+                read_traceback_table(obj, &obj.sections[start.section], start, end)
+            } else {
+                None
+            };
+            state.functions.insert(start, FunctionInfo {
+                analyzed: true,
+                end: Some(end),
+                slices: None,
+                tbtab,
+            });
+        }
+        state.jump_tables.extend(self.jump_tables.iter().cloned());
+    }
+}
+
+fn new_hasher() -> Xxh3 {
+    let mut hasher = Xxh3::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    CACHE_VERSION.hash(&mut hasher);
+    hasher
+}
+
+/// Hashes section contents and symbol bounds. Symbol names are left out,
+/// so renaming symbols doesn't invalidate the cache.
+///
+/// Functions are hashed separately by each key. Other auto-generated symbols
+/// (`lbl_`, `jumptable_`, ...) are left out: analysis creates them, and `dol split`
+/// writes them to symbols.txt, so the next run reads back what the previous one produced.
+fn hash_object(obj: &ObjInfo, hasher: &mut Xxh3) {
+    (obj.kind, obj.module_id, obj.entry).hash(hasher);
+    for (section_index, section) in obj.sections.iter() {
+        (section_index, &section.name, section.kind, section.address, section.size).hash(hasher);
+        hasher.write(&section.data);
+    }
+    for (_, symbol) in
+        obj.symbols.iter().filter(|(_, s)| s.kind != ObjSymbolKind::Function && !is_auto_symbol(s))
+    {
+        (symbol.section, symbol.address, symbol.size, symbol.size_known, symbol.kind, symbol.flags)
+            .hash(hasher);
+    }
+}
+
+/// Start and size (if known) of every function symbol.
+fn function_bounds(obj: &ObjInfo) -> BTreeSet<(SectionAddress, Option<u32>)> {
+    obj.symbols
+        .by_kind(ObjSymbolKind::Function)
+        .filter_map(|(_, symbol)| {
+            let start = SectionAddress::new(symbol.section?, symbol.address as u32);
+            Some((start, symbol.size_known.then_some(symbol.size as u32)))
+        })
+        .collect()
+}
+
+/// Hashes everything function detection reads from the object.
+///
+/// The bounds of the `detected` functions are merged into those of the function
+/// symbols, so the key is the same on the next run, once the detected functions
+/// are read back from symbols.txt. Editing a function's size still changes it.
+fn functions_key(obj: &ObjInfo, detected: &[(SectionAddress, SectionAddress, bool)]) -> u64 {
+    let mut bounds = function_bounds(obj);
+    bounds
+        .extend(detected.iter().map(|&(start, end, _)| (start, Some(end.address - start.address))));
+    // Symbols of unknown size are sized by detection
+    let sized = bounds
+        .iter()
+        .filter(|(_, size)| size.is_some())
+        .map(|&(start, _)| start)
+        .collect::<BTreeSet<_>>();
+    bounds.retain(|(start, size)| size.is_some() || !sized.contains(start));
+
+    let mut hasher = new_hasher();
+    hash_object(obj, &mut hasher);
+    bounds.hash(&mut hasher);
+    obj.known_functions.hash(&mut hasher);
+    obj.toc_base.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hashes everything relocation analysis reads from the object.
+fn tracker_key(obj: &ObjInfo) -> u64 {
+    let mut hasher = new_hasher();
+    hash_object(obj, &mut hasher);
+    // Function bounds, including the detected ones
+    function_bounds(obj).hash(&mut hasher);
+    (obj.sda2_base, obj.sda_base, obj.toc_base).hash(&mut hasher);
+    (obj.stack_address, obj.stack_end, obj.db_stack_addr, obj.arena_lo, obj.arena_hi)
+        .hash(&mut hasher);
+    for (_, section) in obj.sections.iter() {
+        for (address, reloc) in section.relocations.iter() {
+            let target = &obj.symbols[reloc.target_symbol];
+            (address, reloc.kind, reloc.addend, reloc.module).hash(&mut hasher);
+            (target.section, target.address).hash(&mut hasher);
+        }
+    }
+    for reloc in &obj.unresolved_relocations {
+        (reloc.kind, reloc.section, reloc.address, reloc.module_id).hash(&mut hasher);
+        (reloc.target_section, reloc.addend).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Runs [`AnalyzerState::detect_functions`], reusing the cached function bounds
+/// when the object and its known symbols are unchanged.
+pub fn detect_functions_cached(
+    state: &mut AnalyzerState,
+    obj: &ObjInfo,
+    cache: Option<&mut AnalysisCache>,
+) -> Result<()> {
+    let Some(cache) = cache else {
+        return state.detect_functions(obj);
+    };
+    if let Some(functions) =
+        cache.functions.as_ref().filter(|c| c.key == functions_key(obj, &c.functions))
+    {
+        log::debug!("Using {} cached functions", functions.functions.len());
+        functions.restore(obj, state);
+        return Ok(());
+    }
+    state.detect_functions(obj)?;
+    cache.functions = Some(FunctionsCache::new(obj, state));
+    cache.dirty = true;
+    Ok(())
+}
+
+/// Runs [`Tracker::process`], reusing the cached results when the analyzed
+/// object is unchanged.
+pub fn process_tracker_cached(
+    tracker: &mut Tracker,
+    obj: &ObjInfo,
+    cache: Option<&mut AnalysisCache>,
+) -> Result<()> {
+    let Some(cache) = cache else {
+        return tracker.process(obj);
+    };
+    let key = tracker_key(obj);
+    if let Some(tracker_cache) = cache.tracker.as_ref().filter(|c| c.key == key) {
+        log::debug!("Using {} cached relocations", tracker_cache.relocations.len());
+        tracker.restore(tracker_cache);
+        return Ok(());
+    }
+    tracker.process(obj)?;
+    cache.tracker = Some(tracker.to_cache(key));
+    cache.dirty = true;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, section},
+        ObjKind, ObjSectionKind, ObjSymbol,
+    };
+
+    /// li r3, 0; blr
+    fn code() -> ObjInfo {
+        let data = vec![0x38, 0x60, 0x00, 0x00, 0x4E, 0x80, 0x00, 0x20];
+        let text = section(".text", ObjSectionKind::Code, 0x80003100, data);
+        obj(ObjKind::Executable, vec![], vec![text])
+    }
+
+    #[test]
+    fn test_functions_cache_second_run() {
+        let mut obj = code();
+        let mut cache = AnalysisCache::default();
+        let mut state = AnalyzerState::default();
+        detect_functions_cached(&mut state, &obj, Some(&mut cache)).unwrap();
+        assert!(cache.dirty);
+        assert_eq!(cache.functions.as_ref().unwrap().functions.len(), 1);
+        // The detected fn_ symbols are written to symbols.txt and read back next run
+        state.apply(&mut obj).unwrap();
+        assert!(obj.symbols.by_name("fn_80003100").unwrap().is_some());
+
+        let mut cache: AnalysisCache =
+            serde_json::from_slice(&serde_json::to_vec(&cache).unwrap()).unwrap();
+        let mut state = AnalyzerState::default();
+        detect_functions_cached(&mut state, &obj, Some(&mut cache)).unwrap();
+        assert!(!cache.dirty);
+        let start = SectionAddress::new(0, 0x80003100);
+        assert_eq!(state.functions[&start].end, Some(start + 8));
+    }
+
+    #[test]
+    fn test_functions_cache_edited_size() {
+        let mut obj = code();
+        let mut state = AnalyzerState::default();
+        state.detect_functions(&obj).unwrap();
+        let functions = FunctionsCache::new(&obj, &state);
+        state.apply(&mut obj).unwrap();
+        assert_eq!(functions_key(&obj, &functions.functions), functions.key);
+
+        // fn_80003100 = .text:0x80003100; // type:function size:0x4
+        let (index, symbol) = obj.symbols.by_name("fn_80003100").unwrap().unwrap();
+        let symbol = ObjSymbol { size: 4, ..symbol.clone() };
+        obj.symbols.replace(index, symbol).unwrap();
+        assert_ne!(functions_key(&obj, &functions.functions), functions.key);
+    }
+}
@@ -1,27 +1,37 @@
 use std::{
-    cmp::min, collections::BTreeMap, fmt::{Debug, Display, Formatter, UpperHex}, mem, ops::{Add, AddAssign, BitAnd, Sub}
+    cmp::min, collections::BTreeMap, fmt::{Debug, Display, Formatter, UpperHex}, mem, ops::{Add, AddAssign, BitAnd, Sub}, sync::Arc
 };
 
 use anyhow::{bail, ensure, Context, Result};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     analysis::{
-        executor::{ExecCbData, ExecCbResult, Executor}, skip_alignment, slices::{FunctionSlices, TailCallResult}, vm::{BranchTarget, GprValue, StepResult, VM}, RelocationTarget
+        executor::{ExecCbData, ExecCbResult, Executor}, skip_alignment, slices::{FunctionSlices, LeafIdiom, LeafIdioms, TailCallResult}, vm::{BranchTarget, GprValue, StepResult, VM}, RelocationTarget
     },
     obj::{
-        ObjInfo, ObjSectionKind, ObjSymbol, ObjSymbolFlagSet, ObjSymbolFlags, ObjSymbolKind,
-        SectionIndex,
+        ObjInfo, ObjSection, ObjSectionKind, ObjSymbol, ObjSymbolFlagSet, ObjSymbolFlags,
+        ObjSymbolKind, SectionIndex,
     },
     util::tbtab::{ TracebackTable },
 };
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "(SectionIndex, u32)", into = "(SectionIndex, u32)")]
 pub struct SectionAddress {
     pub section: SectionIndex,
     pub address: u32,
 }
 
+impl From<(SectionIndex, u32)> for SectionAddress {
+    fn from((section, address): (SectionIndex, u32)) -> Self { Self { section, address } }
+}
+
+impl From<SectionAddress> for (SectionIndex, u32) {
+    fn from(addr: SectionAddress) -> Self { (addr.section, addr.address) }
+}
+
 impl Debug for SectionAddress {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:{:#X}", self.section as isize, self.address)
@@ -123,9 +133,15 @@ pub struct AnalyzerState {
     pub jump_tables: BTreeMap<SectionAddress, u32>,
     pub known_symbols: BTreeMap<SectionAddress, Vec<ObjSymbol>>,
     pub known_sections: BTreeMap<SectionIndex, String>,
+    pub leaf_idioms: Arc<LeafIdioms>,
 }
 
 impl AnalyzerState {
+    /// Registers a leaf idiom to check during function analysis.
+    pub fn register_leaf_idiom(&mut self, idiom: LeafIdiom) {
+        Arc::make_mut(&mut self.leaf_idioms).register(idiom);
+    }
+
     pub fn apply(&self, obj: &mut ObjInfo) -> Result<()> {
         for (&section_index, section_name) in &self.known_sections {
             obj.sections[section_index].rename(section_name.clone())?;
@@ -166,6 +182,21 @@ impl AnalyzerState {
                 },
                 false,
             )?;
+            if let Some(tb) = &fnc_info.tbtab {
+                obj.add_symbol(
+                    ObjSymbol {
+                        name: format!("tbtab_{}", auto_address_str(obj, end)),
+                        address: end.address as u64,
+                        section: Some(end.section),
+                        size: tb.size() as u64,
+                        size_known: true,
+                        flags: ObjSymbolFlagSet(ObjSymbolFlags::Local.into()),
+                        kind: ObjSymbolKind::Object,
+                        ..Default::default()
+                    },
+                    false,
+                )?;
+            }
         }
         let mut iter = self.jump_tables.iter().peekable();
         while let Some((&addr, &(mut size))) = iter.next() {
@@ -185,19 +216,9 @@ impl AnalyzerState {
                 section.address,
                 section.address + section.size
             );
-            let address_str = if obj.module_id == 0 {
-                format!("{:08X}", addr.address)
-            } else {
-                format!(
-                    "{}_{}_{:X}",
-                    obj.module_id,
-                    section.name.trim_start_matches('.'),
-                    addr.address
-                )
-            };
             obj.add_symbol(
                 ObjSymbol {
-                    name: format!("jumptable_{}", address_str),
+                    name: format!("jumptable_{}", auto_address_str(obj, addr)),
                     address: addr.address as u64,
                     section: Some(addr.section),
                     size: size as u64,
@@ -460,7 +481,8 @@ impl AnalyzerState {
         obj: &ObjInfo,
         start: SectionAddress,
     ) -> Result<Option<FunctionSlices>> {
-        let mut slices = FunctionSlices::default();
+        let mut slices =
+            FunctionSlices { idioms: self.leaf_idioms.clone(), ..Default::default() };
         let function_end = self.functions.get(&start).and_then(|info| info.end);
         Ok(match slices.analyze(obj, start, start, function_end, &self.functions, None)? {
             true => Some(slices),
@@ -489,19 +511,15 @@ impl AnalyzerState {
                             None => continue,
                         };
                         if second > addr {
-                            // if (pef_flag)
-                            let potential_tb_offset = first_end.address as usize - section.address as usize;
-                            let mut potential_tb = TracebackTable::read(&section.data, potential_tb_offset);
-                            if let Some(tb) = potential_tb.take() {
-                                let after_tb_off = first_end.address as usize + tb.size();
-                                if after_tb_off == second.address as usize {
-                                    first_info.tbtab = Some(tb);
-                                    continue;
-                                }
-                                else {
-                                    addr.address = after_tb_off as u32;
-                                    potential_tb = Some(tb);
-                                }
+                            if let Some(tb) = read_traceback_table(obj, section, first, first_end) {
+                                // The traceback table belongs to the preceding function,
+                                // any new function can only start after it
+                                let after_tb = first_end + tb.size() as u32;
+                                first_info.tbtab = Some(tb);
+                                addr = match skip_alignment(section, after_tb, second) {
+                                    Some(addr) => addr,
+                                    None => continue,
+                                };
                             }
                             log::trace!(
                                 "Trying function @ {:#010X} (from {:#010X}-{:#010X} <-> {:#010X}-{:#010X?})",
@@ -511,7 +529,7 @@ impl AnalyzerState {
                                 second.address,
                                 second_info.end,
                             );
-                            new_functions.push((addr, potential_tb));
+                            new_functions.push(addr);
                         }
                     }
                     (Some((last, last_info)), None) => {
@@ -521,14 +539,15 @@ impl AnalyzerState {
                                 Some(addr) => addr,
                                 None => continue,
                             };
+                            if let Some(tb) = read_traceback_table(obj, section, *last, last_end) {
+                                let after_tb = last_end + tb.size() as u32;
+                                last_info.tbtab = Some(tb);
+                                addr = match skip_alignment(section, after_tb, section_end) {
+                                    Some(addr) => addr,
+                                    None => continue,
+                                };
+                            }
                             if addr < section_end {
-                                let potential_tb_offset = last_end.address as usize - section.address as usize;
-                                let mut potential_tb = TracebackTable::read(&section.data, potential_tb_offset);
-                                if let Some(tb) = potential_tb.take() {
-                                    let after_tb_off = last_end.address as usize + tb.size();
-                                    addr.address = after_tb_off as u32;
-                                    potential_tb = Some(tb);          
-                                }
                                 log::trace!(
                                     "Trying function @ {:#010X} (from {:#010X}-{:#010X} <-> {:#010X})",
                                     addr,
@@ -536,7 +555,7 @@ impl AnalyzerState {
                                     last_end,
                                     section_end,
                                 );
-                                new_functions.push((addr, potential_tb));
+                                new_functions.push(addr);
                             }
                         }
                     }
@@ -545,24 +564,56 @@ impl AnalyzerState {
             }
         }
         let found_new = !new_functions.is_empty();
-        for (addr, tbtab) in new_functions {
-            if let Some(tbtab) = tbtab {
-                let fi = FunctionInfo {
-                    tbtab: Some(tbtab),      
-                    ..Default::default()
-                };
-                let opt = self.functions.insert(addr, fi);
-                ensure!(opt.is_none(), "Attempted to detect duplicate function @ {:#010X}", addr);
-            } 
-            else {
-                let opt = self.functions.insert(addr, FunctionInfo::default());
-                ensure!(opt.is_none(), "Attempted to detect duplicate function @ {:#010X}", addr);
-            }
+        for addr in new_functions {
+            let opt = self.functions.insert(addr, FunctionInfo::default());
+            ensure!(opt.is_none(), "Attempted to detect duplicate function @ {:#010X}", addr);
         }
         Ok(found_new)
     }
 }
 
+fn auto_address_str(obj: &ObjInfo, addr: SectionAddress) -> String {
+    if obj.module_id == 0 {
+        format!("{:08X}", addr.address)
+    } else {
+        format!(
+            "{}_{}_{:X}",
+            obj.module_id,
+            obj.sections[addr.section].name.trim_start_matches('.'),
+            addr.address
+        )
+    }
+}
+
+/// Reads the traceback table emitted after a function by XCOFF-derived toolchains.
+/// When the table records a function size that disagrees with the detected function end,
+/// the table is still returned so that its bytes are skipped, but its name is dropped.
+pub fn read_traceback_table(
+    obj: &ObjInfo,
+    section: &ObjSection,
+    start: SectionAddress,
+    end: SectionAddress,
+) -> Option<TracebackTable> {
+    // Only TOC-based (PEF) objects carry traceback tables; elsewhere the
+    // zero padding between functions would decode as one.
+    obj.toc_base?;
+    let mut tb = TracebackTable::read(&section.data, (end.address - section.address as u32) as usize)?;
+    if let Some(fnc_size) = tb.fnc_size {
+        if fnc_size != end.address - start.address {
+            log::warn!(
+                "Traceback table @ {:#010X} reports function size {:#X}, but {:#010X}..{:#010X} is {:#X}",
+                end,
+                fnc_size,
+                start,
+                end,
+                end.address - start.address
+            );
+            tb.name = None;
+        }
+    }
+    Some(tb)
+}
+
 /// Execute VM from entry point following branches and function calls
 /// until SDA bases are initialized (__init_registers)
 pub fn locate_sda_bases(obj: &mut ObjInfo) -> Result<bool> {
@@ -579,7 +630,16 @@ pub fn locate_sda_bases(obj: &mut ObjInfo) -> Result<bool> {
     executor.push(entry_addr, VM::new(), false);
     let result = executor.run(
         obj,
-        |ExecCbData { executor, vm, result, ins_addr, section: _, ins: _, block_start: _ }| {
+        |ExecCbData {
+             executor,
+             vm,
+             result,
+             ins_addr,
+             section: _,
+             ins: _,
+             block_start: _,
+             operands: _,
+         }| {
             match result {
                 StepResult::Continue | StepResult::LoadStore { .. } => {
                     return Ok(ExecCbResult::Continue);
@@ -638,7 +698,16 @@ pub fn locate_bss_memsets(obj: &mut ObjInfo) -> Result<Vec<(u32, u32)>> {
     executor.push(entry_addr, VM::new(), false);
     executor.run(
         obj,
-        |ExecCbData { executor: _, vm, result, ins_addr, section: _, ins: _, block_start: _ }| {
+        |ExecCbData {
+             executor: _,
+             vm,
+             result,
+             ins_addr,
+             section: _,
+             ins: _,
+             block_start: _,
+             operands: _,
+         }| {
             match result {
                 StepResult::Continue | StepResult::LoadStore { .. } => Ok(ExecCbResult::Continue),
                 StepResult::Illegal => bail!("Illegal instruction @ {}", ins_addr),
@@ -674,3 +743,95 @@ pub fn locate_bss_memsets(obj: &mut ObjInfo) -> Result<Vec<(u32, u32)>> {
     )?;
     Ok(bss_sections)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{tests::{obj, section}, ObjKind};
+
+    /// Traceback table with one fixed parameter, the function size and a name.
+    fn tbtab(fnc_size: u32, name: &str) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0x20, 0x40, 0, 0, 1, 0];
+        data.extend(0u32.to_be_bytes()); // parm_info
+        data.extend(fnc_size.to_be_bytes());
+        data.extend((name.len() as u16).to_be_bytes());
+        data.extend(name.as_bytes());
+        data.resize((data.len() + 3) & !3, 0);
+        data
+    }
+
+    /// A `blr` function at 0x80003100, followed by `tbtab` and another `blr`.
+    fn tbtab_obj(tbtab: &[u8]) -> ObjInfo {
+        let mut data = 0x4E800020u32.to_be_bytes().to_vec();
+        data.extend(tbtab);
+        data.extend(0x4E800020u32.to_be_bytes());
+        let mut obj = obj(ObjKind::Executable, vec![], vec![
+            section(".text", ObjSectionKind::Code, 0x80003100, data),
+        ]);
+        obj.toc_base = Some(0x80010000);
+        obj
+    }
+
+    #[test]
+    fn test_read_traceback_table() {
+        let obj = tbtab_obj(&tbtab(4, "foo"));
+        let start = SectionAddress::new(0, 0x80003100);
+        let tb = read_traceback_table(&obj, &obj.sections[0], start, start + 4).unwrap();
+        assert_eq!(tb.fnc_size, Some(4));
+        assert_eq!(tb.parm_info, Some(0));
+        assert_eq!(tb.name.as_deref(), Some("foo"));
+        assert_eq!(tb.size(), 0x1C);
+    }
+
+    #[test]
+    fn test_read_traceback_table_size_mismatch() {
+        let obj = tbtab_obj(&tbtab(0x40, "foo"));
+        let start = SectionAddress::new(0, 0x80003100);
+        let tb = read_traceback_table(&obj, &obj.sections[0], start, start + 4).unwrap();
+        assert_eq!(tb.name, None);
+
+        // The next function starts after the table, not inside it
+        let mut state = AnalyzerState::default();
+        state.functions.insert(start, FunctionInfo {
+            analyzed: true,
+            end: Some(start + 4),
+            slices: None,
+            tbtab: None,
+        });
+        assert!(state.detect_new_functions(&obj).unwrap());
+        assert_eq!(state.functions.keys().copied().collect::<Vec<_>>(), vec![start, start + 0x20]);
+        assert_eq!(state.functions[&start].tbtab.as_ref().map(|tb| tb.size()), Some(0x1C));
+    }
+
+    #[test]
+    fn test_read_traceback_table_optional_fields() {
+        #[rustfmt::skip]
+        let mut data = vec![
+            0, 0, 0, 0, 0, 0,
+            0x28, // has_tb_table_offset, controlled_storage
+            0xE0, // interrupt_handler, fn_name_present, alloca_used
+            0, 0,
+            2,    // fixed parameters
+            0x02, // one floating point parameter
+        ];
+        data.extend(0x20000000u32.to_be_bytes()); // parm_info: i, i, f
+        data.extend(4u32.to_be_bytes()); // fnc_size
+        data.extend(0x10i32.to_be_bytes()); // hand_mask
+        data.extend(1i32.to_be_bytes()); // ctl_info count
+        data.extend(0x18i32.to_be_bytes()); // ctl_info anchor
+        data.extend(3u16.to_be_bytes());
+        data.extend(b"bar");
+        data.push(31); // alloca_reg
+        data.resize(0x28, 0);
+        let obj = tbtab_obj(&data);
+        let start = SectionAddress::new(0, 0x80003100);
+        let tb = read_traceback_table(&obj, &obj.sections[0], start, start + 4).unwrap();
+        assert_eq!(tb.fnc_size, Some(4));
+        assert_eq!(tb.hand_mask, Some(0x10));
+        assert_eq!(tb.ctl_info, Some(vec![0x18]));
+        assert_eq!(tb.name.as_deref(), Some("bar"));
+        assert_eq!(tb.alloca_reg, Some(31));
+        assert_eq!(tb.param_types().as_deref(), Some("iif"));
+        assert_eq!(tb.size(), 0x28);
+    }
+}
@@ -6,7 +6,7 @@ use crate::{
     analysis::{
         cfa::SectionAddress,
         disassemble,
-        vm::{StepResult, VM},
+        vm::{GprValue, StepResult, VM},
     },
     obj::{ObjInfo, ObjSection, ObjSectionKind},
 };
@@ -66,6 +66,8 @@ pub struct ExecCbData<'a> {
     pub section: &'a ObjSection,
     pub ins: Ins,
     pub block_start: SectionAddress,
+    /// Values of rA and rB before the instruction, which may overwrite them.
+    pub operands: (GprValue, GprValue),
 }
 
 pub enum ExecCbResult<T = ()> {
@@ -111,6 +113,10 @@ impl Executor {
                     Some(ins) => ins,
                     None => return Ok(None),
                 };
+                let operands = (
+                    state.vm.gpr[ins.field_ra() as usize].value,
+                    state.vm.gpr[ins.field_rb() as usize].value,
+                );
                 let result = state.vm.step(obj, state.address, ins);
                 match cb(ExecCbData {
                     executor: self,
@@ -120,6 +126,7 @@ impl Executor {
                     section,
                     ins,
                     block_start,
+                    operands,
                 })? {
                     ExecCbResult::Continue => {
                         state.address += 4;
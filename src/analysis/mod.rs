@@ -2,6 +2,7 @@ use std::{collections::BTreeSet, num::NonZeroU32};
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use ppc750cl::Ins;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     analysis::cfa::SectionAddress,
@@ -11,10 +12,12 @@ use crate::{
     },
 };
 
+pub mod cache;
 pub mod cfa;
 pub mod executor;
 pub mod objects;
 pub mod pass;
+pub mod rtti;
 pub mod signatures;
 pub mod slices;
 pub mod tracker;
@@ -24,6 +27,14 @@ pub fn disassemble(section: &ObjSection, address: u32) -> Option<Ins> {
     read_u32(section, address).map(Ins::new)
 }
 
+pub fn read_u16(section: &ObjSection, address: u32) -> Option<u16> {
+    let offset = (address as u64 - section.address) as usize;
+    if section.data.len() < offset + 2 {
+        return None;
+    }
+    Some(u16::from_be_bytes(*array_ref!(section.data, offset, 2)))
+}
+
 pub fn read_u32(section: &ObjSection, address: u32) -> Option<u32> {
     let offset = (address as u64 - section.address) as usize;
     if section.data.len() < offset + 4 {
@@ -115,7 +126,7 @@ fn is_valid_jump_table_addr(obj: &ObjInfo, addr: SectionAddress) -> bool {
     !matches!(obj.sections[addr.section].kind, ObjSectionKind::Code | ObjSectionKind::Bss)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RelocationTarget {
     Address(SectionAddress),
     External,
@@ -135,14 +146,101 @@ pub fn relocation_target_for(
     Ok(opt)
 }
 
+/// Layout of jump table entries.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum JumpTableKind {
+    /// Absolute 32-bit addresses, loaded with `lwzx`. (MWCC)
+    #[default]
+    Absolute,
+    /// 32-bit offsets from the table address, loaded with `lwzx` and added to it. (GCC)
+    Relative32,
+    /// Signed 16-bit offsets from the table address, loaded with `lhax` or `lhzx`.
+    Relative16,
+}
+
+impl JumpTableKind {
+    #[inline]
+    pub fn entry_size(self) -> u32 {
+        match self {
+            JumpTableKind::Absolute | JumpTableKind::Relative32 => 4,
+            JumpTableKind::Relative16 => 2,
+        }
+    }
+
+    /// The kind after the loaded entry is added to the table address.
+    #[inline]
+    pub fn relative(self) -> Self {
+        match self {
+            JumpTableKind::Absolute => JumpTableKind::Relative32,
+            kind => kind,
+        }
+    }
+}
+
+/// Reads the target of a relative jump table entry at `entry_addr`.
+///
+/// In relocatable objects, 32-bit entries are `R_PPC_REL32` relocations, which are relative
+/// to the entry itself rather than the table.
+pub fn read_relative_jump_table_entry(
+    obj: &ObjInfo,
+    table_addr: SectionAddress,
+    entry_addr: SectionAddress,
+    kind: JumpTableKind,
+) -> Result<Option<SectionAddress>> {
+    let section = &obj.sections[table_addr.section];
+    if let Some(reloc) = section.relocations.at(entry_addr.address) {
+        if reloc.kind != ObjRelocKind::PpcRel32 {
+            return Ok(None);
+        }
+        return Ok(match relocation_target_for(obj, entry_addr, Some(ObjRelocKind::PpcRel32))? {
+            Some(RelocationTarget::Address(target)) => {
+                Some(target - (entry_addr.address - table_addr.address))
+            }
+            _ => None,
+        });
+    }
+    let offset = match kind {
+        JumpTableKind::Relative32 => read_u32(section, entry_addr.address).map(|v| v as i32),
+        JumpTableKind::Relative16 => read_u16(section, entry_addr.address).map(|v| v as i16 as i32),
+        JumpTableKind::Absolute => bail!("Not a relative jump table @ {:#010X}", table_addr),
+    };
+    let Some(offset) = offset.filter(|&o| o != 0) else {
+        return Ok(None);
+    };
+    let target = table_addr.address.wrapping_add(offset as u32);
+    if obj.kind == ObjKind::Executable {
+        Ok(obj
+            .sections
+            .at_address(target)
+            .ok()
+            .map(|(section_index, _)| SectionAddress::new(section_index, target)))
+    } else if section.contains(target) {
+        Ok(Some(SectionAddress::new(table_addr.section, target)))
+    } else {
+        Ok(None)
+    }
+}
+
 fn get_jump_table_entries(
     obj: &ObjInfo,
     addr: SectionAddress,
     size: Option<NonZeroU32>,
+    kind: JumpTableKind,
     from: SectionAddress,
     function_start: SectionAddress,
     function_end: Option<SectionAddress>,
 ) -> Result<(Vec<SectionAddress>, u32)> {
+    if kind != JumpTableKind::Absolute {
+        return get_relative_jump_table_entries(
+            obj,
+            addr,
+            size,
+            kind,
+            from,
+            function_start,
+            function_end,
+        );
+    }
     let section = &obj.sections[addr.section];
 
     // Check for an existing symbol with a known size, and use that if available.
@@ -233,10 +331,65 @@ fn get_jump_table_entries(
     }
 }
 
+fn get_relative_jump_table_entries(
+    obj: &ObjInfo,
+    addr: SectionAddress,
+    size: Option<NonZeroU32>,
+    kind: JumpTableKind,
+    from: SectionAddress,
+    function_start: SectionAddress,
+    function_end: Option<SectionAddress>,
+) -> Result<(Vec<SectionAddress>, u32)> {
+    let known_size = obj
+        .symbols
+        .kind_at_section_address(addr.section, addr.address, ObjSymbolKind::Object)
+        .ok()
+        .flatten()
+        .and_then(|(_, s)| if s.size_known { NonZeroU32::new(s.size as u32) } else { None });
+
+    let entry_size = kind.entry_size();
+    let mut entries = Vec::new();
+    let mut cur_addr = addr;
+    if let Some(size) = known_size.or(size).map(|n| n.get()) {
+        log::trace!(
+            "Located {:?} jump table @ {:#010X} with entry count {} (from {:#010X})",
+            kind,
+            addr,
+            size / entry_size,
+            from
+        );
+        while cur_addr.address < addr.address + size {
+            if let Some(target) = read_relative_jump_table_entry(obj, addr, cur_addr, kind)? {
+                entries.push(target);
+            }
+            cur_addr += entry_size;
+        }
+        Ok((entries, size))
+    } else {
+        while let Some(target) = read_relative_jump_table_entry(obj, addr, cur_addr, kind)? {
+            if target < function_start || matches!(function_end, Some(end) if target >= end) {
+                break;
+            }
+            entries.push(target);
+            cur_addr += entry_size;
+        }
+        let size = cur_addr.address - addr.address;
+        log::debug!(
+            "Guessed {:?} jump table @ {:#010X} with entry count {} (from {:#010X})",
+            kind,
+            addr,
+            size / entry_size,
+            from
+        );
+        Ok((entries, size))
+    }
+}
+
 pub fn uniq_jump_table_entries(
     obj: &ObjInfo,
     addr: SectionAddress,
     size: Option<NonZeroU32>,
+    kind: JumpTableKind,
     from: SectionAddress,
     function_start: SectionAddress,
     function_end: Option<SectionAddress>,
@@ -245,7 +398,7 @@ pub fn uniq_jump_table_entries(
         return Ok((BTreeSet::new(), 0));
     }
     let (entries, size) =
-        get_jump_table_entries(obj, addr, size, from, function_start, function_end)?;
+        get_jump_table_entries(obj, addr, size, kind, from, function_start, function_end)?;
     Ok((BTreeSet::from_iter(entries.iter().cloned()), size))
 }
 
@@ -1,7 +1,7 @@
 use anyhow::Result;
 
 use crate::{
-    obj::{ObjDataKind, ObjInfo, ObjSectionKind, ObjSymbolKind, SymbolIndex},
+    obj::{ObjDataKind, ObjInfo, ObjRelocKind, ObjSectionKind, ObjSymbolKind, SymbolIndex},
     util::split::is_linker_generated_label,
 };
 
@@ -54,6 +54,11 @@ pub fn detect_objects(obj: &mut ObjInfo) -> Result<()> {
             if expected_size > 1 && symbol.size as u32 % expected_size != 0 {
                 symbol.data_kind = ObjDataKind::Unknown;
             }
+            if let ObjDataKind::Array(stride) = symbol.data_kind {
+                if symbol.size as u32 % stride != 0 {
+                    symbol.data_kind = ObjDataKind::Unknown;
+                }
+            }
             replace_symbols.push((idx, symbol));
         }
         for (idx, symbol) in replace_symbols {
@@ -161,3 +166,135 @@ pub fn detect_strings(obj: &mut ObjInfo) -> Result<()> {
     }
     Ok(())
 }
+
+/// Infers pointer tables, string pointer tables and vtables from the relocations
+/// in data objects. Every word must be a relocation or null.
+pub fn detect_data_types(obj: &mut ObjInfo) -> Result<()> {
+    let mut symbols_set = Vec::<(SymbolIndex, ObjDataKind)>::new();
+    for (section_index, section) in obj
+        .sections
+        .iter()
+        .filter(|(_, s)| matches!(s.kind, ObjSectionKind::Data | ObjSectionKind::ReadOnlyData))
+    {
+        if matches!(section.name.as_str(), "extab" | "extabindex" | ".ctors" | ".dtors") {
+            continue;
+        }
+        'symbols: for (symbol_idx, symbol) in obj.symbols.for_section(section_index) {
+            if symbol.kind != ObjSymbolKind::Object
+                || !matches!(symbol.data_kind, ObjDataKind::Unknown | ObjDataKind::Byte4)
+                || !symbol.size_known
+                || symbol.size == 0
+                || symbol.size % 4 != 0
+                || symbol.address % 4 != 0
+                || is_linker_generated_label(&symbol.name)
+                || symbol.name.starts_with("..")
+                || symbol.name.starts_with("jumptable_")
+            {
+                continue;
+            }
+
+            // Target of each word, or None for null
+            let mut targets = Vec::with_capacity(symbol.size as usize / 4);
+            let data = section.symbol_data(symbol)?;
+            for (i, word) in data.chunks_exact(4).enumerate() {
+                let address = symbol.address as u32 + i as u32 * 4;
+                match section.relocations.at(address) {
+                    Some(reloc) if reloc.kind == ObjRelocKind::Absolute => {
+                        targets.push(Some(&obj.symbols[reloc.target_symbol]));
+                    }
+                    None if word == [0; 4] => targets.push(None),
+                    _ => continue 'symbols,
+                }
+            }
+            if targets.iter().all(|t| t.is_none()) {
+                continue;
+            }
+
+            let is_rtti = matches!(targets[0], Some(s) if s.name.starts_with("__RTTI__"));
+            let data_kind = if targets.len() > 2
+                && (symbol.name.starts_with("__vt__") || is_rtti)
+                && targets[1].is_none()
+                && targets[2..].iter().flatten().all(|s| s.kind == ObjSymbolKind::Function)
+            {
+                ObjDataKind::Vtable
+            } else if targets.len() > 1
+                && targets.iter().flatten().all(|s| {
+                    matches!(
+                        s.data_kind,
+                        ObjDataKind::String
+                            | ObjDataKind::String16
+                            | ObjDataKind::StringTable
+                            | ObjDataKind::String16Table
+                    )
+                })
+            {
+                ObjDataKind::StringPointerTable
+            } else {
+                ObjDataKind::Pointer
+            };
+            log::debug!("Found {:?} @ {}", data_kind, symbol.name);
+            symbols_set.push((symbol_idx, data_kind));
+        }
+    }
+
+    for (symbol_idx, data_kind) in symbols_set {
+        let mut symbol = obj.symbols[symbol_idx].clone();
+        symbol.data_kind = data_kind;
+        obj.symbols.replace(symbol_idx, symbol)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, reloc, section, symbol},
+        ObjKind, ObjSymbol,
+    };
+
+    fn object(name: &str, section: u32, address: u64, size: u64, kind: ObjDataKind) -> ObjSymbol {
+        ObjSymbol { data_kind: kind, ..symbol(name, ObjSymbolKind::Object, section, address, size) }
+    }
+
+    #[test]
+    fn test_detect_data_types() {
+        let text = section(".text", ObjSectionKind::Code, 0x80003100, vec![0; 0x10]);
+        let mut data_bytes = vec![0; 0x2C];
+        data_bytes[0x2B] = 1;
+        let mut data = section(".data", ObjSectionKind::Data, 0x80004000, data_bytes);
+        let rodata = section(".rodata", ObjSectionKind::ReadOnlyData, 0x80005000, vec![0; 8]);
+        for (address, target) in [
+            (0x80004008, 2), // __vt__4CFoo -> __RTTI__4CFoo
+            (0x80004010, 0), // __vt__4CFoo -> fn_80003100
+            (0x80004014, 1), // __vt__4CFoo -> fn_80003108
+            (0x80004018, 4), // table -> str_a
+            (0x8000401C, 5), // table -> str_b
+            (0x80004020, 0), // ptr -> fn_80003100
+        ] {
+            data.relocations.insert(address, reloc(ObjRelocKind::Absolute, target, 0)).unwrap();
+        }
+        let symbols = vec![
+            symbol("fn_80003100", ObjSymbolKind::Function, 0, 0x80003100, 8),
+            symbol("fn_80003108", ObjSymbolKind::Function, 0, 0x80003108, 8),
+            object("__RTTI__4CFoo", 1, 0x80004000, 8, ObjDataKind::Unknown),
+            object("__vt__4CFoo", 1, 0x80004008, 0x10, ObjDataKind::Unknown),
+            object("str_a", 2, 0x80005000, 4, ObjDataKind::String),
+            object("str_b", 2, 0x80005004, 4, ObjDataKind::String),
+            object("table", 1, 0x80004018, 8, ObjDataKind::Unknown),
+            object("ptr", 1, 0x80004020, 8, ObjDataKind::Byte4),
+            object("word", 1, 0x80004028, 4, ObjDataKind::Unknown),
+        ];
+        let mut obj = obj(ObjKind::Executable, symbols, vec![text, data, rodata]);
+        detect_data_types(&mut obj).unwrap();
+
+        let data_kind = |name: &str| obj.symbols.by_name(name).unwrap().unwrap().1.data_kind;
+        // All null, so nothing to classify
+        assert_eq!(data_kind("__RTTI__4CFoo"), ObjDataKind::Unknown);
+        assert_eq!(data_kind("__vt__4CFoo"), ObjDataKind::Vtable);
+        assert_eq!(data_kind("table"), ObjDataKind::StringPointerTable);
+        assert_eq!(data_kind("ptr"), ObjDataKind::Pointer);
+        // Not a relocation or null
+        assert_eq!(data_kind("word"), ObjDataKind::Unknown);
+    }
+}
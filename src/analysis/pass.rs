@@ -2,9 +2,13 @@ use anyhow::{bail, ensure, Result};
 use flagset::FlagSet;
 use itertools::Itertools;
 use memchr::memmem;
+use ppc750cl::Opcode;
 
 use crate::{
-    analysis::cfa::{AnalyzerState, FunctionInfo, SectionAddress},
+    analysis::{
+        cfa::{AnalyzerState, FunctionInfo, SectionAddress},
+        slices::{IdiomAction, IdiomContext, LeafIdiom},
+    },
     obj::{
         ObjInfo, ObjKind, ObjRelocKind, ObjSectionKind, ObjSymbol, ObjSymbolFlagSet,
         ObjSymbolFlags, ObjSymbolKind, SectionIndex,
@@ -72,6 +76,18 @@ const SLEDS: [([u8; 8], &str, &str, u32, u32, u32); 6] = [
     ([0x39, 0x80, 0xff, 0x40, 0x7e, 0x8c, 0x00, 0xce], "_restorevr", "_restv", 20, 32, 8),
 ];
 
+/// Saves and restores through r11 without setting up a stack frame, which
+/// would otherwise be mistaken for a prologue or epilogue.
+fn is_save_rest_sled(ctx: &IdiomContext) -> bool {
+    ctx.ins_addr == ctx.function_start
+        && matches!(ctx.ins.op, Opcode::Stw | Opcode::Lwz | Opcode::Stfd | Opcode::Lfd)
+        && ctx.ins.field_ra() == 11
+        && ctx.ins.field_simm() < 0
+}
+
+const SAVE_REST_SLED: LeafIdiom =
+    LeafIdiom { name: "save-rest-sled", matches: is_save_rest_sled, action: IdiomAction::Leaf };
+
 // Runtime.PPCEABI.H.a runtime.c
 impl AnalysisPass for FindSaveRestSleds {
     fn execute(state: &mut AnalyzerState, obj: &ObjInfo) -> Result<()> {
@@ -82,6 +98,7 @@ impl AnalysisPass for FindSaveRestSleds {
                 };
                 let start = SectionAddress::new(section_index, section.address as u32 + pos as u32);
                 log::debug!("Found {} @ {:#010X}", func, start);
+                state.register_leaf_idiom(SAVE_REST_SLED);
                 let sled_size = (reg_end - reg_start) * step_size + 4 /* blr */;
                 state.functions.insert(start, FunctionInfo {
                     analyzed: false,
@@ -116,6 +133,70 @@ impl AnalysisPass for FindSaveRestSleds {
     }
 }
 
+pub struct FindCfmGlueStubs {}
+
+/// Cross-fragment glue, following the initial `lwz r12, import(r2)`:
+/// `stw r2, 20(r1); lwz r0, 0(r12); lwz r2, 4(r12); mtctr r0; bctr`
+const CFM_GLUE: [u8; 20] = [
+    0x90, 0x41, 0x00, 0x14, 0x80, 0x0c, 0x00, 0x00, 0x80, 0x4c, 0x00, 0x04, 0x7c, 0x09, 0x03, 0xa6,
+    0x4e, 0x80, 0x04, 0x20,
+];
+const CFM_GLUE_SIZE: u32 = 24;
+
+// Code Fragment Manager cross-TOC calls, named after the import (XCOFF convention)
+impl AnalysisPass for FindCfmGlueStubs {
+    fn execute(state: &mut AnalyzerState, obj: &ObjInfo) -> Result<()> {
+        let Some(toc_base) = obj.toc_base else {
+            return Ok(());
+        };
+        for (section_index, section) in obj.sections.by_kind(ObjSectionKind::Code) {
+            for pos in memmem::find_iter(&section.data, &CFM_GLUE) {
+                if pos < 4 || pos % 4 != 0 {
+                    continue;
+                }
+                // lwz r12, d(r2)
+                let ins = u32::from_be_bytes(section.data[pos - 4..pos].try_into().unwrap());
+                if ins & 0xFFFF0000 != 0x81820000 {
+                    continue;
+                }
+                let slot = toc_base.wrapping_add(ins as i16 as i32 as u32);
+                let Ok((_, toc_section)) = obj.sections.at_address(slot) else {
+                    continue;
+                };
+                let Some(reloc) = toc_section.relocations.at(slot) else {
+                    continue;
+                };
+                let target = &obj.symbols[reloc.target_symbol];
+                if target.section.is_some() || reloc.addend != 0 {
+                    // Not an imported symbol
+                    continue;
+                }
+                let start =
+                    SectionAddress::new(section_index, section.address as u32 + pos as u32 - 4);
+                let name = format!(".{}", target.name);
+                log::debug!("Found glue {} @ {:#010X}", name, start);
+                state.functions.insert(start, FunctionInfo {
+                    analyzed: false,
+                    end: Some(start + CFM_GLUE_SIZE),
+                    slices: None,
+                    tbtab: None,
+                });
+                state.known_symbols.entry(start).or_default().push(ObjSymbol {
+                    name,
+                    address: start.address as u64,
+                    section: Some(start.section),
+                    size: CFM_GLUE_SIZE as u64,
+                    size_known: true,
+                    flags: ObjSymbolFlagSet(ObjSymbolFlags::Global.into()),
+                    kind: ObjSymbolKind::Function,
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct FindRelCtorsDtors {}
 
 impl AnalysisPass for FindRelCtorsDtors {
@@ -302,3 +383,79 @@ impl AnalysisPass for FindRelRodataData {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ppc750cl::Ins;
+
+    use super::*;
+    use crate::obj::tests::{obj, reloc, section, symbol};
+
+    #[test]
+    fn test_save_rest_sled() {
+        // stfd f14, -0x90(r11) ... stfd f31, -0x8(r11); blr
+        let mut code = (0..18u32).map(|i| 0xD9CBFF70 + (i << 21) + i * 8).collect::<Vec<_>>();
+        code.push(0x4E800020);
+        let data = code.iter().flat_map(|ins| ins.to_be_bytes()).collect();
+        let obj = obj(ObjKind::Executable, vec![], vec![section(
+            ".text",
+            ObjSectionKind::Code,
+            0x80003100,
+            data,
+        )]);
+        let mut state = AnalyzerState::default();
+        FindSaveRestSleds::execute(&mut state, &obj).unwrap();
+
+        let start = SectionAddress::new(0, 0x80003100);
+        assert_eq!(state.functions[&start].end, Some(start + 0x4C));
+        assert_eq!(state.known_symbols[&start][0].name, "__save_fpr");
+        assert_eq!(state.known_symbols[&(start + 4)][0].name, "_savefpr_15");
+
+        let section = &obj.sections[0];
+        let matching = |ins_addr: SectionAddress| {
+            let ins = Ins::new(code[((ins_addr.address - start.address) / 4) as usize]);
+            let ctx = IdiomContext { section, function_start: start, ins_addr, ins };
+            state.leaf_idioms.matching(&ctx).map(|idiom| idiom.name).collect::<Vec<_>>()
+        };
+        assert_eq!(matching(start), vec![SAVE_REST_SLED.name]);
+        // Only the function's first instruction marks it as a leaf
+        assert!(matching(start + 4).is_empty());
+    }
+
+    #[test]
+    fn test_cfm_glue_stubs() {
+        // Glue for an imported symbol, then glue through a TOC slot for a local symbol
+        let mut data = vec![0x60, 0x00, 0x00, 0x00]; // nop
+        for slot in [0x10u32, 0x14] {
+            data.extend((0x81820000 | slot).to_be_bytes()); // lwz r12, slot(r2)
+            data.extend(CFM_GLUE);
+        }
+        data.extend([0x4E, 0x80, 0x00, 0x20]); // blr
+        let mut import = symbol("InitGraf", ObjSymbolKind::Unknown, 0, 0, 0);
+        import.section = None;
+        let local = symbol("lbl_80010000", ObjSymbolKind::Object, 1, 0x80010000, 4);
+        let mut obj = obj(ObjKind::Executable, vec![import, local], vec![
+            section(".text", ObjSectionKind::Code, 0x80003100, data),
+            section(".data", ObjSectionKind::Data, 0x80010000, vec![0; 0x20]),
+        ]);
+        let relocations = &mut obj.sections[1].relocations;
+        relocations.insert(0x80010010, reloc(ObjRelocKind::Absolute, 0, 0)).unwrap();
+        relocations.insert(0x80010014, reloc(ObjRelocKind::Absolute, 1, 0)).unwrap();
+
+        // The TOC anchor is required to locate the slots
+        let mut state = AnalyzerState::default();
+        FindCfmGlueStubs::execute(&mut state, &obj).unwrap();
+        assert!(state.functions.is_empty());
+
+        obj.toc_base = Some(0x80010000);
+        let mut state = AnalyzerState::default();
+        FindCfmGlueStubs::execute(&mut state, &obj).unwrap();
+        let start = SectionAddress::new(0, 0x80003104);
+        assert_eq!(state.functions.keys().copied().collect::<Vec<_>>(), vec![start]);
+        assert_eq!(state.functions[&start].end, Some(start + CFM_GLUE_SIZE));
+        let symbol = &state.known_symbols[&start][0];
+        assert_eq!(symbol.name, ".InitGraf");
+        assert_eq!(symbol.size, CFM_GLUE_SIZE as u64);
+        assert_eq!(symbol.kind, ObjSymbolKind::Function);
+    }
+}
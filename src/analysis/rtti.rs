@@ -0,0 +1,496 @@
+use std::{collections::BTreeMap, io::Write};
+
+use anyhow::Result;
+use cwdemangle::{demangle, DemangleOptions};
+
+use crate::{
+    analysis::cfa::SectionAddress,
+    obj::{ObjDataKind, ObjInfo, ObjRelocKind, ObjSectionKind, ObjSymbolKind, SymbolIndex},
+    util::{config::is_auto_symbol, split::is_linker_generated_label},
+};
+
+/// A C++ class recovered from a vtable and, if present, its RTTI record.
+#[derive(Debug, Clone)]
+pub struct ClassInfo {
+    pub vtable: SectionAddress,
+    pub rtti: Option<SectionAddress>,
+    /// Class name as stored in the RTTI type name string.
+    pub name: Option<String>,
+    /// Mangled class name, e.g. `7CFooBar`.
+    pub mangled: Option<String>,
+    /// RTTI records of all base classes, with their offset in the class.
+    pub bases: Vec<(SectionAddress, u32)>,
+    /// Virtual functions of the primary vtable, in slot order.
+    pub slots: Vec<SectionAddress>,
+}
+
+/// Reads the target of an absolute relocation at `address`.
+fn read_pointer(obj: &ObjInfo, address: SectionAddress) -> Option<SectionAddress> {
+    let reloc = obj.sections[address.section].relocations.at(address.address)?;
+    if reloc.kind != ObjRelocKind::Absolute {
+        return None;
+    }
+    let target = &obj.symbols[reloc.target_symbol];
+    let section = target.section?;
+    Some(SectionAddress::new(section, (target.address as i64 + reloc.addend) as u32))
+}
+
+fn read_word(obj: &ObjInfo, address: SectionAddress) -> Option<u32> {
+    let data =
+        obj.sections[address.section].data_range(address.address, address.address + 4).ok()?;
+    Some(u32::from_be_bytes(data.try_into().ok()?))
+}
+
+fn is_function(obj: &ObjInfo, address: SectionAddress) -> bool {
+    obj.symbols
+        .kind_at_section_address(address.section, address.address, ObjSymbolKind::Function)
+        .ok()
+        .flatten()
+        .is_some()
+}
+
+/// Reads a NUL-terminated ASCII type name.
+fn read_type_name(obj: &ObjInfo, address: SectionAddress) -> Option<String> {
+    let section = &obj.sections[address.section];
+    let data = section.data_range(address.address, (section.address + section.size) as u32).ok()?;
+    let len = data.iter().take(256).position(|&c| c == 0)?;
+    let name = &data[..len];
+    if name.is_empty() || !name.iter().all(|c| c.is_ascii_graphic() || *c == b' ') {
+        return None;
+    }
+    Some(String::from_utf8_lossy(name).into_owned())
+}
+
+/// Reads an RTTI record: a pointer to the type name, followed by a pointer to
+/// the base class list (or null). The base class list holds pairs of
+/// (`__RTTI__` record, offset) terminated by a null pointer.
+fn read_rtti(
+    obj: &ObjInfo,
+    address: SectionAddress,
+) -> Option<(Option<String>, Vec<(SectionAddress, u32)>)> {
+    let name_addr = read_pointer(obj, address)?;
+    let name = read_type_name(obj, name_addr);
+    let mut bases = vec![];
+    if let Some(mut base_addr) = read_pointer(obj, address + 4) {
+        while let Some(base) = read_pointer(obj, base_addr) {
+            bases.push((base, read_word(obj, base_addr + 4)?));
+            base_addr += 8;
+        }
+        if read_word(obj, base_addr)? != 0 {
+            return None;
+        }
+    } else if read_word(obj, address + 4)? != 0 {
+        return None;
+    }
+    Some((name, bases))
+}
+
+/// Mangles a class name the way MWCC does, e.g. `CFooBar` -> `7CFooBar`
+/// and `ns::CFooBar` -> `Q22ns7CFooBar`. Template classes are not supported.
+fn mangle_class_name(name: &str) -> Option<String> {
+    let parts = name.split("::").collect::<Vec<_>>();
+    if parts.len() > 9
+        || !parts.iter().all(|p| {
+            p.chars().next().is_some_and(|c| !c.is_ascii_digit())
+                && p.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+    {
+        return None;
+    }
+    let mut mangled = String::new();
+    if parts.len() > 1 {
+        mangled.push_str(&format!("Q{}", parts.len()));
+    }
+    for part in parts {
+        mangled.push_str(&format!("{}{}", part.len(), part));
+    }
+    demangle(&format!("__vt__{}", mangled), &DemangleOptions::default())?;
+    Some(mangled)
+}
+
+/// Reads the vtable at `address`: one or more groups of an RTTI pointer (or null),
+/// an offset (zero for the primary group), and the virtual function pointers.
+fn read_vtable(obj: &ObjInfo, address: SectionAddress, size: u32) -> Option<ClassInfo> {
+    let end = address + size;
+    let rtti = read_pointer(obj, address);
+    if rtti.is_none() && read_word(obj, address)? != 0 {
+        return None;
+    }
+    if read_pointer(obj, address + 4).is_some() || read_word(obj, address + 4)? != 0 {
+        return None;
+    }
+    let mut slots = vec![];
+    let mut cur = address + 8;
+    while cur < end {
+        match read_pointer(obj, cur) {
+            Some(target) if is_function(obj, target) => slots.push(target),
+            _ => break,
+        }
+        cur += 4;
+    }
+    if slots.is_empty() {
+        return None;
+    }
+    // Secondary vtables for multiple inheritance
+    while cur < end {
+        if read_pointer(obj, cur) != rtti || read_pointer(obj, cur + 4).is_some() {
+            return None;
+        }
+        cur += 8;
+        let start = cur;
+        while cur < end && read_pointer(obj, cur).is_some_and(|t| is_function(obj, t)) {
+            cur += 4;
+        }
+        if cur == start {
+            return None;
+        }
+    }
+
+    let (name, bases) = match rtti {
+        Some(rtti) => read_rtti(obj, rtti)?,
+        None => (None, vec![]),
+    };
+    let mangled = name.as_deref().and_then(mangle_class_name);
+    Some(ClassInfo { vtable: address, rtti, name, mangled, bases, slots })
+}
+
+fn auto_name(obj: &ObjInfo, prefix: &str, address: SectionAddress) -> String {
+    if obj.module_id == 0 {
+        format!("{}_{:08X}", prefix, address.address)
+    } else {
+        format!(
+            "{}_{}_{}_{:X}",
+            prefix,
+            obj.module_id,
+            obj.sections[address.section].name.trim_start_matches('.'),
+            address.address
+        )
+    }
+}
+
+/// Candidate name for the virtual function in `slot`, e.g. `vfunc_2__7CFooBarFv`
+/// (`CFooBar::vfunc_2(void)`). The argument list is a placeholder, since the
+/// actual parameters are unknown, but it keeps the name demangleable.
+fn vfunc_name(slot: usize, mangled_class: &str) -> String {
+    format!("vfunc_{}__{}Fv", slot, mangled_class)
+}
+
+/// Renames an auto-generated symbol, unless the name is already taken.
+fn rename_symbol(
+    obj: &mut ObjInfo,
+    symbol_idx: SymbolIndex,
+    name: String,
+    data_kind: Option<ObjDataKind>,
+) -> Result<()> {
+    let mut symbol = obj.symbols[symbol_idx].clone();
+    if is_auto_symbol(&symbol) && symbol.name != name {
+        if obj.symbols.for_name(&name).next().is_some() {
+            log::debug!("Not renaming {} to {}: name already in use", symbol.name, name);
+        } else {
+            log::debug!("Renaming {} to {}", symbol.name, name);
+            symbol.demangled_name = demangle(&name, &DemangleOptions::default());
+            symbol.name = name;
+        }
+    }
+    if let Some(data_kind) = data_kind {
+        symbol.data_kind = data_kind;
+    }
+    obj.symbols.replace(symbol_idx, symbol)
+}
+
+/// Finds C++ vtables and RTTI records in data sections and names them.
+///
+/// Vtables are named `__vt__<class>` when the RTTI type name is known,
+/// otherwise `vtable_<address>`. RTTI records are named `__RTTI__<class>`,
+/// and auto-named virtual functions get a `vfunc_<slot>__<class>Fv` candidate name.
+/// Base classes are processed first, so inherited slots keep the base class name.
+pub fn detect_vtables(obj: &mut ObjInfo) -> Result<Vec<ClassInfo>> {
+    let mut classes = vec![];
+    for (section_index, section) in
+        obj.sections.iter().filter(|(_, s)| s.kind == ObjSectionKind::Data)
+    {
+        if matches!(section.name.as_str(), "extab" | "extabindex" | ".ctors" | ".dtors") {
+            continue;
+        }
+        for (symbol_idx, symbol) in obj.symbols.for_section(section_index) {
+            if symbol.kind != ObjSymbolKind::Object
+                || !matches!(
+                    symbol.data_kind,
+                    ObjDataKind::Unknown
+                        | ObjDataKind::Byte4
+                        | ObjDataKind::Pointer
+                        | ObjDataKind::Vtable
+                )
+                || !symbol.size_known
+                || symbol.size < 12
+                || symbol.size % 4 != 0
+                || symbol.address % 4 != 0
+                || is_linker_generated_label(&symbol.name)
+                || symbol.name.starts_with("..")
+            {
+                continue;
+            }
+            let address = SectionAddress::new(section_index, symbol.address as u32);
+            if let Some(class) = read_vtable(obj, address, symbol.size as u32) {
+                classes.push((symbol_idx, class));
+            }
+        }
+    }
+
+    // Base classes have fewer bases, so they come first
+    classes.sort_by_key(|(_, class)| (class.bases.len(), class.vtable));
+    let mut named = 0;
+    for (symbol_idx, class) in &classes {
+        let vtable_name = match &class.mangled {
+            Some(mangled) => {
+                named += 1;
+                format!("__vt__{}", mangled)
+            }
+            None => auto_name(obj, "vtable", class.vtable),
+        };
+        rename_symbol(obj, *symbol_idx, vtable_name, Some(ObjDataKind::Vtable))?;
+        let Some(mangled) = &class.mangled else {
+            continue;
+        };
+        if let Some(rtti) = class.rtti {
+            if let Some((rtti_idx, _)) = obj
+                .symbols
+                .at_section_address(rtti.section, rtti.address)
+                .find(|(_, s)| s.kind == ObjSymbolKind::Object)
+            {
+                rename_symbol(obj, rtti_idx, format!("__RTTI__{}", mangled), None)?;
+            }
+        }
+        for (slot, &target) in class.slots.iter().enumerate() {
+            let Some((function_idx, function)) = obj.symbols.kind_at_section_address(
+                target.section,
+                target.address,
+                ObjSymbolKind::Function,
+            )?
+            else {
+                continue;
+            };
+            // Only replace the generated fn_ names, not a previous candidate
+            if function.name.starts_with("fn_") {
+                rename_symbol(obj, function_idx, vfunc_name(slot, mangled), None)?;
+            }
+        }
+    }
+    if !classes.is_empty() {
+        log::info!("Found {} vtables ({} with class names)", classes.len(), named);
+    }
+    Ok(classes.into_iter().map(|(_, class)| class).collect())
+}
+
+/// Writes the recovered classes and their base classes, one class per line.
+pub fn write_classes<W>(w: &mut W, obj: &ObjInfo, classes: &[ClassInfo]) -> Result<()>
+where W: Write + ?Sized {
+    let names = classes
+        .iter()
+        .filter_map(|c| Some((c.rtti?, c.name.clone()?)))
+        .collect::<BTreeMap<SectionAddress, String>>();
+    let class_name = |rtti: SectionAddress| {
+        names.get(&rtti).cloned().or_else(|| read_rtti(obj, rtti).and_then(|(name, _)| name))
+    };
+    for class in classes {
+        let vtable_name = obj
+            .symbols
+            .at_section_address(class.vtable.section, class.vtable.address)
+            .find(|(_, s)| s.kind == ObjSymbolKind::Object)
+            .map_or_else(|| class.vtable.to_string(), |(_, s)| s.name.clone());
+        let name = class.name.clone().unwrap_or_else(|| vtable_name.clone());
+        write!(w, "{}", name)?;
+        if !class.bases.is_empty() {
+            let bases = class
+                .bases
+                .iter()
+                .map(|&(rtti, offset)| {
+                    let base = class_name(rtti).unwrap_or_else(|| rtti.to_string());
+                    if offset != 0 {
+                        format!("{} (+{:#X})", base, offset)
+                    } else {
+                        base
+                    }
+                })
+                .collect::<Vec<_>>();
+            write!(w, " : {}", bases.join(", "))?;
+        }
+        writeln!(
+            w,
+            " // {} @ {}, {} virtual functions",
+            vtable_name,
+            class.vtable,
+            class.slots.len()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, reloc, section, symbol},
+        ObjKind, SectionIndex,
+    };
+
+    /// Two classes, `CDerived : CBase`, plus a vtable without RTTI and
+    /// a function pointer table that is not a vtable.
+    fn class_obj() -> ObjInfo {
+        let mut sections = vec![
+            section(".text", ObjSectionKind::Code, 0x80003000, vec![0; 0x10]),
+            section(".rodata", ObjSectionKind::ReadOnlyData, 0x80004000, {
+                let mut data = b"CBase\0\0\0CDerived\0".to_vec();
+                data.resize(0x14, 0);
+                data
+            }),
+            section(".data", ObjSectionKind::Data, 0x80005000, vec![0; 0x54]),
+        ];
+        let mut symbols = (0..4)
+            .map(|i| {
+                let address = 0x80003000 + i * 4;
+                symbol(&format!("fn_{:08X}", address), ObjSymbolKind::Function, 0, address, 4)
+            })
+            .collect::<Vec<_>>();
+        for (address, size) in [(0x80004000, 6), (0x80004008, 9)] {
+            symbols.push(symbol(
+                &format!("lbl_{:08X}", address),
+                ObjSymbolKind::Object,
+                1,
+                address,
+                size,
+            ));
+        }
+        for (address, size) in [
+            (0x80005000, 8),  // RTTI CBase
+            (0x80005008, 8),  // RTTI CDerived
+            (0x80005010, 12), // CDerived base class list
+            (0x8000501C, 16), // vtable CBase
+            (0x8000502C, 16), // vtable CDerived
+            (0x8000503C, 12), // function pointer table
+            (0x80005048, 12), // vtable without RTTI
+        ] {
+            symbols.push(symbol(
+                &format!("lbl_{:08X}", address),
+                ObjSymbolKind::Object,
+                2,
+                address,
+                size,
+            ));
+        }
+        for (address, target) in [
+            (0x80005000, 4),
+            (0x80005008, 5),
+            (0x8000500C, 8),
+            (0x80005010, 6),
+            (0x8000501C, 6),
+            (0x80005024, 0),
+            (0x80005028, 1),
+            (0x8000502C, 7),
+            (0x80005034, 2),
+            (0x80005038, 1),
+            (0x8000503C, 0),
+            (0x80005040, 1),
+            (0x80005044, 2),
+            (0x80005050, 3),
+        ] {
+            sections[2]
+                .relocations
+                .insert(address, reloc(ObjRelocKind::Absolute, target, 0))
+                .unwrap();
+        }
+        obj(ObjKind::Executable, symbols, sections)
+    }
+
+    fn data_addr(address: u32) -> SectionAddress { SectionAddress::new(2, address) }
+
+    fn text_addr(address: u32) -> SectionAddress { SectionAddress::new(0, address) }
+
+    #[test]
+    fn test_read_rtti() {
+        let obj = class_obj();
+        let (name, bases) = read_rtti(&obj, data_addr(0x80005000)).unwrap();
+        assert_eq!(name.as_deref(), Some("CBase"));
+        assert!(bases.is_empty());
+        let (name, bases) = read_rtti(&obj, data_addr(0x80005008)).unwrap();
+        assert_eq!(name.as_deref(), Some("CDerived"));
+        assert_eq!(bases, [(data_addr(0x80005000), 0)]);
+        // The base class list is not an RTTI record
+        assert!(read_rtti(&obj, data_addr(0x80005014)).is_none());
+    }
+
+    #[test]
+    fn test_read_vtable() {
+        let obj = class_obj();
+        let class = read_vtable(&obj, data_addr(0x8000502C), 16).unwrap();
+        assert_eq!(class.rtti, Some(data_addr(0x80005008)));
+        assert_eq!(class.name.as_deref(), Some("CDerived"));
+        assert_eq!(class.mangled.as_deref(), Some("8CDerived"));
+        assert_eq!(class.bases, [(data_addr(0x80005000), 0)]);
+        assert_eq!(class.slots, [text_addr(0x80003008), text_addr(0x80003004)]);
+
+        let class = read_vtable(&obj, data_addr(0x80005048), 12).unwrap();
+        assert_eq!(class.rtti, None);
+        assert_eq!(class.name, None);
+        assert_eq!(class.slots, [text_addr(0x8000300C)]);
+
+        // Base class list: no function pointers after the RTTI pointer and offset
+        assert!(read_vtable(&obj, data_addr(0x80005010), 12).is_none());
+        // Function pointer table: no zero offset after the first pointer
+        assert!(read_vtable(&obj, data_addr(0x8000503C), 12).is_none());
+    }
+
+    #[test]
+    fn test_detect_vtables() {
+        let mut obj = class_obj();
+        let classes = detect_vtables(&mut obj).unwrap();
+        let vtables = classes.iter().map(|c| c.vtable.address).collect::<Vec<_>>();
+        assert_eq!(vtables, [0x8000501C, 0x80005048, 0x8000502C]);
+
+        let name_at = |section: SectionIndex, address: u32| {
+            obj.symbols.at_section_address(section, address).next().unwrap().1.name.clone()
+        };
+        assert_eq!(name_at(2, 0x8000501C), "__vt__5CBase");
+        assert_eq!(name_at(2, 0x80005000), "__RTTI__5CBase");
+        assert_eq!(name_at(2, 0x8000502C), "__vt__8CDerived");
+        assert_eq!(name_at(2, 0x80005008), "__RTTI__8CDerived");
+        assert_eq!(name_at(2, 0x80005048), "vtable_80005048");
+        assert_eq!(name_at(2, 0x80005010), "lbl_80005010");
+        assert_eq!(name_at(2, 0x8000503C), "lbl_8000503C");
+        assert_eq!(name_at(0, 0x80003000), "vfunc_0__5CBaseFv");
+        // Inherited slots keep the base class name
+        assert_eq!(name_at(0, 0x80003004), "vfunc_1__5CBaseFv");
+        assert_eq!(name_at(0, 0x80003008), "vfunc_0__8CDerivedFv");
+        assert_eq!(name_at(0, 0x8000300C), "fn_8000300C");
+        let (_, vtable) = obj.symbols.by_name("__vt__8CDerived").unwrap().unwrap();
+        assert_eq!(vtable.data_kind, ObjDataKind::Vtable);
+
+        let mut out = vec![];
+        write_classes(&mut out, &obj, &classes).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text
+            .contains("CDerived : CBase // __vt__8CDerived @ 2:0x8000502C, 2 virtual functions\n"));
+    }
+
+    #[test]
+    fn test_mangle_class_name() {
+        assert_eq!(mangle_class_name("CFooBar").as_deref(), Some("7CFooBar"));
+        assert_eq!(mangle_class_name("ns::CFoo").as_deref(), Some("Q22ns4CFoo"));
+        assert_eq!(mangle_class_name("a::b::C").as_deref(), Some("Q31a1b1C"));
+        assert_eq!(mangle_class_name("Foo<int>"), None);
+        assert_eq!(mangle_class_name("ns::Foo<int>"), None);
+        assert_eq!(mangle_class_name("1Foo"), None);
+        assert_eq!(mangle_class_name("ns::"), None);
+        assert_eq!(mangle_class_name(""), None);
+    }
+
+    #[test]
+    fn test_vfunc_name() {
+        let name = vfunc_name(2, "7CFooBar");
+        assert_eq!(name, "vfunc_2__7CFooBarFv");
+        let demangled = demangle(&name, &DemangleOptions::default()).unwrap();
+        assert!(demangled.starts_with("CFooBar::vfunc_2("), "{}", demangled);
+        let demangled = demangle(&vfunc_name(0, "Q22ns4CFoo"), &DemangleOptions::default());
+        assert!(demangled.is_some_and(|s| s.starts_with("ns::CFoo::vfunc_0(")));
+    }
+}
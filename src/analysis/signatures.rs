@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use anyhow::{anyhow, Result};
 use itertools::Itertools;
 
@@ -10,7 +12,10 @@ use crate::{
         ObjInfo, ObjSectionKind, ObjSplit, ObjSymbol, ObjSymbolFlagSet, ObjSymbolFlags,
         ObjSymbolKind,
     },
-    util::signatures::{apply_signature, check_signatures, check_signatures_str, parse_signatures},
+    util::signatures::{
+        apply_signature, check_signatures, check_signatures_str, parse_signatures,
+        SignatureDatabase,
+    },
 };
 
 const SIGNATURES: &[(&str, &str)] = &[
@@ -441,6 +446,127 @@ pub fn apply_signatures_post(obj: &mut ObjInfo) -> Result<()> {
     Ok(())
 }
 
+/// Functions smaller than this (4 instructions) are not matched against a signature
+/// database, since tiny stubs like a lone `blr` would match everywhere.
+pub const MIN_SIGNATURE_SIZE: u64 = 16;
+
+/// Result of scanning unnamed functions against a signature database.
+#[derive(Debug, Default)]
+pub struct SignatureScanResult {
+    /// Functions with a single matching name, and the signature index.
+    pub matches: Vec<(SectionAddress, usize)>,
+    /// Functions matching signatures with differing names.
+    pub ambiguous: Vec<(SectionAddress, Vec<usize>)>,
+    /// Signatures matching more than one function, which can't all be given the same name.
+    pub duplicates: Vec<(usize, Vec<SectionAddress>)>,
+}
+
+/// Matches every unnamed function (`fn_XXXXXXXX`) with a known size
+/// against the signature database.
+///
+/// Functions smaller than [MIN_SIGNATURE_SIZE] are skipped. A name matching more
+/// than one function is reported in [SignatureScanResult::duplicates] instead.
+pub fn scan_signature_database(
+    obj: &ObjInfo,
+    db: &SignatureDatabase,
+) -> Result<SignatureScanResult> {
+    let mut result = SignatureScanResult::default();
+    for (section_index, section) in obj.sections.by_kind(ObjSectionKind::Code) {
+        for (_, symbol) in obj.symbols.for_section(section_index) {
+            if symbol.kind != ObjSymbolKind::Function
+                || !symbol.name.starts_with("fn_")
+                || !symbol.size_known
+                || symbol.size < MIN_SIGNATURE_SIZE
+            {
+                continue;
+            }
+            let start = symbol.address as u32;
+            let data = section.data_range(start, start + symbol.size as u32)?;
+            let found = db.find(data);
+            let Some(&first) = found.first() else {
+                continue;
+            };
+            let addr = SectionAddress::new(section_index, start);
+            let name = db.signatures[first].name();
+            if found.iter().all(|&idx| db.signatures[idx].name() == name) {
+                result.matches.push((addr, first));
+            } else {
+                result.ambiguous.push((addr, found));
+            }
+        }
+    }
+
+    // Each name can only be applied once
+    let mut by_name = BTreeMap::<&str, Vec<(SectionAddress, usize)>>::new();
+    for &(addr, idx) in &result.matches {
+        by_name.entry(db.signatures[idx].name()).or_default().push((addr, idx));
+    }
+    result.matches.clear();
+    for (_, matches) in by_name {
+        if let [single] = matches.as_slice() {
+            result.matches.push(*single);
+        } else {
+            result.duplicates.push((matches[0].1, matches.iter().map(|&(addr, _)| addr).collect()));
+        }
+    }
+    result.matches.sort_by_key(|&(addr, _)| addr);
+    Ok(result)
+}
+
+/// Names unnamed functions from a signature database, reporting ambiguous matches.
+pub fn apply_signature_database(
+    obj: &mut ObjInfo,
+    db: &SignatureDatabase,
+) -> Result<SignatureScanResult> {
+    let result = scan_signature_database(obj, db)?;
+    for (addr, candidates) in &result.ambiguous {
+        log::warn!(
+            "Ambiguous signature match @ {:#010X}: {}",
+            addr,
+            candidates
+                .iter()
+                .map(|&idx| {
+                    let signature = &db.signatures[idx];
+                    match &signature.source {
+                        Some(source) => format!("{} ({})", signature.name(), source),
+                        None => signature.name().to_string(),
+                    }
+                })
+                .join(", ")
+        );
+    }
+    for (idx, addrs) in &result.duplicates {
+        log::warn!(
+            "Signature {} matches {} functions, skipping: {}",
+            db.signatures[*idx].name(),
+            addrs.len(),
+            addrs.iter().join(", ")
+        );
+    }
+    let mut libraries = BTreeMap::<&str, usize>::new();
+    let mut applied = 0;
+    for &(addr, idx) in &result.matches {
+        let signature = &db.signatures[idx];
+        if let Err(e) = apply_signature(obj, addr, signature) {
+            log::warn!("Failed to apply signature {} @ {:#010X}: {:#}", signature.name(), addr, e);
+            continue;
+        }
+        *libraries.entry(signature.library().unwrap_or("<unknown>")).or_default() += 1;
+        applied += 1;
+    }
+    for (library, count) in &libraries {
+        log::debug!("Matched {} functions from {}", count, library);
+    }
+    log::info!(
+        "Signature database: named {} functions from {} libraries ({} ambiguous, {} duplicate)",
+        applied,
+        libraries.len(),
+        result.ambiguous.len(),
+        result.duplicates.len()
+    );
+    Ok(result)
+}
+
 /// Create _ctors and _dtors symbols if missing
 pub fn update_ctors_dtors(obj: &mut ObjInfo) -> Result<()> {
     if obj.symbols.by_name("_ctors")?.is_none() {
@@ -469,3 +595,119 @@ pub fn update_ctors_dtors(obj: &mut ObjInfo) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        obj::{
+            tests::{obj, reloc, section, symbol},
+            ObjKind, ObjRelocKind,
+        },
+        util::signatures::generate_signatures,
+    };
+
+    fn words(code: &[u32]) -> Vec<u8> { code.iter().flat_map(|i| i.to_be_bytes()).collect() }
+
+    const OS_FOO: [u32; 5] = [
+        0x9421FFF0, // stwu r1, -0x10(r1)
+        0x7C0802A6, // mflr r0
+        0x48000001, // bl helper
+        0x38210010, // addi r1, r1, 0x10
+        0x4E800020, // blr
+    ];
+    const OS_BAR: [u32; 4] = [
+        0x38600001, // li r3, 1
+        0x38800002, // li r4, 2
+        0x7C632214, // add r3, r3, r4
+        0x4E800020, // blr
+    ];
+    const OS_QUX: [u32; 5] = [
+        0x38600000, // li r3, 0
+        0x38800000, // li r4, 0
+        0x38A00000, // li r5, 0
+        0x38C00000, // li r6, 0
+        0x4E800020, // blr
+    ];
+
+    /// Signatures for OSFoo, OSBar, OSQux and a lone blr, plus OSBaz with the same code as OSBar.
+    fn database() -> SignatureDatabase {
+        let mut code = words(&OS_FOO);
+        code.extend(words(&OS_BAR));
+        code.extend(words(&OS_QUX));
+        code.extend(words(&[0x4E800020]));
+        let mut helper = symbol("helper", ObjSymbolKind::Unknown, 0, 0, 0);
+        helper.section = None;
+        let mut lib = obj(
+            ObjKind::Relocatable,
+            vec![
+                symbol("OSFoo", ObjSymbolKind::Function, 0, 0, 0x14),
+                symbol("OSBar", ObjSymbolKind::Function, 0, 0x14, 0x10),
+                symbol("OSQux", ObjSymbolKind::Function, 0, 0x24, 0x14),
+                symbol("stub", ObjSymbolKind::Function, 0, 0x38, 4),
+                helper,
+            ],
+            vec![section(".text", ObjSectionKind::Code, 0, code)],
+        );
+        lib.sections[0].relocations.insert(8, reloc(ObjRelocKind::PpcRel24, 4, 0)).unwrap();
+        let mut signatures = generate_signatures(&lib, "os.a:OSFoo.o").unwrap();
+        assert_eq!(signatures.len(), 4);
+        let mut baz = signatures[1].clone();
+        baz.symbols[0].name = "OSBaz".to_string();
+        signatures.push(baz);
+        // The same object from another library is only indexed once
+        signatures.push(signatures[0].clone());
+        let db = SignatureDatabase::new(signatures).unwrap();
+        assert_eq!(db.len(), 5);
+        db
+    }
+
+    #[test]
+    fn test_scan_signature_database() {
+        let db = database();
+        let mut code = words(&OS_FOO);
+        code[8..12].copy_from_slice(&0x4800001Du32.to_be_bytes()); // bl 0x80003124
+        code.extend(words(&OS_BAR));
+        code.extend(words(&[0x4E800020]));
+        code.extend(words(&OS_QUX));
+        code.extend(words(&OS_QUX));
+        code.extend(words(&OS_FOO));
+        let mut obj = obj(
+            ObjKind::Executable,
+            vec![
+                symbol("fn_80003100", ObjSymbolKind::Function, 0, 0x80003100, 0x14),
+                symbol("fn_80003114", ObjSymbolKind::Function, 0, 0x80003114, 0x10),
+                symbol("fn_80003124", ObjSymbolKind::Function, 0, 0x80003124, 4),
+                symbol("fn_80003128", ObjSymbolKind::Function, 0, 0x80003128, 0x14),
+                symbol("fn_8000313C", ObjSymbolKind::Function, 0, 0x8000313C, 0x14),
+                symbol("main", ObjSymbolKind::Function, 0, 0x80003150, 0x14),
+            ],
+            vec![section(".text", ObjSectionKind::Code, 0x80003100, code)],
+        );
+        let addr = |address: u32| SectionAddress::new(0, address);
+        let name = |idx: usize| db.signatures[idx].name();
+
+        let result = scan_signature_database(&obj, &db).unwrap();
+        // The relocated branch is masked out; named functions are not scanned
+        let matches = result.matches.iter().map(|&(a, idx)| (a, name(idx))).collect_vec();
+        assert_eq!(matches, vec![(addr(0x80003100), "OSFoo")]);
+        // OSBar and OSBaz share their code
+        assert_eq!(result.ambiguous.len(), 1);
+        let (ambiguous_addr, candidates) = &result.ambiguous[0];
+        assert_eq!(*ambiguous_addr, addr(0x80003114));
+        assert_eq!(candidates.iter().map(|&idx| name(idx)).collect_vec(), vec!["OSBar", "OSBaz"]);
+        // OSQux matches two functions; the lone blr is too small to match
+        assert_eq!(result.duplicates.len(), 1);
+        assert_eq!(name(result.duplicates[0].0), "OSQux");
+        assert_eq!(result.duplicates[0].1, vec![addr(0x80003128), addr(0x8000313C)]);
+
+        apply_signature_database(&mut obj, &db).unwrap();
+        let (_, symbol) = obj.symbols.by_name("OSFoo").unwrap().unwrap();
+        assert_eq!(symbol.address, 0x80003100);
+        let reloc = obj.sections[0].relocations.at(0x80003108).unwrap();
+        assert_eq!(reloc.kind, ObjRelocKind::PpcRel24);
+        assert_eq!(obj.symbols[reloc.target_symbol].name, "helper");
+        assert!(obj.symbols.by_name("OSBar").unwrap().is_none());
+        assert!(obj.symbols.by_name("OSQux").unwrap().is_none());
+    }
+}
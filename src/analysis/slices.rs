@@ -1,6 +1,8 @@
 use std::{
     collections::{btree_map, BTreeMap, BTreeSet},
+    fmt,
     ops::Range,
+    sync::Arc,
 };
 
 use anyhow::{bail, ensure, Context, Result};
@@ -32,6 +34,10 @@ pub struct FunctionSlices {
     pub has_rfi: bool,
     pub finalized: bool,
     pub has_r1_load: bool, // Possibly instead of a prologue
+    /// Leaf idioms to check each instruction against.
+    pub idioms: Arc<LeafIdioms>,
+    /// Name of the leaf idiom that marked this function as hand-written.
+    pub leaf: Option<&'static str>,
 }
 
 pub enum TailCallResult {
@@ -45,6 +51,107 @@ type BlockRange = Range<SectionAddress>;
 
 type InsCheck = dyn Fn(Ins) -> bool;
 
+/// What a matched leaf idiom means for the function being analyzed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IdiomAction {
+    /// The instruction ends its block without a successor.
+    Terminator,
+    /// The function is hand-written and doesn't follow the compiler's prologue
+    /// and epilogue shapes, so stop looking for them.
+    Leaf,
+    /// A `bctr` to a known function, or past the end of the function, leaves the function.
+    TailCall,
+}
+
+/// The instruction being matched against the leaf idioms.
+pub struct IdiomContext<'a> {
+    pub section: &'a ObjSection,
+    pub function_start: SectionAddress,
+    pub ins_addr: SectionAddress,
+    pub ins: Ins,
+}
+
+/// A known idiom of hand-written code that breaks the prologue, epilogue
+/// and tail call heuristics.
+#[derive(Clone, Copy)]
+pub struct LeafIdiom {
+    /// Rule name, logged whenever the rule fires.
+    pub name: &'static str,
+    pub matches: fn(&IdiomContext) -> bool,
+    pub action: IdiomAction,
+}
+
+/// Registry of the leaf idioms checked during function analysis.
+///
+/// Starts out with the built-in rules. Analysis passes can add their own with
+/// [`AnalyzerState::register_leaf_idiom`](crate::analysis::cfa::AnalyzerState::register_leaf_idiom).
+#[derive(Clone)]
+pub struct LeafIdioms {
+    rules: Vec<LeafIdiom>,
+}
+
+impl Default for LeafIdioms {
+    fn default() -> Self { Self { rules: vec![RFI_TERMINATOR, MTSRR0_EXIT, BCTR_TRAMPOLINE] } }
+}
+
+impl fmt::Debug for LeafIdioms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.rules.iter().map(|rule| rule.name)).finish()
+    }
+}
+
+impl LeafIdioms {
+    /// Adds a rule, unless one with the same name is already registered.
+    pub fn register(&mut self, idiom: LeafIdiom) {
+        if !self.rules.iter().any(|rule| rule.name == idiom.name) {
+            self.rules.push(idiom);
+        }
+    }
+
+    pub(crate) fn matching<'a>(
+        &'a self,
+        ctx: &'a IdiomContext<'a>,
+    ) -> impl Iterator<Item = &'a LeafIdiom> {
+        self.rules.iter().filter(move |rule| (rule.matches)(ctx))
+    }
+}
+
+fn is_rfi(ctx: &IdiomContext) -> bool { ctx.ins.op == Opcode::Rfi }
+
+fn is_mtsrr0(ctx: &IdiomContext) -> bool {
+    // mtspr SRR0, rS
+    if ctx.ins.op != Opcode::Mtspr || ctx.ins.field_spr() != 26 {
+        return false;
+    }
+    // Only an exit if the block ends with rfi
+    let mut address = ctx.ins_addr.address + 4;
+    while let Some(ins) = disassemble(ctx.section, address) {
+        if ins.op == Opcode::Rfi || ins.is_branch() {
+            return ins.op == Opcode::Rfi;
+        }
+        address += 4;
+    }
+    false
+}
+
+fn is_bctr(ctx: &IdiomContext) -> bool {
+    // bctr
+    ctx.ins.op == Opcode::Bcctr && !ctx.ins.field_lk() && ctx.ins.field_bo() & 0b10100 == 0b10100
+}
+
+/// Exception handlers return with `rfi`, which never falls through.
+pub const RFI_TERMINATOR: LeafIdiom =
+    LeafIdiom { name: "rfi-terminator", matches: is_rfi, action: IdiomAction::Terminator };
+
+/// Exception handlers that set SRR0 before an `rfi` restore registers by hand,
+/// in sequences that can look like epilogues.
+pub const MTSRR0_EXIT: LeafIdiom =
+    LeafIdiom { name: "mtsrr0-exit", matches: is_mtsrr0, action: IdiomAction::Leaf };
+
+/// Glue and trampolines load a target address into CTR and jump to it without a `blr`.
+pub const BCTR_TRAMPOLINE: LeafIdiom =
+    LeafIdiom { name: "bctr-trampoline", matches: is_bctr, action: IdiomAction::TailCall };
+
 #[inline(always)]
 fn check_sequence(
     section: &ObjSection,
@@ -216,18 +323,44 @@ impl FunctionSlices {
         function_end: Option<SectionAddress>,
         known_functions: &BTreeMap<SectionAddress, FunctionInfo>,
     ) -> Result<ExecCbResult<bool>> {
-        let ExecCbData { executor, vm, result, ins_addr, section, ins, block_start } = data;
+        let ExecCbData { executor, vm, result, ins_addr, section, ins, block_start, operands: _ } =
+            data;
+
+        // Check for hand-written idioms that the heuristics below can't handle
+        let mut terminator = None;
+        let mut tail_call = None;
+        let idioms = self.idioms.clone();
+        let ctx = IdiomContext { section, function_start, ins_addr, ins };
+        for idiom in idioms.matching(&ctx) {
+            match idiom.action {
+                IdiomAction::Leaf if self.leaf.is_none() => {
+                    log::debug!(
+                        "{} @ {}: treating {} as hand-written, ignoring prologue and epilogue",
+                        idiom.name,
+                        ins_addr,
+                        function_start
+                    );
+                    self.leaf = Some(idiom.name);
+                }
+                IdiomAction::Leaf => {}
+                IdiomAction::Terminator => terminator = Some(idiom.name),
+                IdiomAction::TailCall => tail_call = Some(idiom.name),
+            }
+        }
 
         // Track discovered prologue(s) and epilogue(s)
-        // HACK: ProDG sometimes uses LR as a storage register for int-to-float conversions
-        // To our heuristic, this looks like a prologue, so first check LR for the magic number.
-        if vm.lr != GprValue::Constant(0x43300000) {
-            self.check_prologue(section, ins_addr, ins).with_context(|| {
-                format!("While processing {:#010X}: {:#?} {:#?}", function_start, self, vm.gpr)
+        if self.leaf.is_none() {
+            // HACK: ProDG sometimes uses LR as a storage register for int-to-float conversions
+            // To our heuristic, this looks like a prologue, so first check LR for the magic number.
+            if vm.lr != GprValue::Constant(0x43300000) {
+                self.check_prologue(section, ins_addr, ins).with_context(|| {
+                    format!("While processing {:#010X}: {:#?} {:#?}", function_start, self, vm.gpr)
+                })?;
+            }
+            self.check_epilogue(section, ins_addr, ins).with_context(|| {
+                format!("While processing {:#010X}: {:#?}", function_start, self)
             })?;
         }
-        self.check_epilogue(section, ins_addr, ins)
-            .with_context(|| format!("While processing {:#010X}: {:#?}", function_start, self))?;
         if !self.has_conditional_blr && is_conditional_blr(ins) {
             self.has_conditional_blr = true;
         }
@@ -251,6 +384,38 @@ impl FunctionSlices {
             }
         }
 
+        if let Some(rule) = terminator {
+            log::debug!("{} @ {}: ending block in {}", rule, ins_addr, function_start);
+            self.blocks.insert(block_start, Some(ins_addr + 4));
+            return Ok(ExecCbResult::EndBlock);
+        }
+        if let (Some(rule), None) = (tail_call, self.prologue) {
+            if let StepResult::Jump(BranchTarget::Address(RelocationTarget::Address(target))) =
+                result
+            {
+                // A forward jump within the function isn't a tail call, so only accept
+                // known functions and targets past the end of the function
+                let is_function = target != function_start
+                    && (known_functions.contains_key(&target)
+                        || self.function_references.contains(&target));
+                if (is_function || function_end.is_some_and(|end| target >= end))
+                    && !self.blocks.contains_key(&target)
+                {
+                    log::debug!(
+                        "{} @ {}: tail call from {} to {}",
+                        rule,
+                        ins_addr,
+                        function_start,
+                        target
+                    );
+                    self.blocks.insert(block_start, Some(ins_addr + 4));
+                    self.branches.insert(ins_addr, vec![target]);
+                    self.function_references.insert(target);
+                    return Ok(ExecCbResult::EndBlock);
+                }
+            }
+        }
+
         match result {
             StepResult::Continue | StepResult::LoadStore { .. } => {
                 let next_address = ins_addr + 4;
@@ -325,16 +490,26 @@ impl FunctionSlices {
                     }
                     Ok(ExecCbResult::EndBlock)
                 }
-                BranchTarget::JumpTable { address: RelocationTarget::Address(address), size } => {
+                BranchTarget::JumpTable {
+                    address: RelocationTarget::Address(address),
+                    size,
+                    kind,
+                } => {
                     // End of block
                     let next_address = ins_addr + 4;
                     self.blocks.insert(block_start, Some(next_address));
 
-                    log::debug!("Fetching jump table entries @ {} with size {:?}", address, size);
+                    log::debug!(
+                        "Fetching {:?} jump table entries @ {} with size {:?}",
+                        kind,
+                        address,
+                        size
+                    );
                     let (entries, size) = uniq_jump_table_entries(
                         obj,
                         address,
                         size,
+                        kind,
                         ins_addr,
                         function_start,
                         function_end.or_else(|| self.end()),
@@ -405,7 +580,7 @@ impl FunctionSlices {
                                 }
                             }
                         }
-                        BranchTarget::JumpTable { address, size } => {
+                        BranchTarget::JumpTable { address, size, .. } => {
                             bail!(
                                 "Conditional jump table unsupported @ {:#010X} -> {:?} size {:#X?}",
                                 ins_addr,
@@ -519,12 +694,12 @@ impl FunctionSlices {
             (Some(_), None, _) => {
                 // Likely __noreturn
             }
-            (None, Some(e), false) => {
+            (None, Some(e), false) if self.leaf.is_none() => {
                 log::warn!("{:#010X?}", self);
                 bail!("Unpaired epilogue {:#010X}", e);
             }
-            (None, Some(_), true) => {
-                // Possible stack setup
+            (None, Some(_), _) => {
+                // Possible stack setup, or hand-written
             }
         }
 
@@ -668,6 +843,7 @@ impl FunctionSlices {
         // Perform CFA on jump target to determine more
         let mut slices = FunctionSlices {
             function_references: self.function_references.clone(),
+            idioms: self.idioms.clone(),
             ..Default::default()
         };
         if let Ok(result) =
@@ -740,3 +916,59 @@ fn is_nop(ins: Ins) -> bool {
     // ori r0, r0, 0
     ins.code == 0x60000000
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{tests::section, ObjSectionKind};
+
+    /// Names of the built-in idioms matching the instruction at `index`.
+    fn matching_idioms(code: &[u32], index: usize) -> Vec<&'static str> {
+        let data = code.iter().flat_map(|ins| ins.to_be_bytes()).collect();
+        let section = section(".text", ObjSectionKind::Code, 0x80003100, data);
+        let ins_addr = SectionAddress::new(0, 0x80003100 + index as u32 * 4);
+        let ctx = IdiomContext {
+            section: &section,
+            function_start: SectionAddress::new(0, 0x80003100),
+            ins_addr,
+            ins: Ins::new(code[index]),
+        };
+        LeafIdioms::default().matching(&ctx).map(|idiom| idiom.name).collect()
+    }
+
+    #[test]
+    fn test_rfi_terminator() {
+        let code = [0x80610010, 0x4C000064]; // lwz r3, 0x10(r1); rfi
+        assert_eq!(matching_idioms(&code, 1), vec![RFI_TERMINATOR.name]);
+        assert!(matching_idioms(&code, 0).is_empty());
+    }
+
+    #[test]
+    fn test_mtsrr0_exit() {
+        let code = [
+            0x7C7A03A6, // mtsrr0 r3
+            0x80610010, // lwz r3, 0x10(r1)
+            0x4C000064, // rfi
+        ];
+        assert_eq!(matching_idioms(&code, 0), vec![MTSRR0_EXIT.name]);
+
+        // Saving SRR0 for later isn't an exit
+        let code = [
+            0x7C7A03A6, // mtsrr0 r3
+            0x80610010, // lwz r3, 0x10(r1)
+            0x4E800020, // blr
+        ];
+        assert!(matching_idioms(&code, 0).is_empty());
+    }
+
+    #[test]
+    fn test_bctr_trampoline() {
+        let code = [
+            0x7D8903A6, // mtctr r12
+            0x4E800420, // bctr
+            0x4E800421, // bctrl
+        ];
+        assert_eq!(matching_idioms(&code, 1), vec![BCTR_TRAMPOLINE.name]);
+        assert!(matching_idioms(&code, 2).is_empty());
+    }
+}
@@ -5,17 +5,19 @@ use std::{
 
 use anyhow::{bail, Result};
 use cwextab::decode_extab;
-use ppc750cl::Opcode;
+use ppc750cl::{Ins, Opcode};
+use serde::{Deserialize, Serialize};
 use tracing::{debug_span, info_span};
 use tracing_attributes::instrument;
 
 use crate::{
     analysis::{
+        cache::TrackerCache,
         cfa::SectionAddress,
         executor::{ExecCbData, ExecCbResult, Executor},
-        relocation_target_for, uniq_jump_table_entries,
+        read_relative_jump_table_entry, relocation_target_for, uniq_jump_table_entries,
         vm::{is_store_op, BranchTarget, GprValue, StepResult, VM},
-        RelocationTarget,
+        JumpTableKind, RelocationTarget,
     },
     obj::{
         ObjDataKind, ObjInfo, ObjKind, ObjReloc, ObjRelocKind, ObjSection, ObjSectionKind,
@@ -23,15 +25,18 @@ use crate::{
     },
 };
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum Relocation {
     Ha(RelocationTarget),
     Hi(RelocationTarget),
     Lo(RelocationTarget),
     Sda21(RelocationTarget),
+    Toc16(RelocationTarget),
     Rel14(RelocationTarget),
     Rel24(RelocationTarget),
     Absolute(RelocationTarget),
+    /// Relative jump table entry, with the entry's offset within the table.
+    Rel32(RelocationTarget, u32),
 }
 
 impl Relocation {
@@ -41,9 +46,11 @@ impl Relocation {
             Relocation::Hi(v) => (ObjRelocKind::PpcAddr16Hi, v),
             Relocation::Lo(v) => (ObjRelocKind::PpcAddr16Lo, v),
             Relocation::Sda21(v) => (ObjRelocKind::PpcEmbSda21, v),
+            Relocation::Toc16(v) => (ObjRelocKind::PpcToc16, v),
             Relocation::Rel14(v) => (ObjRelocKind::PpcRel14, v),
             Relocation::Rel24(v) => (ObjRelocKind::PpcRel24, v),
             Relocation::Absolute(v) => (ObjRelocKind::Absolute, v),
+            Relocation::Rel32(v, _) => (ObjRelocKind::PpcRel32, v),
         };
         match *target {
             RelocationTarget::Address(address) => Some((reloc_kind, address)),
@@ -52,7 +59,7 @@ impl Relocation {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum DataKind {
     Unknown = -1,
     Word,
@@ -68,8 +75,10 @@ pub struct Tracker {
     processed_functions: BTreeSet<SectionAddress>,
     sda2_base: Option<u32>, // r2
     sda_base: Option<u32>,  // r13
+    toc_base: Option<u32>,  // r2 (PEF/XCOFF)
     pub relocations: BTreeMap<SectionAddress, Relocation>,
     data_types: BTreeMap<SectionAddress, DataKind>,
+    array_strides: BTreeMap<SectionAddress, u32>,
     stack_address: Option<u32>,
     stack_end: Option<u32>,
     db_stack_addr: Option<u32>,
@@ -88,8 +97,10 @@ impl Tracker {
             processed_functions: Default::default(),
             sda2_base: obj.sda2_base,
             sda_base: obj.sda_base,
+            toc_base: obj.toc_base,
             relocations: Default::default(),
             data_types: Default::default(),
+            array_strides: Default::default(),
             stack_address: obj.stack_address,
             stack_end: obj.stack_end.or_else(|| {
                 // Stack ends after all BSS sections
@@ -126,6 +137,31 @@ impl Tracker {
         Ok(())
     }
 
+    /// Records the analysis results for the analysis cache.
+    pub fn to_cache(&self, key: u64) -> TrackerCache {
+        TrackerCache {
+            key,
+            relocations: self.relocations.iter().map(|(&a, &r)| (a, r)).collect(),
+            data_types: self.data_types.iter().map(|(&a, &k)| (a, k)).collect(),
+            array_strides: self.array_strides.iter().map(|(&a, &s)| (a, s)).collect(),
+            known_relocations: self.known_relocations.iter().cloned().collect(),
+            stores_to: self.stores_to.iter().cloned().collect(),
+            sda_to: self.sda_to.iter().cloned().collect(),
+            hal_to: self.hal_to.iter().cloned().collect(),
+        }
+    }
+
+    /// Restores cached analysis results, in place of [`Tracker::process`].
+    pub fn restore(&mut self, cache: &TrackerCache) {
+        self.relocations = cache.relocations.iter().cloned().collect();
+        self.data_types = cache.data_types.iter().cloned().collect();
+        self.array_strides = cache.array_strides.iter().cloned().collect();
+        self.known_relocations = cache.known_relocations.iter().cloned().collect();
+        self.stores_to = cache.stores_to.iter().cloned().collect();
+        self.sda_to = cache.sda_to.iter().cloned().collect();
+        self.hal_to = cache.hal_to.iter().cloned().collect();
+    }
+
     /// Remove data relocations that point to an unaligned address if the aligned address has a
     /// relocation. A relocation will never point to the middle of an address.
     fn reject_invalid_relocations(&mut self, obj: &ObjInfo) -> Result<()> {
@@ -260,6 +296,48 @@ impl Tracker {
         }
     }
 
+    /// Records the element kind and stride of arrays accessed through a scaled index,
+    /// e.g. `lfsx f1, r3, r0`, or `add r3, r3, r0` after `mulli r0, r4, 0xC`.
+    /// (`lwzx` is left to jump table analysis)
+    ///
+    /// `operands` are the values of rA and rB before the instruction, since the
+    /// result often replaces the base.
+    fn record_indexed_access(
+        &mut self,
+        obj: &ObjInfo,
+        ins_addr: SectionAddress,
+        operands: (GprValue, GprValue),
+        ins: Ins,
+    ) {
+        let data_kind = data_kind_from_op(ins.op);
+        let width = match data_kind {
+            DataKind::Unknown => 0,
+            kind => data_kind_width(kind),
+        };
+        let (base, step) = match operands {
+            (base, GprValue::Range { step, .. }) | (GprValue::Range { step, .. }, base) => {
+                (base, step)
+            }
+            (base, _) if width != 0 => (base, width),
+            _ => return,
+        };
+        let Some(RelocationTarget::Address(address)) = self.gpr_address(obj, ins_addr, &base)
+        else {
+            return;
+        };
+        if width != 0 {
+            self.data_types.entry(address).or_insert(data_kind);
+        }
+        if step > width && (width == 0 || step % width == 0) {
+            self.array_strides.entry(address).or_insert(step);
+        }
+    }
+
+    #[inline]
+    fn is_toc_relative(&self, source_reg: u8, value: GprValue) -> bool {
+        source_reg == 2 && matches!(self.toc_base, Some(v) if value == GprValue::Constant(v))
+    }
+
     fn instruction_callback(
         &mut self,
         data: ExecCbData,
@@ -268,7 +346,16 @@ impl Tracker {
         function_end: SectionAddress,
         possible_missed_branches: &mut BTreeMap<SectionAddress, Box<VM>>,
     ) -> Result<ExecCbResult<()>> {
-        let ExecCbData { executor, vm, result, ins_addr, section: _, ins, block_start: _ } = data;
+        let ExecCbData {
+            executor,
+            vm,
+            result,
+            ins_addr,
+            section: _,
+            ins,
+            block_start: _,
+            operands,
+        } = data;
         // Using > instead of >= to treat a branch to the beginning of the function as a tail call
         let is_function_addr = |addr: SectionAddress| addr > function_start && addr < function_end;
         let _span = debug_span!("ins", addr = %ins_addr, op = ?ins.op).entered();
@@ -291,6 +378,8 @@ impl Tracker {
                                 if let RelocationTarget::Address(address) = value {
                                     self.sda_to.insert(address);
                                 }
+                            } else if self.is_toc_relative(source as u8, vm.gpr[2].value) {
+                                self.relocations.insert(ins_addr, Relocation::Toc16(value));
                             } else if let (Some(hi_addr), Some(lo_addr)) =
                                 (vm.gpr[target].hi_addr, vm.gpr[target].lo_addr)
                             {
@@ -337,6 +426,17 @@ impl Tracker {
                             }
                         }
                     }
+                    Opcode::Add
+                    | Opcode::Lbzx
+                    | Opcode::Lhax
+                    | Opcode::Lhzx
+                    | Opcode::Lfsx
+                    | Opcode::Lfdx
+                    | Opcode::Stbx
+                    | Opcode::Sthx
+                    | Opcode::Stwx
+                    | Opcode::Stfsx
+                    | Opcode::Stfdx => self.record_indexed_access(obj, ins_addr, operands, ins),
                     _ => {}
                 }
                 Ok(ExecCbResult::Continue)
@@ -352,6 +452,8 @@ impl Tracker {
                         if let RelocationTarget::Address(address) = address {
                             self.sda_to.insert(address);
                         }
+                    } else if self.is_toc_relative(source_reg, source.value) {
+                        self.relocations.insert(ins_addr, Relocation::Toc16(address));
                     } else {
                         match (source.hi_addr, source.lo_addr) {
                             (Some(hi_addr), None) => {
@@ -437,15 +539,23 @@ impl Tracker {
                     }
                     Ok(ExecCbResult::EndBlock)
                 }
-                BranchTarget::JumpTable { address: RelocationTarget::Address(address), size } => {
-                    let (entries, _) = uniq_jump_table_entries(
+                BranchTarget::JumpTable {
+                    address: RelocationTarget::Address(address),
+                    size,
+                    kind,
+                } => {
+                    let (entries, size) = uniq_jump_table_entries(
                         obj,
                         address,
                         size,
+                        kind,
                         ins_addr,
                         function_start,
                         Some(function_end),
                     )?;
+                    if kind == JumpTableKind::Relative32 {
+                        self.relative_jump_table_relocations(obj, address, size)?;
+                    }
                     for target in entries {
                         if is_function_addr(target) {
                             executor.push(target, vm.clone_all(), true);
@@ -480,11 +590,13 @@ impl Tracker {
                         BranchTarget::JumpTable {
                             address: RelocationTarget::Address(address),
                             size,
+                            kind,
                         } => {
                             let (entries, _) = uniq_jump_table_entries(
                                 obj,
                                 address,
                                 size,
+                                kind,
                                 ins_addr,
                                 function_start,
                                 Some(function_end),
@@ -517,7 +629,11 @@ impl Tracker {
         let mut possible_missed_branches = BTreeMap::new();
 
         let mut executor = Executor::new(obj);
-        executor.push(function_start, VM::new_with_base(self.sda2_base, self.sda_base), false);
+        executor.push(
+            function_start,
+            VM::new_with_base(self.sda2_base.or(self.toc_base), self.sda_base),
+            false,
+        );
         loop {
             executor.run(obj, |data| -> Result<ExecCbResult<()>> {
                 self.instruction_callback(
@@ -547,6 +663,34 @@ impl Tracker {
         Ok(())
     }
 
+    /// Adds `R_PPC_REL32` relocations for the entries of a 32-bit relative jump table.
+    fn relative_jump_table_relocations(
+        &mut self,
+        obj: &ObjInfo,
+        table_addr: SectionAddress,
+        size: u32,
+    ) -> Result<()> {
+        let mut entry_addr = table_addr;
+        while entry_addr.address < table_addr.address + size {
+            if let Some(target) = read_relative_jump_table_entry(
+                obj,
+                table_addr,
+                entry_addr,
+                JumpTableKind::Relative32,
+            )? {
+                self.relocations.insert(
+                    entry_addr,
+                    Relocation::Rel32(
+                        RelocationTarget::Address(target),
+                        entry_addr.address - table_addr.address,
+                    ),
+                );
+            }
+            entry_addr += 4;
+        }
+        Ok(())
+    }
+
     fn process_data(
         &mut self,
         obj: &ObjInfo,
@@ -555,6 +699,11 @@ impl Tracker {
     ) -> Result<()> {
         let mut addr = SectionAddress::new(section_index, section.address as u32);
         for chunk in section.data.chunks_exact(4) {
+            // Keep relocations provided by the executable format (e.g. the PEF loader)
+            if section.relocations.contains(addr.address) {
+                addr += 4;
+                continue;
+            }
             let value = u32::from_be_bytes(chunk.try_into()?);
             if let Some(value) = self.is_valid_address(obj, addr, value) {
                 self.relocations
@@ -746,6 +895,11 @@ impl Tracker {
                     DataKind::Double => (ObjDataKind::Double, Some(8)),
                 })
                 .unwrap_or_default();
+            let element_width = self.data_types.get(&target).map_or(4, |&k| data_kind_width(k));
+            let data_kind = match self.array_strides.get(&target) {
+                Some(&stride) if stride > element_width => ObjDataKind::Array(stride),
+                _ => data_kind,
+            };
             let (target_symbol, addend) =
                 if let Some(symbol) = self.special_symbol(obj, target.address, reloc_kind) {
                     (symbol, 0)
@@ -793,6 +947,11 @@ impl Tracker {
                     })?;
                     (symbol_idx, 0)
                 };
+            // R_PPC_REL32 is relative to the entry, but jump table entries are relative to the table
+            let addend = match reloc {
+                Relocation::Rel32(_, offset) => addend + *offset as i64,
+                _ => addend,
+            };
             let reloc = ObjReloc { kind: reloc_kind, target_symbol, addend, module: None };
             let section = &mut obj.sections[addr.section];
             if replace {
@@ -877,6 +1036,15 @@ fn data_kind_from_op(op: Opcode) -> DataKind {
     }
 }
 
+fn data_kind_width(kind: DataKind) -> u32 {
+    match kind {
+        DataKind::Byte => 1,
+        DataKind::Half => 2,
+        DataKind::Unknown | DataKind::Word | DataKind::Float => 4,
+        DataKind::Double => 8,
+    }
+}
+
 fn generate_special_symbol(obj: &mut ObjInfo, addr: u32, name: &str) -> Result<SymbolIndex> {
     obj.add_symbol(
         ObjSymbol {
@@ -890,3 +1058,88 @@ fn generate_special_symbol(obj: &mut ObjInfo, addr: u32, name: &str) -> Result<S
         true,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::tests::{obj, reloc, section, symbol};
+
+    #[test]
+    fn test_array_stride() {
+        let code = [
+            0x3C608000u32, // lis r3, 0x8000
+            0x38634000,    // addi r3, r3, 0x4000
+            0x1C04000C,    // mulli r0, r4, 12
+            0x7C630214,    // add r3, r3, r0
+            0x4E800020,    // blr
+        ];
+        let text = code.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let function = symbol("fn_80003100", ObjSymbolKind::Function, 0, 0x80003100, 0x14);
+        let mut obj = obj(ObjKind::Executable, vec![function], vec![
+            section(".text", ObjSectionKind::Code, 0x80003100, text),
+            section(".data", ObjSectionKind::Data, 0x80004000, vec![0; 0x30]),
+        ]);
+        let mut tracker = Tracker::new(&obj);
+        tracker.process(&obj).unwrap();
+        let target = SectionAddress::new(1, 0x80004000);
+        assert_eq!(tracker.array_strides.get(&target), Some(&12));
+
+        tracker.apply(&mut obj, false).unwrap();
+        let (_, symbol) =
+            obj.symbols.for_section(1).find(|(_, s)| s.address == 0x80004000).unwrap();
+        assert_eq!(symbol.data_kind, ObjDataKind::Array(12));
+    }
+
+    fn toc_obj(toc_base: Option<u32>) -> ObjInfo {
+        let code = [
+            0x80620010u32, // lwz r3, 0x10(r2)
+            0x80830000,    // lwz r4, 0(r3)
+            0x38A20020,    // addi r5, r2, 0x20
+            0x4E800020,    // blr
+        ];
+        let text = code.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let mut obj = obj(
+            ObjKind::Executable,
+            vec![
+                symbol("fn_80003100", ObjSymbolKind::Function, 0, 0x80003100, 0x10),
+                symbol("lbl_80010028", ObjSymbolKind::Object, 1, 0x80010028, 4),
+            ],
+            vec![
+                section(".text", ObjSectionKind::Code, 0x80003100, text),
+                section(".data", ObjSectionKind::Data, 0x80010000, vec![0; 0x30]),
+            ],
+        );
+        obj.sections[1]
+            .relocations
+            .insert(0x80010010, reloc(ObjRelocKind::Absolute, 1, 0))
+            .unwrap();
+        obj.toc_base = toc_base;
+        obj
+    }
+
+    #[test]
+    fn test_toc_relocations() {
+        let mut obj = toc_obj(Some(0x80010000));
+        let mut tracker = Tracker::new(&obj);
+        tracker.process(&obj).unwrap();
+        for (ins, target) in [(0x80003100, 0x80010010), (0x80003108, 0x80010020)] {
+            let reloc = tracker.relocations.get(&SectionAddress::new(0, ins));
+            let target = RelocationTarget::Address(SectionAddress::new(1, target));
+            assert!(matches!(reloc, Some(Relocation::Toc16(t)) if *t == target), "{:?}", reloc);
+        }
+
+        tracker.apply(&mut obj, false).unwrap();
+        let reloc = obj.sections[0].relocations.at(0x80003100).unwrap();
+        assert_eq!(reloc.kind, ObjRelocKind::PpcToc16);
+        assert_eq!(obj.symbols[reloc.target_symbol].address, 0x80010010);
+        let reloc = obj.sections[0].relocations.at(0x80003108).unwrap();
+        assert_eq!(reloc.kind, ObjRelocKind::PpcToc16);
+        assert_eq!(obj.symbols[reloc.target_symbol].address, 0x80010020);
+
+        // Without a TOC anchor, r2 is not tracked
+        let obj = toc_obj(None);
+        let mut tracker = Tracker::new(&obj);
+        tracker.process(&obj).unwrap();
+        assert!(!tracker.relocations.values().any(|r| matches!(r, Relocation::Toc16(_))));
+    }
+}
@@ -3,7 +3,7 @@ use std::num::NonZeroU32;
 use ppc750cl::{Argument, Ins, Opcode, GPR};
 
 use crate::{
-    analysis::{cfa::SectionAddress, relocation_target_for, RelocationTarget},
+    analysis::{cfa::SectionAddress, relocation_target_for, JumpTableKind, RelocationTarget},
     obj::{ObjInfo, ObjKind},
 };
 
@@ -21,7 +21,7 @@ pub enum GprValue {
     /// GPR value is within a range
     Range { min: u32, max: u32, step: u32 },
     /// GPR value is loaded from an address with a max offset (jump table)
-    LoadIndexed { address: RelocationTarget, max_offset: Option<NonZeroU32> },
+    LoadIndexed { address: RelocationTarget, max_offset: Option<NonZeroU32>, kind: JumpTableKind },
 }
 
 #[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
@@ -97,7 +97,7 @@ pub enum BranchTarget {
     /// Branch to address
     Address(RelocationTarget),
     /// Branch to jump table
-    JumpTable { address: RelocationTarget, size: Option<NonZeroU32> },
+    JumpTable { address: RelocationTarget, size: Option<NonZeroU32>, kind: JumpTableKind },
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -143,13 +143,19 @@ pub fn section_address_for(
     }
 }
 
+/// Whether the value is the TOC anchor of a PEF/XCOFF executable.
+#[inline]
+pub fn is_toc_base(obj: &ObjInfo, value: GprValue) -> bool {
+    matches!(obj.toc_base, Some(v) if value == GprValue::Constant(v))
+}
+
 impl VM {
     #[inline]
     pub fn new() -> Box<Self> { Box::default() }
 
     #[inline]
     pub fn new_from_obj(obj: &ObjInfo) -> Box<Self> {
-        Self::new_with_base(obj.sda2_base, obj.sda_base)
+        Self::new_with_base(obj.sda2_base.or(obj.toc_base), obj.sda_base)
     }
 
     #[inline]
@@ -213,6 +219,19 @@ impl VM {
                         GprValue::Constant(left),
                         GprValue::Address(RelocationTarget::Address(right)),
                     ) => GprValue::Address(RelocationTarget::Address(right.wrapping_add(left))),
+                    // Relative jump table entry added to the table address
+                    (GprValue::LoadIndexed { address, max_offset, kind }, _)
+                        if self.gpr[ins.field_rb() as usize].address(obj, ins_addr)
+                            == Some(address) =>
+                    {
+                        GprValue::LoadIndexed { address, max_offset, kind: kind.relative() }
+                    }
+                    (_, GprValue::LoadIndexed { address, max_offset, kind })
+                        if self.gpr[ins.field_ra() as usize].address(obj, ins_addr)
+                            == Some(address) =>
+                    {
+                        GprValue::LoadIndexed { address, max_offset, kind: kind.relative() }
+                    }
                     _ => GprValue::Unknown,
                 };
                 self.gpr[ins.field_rd() as usize].set_direct(value);
@@ -299,6 +318,20 @@ impl VM {
                     },
                 );
             }
+            // mulli rD, rA, SIMM
+            Opcode::Mulli => {
+                let value = match self.gpr[ins.field_ra() as usize].value {
+                    GprValue::Constant(value) => {
+                        GprValue::Constant(value.wrapping_mul(ins.field_simm() as u32))
+                    }
+                    // Scaled index, e.g. into an array of structs
+                    _ if ins.field_simm() > 0 => {
+                        GprValue::Range { min: 0, max: u32::MAX, step: ins.field_simm() as u32 }
+                    }
+                    _ => GprValue::Unknown,
+                };
+                self.gpr[ins.field_rd() as usize].set_direct(value);
+            }
             // subfic rD, rA, SIMM
             Opcode::Subfic => {
                 self.gpr[ins.field_rd() as usize].set_direct(
@@ -421,10 +454,10 @@ impl VM {
                                 }
                             },
                             GprValue::Address(target) => BranchTarget::Address(target),
-                            GprValue::LoadIndexed { address, max_offset }
+                            GprValue::LoadIndexed { address, max_offset, kind }
                             // FIXME: avoids treating bctrl indirect calls as jump tables
                             if !ins.field_lk() => {
-                                BranchTarget::JumpTable { address, size: max_offset.and_then(|n| n.checked_add(4)) }
+                                BranchTarget::JumpTable { address, size: max_offset.and_then(|n| n.checked_add(kind.entry_size())), kind }
                             }
                             _ => BranchTarget::Unknown,
                         }
@@ -494,16 +527,49 @@ impl VM {
                     (Some(address), GprValue::Range { min: _, max, .. })
                         if /*min == 0 &&*/ max < u32::MAX - 4 && max & 3 == 0 =>
                     {
-                        GprValue::LoadIndexed { address, max_offset: NonZeroU32::new(max) }
+                        GprValue::LoadIndexed {
+                            address,
+                            max_offset: NonZeroU32::new(max),
+                            kind: JumpTableKind::Absolute,
+                        }
                     }
                     (Some(address), GprValue::Range { min: _, max, .. })
                         if /*min == 0 &&*/ max < u32::MAX - 4 && max & 3 == 0 =>
                     {
-                        GprValue::LoadIndexed { address, max_offset: NonZeroU32::new(max) }
+                        GprValue::LoadIndexed {
+                            address,
+                            max_offset: NonZeroU32::new(max),
+                            kind: JumpTableKind::Absolute,
+                        }
                     }
                     (Some(address), _) => {
-                        GprValue::LoadIndexed { address, max_offset: None }
+                        GprValue::LoadIndexed { address, max_offset: None, kind: JumpTableKind::Absolute }
+                    }
+                    _ => GprValue::Unknown,
+                };
+                self.gpr[ins.field_rd() as usize].set_direct(value);
+            }
+            // lhax rD, rA, rB
+            // lhzx rD, rA, rB
+            Opcode::Lhax | Opcode::Lhzx => {
+                let left = self.gpr[ins.field_ra() as usize].address(obj, ins_addr);
+                let right = self.gpr[ins.field_rb() as usize].value;
+                // Only useful as a 16-bit jump table entry, which is then added to the table address
+                let value = match (left, right) {
+                    (Some(address), GprValue::Range { max, .. })
+                        if max < u32::MAX - 2 && max & 1 == 0 =>
+                    {
+                        GprValue::LoadIndexed {
+                            address,
+                            max_offset: NonZeroU32::new(max),
+                            kind: JumpTableKind::Relative16,
+                        }
                     }
+                    (Some(address), _) => GprValue::LoadIndexed {
+                        address,
+                        max_offset: None,
+                        kind: JumpTableKind::Relative16,
+                    },
                     _ => GprValue::Unknown,
                 };
                 self.gpr[ins.field_rd() as usize].set_direct(value);
@@ -563,7 +629,21 @@ impl VM {
                     self.gpr[source].set_direct(GprValue::Unknown);
                 }
                 if is_load_op(op) {
-                    self.gpr[ins.field_rd() as usize].set_direct(GprValue::Unknown);
+                    let value = match result {
+                        // Loading a pointer from the TOC
+                        StepResult::LoadStore {
+                            address: RelocationTarget::Address(address),
+                            source_reg: 2,
+                            ..
+                        } if op == Opcode::Lwz && is_toc_base(obj, self.gpr[2].value) => {
+                            relocation_target_for(obj, address, None)
+                                .ok()
+                                .flatten()
+                                .map_or(GprValue::Unknown, GprValue::Address)
+                        }
+                        _ => GprValue::Unknown,
+                    };
+                    self.gpr[ins.field_rd() as usize].set_direct(value);
                 }
                 return result;
             }
@@ -886,3 +966,211 @@ pub fn is_update_op(op: Opcode) -> bool {
 //         );
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::read_relative_jump_table_entry,
+        obj::{
+            tests::{obj, reloc, section, symbol},
+            ObjRelocKind, ObjSectionKind, ObjSymbolKind,
+        },
+    };
+
+    /// Executable with code at 0x80003100 and a jump table at 0x80005000.
+    fn jump_table_obj(table: Vec<u8>) -> ObjInfo {
+        obj(ObjKind::Executable, vec![], vec![
+            section(".text", ObjSectionKind::Code, 0x80003100, vec![0; 0x40]),
+            section(".rodata", ObjSectionKind::ReadOnlyData, 0x80005000, table),
+        ])
+    }
+
+    /// Steps through the instructions, returning the result of the last one.
+    fn step_all(vm: &mut VM, obj: &ObjInfo, code: &[u32]) -> StepResult {
+        let mut result = StepResult::Continue;
+        for (i, &ins) in code.iter().enumerate() {
+            assert_eq!(result, StepResult::Continue);
+            result = vm.step(obj, SectionAddress::new(0, 0x80003100 + i as u32 * 4), Ins::new(ins));
+        }
+        result
+    }
+
+    #[test]
+    fn test_relative32_jump_table() {
+        // Offsets of 0x80003110 and 0x80003120 from the table
+        let mut table = vec![];
+        for target in [0x80003110u32, 0x80003120] {
+            table.extend(target.wrapping_sub(0x80005000).to_be_bytes());
+        }
+        let obj = jump_table_obj(table);
+        let table_addr = SectionAddress::new(1, 0x80005000);
+        let mut vm = VM::new();
+        // Index checked against 1 (cmplwi r3, 1; bgt default)
+        vm.gpr[3].value = GprValue::Range { min: 0, max: 1, step: 1 };
+        let result = step_all(&mut vm, &obj, &[
+            0x3D208000, // lis r9, 0x8000
+            0x39295000, // addi r9, r9, 0x5000
+            0x5463103A, // slwi r3, r3, 2
+            0x7C09182E, // lwzx r0, r9, r3
+        ]);
+        assert_eq!(result, StepResult::Continue);
+        assert_eq!(vm.gpr[0].value, GprValue::LoadIndexed {
+            address: RelocationTarget::Address(table_addr),
+            max_offset: NonZeroU32::new(4),
+            kind: JumpTableKind::Absolute,
+        });
+        let result = step_all(&mut vm, &obj, &[
+            0x7C004A14, // add r0, r0, r9
+            0x7C0903A6, // mtctr r0
+            0x4E800420, // bctr
+        ]);
+        assert_eq!(
+            result,
+            StepResult::Jump(BranchTarget::JumpTable {
+                address: RelocationTarget::Address(table_addr),
+                size: NonZeroU32::new(8),
+                kind: JumpTableKind::Relative32,
+            })
+        );
+        for (entry, target) in [(0x80005000, 0x80003110), (0x80005004, 0x80003120)] {
+            let entry_addr = SectionAddress::new(1, entry);
+            assert_eq!(
+                read_relative_jump_table_entry(
+                    &obj,
+                    table_addr,
+                    entry_addr,
+                    JumpTableKind::Relative32
+                )
+                .unwrap(),
+                Some(SectionAddress::new(0, target))
+            );
+        }
+    }
+
+    #[test]
+    fn test_relative16_jump_table() {
+        let mut table = vec![];
+        for target in [0x80003110u32, 0x80003120, 0x80003130] {
+            table.extend((target.wrapping_sub(0x80005000) as u16).to_be_bytes());
+        }
+        let obj = jump_table_obj(table);
+        let table_addr = SectionAddress::new(1, 0x80005000);
+        let mut vm = VM::new();
+        vm.gpr[3].value = GprValue::Range { min: 0, max: 2, step: 1 };
+        let result = step_all(&mut vm, &obj, &[
+            0x3D208000, // lis r9, 0x8000
+            0x39295000, // addi r9, r9, 0x5000
+            0x5463083C, // slwi r3, r3, 1
+            0x7C091AAE, // lhax r0, r9, r3
+            0x7C004A14, // add r0, r0, r9
+            0x7C0903A6, // mtctr r0
+            0x4E800420, // bctr
+        ]);
+        assert_eq!(
+            result,
+            StepResult::Jump(BranchTarget::JumpTable {
+                address: RelocationTarget::Address(table_addr),
+                size: NonZeroU32::new(6),
+                kind: JumpTableKind::Relative16,
+            })
+        );
+        assert_eq!(
+            read_relative_jump_table_entry(
+                &obj,
+                table_addr,
+                SectionAddress::new(1, 0x80005004),
+                JumpTableKind::Relative16
+            )
+            .unwrap(),
+            Some(SectionAddress::new(0, 0x80003130))
+        );
+    }
+
+    #[test]
+    fn test_mulli_scaled_index() {
+        let obj = jump_table_obj(vec![]);
+        let mut vm = VM::new();
+        vm.gpr[4].value = GprValue::Unknown;
+        let result = step_all(&mut vm, &obj, &[
+            0x1C04000C, // mulli r0, r4, 12
+        ]);
+        assert_eq!(result, StepResult::Continue);
+        assert_eq!(vm.gpr[0].value, GprValue::Range { min: 0, max: u32::MAX, step: 12 });
+    }
+
+    #[test]
+    fn test_toc_load() {
+        // TOC anchor at 0x80010000, with a pointer to 0x80010028 in slot 0x10
+        let mut obj = obj(
+            ObjKind::Executable,
+            vec![symbol("lbl_80010028", ObjSymbolKind::Object, 1, 0x80010028, 4)],
+            vec![
+                section(".text", ObjSectionKind::Code, 0x80003100, vec![0; 0x10]),
+                section(".data", ObjSectionKind::Data, 0x80010000, vec![0; 0x30]),
+            ],
+        );
+        obj.sections[1]
+            .relocations
+            .insert(0x80010010, reloc(ObjRelocKind::Absolute, 0, 0))
+            .unwrap();
+        obj.toc_base = Some(0x80010000);
+
+        let mut vm = VM::new_from_obj(&obj);
+        assert_eq!(vm.gpr[2].value, GprValue::Constant(0x80010000));
+        let result = step_all(&mut vm, &obj, &[
+            0x80620010, // lwz r3, 0x10(r2)
+        ]);
+        assert!(matches!(result, StepResult::LoadStore {
+            address: RelocationTarget::Address(address),
+            source_reg: 2,
+            ..
+        } if address == SectionAddress::new(1, 0x80010010)));
+        let target = RelocationTarget::Address(SectionAddress::new(1, 0x80010028));
+        assert_eq!(vm.gpr[3].value, GprValue::Address(target));
+
+        // Other loads from the TOC are not pointers
+        let mut vm = VM::new_from_obj(&obj);
+        step_all(&mut vm, &obj, &[
+            0x88620010, // lbz r3, 0x10(r2)
+        ]);
+        assert_eq!(vm.gpr[3].value, GprValue::Unknown);
+
+        // Without a TOC anchor, r2 is unknown
+        obj.toc_base = None;
+        let mut vm = VM::new_from_obj(&obj);
+        step_all(&mut vm, &obj, &[
+            0x80620010, // lwz r3, 0x10(r2)
+        ]);
+        assert_eq!(vm.gpr[3].value, GprValue::Unknown);
+    }
+
+    #[test]
+    fn test_relative32_jump_table_rel32() {
+        // In an object, each entry is R_PPC_REL32 against the target, with the entry's
+        // offset into the table added to the addend. (`.long .L5-.L4`)
+        let mut rodata = section(".rodata", ObjSectionKind::ReadOnlyData, 0, vec![0; 8]);
+        for (offset, target) in [(0u32, 0x10i64), (4, 0x18)] {
+            let reloc = reloc(ObjRelocKind::PpcRel32, 0, target + offset as i64);
+            rodata.relocations.insert(offset, reloc).unwrap();
+        }
+        let text_symbol = symbol(".text", ObjSymbolKind::Section, 0, 0, 0);
+        let obj = obj(ObjKind::Relocatable, vec![text_symbol], vec![
+            section(".text", ObjSectionKind::Code, 0, vec![0; 0x20]),
+            rodata,
+        ]);
+        let table_addr = SectionAddress::new(1, 0);
+        for (entry, target) in [(0, 0x10), (4, 0x18)] {
+            assert_eq!(
+                read_relative_jump_table_entry(
+                    &obj,
+                    table_addr,
+                    SectionAddress::new(1, entry),
+                    JumpTableKind::Relative32
+                )
+                .unwrap(),
+                Some(SectionAddress::new(0, target))
+            );
+        }
+    }
+}
@@ -3,13 +3,13 @@ use std::{
     collections::{btree_map::Entry, hash_map, BTreeMap, HashMap},
     fs,
     fs::DirBuilder,
-    io::{Cursor, Seek, Write},
+    io::{stdout, Cursor, Seek, Write},
     mem::take,
     str::FromStr,
     time::Instant,
 };
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use argp::FromArgs;
 use cwdemangle::demangle;
 use itertools::Itertools;
@@ -21,13 +21,19 @@ use xxhash_rust::xxh3::xxh3_64;
 
 use crate::{
     analysis::{
+        cache::{
+            detect_functions_cached, process_tracker_cached, AnalysisCache, ANALYSIS_CACHE_FILE,
+        },
         cfa::{AnalyzerState, SectionAddress},
-        objects::{detect_objects, detect_strings},
+        objects::{detect_data_types, detect_objects, detect_strings},
         pass::{
-            AnalysisPass, FindRelCtorsDtors, FindRelRodataData, FindSaveRestSleds,
-            FindTRKInterruptVectorTable,
+            AnalysisPass, FindCfmGlueStubs, FindRelCtorsDtors, FindRelRodataData,
+            FindSaveRestSleds, FindTRKInterruptVectorTable,
+        },
+        rtti::{detect_vtables, write_classes},
+        signatures::{
+            apply_signature_database, apply_signatures, apply_signatures_post, update_ctors_dtors,
         },
-        signatures::{apply_signatures, apply_signatures_post, update_ctors_dtors},
         tracker::Tracker,
     },
     cmd::shasum::file_sha1_string,
@@ -38,25 +44,37 @@ use crate::{
     util::{
         asm::write_asm,
         bin2c::{bin2c, HeaderKind},
+        boundaries::{suggest_units, write_unit_suggestions},
+        cfg::{analyze_function, build_cfg, write_cfg_dot},
+        cfrg::locate_fragment,
         comment::MWComment,
         config::{
-            apply_splits_file, apply_symbols_file, is_auto_symbol, signed_hex_serde,
+            apply_splits_file, apply_symbols_file, is_auto_symbol, parse_u32, signed_hex_serde,
             write_splits_file, write_symbols_file, SectionAddressRef,
         },
         dep::DepFile,
-        diff::{calc_diff_ranges, print_diff, process_code},
+        diff::{calc_diff_ranges, print_diff, process_code, symbol_name_fuzzy_eq},
         dol::process_dol,
         elf::{process_elf, write_elf},
+        extab::{extab_report, write_extab_report},
         file::{
             buf_copy_with_hash, buf_writer, check_hash_str, touch, verify_hash, FileIterator,
             FileReadInfo,
         },
-        lcf::{asm_path_for_unit, generate_ldscript, obj_path_for_unit},
+        graph::GraphFormat,
+        lcf::{asm_path_for_unit, obj_path_for_unit, Linker},
         map::apply_map_file,
         path::{check_path_buf, native_path},
+        pef::{is_pef, process_pef},
+        progress::{
+            unit_progress, write_progress_markdown, ModuleProgress, ProgressReport, UnitProgress,
+        },
         rel::{process_rel, process_rel_header, update_rel_section_alignment},
+        roundtrip::{split_symbol_addresses, verify_split_obj},
         rso::{process_rso, DOL_SECTION_ABS, DOL_SECTION_ETI, DOL_SECTION_NAMES},
+        signatures::SignatureDatabase,
         split::{is_linker_generated_object, split_obj, update_splits},
+        xrefs::analyze_and_write_xrefs,
         IntoCow, ToCow,
     },
     vfs::{detect, open_file, open_file_with_fs, open_fs, ArchiveKind, FileFormat, Vfs, VfsFile},
@@ -78,6 +96,10 @@ enum SubCommand {
     Diff(DiffArgs),
     Apply(ApplyArgs),
     Config(ConfigArgs),
+    Xrefs(XrefsArgs),
+    Cfg(CfgArgs),
+    Extab(ExtabArgs),
+    Progress(ProgressArgs),
 }
 
 #[derive(FromArgs, PartialEq, Eq, Debug)]
@@ -98,16 +120,19 @@ pub struct InfoArgs {
 pub struct SplitArgs {
     #[argp(positional, from_str_fn(native_path))]
     /// input configuration file
-    config: Utf8NativePathBuf,
+    pub config: Utf8NativePathBuf,
     #[argp(positional, from_str_fn(native_path))]
     /// output directory
-    out_dir: Utf8NativePathBuf,
+    pub out_dir: Utf8NativePathBuf,
     #[argp(switch)]
     /// skip updating splits & symbol files (for build systems)
-    no_update: bool,
+    pub no_update: bool,
     #[argp(option, short = 'j')]
     /// number of threads to use (default: number of logical CPUs)
-    jobs: Option<usize>,
+    pub jobs: Option<usize>,
+    #[argp(switch)]
+    /// verify that the written objects link back to the original bytes
+    pub verify: bool,
 }
 
 #[derive(FromArgs, PartialEq, Eq, Debug)]
@@ -134,6 +159,69 @@ pub struct ApplyArgs {
     elf_file: Utf8NativePathBuf,
 }
 
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Writes the call graph and data cross-references of a DOL.
+#[argp(subcommand, name = "xrefs")]
+pub struct XrefsArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// DOL file
+    dol_file: Utf8NativePathBuf,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output file (.json, or .dot for Graphviz)
+    out_file: Utf8NativePathBuf,
+    #[argp(option)]
+    /// output format: json, dot (default: from the output extension)
+    format: Option<GraphFormat>,
+    #[argp(option, from_str_fn(native_path))]
+    /// symbols file to name functions with
+    symbols: Option<Utf8NativePathBuf>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Writes the control-flow graph of a single function.
+#[argp(subcommand, name = "cfg")]
+pub struct CfgArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input configuration file
+    config: Utf8NativePathBuf,
+    #[argp(positional)]
+    /// function name or address
+    symbol: String,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output file (.json, or .dot for Graphviz; default: DOT to stdout)
+    out_file: Option<Utf8NativePathBuf>,
+    #[argp(option)]
+    /// output format: json, dot (default: from the output extension)
+    format: Option<GraphFormat>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Decodes exception tables and cross-checks them against the splits.
+#[argp(subcommand, name = "extab")]
+pub struct ExtabArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input configuration file
+    config: Utf8NativePathBuf,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output file (.json for JSON, otherwise text; default: text to stdout)
+    out_file: Option<Utf8NativePathBuf>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Reports matching progress of the built objects against the split objects.
+#[argp(subcommand, name = "progress")]
+pub struct ProgressArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// output configuration file written by `dol split` (config.json)
+    config: Utf8NativePathBuf,
+    #[argp(positional, from_str_fn(native_path))]
+    /// directory containing the built objects
+    build_dir: Utf8NativePathBuf,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output file (.json for JSON, otherwise Markdown; default: Markdown to stdout)
+    out_file: Option<Utf8NativePathBuf>,
+}
+
 #[derive(FromArgs, PartialEq, Eq, Debug)]
 /// Generates a project configuration file from a DOL (& RELs).
 #[argp(subcommand, name = "config")]
@@ -192,6 +280,24 @@ mod unix_path_serde_option {
     }
 }
 
+mod unix_path_serde_vec {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use typed_path::Utf8UnixPathBuf;
+
+    pub fn serialize<S>(paths: &[Utf8UnixPathBuf], s: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        s.collect_seq(paths.iter().map(|p| p.as_str()))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Utf8UnixPathBuf>, D::Error>
+    where D: Deserializer<'de> {
+        Ok(Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(Utf8UnixPathBuf::from)
+            .collect())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProjectConfig {
     #[serde(flatten)]
@@ -200,6 +306,14 @@ pub struct ProjectConfig {
     pub selfile: Option<Utf8UnixPathBuf>,
     #[serde(skip_serializing_if = "is_default")]
     pub selfile_hash: Option<String>,
+    /// Resource fork of a PEF application. Its `cfrg` resource locates the code
+    /// fragments within the data fork (`object`).
+    #[serde(with = "unix_path_serde_option", default, skip_serializing_if = "is_default")]
+    pub rsrc: Option<Utf8UnixPathBuf>,
+    /// Code fragment to load, by `cfrg` index or name.
+    /// If not present, the first PowerPC fragment in the data fork is used.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub fragment: Option<String>,
     /// Version of the MW `.comment` section format.
     /// If not present, no `.comment` sections will be written.
     #[serde(skip_serializing_if = "is_default")]
@@ -208,6 +322,9 @@ pub struct ProjectConfig {
     /// Useful when the symbols file is already created.
     #[serde(default, skip_serializing_if = "is_default")]
     pub quick_analysis: bool,
+    /// Reuses analysis results from previous runs, cached in the output directory.
+    #[serde(default = "bool_true", skip_serializing_if = "is_true")]
+    pub analysis_cache: bool,
     #[serde(default, skip_serializing_if = "is_default")]
     pub modules: Vec<ModuleConfig>,
     // Analysis options
@@ -215,6 +332,16 @@ pub struct ProjectConfig {
     pub detect_objects: bool,
     #[serde(default = "bool_true", skip_serializing_if = "is_true")]
     pub detect_strings: bool,
+    /// Infers pointer tables, string pointer tables and vtables from data relocations.
+    #[serde(default = "bool_true", skip_serializing_if = "is_true")]
+    pub detect_data_types: bool,
+    /// Names C++ vtables, RTTI records and virtual functions, and writes `classes.txt`.
+    #[serde(default = "bool_true", skip_serializing_if = "is_true")]
+    pub detect_vtables: bool,
+    /// Infers translation unit boundaries in unsplit ranges and writes the proposed
+    /// units to `splits_suggested.txt`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub suggest_splits: bool,
     #[serde(default = "bool_true", skip_serializing_if = "is_true")]
     pub write_asm: bool,
     /// Specifies the start of the common BSS section.
@@ -224,12 +351,19 @@ pub struct ProjectConfig {
     /// and instead assumes that all symbols are known.
     #[serde(default, skip_serializing_if = "is_default")]
     pub symbols_known: bool,
+    /// Signature databases (generated by `dtk elf sigs`) used to name
+    /// unnamed functions after analysis.
+    #[serde(with = "unix_path_serde_vec", default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<Utf8UnixPathBuf>,
     /// Fills gaps between symbols to avoid linker realignment.
     #[serde(default = "bool_true", skip_serializing_if = "is_true")]
     pub fill_gaps: bool,
     /// Marks all emitted symbols as "exported" to prevent the linker from removing them.
     #[serde(default = "bool_true", skip_serializing_if = "is_true")]
     pub export_all: bool,
+    /// Linker to generate the linker script for: `mwld` (default) or `gnu`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub linker: Linker,
     /// Optional base path for all object files.
     #[serde(with = "unix_path_serde_option", default, skip_serializing_if = "is_default")]
     pub object_base: Option<Utf8UnixPathBuf>,
@@ -245,16 +379,24 @@ impl Default for ProjectConfig {
             base: Default::default(),
             selfile: None,
             selfile_hash: None,
+            rsrc: None,
+            fragment: None,
             mw_comment_version: None,
             quick_analysis: false,
+            analysis_cache: true,
             modules: vec![],
             detect_objects: true,
             detect_strings: true,
+            detect_data_types: true,
+            detect_vtables: true,
+            suggest_splits: false,
             write_asm: true,
             common_start: None,
             symbols_known: false,
+            signatures: vec![],
             fill_gaps: true,
             export_all: true,
+            linker: Linker::default(),
             object_base: None,
             extract_objects: true,
         }
@@ -279,8 +421,12 @@ pub struct ModuleConfig {
     /// Forces the given symbols to be active (exported) in the linker script.
     #[serde(default, skip_serializing_if = "is_default")]
     pub force_active: Vec<String>,
+    /// Template for the mwld linker script (`ldscript.lcf`).
     #[serde(with = "unix_path_serde_option", default, skip_serializing_if = "is_default")]
     pub ldscript_template: Option<Utf8UnixPathBuf>,
+    /// Template for the GNU ld linker script (`ldscript.ld`), with `linker: gnu`.
+    #[serde(with = "unix_path_serde_option", default, skip_serializing_if = "is_default")]
+    pub ld_template: Option<Utf8UnixPathBuf>,
     /// Overrides links to other modules.
     #[serde(skip_serializing_if = "is_default")]
     pub links: Option<Vec<String>>,
@@ -418,6 +564,10 @@ pub fn run(args: Args) -> Result<()> {
         SubCommand::Diff(c_args) => diff(c_args),
         SubCommand::Apply(c_args) => apply(c_args),
         SubCommand::Config(c_args) => config(c_args),
+        SubCommand::Xrefs(c_args) => xrefs(c_args),
+        SubCommand::Cfg(c_args) => cfg(c_args),
+        SubCommand::Extab(c_args) => extab(c_args),
+        SubCommand::Progress(c_args) => progress(c_args),
     }
 }
 
@@ -515,6 +665,7 @@ pub fn info(args: InfoArgs) -> Result<()> {
 
     let mut state = AnalyzerState::default();
     FindSaveRestSleds::execute(&mut state, &obj)?;
+    FindCfmGlueStubs::execute(&mut state, &obj)?;
     state.detect_functions(&obj)?;
     log::debug!(
         "Discovered {} functions",
@@ -570,11 +721,156 @@ pub fn info(args: InfoArgs) -> Result<()> {
     Ok(())
 }
 
+fn xrefs(args: XrefsArgs) -> Result<()> {
+    let obj = {
+        let mut file = open_file(&args.dol_file, true)?;
+        process_dol(file.map()?, "")?
+    };
+    analyze_and_write_xrefs(obj, args.symbols.as_deref(), &args.out_file, args.format)
+}
+
+fn cfg(args: CfgArgs) -> Result<()> {
+    log::info!("Loading {}", args.config);
+    let mut config_file = open_file(&args.config, true)?;
+    let config: ProjectConfig = serde_yaml::from_reader(config_file.as_mut())?;
+    let object_base = find_object_base(&config)?;
+    let AnalyzeResult { mut obj, .. } = load_analyze_dol(&config, &object_base, None)?;
+
+    let symbol = match obj.symbols.by_name(&args.symbol)? {
+        Some((_, symbol)) => symbol,
+        None => {
+            let addr = parse_u32(&args.symbol)
+                .map_err(|_| anyhow!("Function '{}' not found", args.symbol))?;
+            let (section_index, _) = obj.sections.at_address(addr)?;
+            obj.symbols
+                .at_section_address(section_index, addr)
+                .find(|(_, s)| s.kind == ObjSymbolKind::Function)
+                .map(|(_, s)| s)
+                .ok_or_else(|| anyhow!("No function @ {:#010X}", addr))?
+        }
+    };
+    ensure!(symbol.kind == ObjSymbolKind::Function, "Symbol '{}' is not a function", symbol.name);
+    let symbol = symbol.clone();
+    let section_index =
+        symbol.section.ok_or_else(|| anyhow!("Function '{}' has no section", symbol.name))?;
+
+    // Only the relocations of this function are needed for the disassembly
+    let mut tracker = Tracker::new(&obj);
+    tracker.process_function(&obj, &symbol)?;
+    tracker.apply(&mut obj, false)?;
+
+    let slices = analyze_function(&obj, SectionAddress::new(section_index, symbol.address as u32))?;
+    let cfg = build_cfg(&obj, &symbol.name, &slices)?;
+    let format = match (args.format, &args.out_file) {
+        (Some(format), _) => format,
+        (None, Some(out_file)) => GraphFormat::from_path(out_file),
+        (None, None) => GraphFormat::Dot,
+    };
+    let mut out: Box<dyn Write> = if let Some(out_file) = &args.out_file {
+        Box::new(buf_writer(out_file)?)
+    } else {
+        Box::new(stdout())
+    };
+    match format {
+        GraphFormat::Json => serde_json::to_writer_pretty(&mut out, &cfg)?,
+        GraphFormat::Dot => write_cfg_dot(&mut out, &cfg)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn extab(args: ExtabArgs) -> Result<()> {
+    log::info!("Loading {}", args.config);
+    let mut config_file = open_file(&args.config, true)?;
+    let config: ProjectConfig = serde_yaml::from_reader(config_file.as_mut())?;
+    let object_base = find_object_base(&config)?;
+    let AnalyzeResult { mut obj, .. } = load_analyze_dol(&config, &object_base, None)?;
+
+    // Destructor and type info references in extab come from relocation analysis
+    let mut tracker = Tracker::new(&obj);
+    tracker.process(&obj)?;
+    tracker.apply(&mut obj, false)?;
+
+    let report = extab_report(&obj)?;
+    let mut out: Box<dyn Write> = if let Some(out_file) = &args.out_file {
+        Box::new(buf_writer(out_file)?)
+    } else {
+        Box::new(stdout())
+    };
+    if args.out_file.as_ref().is_some_and(|p| p.extension() == Some("json")) {
+        serde_json::to_writer_pretty(&mut out, &report)?;
+    } else {
+        write_extab_report(&mut out, &report)?;
+    }
+    out.flush()?;
+    if !report.issues.is_empty() {
+        log::warn!("Found {} extab issues", report.issues.len());
+    }
+    Ok(())
+}
+
+fn progress(args: ProgressArgs) -> Result<()> {
+    log::info!("Loading {}", args.config);
+    let mut config_file = open_file(&args.config, true)?;
+    let config: OutputConfig = serde_json::from_reader(config_file.as_mut())?;
+
+    let mut report = ProgressReport::default();
+    for module in std::iter::once(&config.base).chain(&config.modules) {
+        let _span = info_span!("module", name = %module.name).entered();
+        let units = module
+            .units
+            .par_iter()
+            .map(|unit| -> Result<UnitProgress> {
+                let target_obj = process_elf(&unit.object.with_encoding())
+                    .with_context(|| format!("While loading split object for {}", unit.name))?;
+                let built_path = args.build_dir.join(obj_path_for_unit(&unit.name));
+                let built_obj = if fs::metadata(&built_path).is_ok_and(|m| m.is_file()) {
+                    Some(process_elf(&built_path)?)
+                } else {
+                    None
+                };
+                unit_progress(&unit.name, unit.autogenerated, &target_obj, built_obj.as_ref())
+                    .with_context(|| format!("While diffing {}", unit.name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut module_progress = ModuleProgress {
+            name: module.name.clone(),
+            module_id: module.module_id,
+            ..Default::default()
+        };
+        for unit in units {
+            module_progress.add_unit(unit);
+        }
+        info!(
+            "{}: {:.2}% code, {:.2}% fuzzy, {:.2}% data",
+            module.name,
+            module_progress.measures.matched_code_percent,
+            module_progress.measures.fuzzy_match_percent,
+            module_progress.measures.matched_data_percent
+        );
+        report.add_module(module_progress);
+    }
+
+    let mut out: Box<dyn Write> = if let Some(out_file) = &args.out_file {
+        Box::new(buf_writer(out_file)?)
+    } else {
+        Box::new(stdout())
+    };
+    if args.out_file.as_ref().is_some_and(|p| p.extension() == Some("json")) {
+        serde_json::to_writer_pretty(&mut out, &report)?;
+    } else {
+        write_progress_markdown(&mut out, &report)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
 struct ModuleInfo<'a> {
     obj: ObjInfo,
     config: &'a ModuleConfig,
     symbols_cache: Option<FileReadInfo>,
     splits_cache: Option<FileReadInfo>,
+    analysis_cache: Option<AnalysisCache>,
 }
 
 type ModuleMapByName<'a> = BTreeMap<String, ModuleInfo<'a>>;
@@ -812,9 +1108,14 @@ struct AnalyzeResult {
     dep: Vec<Utf8NativePathBuf>,
     symbols_cache: Option<FileReadInfo>,
     splits_cache: Option<FileReadInfo>,
+    analysis_cache: Option<AnalysisCache>,
 }
 
-fn load_analyze_dol(config: &ProjectConfig, object_base: &ObjectBase) -> Result<AnalyzeResult> {
+fn load_analyze_dol(
+    config: &ProjectConfig,
+    object_base: &ObjectBase,
+    cache_path: Option<&Utf8NativePath>,
+) -> Result<AnalyzeResult> {
     let object_path = object_base.join(&config.base.object);
     log::debug!("Loading {}", object_path);
     let mut obj = {
@@ -823,9 +1124,20 @@ fn load_analyze_dol(config: &ProjectConfig, object_base: &ObjectBase) -> Result<
         if let Some(hash_str) = &config.base.hash {
             verify_hash(data, hash_str)?;
         }
-        process_dol(data, config.base.name())?
+        let mut rsrc_file = config.rsrc.as_ref().map(|path| object_base.open(path)).transpose()?;
+        let rsrc = rsrc_file.as_mut().map(|file| file.map()).transpose()?;
+        let data = locate_fragment(data, rsrc, config.fragment.as_deref())?;
+        if is_pef(data) {
+            let (_, obj) = process_pef(data, config.base.name())?;
+            obj
+        } else {
+            process_dol(data, config.base.name())?
+        }
     };
     let mut dep = vec![object_path];
+    if let Some(rsrc_path) = &config.rsrc {
+        dep.push(object_base.join(rsrc_path));
+    }
 
     if let Some(comment_version) = config.mw_comment_version {
         obj.mw_comment = Some(MWComment::new(comment_version)?);
@@ -858,6 +1170,7 @@ fn load_analyze_dol(config: &ProjectConfig, object_base: &ObjectBase) -> Result<
     // Apply block relocations from config
     apply_block_relocations(&mut obj, &config.base.block_relocations)?;
 
+    let mut analysis_cache = cache_path.map(AnalysisCache::read);
     if !config.symbols_known {
         // TODO move before symbols?
         debug!("Performing signature analysis");
@@ -867,12 +1180,21 @@ fn load_analyze_dol(config: &ProjectConfig, object_base: &ObjectBase) -> Result<
             let mut state = AnalyzerState::default();
             debug!("Detecting function boundaries");
             FindSaveRestSleds::execute(&mut state, &obj)?;
-            state.detect_functions(&obj)?;
+            FindCfmGlueStubs::execute(&mut state, &obj)?;
+            detect_functions_cached(&mut state, &obj, analysis_cache.as_mut())?;
             FindTRKInterruptVectorTable::execute(&mut state, &obj)?;
             state.apply(&mut obj)?;
         }
 
         apply_signatures_post(&mut obj)?;
+
+        if !config.signatures.is_empty() {
+            let paths = config.signatures.iter().map(|p| p.with_encoding()).collect::<Vec<_>>();
+            let db = SignatureDatabase::load(&paths)?;
+            debug!("Scanning with {} signatures", db.len());
+            apply_signature_database(&mut obj, &db)?;
+            dep.extend(paths);
+        }
     }
 
     if let Some(selfile) = &config.selfile {
@@ -893,7 +1215,7 @@ fn load_analyze_dol(config: &ProjectConfig, object_base: &ObjectBase) -> Result<
     // Apply additional relocations from config
     apply_add_relocations(&mut obj, &config.base.add_relocations)?;
 
-    Ok(AnalyzeResult { obj, dep, symbols_cache, splits_cache })
+    Ok(AnalyzeResult { obj, dep, symbols_cache, splits_cache, analysis_cache })
 }
 
 fn split_write_obj(
@@ -902,10 +1224,11 @@ fn split_write_obj(
     base_dir: &Utf8NativePath,
     out_dir: &Utf8NativePath,
     no_update: bool,
+    verify: bool,
 ) -> Result<OutputModule> {
     debug!("Performing relocation analysis");
     let mut tracker = Tracker::new(&module.obj);
-    tracker.process(&module.obj)?;
+    process_tracker_cached(&mut tracker, &module.obj, module.analysis_cache.as_mut())?;
 
     debug!("Applying relocations");
     tracker.apply(&mut module.obj, false)?;
@@ -920,6 +1243,17 @@ fn split_write_obj(
         detect_strings(&mut module.obj)?;
     }
 
+    if !config.symbols_known && config.detect_data_types {
+        debug!("Detecting data types");
+        detect_data_types(&mut module.obj)?;
+    }
+
+    let mut classes = vec![];
+    if !config.symbols_known && config.detect_vtables {
+        debug!("Detecting vtables");
+        classes = detect_vtables(&mut module.obj)?;
+    }
+
     debug!("Adjusting splits");
     let module_id = module.obj.module_id;
     update_splits(
@@ -928,6 +1262,12 @@ fn split_write_obj(
         config.fill_gaps,
     )?;
 
+    let mut unit_suggestions = vec![];
+    if config.suggest_splits {
+        debug!("Inferring unit boundaries");
+        unit_suggestions = suggest_units(&module.obj)?;
+    }
+
     if !no_update {
         debug!("Writing configuration");
         if let Some(symbols_path) = &module.config.symbols {
@@ -952,6 +1292,19 @@ fn split_write_obj(
         .recursive(true)
         .create(out_dir)
         .with_context(|| format!("Failed to create out dir '{}'", out_dir))?;
+    if let Some(analysis_cache) = &module.analysis_cache {
+        analysis_cache.write(&out_dir.join(ANALYSIS_CACHE_FILE))?;
+    }
+    if !classes.is_empty() {
+        let mut w = buf_writer(&out_dir.join("classes.txt"))?;
+        write_classes(&mut w, &module.obj, &classes)?;
+        w.flush()?;
+    }
+    if config.suggest_splits {
+        let mut w = buf_writer(&out_dir.join("splits_suggested.txt"))?;
+        write_unit_suggestions(&mut w, &module.obj, &unit_suggestions)?;
+        w.flush()?;
+    }
     let obj_dir = out_dir.join("obj");
     let entry = if module.obj.kind == ObjKind::Executable {
         module.obj.entry.and_then(|e| {
@@ -966,14 +1319,22 @@ fn split_write_obj(
     let mut out_config = OutputModule {
         name: module_name,
         module_id,
-        ldscript: out_dir.join("ldscript.lcf").with_unix_encoding(),
+        ldscript: out_dir.join(config.linker.ldscript_name()).with_unix_encoding(),
         units: Vec::with_capacity(split_objs.len()),
         entry,
         extract: Vec::with_capacity(module.config.extract.len()),
     };
     let mut object_paths = BTreeMap::new();
+    let globals = if verify { split_symbol_addresses(&split_objs) } else { BTreeMap::new() };
+    let mut roundtrip_issues = vec![];
     for (unit, split_obj) in module.obj.link_order.iter().zip(&split_objs) {
         let out_obj = write_elf(split_obj, config.export_all)?;
+        if verify {
+            roundtrip_issues.extend(
+                verify_split_obj(&module.obj, &unit.name, &out_obj, &globals)
+                    .with_context(|| format!("Failed to verify {}", unit.name))?,
+            );
+        }
         let obj_path = obj_path_for_unit(&unit.name);
         let out_path = obj_dir.join(&obj_path);
         if let Some(existing) = object_paths.insert(obj_path, unit) {
@@ -1049,8 +1410,24 @@ fn split_write_obj(
         });
     }
 
-    // Generate ldscript.lcf
-    let ldscript_template = if let Some(template_path) = &module.config.ldscript_template {
+    // Generate ldscript.lcf or ldscript.ld
+    let template_path = match config.linker {
+        Linker::Mwld => {
+            ensure!(
+                module.config.ld_template.is_none(),
+                "ld_template is only used with linker: gnu, use ldscript_template for mwld"
+            );
+            &module.config.ldscript_template
+        }
+        Linker::Gnu => {
+            ensure!(
+                module.config.ldscript_template.is_none(),
+                "ldscript_template is an mwld template, use ld_template with linker: gnu"
+            );
+            &module.config.ld_template
+        }
+    };
+    let ldscript_template = if let Some(template_path) = template_path {
         let template_path = template_path.with_encoding();
         Some(fs::read_to_string(&template_path).with_context(|| {
             format!("Failed to read linker script template '{}'", template_path)
@@ -1058,8 +1435,11 @@ fn split_write_obj(
     } else {
         None
     };
-    let ldscript_string =
-        generate_ldscript(&module.obj, ldscript_template.as_deref(), &module.config.force_active)?;
+    let ldscript_string = config.linker.generate_ldscript(
+        &module.obj,
+        ldscript_template.as_deref(),
+        &module.config.force_active,
+    )?;
     let ldscript_path = out_config.ldscript.with_encoding();
     write_if_changed(&ldscript_path, ldscript_string.as_bytes())?;
 
@@ -1075,6 +1455,18 @@ fn split_write_obj(
             w.flush()?;
         }
     }
+
+    if !roundtrip_issues.is_empty() {
+        for (unit, issues) in &roundtrip_issues.iter().chunk_by(|issue| &issue.unit) {
+            log::error!("{} does not round-trip:", unit);
+            for issue in issues {
+                log::error!("\t{} @ {:#010X}: {}", issue.section, issue.address, issue.message);
+            }
+        }
+        bail!("{} round-trip issues found", roundtrip_issues.len());
+    } else if verify {
+        info!("Verified {} objects", split_objs.len());
+    }
     Ok(out_config)
 }
 
@@ -1096,6 +1488,7 @@ fn load_analyze_rel(
     config: &ProjectConfig,
     object_base: &ObjectBase,
     module_config: &ModuleConfig,
+    cache_path: Option<&Utf8NativePath>,
 ) -> Result<AnalyzeResult> {
     let object_path = object_base.join(&module_config.object);
     debug!("Loading {}", object_path);
@@ -1138,12 +1531,13 @@ fn load_analyze_rel(
     // Apply block relocations from config
     apply_block_relocations(&mut module_obj, &module_config.block_relocations)?;
 
+    let mut analysis_cache = cache_path.map(AnalysisCache::read);
     if !config.symbols_known {
         debug!("Analyzing module {}", module_obj.module_id);
         if !config.quick_analysis {
             let mut state = AnalyzerState::default();
             FindSaveRestSleds::execute(&mut state, &module_obj)?;
-            state.detect_functions(&module_obj)?;
+            detect_functions_cached(&mut state, &module_obj, analysis_cache.as_mut())?;
             FindRelCtorsDtors::execute(&mut state, &module_obj)?;
             FindRelRodataData::execute(&mut state, &module_obj)?;
             state.apply(&mut module_obj)?;
@@ -1161,10 +1555,10 @@ fn load_analyze_rel(
     // Apply additional relocations from config
     apply_add_relocations(&mut module_obj, &module_config.add_relocations)?;
 
-    Ok(AnalyzeResult { obj: module_obj, dep, symbols_cache, splits_cache })
+    Ok(AnalyzeResult { obj: module_obj, dep, symbols_cache, splits_cache, analysis_cache })
 }
 
-fn split(args: SplitArgs) -> Result<()> {
+pub fn split(args: SplitArgs) -> Result<()> {
     if let Some(jobs) = args.jobs {
         rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global()?;
     }
@@ -1212,10 +1606,11 @@ fn split(args: SplitArgs) -> Result<()> {
         // DOL
         s.spawn(|_| {
             let _span = info_span!("module", name = %config.base.name()).entered();
-            dol_result =
-                Some(load_analyze_dol(&config, &object_base).with_context(|| {
-                    format!("While loading object '{}'", config.base.file_name())
-                }));
+            let cache_path = config.analysis_cache.then(|| args.out_dir.join(ANALYSIS_CACHE_FILE));
+            dol_result = Some(
+                load_analyze_dol(&config, &object_base, cache_path.as_deref())
+                    .with_context(|| format!("While loading object '{}'", config.base.file_name())),
+            );
         });
         // Modules
         s.spawn(|_| {
@@ -1225,7 +1620,16 @@ fn split(args: SplitArgs) -> Result<()> {
                     .par_iter()
                     .map(|module_config| {
                         let _span = info_span!("module", name = %module_config.name()).entered();
-                        load_analyze_rel(&config, &object_base, module_config).with_context(|| {
+                        let cache_path = config.analysis_cache.then(|| {
+                            args.out_dir.join(module_config.name()).join(ANALYSIS_CACHE_FILE)
+                        });
+                        load_analyze_rel(
+                            &config,
+                            &object_base,
+                            module_config,
+                            cache_path.as_deref(),
+                        )
+                        .with_context(|| {
                             format!("While loading object '{}'", module_config.file_name())
                         })
                     })
@@ -1242,6 +1646,7 @@ fn split(args: SplitArgs) -> Result<()> {
             config: &config.base,
             symbols_cache: result.symbols_cache,
             splits_cache: result.splits_cache,
+            analysis_cache: result.analysis_cache,
         }
     };
     let mut function_count = dol.obj.symbols.by_kind(ObjSymbolKind::Function).count();
@@ -1256,6 +1661,7 @@ fn split(args: SplitArgs) -> Result<()> {
                 config: &config.modules[idx],
                 symbols_cache: result.symbols_cache,
                 splits_cache: result.splits_cache,
+                analysis_cache: result.analysis_cache,
             }),
             Entry::Occupied(_) => bail!("Duplicate module name {}", result.obj.name),
         };
@@ -1359,14 +1765,21 @@ fn split(args: SplitArgs) -> Result<()> {
             let _span =
                 info_span!("module", name = %config.base.name(), id = dol.obj.module_id).entered();
             dol_result = Some(
-                split_write_obj(&mut dol, &config, &args.out_dir, &args.out_dir, args.no_update)
-                    .with_context(|| {
-                        format!(
-                            "While processing object '{}' (module ID {})",
-                            config.base.file_name(),
-                            dol.obj.module_id
-                        )
-                    }),
+                split_write_obj(
+                    &mut dol,
+                    &config,
+                    &args.out_dir,
+                    &args.out_dir,
+                    args.no_update,
+                    args.verify,
+                )
+                .with_context(|| {
+                    format!(
+                        "While processing object '{}' (module ID {})",
+                        config.base.file_name(),
+                        dol.obj.module_id
+                    )
+                }),
             );
         });
         // Modules
@@ -1379,7 +1792,7 @@ fn split(args: SplitArgs) -> Result<()> {
                             info_span!("module", name = %module.config.name(), id = module.obj.module_id)
                                 .entered();
                         let out_dir = args.out_dir.join(module.config.name());
-                        split_write_obj(module, &config, &args.out_dir, &out_dir, args.no_update).with_context(
+                        split_write_obj(module, &config, &args.out_dir, &out_dir, args.no_update, args.verify).with_context(
                             || {
                                 format!(
                                     "While processing object '{}' (module {} ID {})",
@@ -1604,44 +2017,6 @@ fn validate(obj: &ObjInfo, elf_file: &Utf8NativePath, state: &AnalyzerState) ->
     Ok(())
 }
 
-/// Check if two symbols' names match, allowing for differences in compiler-generated names,
-/// like @1234 and @5678, or init$1234 and init$5678.
-fn symbol_name_fuzzy_eq(a: &str, b: &str) -> bool {
-    if a == b {
-        return true;
-    }
-    // Match e.g. @1234 and @5678
-    if a.starts_with('@') && b.starts_with('@') {
-        return true;
-    }
-    // Match e.g. init$1234 and init$5678
-    if let (Some(a_dollar), Some(b_dollar)) = (a.rfind('$'), b.rfind('$')) {
-        if a[..a_dollar] == b[..b_dollar] {
-            if let (Ok(_), Ok(_)) =
-                (a[a_dollar + 1..].parse::<u32>(), b[b_dollar + 1..].parse::<u32>())
-            {
-                return true;
-            }
-        }
-    }
-    // Match e.g. symbol and symbol_80123456 (globalized symbol)
-    if let Some(a_under) = a.rfind('_') {
-        if &a[..a_under] == b && is_hex(&a[a_under + 1..]) {
-            return true;
-        }
-    }
-    if let Some(b_under) = b.rfind('_') {
-        if a == &b[..b_under] && is_hex(&b[b_under + 1..]) {
-            return true;
-        }
-    }
-    false
-}
-
-fn is_hex(s: &str) -> bool {
-    s.chars().all(|c| c.is_ascii_digit() || matches!(c, 'a'..='f' | 'A'..='F'))
-}
-
 fn diff(args: DiffArgs) -> Result<()> {
     log::info!("Loading {}", args.config);
     let mut config_file = open_file(&args.config, true)?;
@@ -2191,6 +2566,10 @@ pub fn find_object_base(config: &ProjectConfig) -> Result<ObjectBase> {
                         let fs = open_fs(file, ArchiveKind::Wad)?;
                         return Ok(ObjectBase::Vfs(path, fs));
                     }
+                    FileFormat::Archive(ArchiveKind::Hfs(kind)) => {
+                        let fs = open_fs(file, ArchiveKind::Hfs(kind))?;
+                        return Ok(ObjectBase::Vfs(path, fs));
+                    }
                     _ => {}
                 }
             }
@@ -2279,21 +2658,3 @@ fn extracted_path(target_dir: &Utf8NativePath, path: &Utf8UnixPath) -> Utf8Nativ
     }
     target_path
 }
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_symbol_name_fuzzy_eq() {
-        assert!(symbol_name_fuzzy_eq("symbol", "symbol"));
-        assert!(symbol_name_fuzzy_eq("@1234", "@5678"));
-        assert!(symbol_name_fuzzy_eq("symbol$1234", "symbol$5678"));
-        assert!(symbol_name_fuzzy_eq("symbol", "symbol_80123456"));
-        assert!(symbol_name_fuzzy_eq("symbol_80123456", "symbol"));
-        assert!(!symbol_name_fuzzy_eq("symbol", "symbol2"));
-        assert!(!symbol_name_fuzzy_eq("symbol@1234", "symbol@5678"));
-        assert!(!symbol_name_fuzzy_eq("symbol", "symbol_80123456_"));
-        assert!(!symbol_name_fuzzy_eq("symbol_80123456_", "symbol"));
-    }
-}
@@ -17,6 +17,7 @@ use object::{
 use typed_path::Utf8NativePathBuf;
 
 use crate::{
+    cmd::sigs::write_signature_database,
     obj::ObjKind,
     util::{
         asm::write_asm,
@@ -88,6 +89,8 @@ pub struct ConfigArgs {
 
 #[derive(FromArgs, PartialEq, Eq, Debug)]
 /// Builds function signatures from an ELF file.
+/// Without a symbol name, builds a signature database from every
+/// function in the given objects and static libraries.
 #[argp(subcommand, name = "sigs")]
 pub struct SignaturesArgs {
     #[argp(positional, from_str_fn(native_path))]
@@ -95,7 +98,7 @@ pub struct SignaturesArgs {
     files: Vec<Utf8NativePathBuf>,
     #[argp(option, short = 's')]
     /// symbol name
-    symbol: String,
+    symbol: Option<String>,
     #[argp(option, short = 'o', from_str_fn(native_path))]
     /// output yml
     out_file: Utf8NativePathBuf,
@@ -394,11 +397,14 @@ fn has_section_flags(flags: SectionFlags, flag: u32) -> Result<bool> {
 fn signatures(args: SignaturesArgs) -> Result<()> {
     // Process response files (starting with '@')
     let files = process_rsp(&args.files)?;
+    let Some(symbol) = &args.symbol else {
+        return write_signature_database(&files, &args.out_file);
+    };
 
     let mut signatures: HashMap<String, FunctionSignature> = HashMap::new();
     for path in files {
         log::info!("Processing {}", path);
-        let signature = match generate_signature(&path, &args.symbol) {
+        let signature = match generate_signature(&path, symbol) {
             Ok(Some(signature)) => signature,
             Ok(None) => continue,
             Err(e) => {
@@ -190,7 +190,7 @@ fn section_kind(section: &object::Section) -> SectionKind {
 }
 
 #[inline]
-fn is_alloc(flags: object::SectionFlags) -> bool {
+pub fn is_alloc(flags: object::SectionFlags) -> bool {
     matches!(flags, object::SectionFlags::Elf { sh_flags } if sh_flags & object::elf::SHF_ALLOC as u64 != 0)
 }
 
@@ -0,0 +1,484 @@
+use std::{collections::HashMap, io::Write};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use argp::FromArgs;
+use object::{
+    elf, Architecture, Endianness, Object, ObjectKind, ObjectSection, ObjectSymbol,
+    RelocationFlags, RelocationTarget, SectionIndex, SectionKind, SymbolSection,
+};
+use typed_path::Utf8NativePathBuf;
+
+use crate::{
+    cmd::elf2dol::is_alloc,
+    obj::R_PPC_TOC16,
+    util::{
+        cfrg::locate_fragment,
+        file::buf_writer,
+        path::native_path,
+        pef::{
+            read_pef_layout, unpack_pidata, write_pef, PefContainerHeader, PefExportedSymbol,
+            PefFile, PefLayout, PefLayoutSection, PefLoader, PefLoaderHeader, PefReloc,
+            PefRelocTarget, PefSectionData, PefSectionKind, PEF_ARCH_PPC, PEF_SECTION_ABS,
+        },
+    },
+    vfs::open_file,
+};
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Converts an ELF file to a PEF file.
+#[argp(subcommand, name = "elf2pef")]
+pub struct Args {
+    #[argp(positional, from_str_fn(native_path))]
+    /// path to input ELF
+    elf_file: Utf8NativePathBuf,
+    #[argp(positional, from_str_fn(native_path))]
+    /// path to output PEF
+    pef_file: Utf8NativePathBuf,
+    #[argp(option, short = 'l', from_str_fn(native_path))]
+    /// PEF layout file (written by `pef config`)
+    layout: Option<Utf8NativePathBuf>,
+    #[argp(switch)]
+    /// without a layout file, store data pattern-initialized
+    pack_data: bool,
+    #[argp(option, short = 'c', from_str_fn(native_path))]
+    /// original PEF (or data fork) to verify the output's layout and contents against
+    check: Option<Utf8NativePathBuf>,
+    #[argp(option, from_str_fn(native_path))]
+    /// resource fork of the original, used to locate fragments with its `cfrg` resource
+    rsrc: Option<Utf8NativePathBuf>,
+    #[argp(option, short = 'f')]
+    /// fragment of the original to verify against, by `cfrg` index or name
+    fragment: Option<String>,
+    /// sections (by name) to ignore
+    #[argp(option, long = "ignore")]
+    deny_sections: Vec<String>,
+}
+
+/// An instantiated section, built from one or more consecutive ELF sections.
+struct OutSection {
+    name: String,
+    data: PefSectionData,
+    /// Address of the first ELF section.
+    base: u32,
+    elf_sections: Vec<SectionIndex>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut file = open_file(&args.elf_file, true)?;
+    let obj_file = object::read::File::parse(file.map()?)?;
+    match obj_file.architecture() {
+        Architecture::PowerPc => {}
+        arch => bail!("Unexpected architecture: {arch:?}"),
+    };
+    ensure!(obj_file.endianness() == Endianness::Big, "Expected big endian");
+    match obj_file.kind() {
+        ObjectKind::Executable => {}
+        kind => bail!("Unexpected ELF type: {kind:?}"),
+    }
+    let layout = match &args.layout {
+        Some(path) => read_pef_layout(open_file(path, true)?)
+            .with_context(|| format!("Failed to read PEF layout {}", path))?,
+        None => PefLayout::default(),
+    };
+
+    let mut sections = build_sections(&obj_file, &layout, &args)?;
+    let section_for = |index: SectionIndex| {
+        sections.iter().position(|s| s.elf_sections.contains(&index)).map(|i| i as u16)
+    };
+    let symbol_location = |symbol: &object::Symbol| -> Result<Option<(u16, u32)>> {
+        match symbol.section() {
+            SymbolSection::Section(index) => {
+                let section = section_for(index).ok_or_else(|| {
+                    anyhow!("Symbol {} is in an ignored section", symbol.name().unwrap_or("?"))
+                })?;
+                Ok(Some((section, symbol.address() as u32 - sections[section as usize].base)))
+            }
+            _ => Ok(None),
+        }
+    };
+
+    // Relocations to sections are stored relative to the target section
+    let mut relocations = Vec::new();
+    let mut patches = Vec::new();
+    for (index, out_section) in sections.iter().enumerate() {
+        for &elf_index in &out_section.elf_sections {
+            let section = obj_file.section_by_index(elf_index)?;
+            for (address, reloc) in section.relocations() {
+                match reloc.flags() {
+                    RelocationFlags::Elf { r_type: elf::R_PPC_ADDR32 | elf::R_PPC_UADDR32 } => {}
+                    // Position independent
+                    RelocationFlags::Elf {
+                        r_type:
+                            elf::R_PPC_NONE
+                            | elf::R_PPC_REL24
+                            | elf::R_PPC_REL14
+                            | elf::R_PPC_REL32
+                            | R_PPC_TOC16,
+                    } => continue,
+                    flags => bail!(
+                        "Unsupported relocation {:?} @ {:#010X}: PEF fragments must be position independent",
+                        flags,
+                        address
+                    ),
+                }
+                let offset = address as u32 - out_section.base;
+                let target = match reloc.target() {
+                    RelocationTarget::Symbol(symbol_index) => {
+                        let symbol = obj_file.symbol_by_index(symbol_index)?;
+                        if symbol.is_undefined() {
+                            let name = symbol.name()?;
+                            let import =
+                                layout.imports.iter().position(|i| i.name == name).ok_or_else(
+                                    || {
+                                        anyhow!(
+                                            "Undefined symbol {} is not imported in the layout",
+                                            name
+                                        )
+                                    },
+                                )?;
+                            PefRelocTarget::Import(import as u32)
+                        } else if let Some((section, _)) = symbol_location(&symbol)? {
+                            PefRelocTarget::Section(section)
+                        } else {
+                            continue;
+                        }
+                    }
+                    RelocationTarget::Section(section_index) => {
+                        PefRelocTarget::Section(section_for(section_index).ok_or_else(|| {
+                            anyhow!("Relocation @ {:#010X} to ignored section", address)
+                        })?)
+                    }
+                    _ => continue,
+                };
+                if let PefRelocTarget::Section(target) = target {
+                    patches.push((index, offset, sections[target as usize].base));
+                }
+                relocations.push(PefReloc { section: index as u16, offset, target });
+            }
+        }
+    }
+    if relocations.is_empty() {
+        log::warn!("No relocations found, the ELF must be linked with --emit-relocs");
+    }
+    let mut exports = Vec::with_capacity(layout.exports.len());
+    for (name, class) in &layout.exports {
+        let symbol = obj_file
+            .symbol_by_name(name)
+            .filter(|s| s.is_definition())
+            .ok_or_else(|| anyhow!("Exported symbol {} not found", name))?;
+        let (section_index, value) = match symbol_location(&symbol)? {
+            Some((section, offset)) => (section as i16, offset),
+            None => (PEF_SECTION_ABS, symbol.address() as u32),
+        };
+        exports.push(PefExportedSymbol { name: name.clone(), class: *class, value, section_index });
+    }
+
+    // Entry points use the names given to them by `pef split`
+    let entry_point = |name: &str| -> Result<(i32, u32)> {
+        Ok(match obj_file.symbol_by_name(name).filter(|s| s.is_definition()) {
+            Some(symbol) => symbol_location(&symbol)?.map_or((-1, 0), |(s, o)| (s as i32, o)),
+            None => (-1, 0),
+        })
+    };
+    let (mut main_section, mut main_offset) = entry_point("__start")?;
+    let (init_section, init_offset) = entry_point("__initialize")?;
+    let (term_section, term_offset) = entry_point("__terminate")?;
+    if main_section < 0 && obj_file.entry() != 0 {
+        let entry = obj_file.entry() as u32;
+        if let Some((index, section)) = sections
+            .iter()
+            .enumerate()
+            .find(|(_, s)| entry >= s.base && entry < s.base + s.data.total_length)
+        {
+            main_section = index as i32;
+            main_offset = entry - section.base;
+        }
+    }
+    for (index, offset, base) in patches {
+        let data = &mut sections[index].data.data;
+        let word = data
+            .get_mut(offset as usize..offset as usize + 4)
+            .ok_or_else(|| anyhow!("Relocation @ {:#X} in zero-initialized data", offset))?;
+        let value = u32::from_be_bytes(word.try_into()?).wrapping_sub(base);
+        word.copy_from_slice(&value.to_be_bytes());
+    }
+
+    let loader = PefLoader {
+        header: PefLoaderHeader {
+            main_section,
+            main_offset,
+            init_section,
+            init_offset,
+            term_section,
+            term_offset,
+            imported_library_count: 0,
+            total_imported_symbol_count: 0,
+            reloc_section_count: 0,
+            reloc_instr_offset: 0,
+            loader_strings_offset: 0,
+            export_hash_offset: 0,
+            export_hash_table_power: 0,
+            exported_symbol_count: 0,
+        },
+        libraries: layout.libraries.clone(),
+        imports: layout.imports.clone(),
+        exports,
+        relocations,
+    };
+    let header = PefContainerHeader {
+        architecture: PEF_ARCH_PPC,
+        format_version: 1,
+        date_time_stamp: layout.date_time_stamp,
+        old_def_version: layout.old_def_version,
+        old_imp_version: layout.old_imp_version,
+        current_version: layout.current_version,
+        section_count: 0,
+        inst_section_count: 0,
+    };
+
+    let mut out = Vec::new();
+    let section_data = sections.into_iter().map(|s| s.data).collect::<Vec<_>>();
+    write_pef(&mut out, &header, &section_data, &loader)?;
+    let mut w = buf_writer(&args.pef_file)?;
+    w.write_all(&out)?;
+    w.flush()?;
+
+    if let Some(check) = &args.check {
+        let mut original_file = open_file(check, true)
+            .with_context(|| format!("Failed to open original PEF '{}'", check))?;
+        let mut rsrc_file = args.rsrc.as_ref().map(|path| open_file(path, true)).transpose()?;
+        let rsrc = rsrc_file.as_mut().map(|file| file.map()).transpose()?;
+        let original = locate_fragment(original_file.map()?, rsrc, args.fragment.as_deref())?;
+        verify_pef(original, &out)
+            .with_context(|| format!("{} does not match {}", args.pef_file, check))?;
+        log::info!("{} matches {}", args.pef_file, check);
+    }
+    Ok(())
+}
+
+/// Groups the allocated ELF sections into PEF sections. With a layout, each laid out section
+/// starts a new PEF section; otherwise, code and data are split into one section each.
+fn build_sections(
+    obj_file: &object::File,
+    layout: &PefLayout,
+    args: &Args,
+) -> Result<Vec<OutSection>> {
+    let mut elf_sections = obj_file
+        .sections()
+        .filter(|s| {
+            is_alloc(s.flags())
+                && s.size() > 0
+                && !args.deny_sections.iter().any(|n| s.name() == Ok(n.as_str()))
+        })
+        .collect::<Vec<_>>();
+    elf_sections.sort_by_key(|s| s.address());
+
+    let mut out: Vec<OutSection> = Vec::new();
+    for section in elf_sections {
+        let name = section.name()?;
+        let is_code = section.kind() == SectionKind::Text;
+        let laid_out = layout.sections.iter().find(|s| s.name == name);
+        let new_section = if !layout.sections.is_empty() {
+            match laid_out {
+                Some(s) => Some(s.clone()),
+                None if out.is_empty() => {
+                    bail!("Section {} does not follow a section in the PEF layout", name)
+                }
+                None => None,
+            }
+        } else {
+            match out.last() {
+                Some(last) if (last.data.kind == PefSectionKind::Code) == is_code => None,
+                _ => Some(PefLayoutSection {
+                    name: if is_code { ".text" } else { ".data" }.to_string(),
+                    kind: match (is_code, args.pack_data) {
+                        (true, _) => PefSectionKind::Code,
+                        (false, true) => PefSectionKind::PatternInitData,
+                        (false, false) => PefSectionKind::UnpackedData,
+                    },
+                    share_kind: 1, // process share
+                    alignment: 4,
+                    named: false,
+                }),
+            }
+        };
+        if let Some(s) = new_section {
+            out.push(OutSection {
+                name: s.name.clone(),
+                data: PefSectionData {
+                    name: s.named.then(|| s.name.clone()),
+                    kind: s.kind,
+                    share_kind: s.share_kind,
+                    alignment: s.alignment,
+                    data: vec![],
+                    total_length: 0,
+                },
+                base: section.address() as u32,
+                elf_sections: vec![],
+            });
+        }
+        let out_section = out.last_mut().unwrap();
+        let offset = section.address() as u32 - out_section.base;
+        ensure!(
+            offset >= out_section.data.total_length,
+            "Section {} @ {:#010X} overlaps the previous section",
+            name,
+            section.address()
+        );
+        if layout.sections.is_empty() {
+            let align = section.align().max(1).trailing_zeros() as u8;
+            out_section.data.alignment = out_section.data.alignment.max(align);
+        }
+        if section.kind().is_bss() {
+            out_section.data.total_length = offset + section.size() as u32;
+        } else {
+            out_section.data.data.resize(offset as usize, 0);
+            out_section.data.data.extend_from_slice(section.data()?);
+            out_section.data.total_length = out_section.data.data.len() as u32;
+        }
+        out_section.elf_sections.push(section.index());
+    }
+    for section in &layout.sections {
+        if !out.iter().any(|s| s.name == section.name) {
+            log::warn!("Section {} from the PEF layout not found", section.name);
+        }
+    }
+    Ok(out)
+}
+
+/// Compares a rebuilt PEF against the original. On mismatch, reports which parts differ.
+///
+/// Only the layout and contents are compared: toolchains align section contents
+/// and pack pattern-initialized data differently, so the container bytes rarely match.
+fn verify_pef(original: &[u8], rebuilt: &[u8]) -> Result<()> {
+    if original == rebuilt {
+        return Ok(());
+    }
+    let expected = PefFile::parse(original).context("Failed to parse original PEF")?;
+    let actual = PefFile::parse(rebuilt)?;
+    let mut differences = Vec::new();
+    let (eh, rh) = (&expected.header, &actual.header);
+    if (eh.date_time_stamp, eh.old_def_version, eh.old_imp_version, eh.current_version)
+        != (rh.date_time_stamp, rh.old_def_version, rh.old_imp_version, rh.current_version)
+    {
+        differences.push("container header versions differ".to_string());
+    }
+    if eh.inst_section_count != rh.inst_section_count {
+        differences.push(format!(
+            "instantiated section count: expected {}, got {}",
+            eh.inst_section_count, rh.inst_section_count
+        ));
+    }
+    for (index, (es, rs)) in expected.sections.iter().zip(&actual.sections).enumerate() {
+        if !es.is_instantiated() {
+            continue;
+        }
+        if (es.kind, es.share_kind, es.alignment, es.total_length)
+            != (rs.kind, rs.share_kind, rs.alignment, rs.total_length)
+        {
+            differences.push(format!(
+                "section {}: expected {:?} share {} align {} size {:#X}, got {:?} share {} align {} size {:#X}",
+                index,
+                es.kind,
+                es.share_kind,
+                es.alignment,
+                es.total_length,
+                rs.kind,
+                rs.share_kind,
+                rs.alignment,
+                rs.total_length
+            ));
+        }
+        let expected_data = instantiated_data(&expected, original, index)?;
+        let actual_data = instantiated_data(&actual, rebuilt, index)?;
+        if let Some(pos) = expected_data.iter().zip(&actual_data).position(|(a, b)| a != b) {
+            differences.push(format!("section {} contents differ at offset {:#X}", index, pos));
+        } else if expected_data.len() != actual_data.len() {
+            differences.push(format!("section {} initialized size differs", index));
+        } else if es.container_length != rs.container_length {
+            // Same contents, different pattern-initialized encoding
+            log::info!("Section {} is packed differently", index);
+        }
+    }
+    match (&expected.loader, &actual.loader) {
+        (Some(el), Some(al)) => {
+            let libraries = |l: &PefLoader| {
+                l.libraries
+                    .iter()
+                    .map(|lib| (lib.name.clone(), lib.options, lib.current_version))
+                    .collect::<Vec<_>>()
+            };
+            if libraries(el) != libraries(al) {
+                differences.push("imported libraries differ".to_string());
+            }
+            let imports = |l: &PefLoader| {
+                l.imports.iter().map(|i| (i.name.clone(), i.class, i.library)).collect::<Vec<_>>()
+            };
+            if imports(el) != imports(al) {
+                differences.push("imported symbols differ".to_string());
+            }
+            let exports = |l: &PefLoader| {
+                l.exports
+                    .iter()
+                    .map(|e| (e.name.clone(), (e.class, e.value, e.section_index)))
+                    .collect::<HashMap<_, _>>()
+            };
+            if exports(el) != exports(al) {
+                differences.push("exports differ".to_string());
+            }
+            let relocations = |l: &PefLoader| {
+                let mut relocs = l
+                    .relocations
+                    .iter()
+                    .map(|r| (r.section, r.offset, r.target))
+                    .collect::<Vec<_>>();
+                relocs.sort_by_key(|&(section, offset, _)| (section, offset));
+                relocs
+            };
+            let (er, ar) = (relocations(el), relocations(al));
+            if let Some((e, a)) = er.iter().zip(&ar).find(|(e, a)| e != a) {
+                differences.push(format!("relocations differ: expected {:?}, got {:?}", e, a));
+            } else if er.len() != ar.len() {
+                differences.push(format!(
+                    "relocation count: expected {}, got {}",
+                    er.len(),
+                    ar.len()
+                ));
+            }
+            let entries = |l: &PefLoader| {
+                let h = &l.header;
+                (
+                    h.main_section,
+                    h.main_offset,
+                    h.init_section,
+                    h.init_offset,
+                    h.term_section,
+                    h.term_offset,
+                )
+            };
+            if entries(el) != entries(al) {
+                differences.push("entry points differ".to_string());
+            }
+        }
+        (None, None) => {}
+        _ => differences.push("loader section presence differs".to_string()),
+    }
+    if differences.is_empty() {
+        // Same contents, different encoding of the loader section or layout
+        log::info!("Contents match, container encoding differs");
+        return Ok(());
+    }
+    for difference in &differences {
+        log::error!("{}", difference);
+    }
+    bail!("{} difference(s) found", differences.len())
+}
+
+fn instantiated_data(pef: &PefFile, buf: &[u8], index: usize) -> Result<Vec<u8>> {
+    let data = pef.section_data(buf, index)?;
+    if pef.sections[index].is_packed() {
+        unpack_pidata(data)
+    } else {
+        Ok(data.to_vec())
+    }
+}
@@ -6,6 +6,7 @@ pub mod dol;
 pub mod dwarf;
 pub mod elf;
 pub mod elf2dol;
+pub mod elf2pef;
 pub mod map;
 pub mod nlzss;
 pub mod pef;
@@ -13,6 +14,7 @@ pub mod rarc;
 pub mod rel;
 pub mod rso;
 pub mod shasum;
+pub mod sigs;
 pub mod u8_arc;
 pub mod vfs;
 pub mod wad;
@@ -1,11 +1,31 @@
-use std::fs;
+use std::io::Write;
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Result};
 use argp::FromArgs;
 use typed_path::Utf8NativePathBuf;
-use crate::util::config::is_auto_symbol;
-use crate::util::{path::native_path, IntoCow, ToCow, pef::process_pef};
-use crate::analysis::cfa::AnalyzerState;
+
+use crate::{
+    analysis::{
+        cfa::AnalyzerState,
+        pass::{AnalysisPass, FindCfmGlueStubs, FindSaveRestSleds},
+    },
+    cmd::{dol, dol::ProjectConfig, shasum::file_sha1_string},
+    util::{
+        cfrg::{locate_fragment, read_code_resources, CFRG_LOCATION_DATA_FORK},
+        config::is_auto_symbol,
+        file::buf_writer,
+        graph::GraphFormat,
+        mac::fourcc_str,
+        path::native_path,
+        pef::{
+            is_pef, process_pef, write_pef_layout, PefFile, PefLayout, PefRelocTarget,
+            PEF_SECTION_ABS, PEF_SECTION_REEXPORT,
+        },
+        xrefs::analyze_and_write_xrefs,
+        IntoCow, ToCow,
+    },
+    vfs::open_file,
+};
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// Commands for processing PEF files.
@@ -19,6 +39,9 @@ pub struct Args {
 #[argp(subcommand)]
 enum SubCommand {
     Info(InfoArgs),
+    Config(ConfigArgs),
+    Split(SplitArgs),
+    Xrefs(XrefsArgs),
 }
 
 #[derive(FromArgs, PartialEq, Eq, Debug)]
@@ -26,33 +49,183 @@ enum SubCommand {
 #[argp(subcommand, name = "info")]
 pub struct InfoArgs {
     #[argp(positional, from_str_fn(native_path))]
-    /// input file
+    /// input file (data fork)
     input: Utf8NativePathBuf,
+    #[argp(option, from_str_fn(native_path))]
+    /// resource fork, used to locate fragments with its `cfrg` resource
+    rsrc: Option<Utf8NativePathBuf>,
+    #[argp(option, short = 'f')]
+    /// fragment to load, by `cfrg` index or name
+    fragment: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Writes the call graph and data cross-references of a PEF.
+#[argp(subcommand, name = "xrefs")]
+pub struct XrefsArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input file (data fork)
+    input: Utf8NativePathBuf,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output file (.json, or .dot for Graphviz)
+    out_file: Utf8NativePathBuf,
+    #[argp(option)]
+    /// output format: json, dot (default: from the output extension)
+    format: Option<GraphFormat>,
+    #[argp(option, from_str_fn(native_path))]
+    /// symbols file to name functions with
+    symbols: Option<Utf8NativePathBuf>,
+    #[argp(option, from_str_fn(native_path))]
+    /// resource fork, used to locate fragments with its `cfrg` resource
+    rsrc: Option<Utf8NativePathBuf>,
+    #[argp(option, short = 'f')]
+    /// fragment to load, by `cfrg` index or name
+    fragment: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Generates a project configuration file from a PEF.
+#[argp(subcommand, name = "config")]
+pub struct ConfigArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// PEF file
+    object: Utf8NativePathBuf,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output config YAML file
+    out_file: Utf8NativePathBuf,
+    #[argp(option, from_str_fn(native_path))]
+    /// resource fork, used to locate fragments with its `cfrg` resource
+    rsrc: Option<Utf8NativePathBuf>,
+    #[argp(option, short = 'f')]
+    /// fragment to load, by `cfrg` index or name
+    fragment: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Splits a PEF into relocatable objects.
+#[argp(subcommand, name = "split")]
+pub struct SplitArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input configuration file
+    config: Utf8NativePathBuf,
+    #[argp(positional, from_str_fn(native_path))]
+    /// output directory
+    out_dir: Utf8NativePathBuf,
+    #[argp(switch)]
+    /// skip updating splits & symbol files (for build systems)
+    no_update: bool,
+    #[argp(option, short = 'j')]
+    /// number of threads to use (default: number of logical CPUs)
+    jobs: Option<usize>,
 }
 
 pub fn run(args: Args) -> Result<()> {
     match args.command {
         SubCommand::Info(c_args) => info(c_args),
+        SubCommand::Config(c_args) => config(c_args),
+        SubCommand::Split(c_args) => split(c_args),
+        SubCommand::Xrefs(c_args) => xrefs(c_args),
     }
 }
 
-fn info(args: InfoArgs) -> Result<()> {
+fn config(args: ConfigArgs) -> Result<()> {
+    let mut config = ProjectConfig::default();
+    log::info!("Loading {}", args.object);
+    let mut file = open_file(&args.object, true)?;
+    let mut data = file.map()?;
+    let mut rsrc_file = args.rsrc.as_ref().map(|path| open_file(path, true)).transpose()?;
+    let rsrc = rsrc_file.as_mut().map(|file| file.map()).transpose()?;
+    let fragment = locate_fragment(data, rsrc, args.fragment.as_deref())?;
+    ensure!(is_pef(fragment), "{} is not a PEF container", args.object);
+    let pef = PefFile::parse(fragment)?;
+    config.base.object = args.object.with_unix_encoding();
+    config.base.hash = Some(file_sha1_string(&mut data)?);
+    config.rsrc = args.rsrc.as_ref().map(|path| path.with_unix_encoding());
+    config.fragment = args.fragment;
+
+    // Unlike DOLs, PEFs carry exported symbol names, so keep the generated
+    // symbols and splits next to the project configuration.
+    config.base.symbols = Some(args.out_file.with_file_name("symbols.txt").with_unix_encoding());
+    config.base.splits = Some(args.out_file.with_file_name("splits.txt").with_unix_encoding());
+
+    // Container attributes needed by elf2pef to rebuild the PEF
+    let layout_path = args.out_file.with_file_name("pef.txt");
+    let mut out = buf_writer(&layout_path)?;
+    write_pef_layout(&mut out, &PefLayout::from_pef(&pef)?)?;
+    out.flush()?;
+
+    let mut out = buf_writer(&args.out_file)?;
+    serde_yaml::to_writer(&mut out, &config)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// The project flow is shared with DOLs: the loader detects the PEF container.
+fn split(args: SplitArgs) -> Result<()> {
+    dol::split(dol::SplitArgs {
+        config: args.config,
+        out_dir: args.out_dir,
+        no_update: args.no_update,
+        jobs: args.jobs,
+    })
+}
+
+/// Lists the code fragments described by `cfrg` 0, including those that aren't PowerPC.
+fn print_code_resources(rsrc: &[u8]) -> Result<()> {
+    let code = read_code_resources(rsrc)?;
+    if code.code_count > 0 {
+        println!("Classic 68k CODE resources: {}", code.code_count);
+    }
+    let Some(fragments) = &code.fragments else {
+        println!("No cfrg resource\n");
+        return Ok(());
+    };
+    println!("Code fragments:");
+    println!(
+        "\t{: >3} | {: <4} | {: <17} | {: <10} | {: <10} | {: <10}",
+        "#", "Arch", "Usage", "Offset", "Length", "Name"
+    );
+    for (index, member) in fragments.iter().enumerate() {
+        let location = if member.location == CFRG_LOCATION_DATA_FORK {
+            format!("{: <#10X} | {: <#10X}", member.offset, member.length)
+        } else {
+            format!("{: <23}", member.location_str())
+        };
+        println!(
+            "\t{: >3} | {: <4} | {: <17} | {} | {}{}",
+            index,
+            fourcc_str(&member.architecture),
+            member.usage_str(),
+            location,
+            member.name,
+            if member.is_powerpc() { "" } else { " (not PowerPC)" }
+        );
+    }
+    println!();
+    Ok(())
+}
 
-    let in_buf = fs::read(&args.input)
-        .with_context(|| format!("Failed to open input file: '{}'", args.input))?;
-    let mut obj = process_pef(&in_buf, "")?;
-    //apply_signatures(&mut obj)?;
+fn info(args: InfoArgs) -> Result<()> {
+    let mut file = open_file(&args.input, true)?;
+    let data = file.map()?;
+    let mut rsrc_file = args.rsrc.as_ref().map(|path| open_file(path, true)).transpose()?;
+    let rsrc = rsrc_file.as_mut().map(|file| file.map()).transpose()?;
+    if let Some(rsrc) = rsrc {
+        print_code_resources(rsrc)?;
+    }
+    let data = locate_fragment(data, rsrc, args.fragment.as_deref())?;
+    ensure!(
+        is_pef(data),
+        "{} is not a PEF container (for fat or 68k applications, pass the resource fork with --rsrc)",
+        args.input
+    );
+    let (pef, mut obj) = process_pef(data, "")?;
 
     let mut state = AnalyzerState::default();
-    //FindSaveRestSleds::execute(&mut state, &obj)?;
+    FindSaveRestSleds::execute(&mut state, &obj)?;
+    FindCfmGlueStubs::execute(&mut state, &obj)?;
     state.detect_functions(&obj)?;
     state.apply(&mut obj)?;
-    for (addr, _) in state.functions.iter()
-    {
-        log::debug!(
-            "{:#010X}", addr.address, 
-        );
-    }
     log::debug!(
         "Discovered {} functions",
         state.functions.iter().filter(|(_, i)| i.end.is_some()).count()
@@ -94,5 +267,69 @@ fn info(args: InfoArgs) -> Result<()> {
         );
     }
     println!("\n{} discovered functions from exception table", obj.known_functions.len());
+
+    let Some(loader) = &pef.loader else {
+        println!("\nNo loader section");
+        return Ok(());
+    };
+    println!("\nImported libraries:");
+    for library in &loader.libraries {
+        let mut flags = String::new();
+        if library.is_weak() {
+            flags.push_str(" (weak)");
+        }
+        if library.init_before() {
+            flags.push_str(" (init before)");
+        }
+        println!("\t{}{}", library.name, flags);
+        let start = library.first_imported_symbol as usize;
+        let end = start + library.imported_symbol_count as usize;
+        for import in loader.imports.get(start..end).unwrap_or_default() {
+            println!(
+                "\t\t{: <8} {}{}",
+                format!("{:?}", import.class),
+                import.name,
+                if import.weak { " (weak)" } else { "" }
+            );
+        }
+    }
+    println!("\nExports:");
+    println!("\t{: >10} | {: <10} | {: <8} | {: <10}", "Section", "Value", "Class", "Name");
+    for export in &loader.exports {
+        let section_str = match export.section_index {
+            PEF_SECTION_ABS => "ABS".to_string(),
+            PEF_SECTION_REEXPORT => "REEXPORT".to_string(),
+            index => pef
+                .sections
+                .get(index as usize)
+                .and_then(|s| s.name.clone())
+                .unwrap_or_else(|| index.to_string()),
+        };
+        println!(
+            "\t{: >10} | {: <#10X} | {: <8} | {: <10}",
+            section_str,
+            export.value,
+            format!("{:?}", export.class),
+            export.name
+        );
+    }
+    let import_relocs =
+        loader.relocations.iter().filter(|r| matches!(r.target, PefRelocTarget::Import(_))).count();
+    println!(
+        "\n{} relocations ({} to imports, {} to sections)",
+        loader.relocations.len(),
+        import_relocs,
+        loader.relocations.len() - import_relocs
+    );
     Ok(())
 }
+
+fn xrefs(args: XrefsArgs) -> Result<()> {
+    let mut file = open_file(&args.input, true)?;
+    let mut rsrc_file = args.rsrc.as_ref().map(|path| open_file(path, true)).transpose()?;
+    let rsrc = rsrc_file.as_mut().map(|file| file.map()).transpose()?;
+    let data = locate_fragment(file.map()?, rsrc, args.fragment.as_deref())?;
+    ensure!(is_pef(data), "{} is not a PEF container", args.input);
+    let (_, obj) = process_pef(data, "")?;
+    analyze_and_write_xrefs(obj, args.symbols.as_deref(), &args.out_file, args.format)
+}
@@ -37,12 +37,14 @@ use crate::{
         dol::process_dol,
         elf::{to_obj_reloc_kind, write_elf},
         file::{buf_writer, process_rsp, verify_hash, FileIterator},
+        graph::GraphFormat,
         nested::NestedMap,
         path::native_path,
         rel::{
             print_relocations, process_rel, process_rel_header, process_rel_sections, write_rel,
             RelHeader, RelReloc, RelSectionHeader, RelWriteInfo, PERMITTED_SECTIONS,
         },
+        xrefs::analyze_and_write_xrefs,
         IntoCow, ToCow,
     },
     vfs::open_file,
@@ -62,6 +64,7 @@ enum SubCommand {
     Info(InfoArgs),
     Make(MakeArgs),
     Merge(MergeArgs),
+    Xrefs(XrefsArgs),
 }
 
 #[derive(FromArgs, PartialEq, Eq, Debug)]
@@ -76,6 +79,24 @@ pub struct InfoArgs {
     relocations: bool,
 }
 
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Writes the call graph and data cross-references of a REL.
+#[argp(subcommand, name = "xrefs")]
+pub struct XrefsArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// REL file
+    rel_file: Utf8NativePathBuf,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output file (.json, or .dot for Graphviz)
+    out_file: Utf8NativePathBuf,
+    #[argp(option)]
+    /// output format: json, dot (default: from the output extension)
+    format: Option<GraphFormat>,
+    #[argp(option, from_str_fn(native_path))]
+    /// symbols file to name functions with
+    symbols: Option<Utf8NativePathBuf>,
+}
+
 #[derive(FromArgs, PartialEq, Eq, Debug)]
 /// Merges a DOL + REL(s) into an ELF.
 #[argp(subcommand, name = "merge")]
@@ -117,6 +138,7 @@ pub fn run(args: Args) -> Result<()> {
         SubCommand::Info(c_args) => info(c_args),
         SubCommand::Merge(c_args) => merge(c_args),
         SubCommand::Make(c_args) => make(c_args),
+        SubCommand::Xrefs(c_args) => xrefs(c_args),
     }
 }
 
@@ -471,6 +493,12 @@ fn info(args: InfoArgs) -> Result<()> {
     Ok(())
 }
 
+fn xrefs(args: XrefsArgs) -> Result<()> {
+    let mut file = open_file(&args.rel_file, true)?;
+    let (_, module_obj) = process_rel(file.as_mut(), "")?;
+    analyze_and_write_xrefs(module_obj, args.symbols.as_deref(), &args.out_file, args.format)
+}
+
 #[inline]
 const fn align32(x: u32) -> u32 { (x + 31) & !31 }
 
@@ -651,7 +679,10 @@ fn link_relocations(obj: &mut ObjInfo) -> Result<()> {
                     );
                     ins = (ins & !0xfffc) | (diff as u32 & 0xfffc);
                 }
-                ObjRelocKind::PpcEmbSda21 => {
+                ObjRelocKind::PpcRel32 => {
+                    ins = target_address.wrapping_sub(source_address);
+                }
+                ObjRelocKind::PpcEmbSda21 | ObjRelocKind::PpcToc16 => {
                     // Unused in RELs
                 }
             };
@@ -0,0 +1,325 @@
+use std::{collections::BTreeMap, fs, io::Write};
+
+use anyhow::{ensure, Context, Result};
+use argp::FromArgs;
+use itertools::Itertools;
+use ppc750cl::Ins;
+use typed_path::{Utf8NativePath, Utf8NativePathBuf};
+
+use crate::{
+    analysis::{
+        cfa::AnalyzerState,
+        pass::{AnalysisPass, FindCfmGlueStubs, FindSaveRestSleds},
+        signatures::scan_signature_database,
+    },
+    obj::ObjInfo,
+    util::{
+        cfrg::locate_fragment,
+        dol::process_dol,
+        file::{buf_writer, process_rsp},
+        path::{check_path_buf, native_path},
+        pef::{is_pef, process_pef},
+        rel::process_rel,
+        rso::process_rso,
+        signatures::{
+            generate_signatures_from_file, merge_signatures, read_signatures, FunctionSignature,
+            SignatureDatabase,
+        },
+    },
+    vfs::open_file,
+};
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Commands for building and testing function signatures.
+#[argp(subcommand, name = "sigs")]
+pub struct Args {
+    #[argp(subcommand)]
+    command: SubCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand)]
+enum SubCommand {
+    Build(BuildArgs),
+    Merge(MergeArgs),
+    Show(ShowArgs),
+    Scan(ScanArgs),
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Builds signatures for every function in objects or static libraries.
+#[argp(subcommand, name = "build")]
+pub struct BuildArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input object(s), static libraries or directories
+    files: Vec<Utf8NativePathBuf>,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output yml
+    out_file: Utf8NativePathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Merges signature files, keeping every revision of each function.
+#[argp(subcommand, name = "merge")]
+pub struct MergeArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input yml(s)
+    files: Vec<Utf8NativePathBuf>,
+    #[argp(option, short = 'o', from_str_fn(native_path))]
+    /// output yml
+    out_file: Utf8NativePathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Prints the masked instructions and relocations of signatures.
+#[argp(subcommand, name = "show")]
+pub struct ShowArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input yml
+    file: Utf8NativePathBuf,
+    #[argp(option, short = 's')]
+    /// only show signatures for this symbol
+    symbol: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Scans the unnamed functions of a DOL, REL, RSO or PEF for signatures.
+#[argp(subcommand, name = "scan")]
+pub struct ScanArgs {
+    #[argp(positional, from_str_fn(native_path))]
+    /// input DOL, REL, RSO or PEF
+    input: Utf8NativePathBuf,
+    #[argp(positional, from_str_fn(native_path))]
+    /// signature yml(s)
+    signatures: Vec<Utf8NativePathBuf>,
+    #[argp(option, from_str_fn(native_path))]
+    /// resource fork of a PEF application
+    rsrc: Option<Utf8NativePathBuf>,
+    #[argp(option, short = 'f')]
+    /// PEF fragment index or name
+    fragment: Option<String>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    match args.command {
+        SubCommand::Build(c_args) => build(c_args),
+        SubCommand::Merge(c_args) => merge(c_args),
+        SubCommand::Show(c_args) => show(c_args),
+        SubCommand::Scan(c_args) => scan(c_args),
+    }
+}
+
+fn build(args: BuildArgs) -> Result<()> {
+    // Process response files (starting with '@')
+    let files = process_rsp(&args.files)?;
+    let mut inputs = Vec::new();
+    for path in files {
+        if fs::metadata(&path).is_ok_and(|m| m.is_dir()) {
+            collect_objects(&path, &mut inputs)?;
+        } else {
+            inputs.push(path);
+        }
+    }
+    write_signature_database(&inputs, &args.out_file)
+}
+
+/// Recursively collects objects (`.o`) and static libraries (`.a`) in a directory.
+fn collect_objects(dir: &Utf8NativePath, out: &mut Vec<Utf8NativePathBuf>) -> Result<()> {
+    let mut entries = Vec::new();
+    for result in fs::read_dir(dir).with_context(|| format!("Reading directory {}", dir))? {
+        let entry = result.with_context(|| format!("Reading entry in directory {}", dir))?;
+        let Ok(path) = check_path_buf(entry.path()) else {
+            log::warn!("Path is not valid UTF-8: {:?}", entry.path());
+            continue;
+        };
+        entries.push(path);
+    }
+    entries.sort();
+    for path in entries {
+        if fs::metadata(&path).is_ok_and(|m| m.is_dir()) {
+            collect_objects(&path, out)?;
+        } else if matches!(path.extension(), Some("a" | "o")) {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Builds a signature database from objects and static libraries, deduplicating
+/// functions linked into several libraries.
+pub fn write_signature_database(
+    files: &[Utf8NativePathBuf],
+    out_file: &Utf8NativePath,
+) -> Result<()> {
+    let mut signatures: Vec<FunctionSignature> = Vec::new();
+    for path in files {
+        log::info!("Processing {}", path);
+        let generated = generate_signatures_from_file(path)?;
+        log::info!("{} functions in {}", generated.len(), path);
+        signatures.extend(generated);
+    }
+    let db = SignatureDatabase::new(signatures)?;
+    let mut signatures = db.signatures;
+    log::info!("{} unique signatures", signatures.len());
+    signatures.sort_by(|a, b| a.name().cmp(b.name()).then_with(|| a.hash.cmp(&b.hash)));
+    write_signatures(&signatures, out_file)
+}
+
+fn write_signatures(signatures: &[FunctionSignature], out_file: &Utf8NativePath) -> Result<()> {
+    let mut out = buf_writer(out_file)?;
+    serde_yaml::to_writer(&mut out, signatures)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn merge(args: MergeArgs) -> Result<()> {
+    // Process response files (starting with '@')
+    let files = process_rsp(&args.files)?;
+
+    let mut inputs = Vec::new();
+    for path in &files {
+        let signatures = read_signatures(path)?;
+        log::info!("{} signatures in {}", signatures.len(), path);
+        inputs.extend(signatures);
+    }
+    let total = inputs.len();
+    let functions = merge_signatures(inputs);
+
+    let revised = functions.values().filter(|r| r.len() > 1).count();
+    let signatures = functions.into_values().flatten().collect::<Vec<FunctionSignature>>();
+    log::info!(
+        "Merged {} signatures into {} ({} functions with multiple revisions)",
+        total,
+        signatures.len(),
+        revised
+    );
+    write_signatures(&signatures, &args.out_file)
+}
+
+/// Formats an instruction with masked nibbles shown as `?`.
+fn masked_hex(ins: u32, pat: u32) -> String {
+    (0..8)
+        .rev()
+        .map(|i| {
+            let shift = i * 4;
+            if (pat >> shift) & 0xF == 0xF {
+                char::from_digit((ins >> shift) & 0xF, 16).unwrap().to_ascii_uppercase()
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
+fn show(args: ShowArgs) -> Result<()> {
+    let signatures = read_signatures(&args.file)?;
+    let mut found = false;
+    for signature in &signatures {
+        if args.symbol.as_ref().is_some_and(|s| s != signature.name()) {
+            continue;
+        }
+        found = true;
+        print!("{} ({:#X} bytes, hash {})", signature.name(), signature.size(), signature.hash);
+        if let Some(source) = &signature.source {
+            print!(" from {}", source);
+        }
+        println!();
+        for (idx, (ins, pat)) in signature.decode()?.into_iter().enumerate() {
+            let offset = idx as u32 * 4;
+            print!("\t{:#06X}: {}  {}", offset, masked_hex(ins, pat), Ins::new(ins).simplified());
+            if let Some(reloc) = signature.relocations.iter().find(|r| r.offset == offset) {
+                let target = &signature.symbols[reloc.symbol as usize];
+                print!("\t-> {:?} {}", reloc.kind, target.name);
+                match reloc.addend {
+                    0 => {}
+                    addend if addend < 0 => print!("-{:#X}", addend.unsigned_abs()),
+                    addend => print!("+{:#X}", addend),
+                }
+            }
+            println!();
+        }
+        println!();
+    }
+    if let Some(symbol) = &args.symbol {
+        ensure!(found, "No signatures for {} in {}", symbol, args.file);
+    }
+    Ok(())
+}
+
+fn load_object(args: &ScanArgs) -> Result<ObjInfo> {
+    let mut file = open_file(&args.input, true)?;
+    let mut obj = match args.input.extension().map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("rel") => process_rel(file.as_mut(), "")?.1,
+        Some("rso") => process_rso(file.as_mut())?,
+        _ => {
+            let data = file.map()?;
+            let mut rsrc_file = args.rsrc.as_ref().map(|path| open_file(path, true)).transpose()?;
+            let rsrc = rsrc_file.as_mut().map(|file| file.map()).transpose()?;
+            let data = locate_fragment(data, rsrc, args.fragment.as_deref())?;
+            if is_pef(data) {
+                process_pef(data, "")?.1
+            } else {
+                process_dol(data, "")?
+            }
+        }
+    };
+    let mut state = AnalyzerState::default();
+    FindSaveRestSleds::execute(&mut state, &obj)?;
+    FindCfmGlueStubs::execute(&mut state, &obj)?;
+    state.detect_functions(&obj)?;
+    state.apply(&mut obj)?;
+    Ok(obj)
+}
+
+fn scan(args: ScanArgs) -> Result<()> {
+    let db = SignatureDatabase::load(&process_rsp(&args.signatures)?)?;
+    let obj = load_object(&args)?;
+    let result = scan_signature_database(&obj, &db)?;
+
+    let mut libraries = BTreeMap::<&str, usize>::new();
+    println!("Matches:");
+    println!("\t{: <16} | {: <10} | {: <32} | {: <10}", "Address", "Size", "Name", "Source");
+    for &(addr, idx) in &result.matches {
+        let signature = &db.signatures[idx];
+        *libraries.entry(signature.library().unwrap_or("<unknown>")).or_default() += 1;
+        println!(
+            "\t{: <16} | {: <#10X} | {: <32} | {: <10}",
+            addr.to_string(),
+            signature.size(),
+            signature.name(),
+            signature.source.as_deref().unwrap_or("")
+        );
+    }
+    if !result.ambiguous.is_empty() {
+        println!("\nAmbiguous:");
+        for (addr, candidates) in &result.ambiguous {
+            println!("\t{}", addr);
+            for &idx in candidates {
+                let signature = &db.signatures[idx];
+                println!(
+                    "\t\t{} ({})",
+                    signature.name(),
+                    signature.source.as_deref().unwrap_or("unknown source")
+                );
+            }
+        }
+    }
+    if !result.duplicates.is_empty() {
+        println!("\nMatching multiple functions:");
+        for (idx, addrs) in &result.duplicates {
+            println!("\t{}: {}", db.signatures[*idx].name(), addrs.iter().join(", "));
+        }
+    }
+    println!("\nLibraries:");
+    for (library, count) in &libraries {
+        println!("\t{: <32} | {}", library, count);
+    }
+    println!(
+        "\n{} functions matched, {} ambiguous, {} duplicate ({} signatures)",
+        result.matches.len(),
+        result.ambiguous.len(),
+        result.duplicates.len(),
+        db.len()
+    );
+    Ok(())
+}
@@ -96,6 +96,7 @@ enum SubCommand {
     Dwarf(cmd::dwarf::Args),
     Elf(cmd::elf::Args),
     Elf2Dol(cmd::elf2dol::Args),
+    Elf2Pef(cmd::elf2pef::Args),
     Map(cmd::map::Args),
     Nlzss(cmd::nlzss::Args),
     Pef(cmd::pef::Args),
@@ -103,6 +104,7 @@ enum SubCommand {
     Rel(cmd::rel::Args),
     Rso(cmd::rso::Args),
     Shasum(cmd::shasum::Args),
+    Sigs(cmd::sigs::Args),
     U8(cmd::u8_arc::Args),
     Vfs(cmd::vfs::Args),
     Yay0(cmd::yay0::Args),
@@ -173,6 +175,7 @@ fn main() {
         SubCommand::Dwarf(c_args) => cmd::dwarf::run(c_args),
         SubCommand::Elf(c_args) => cmd::elf::run(c_args),
         SubCommand::Elf2Dol(c_args) => cmd::elf2dol::run(c_args),
+        SubCommand::Elf2Pef(c_args) => cmd::elf2pef::run(c_args),
         SubCommand::Map(c_args) => cmd::map::run(c_args),
         SubCommand::Nlzss(c_args) => cmd::nlzss::run(c_args),
         SubCommand::Pef(c_args) => cmd::pef::run(c_args),
@@ -180,6 +183,7 @@ fn main() {
         SubCommand::Rel(c_args) => cmd::rel::run(c_args),
         SubCommand::Rso(c_args) => cmd::rso::run(c_args),
         SubCommand::Shasum(c_args) => cmd::shasum::run(c_args),
+        SubCommand::Sigs(c_args) => cmd::sigs::run(c_args),
         SubCommand::U8(c_args) => cmd::u8_arc::run(c_args),
         SubCommand::Vfs(c_args) => cmd::vfs::run(c_args),
         SubCommand::Yay0(c_args) => cmd::yay0::run(c_args),
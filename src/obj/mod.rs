@@ -12,7 +12,7 @@ use std::{
 
 use anyhow::{anyhow, bail, ensure, Result};
 use objdiff_core::obj::split_meta::SplitMeta;
-pub use relocations::{ObjReloc, ObjRelocKind, ObjRelocations};
+pub use relocations::{ObjReloc, ObjRelocKind, ObjRelocations, R_PPC_TOC16};
 pub use sections::{
     section_kind_for_section, ObjSection, ObjSectionKind, ObjSections, SectionIndex,
 };
@@ -67,6 +67,8 @@ pub struct ObjInfo {
     // Linker generated
     pub sda2_base: Option<u32>,
     pub sda_base: Option<u32>,
+    /// TOC anchor loaded into r2 by PEF/XCOFF code
+    pub toc_base: Option<u32>,
     pub stack_address: Option<u32>,
     pub stack_end: Option<u32>,
     pub db_stack_addr: Option<u32>,
@@ -106,6 +108,7 @@ impl ObjInfo {
             split_meta: None,
             sda2_base: None,
             sda_base: None,
+            toc_base: None,
             stack_address: None,
             stack_end: None,
             db_stack_addr: None,
@@ -335,3 +338,75 @@ impl ObjInfo {
             .sum()
     }
 }
+
+/// Builders for objects in unit tests.
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A section with the given contents, and no relocations or splits.
+    pub(crate) fn section(
+        name: &str,
+        kind: ObjSectionKind,
+        address: u64,
+        data: Vec<u8>,
+    ) -> ObjSection {
+        ObjSection {
+            name: name.to_string(),
+            kind,
+            address,
+            size: data.len() as u64,
+            data,
+            align: 4,
+            elf_index: 0,
+            relocations: Default::default(),
+            virtual_address: None,
+            file_offset: 0,
+            section_known: true,
+            splits: Default::default(),
+        }
+    }
+
+    /// A symbol with a known size.
+    pub(crate) fn symbol(
+        name: &str,
+        kind: ObjSymbolKind,
+        section: SectionIndex,
+        address: u64,
+        size: u64,
+    ) -> ObjSymbol {
+        ObjSymbol {
+            name: name.to_string(),
+            address,
+            section: Some(section),
+            size,
+            size_known: true,
+            kind,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn reloc(kind: ObjRelocKind, target_symbol: SymbolIndex, addend: i64) -> ObjReloc {
+        ObjReloc { kind, target_symbol, addend, module: None }
+    }
+
+    pub(crate) fn split(unit: &str, end: u32) -> ObjSplit {
+        ObjSplit {
+            unit: unit.to_string(),
+            end,
+            align: None,
+            common: false,
+            autogenerated: false,
+            skip: false,
+            rename: None,
+        }
+    }
+
+    pub(crate) fn obj(
+        kind: ObjKind,
+        symbols: Vec<ObjSymbol>,
+        sections: Vec<ObjSection>,
+    ) -> ObjInfo {
+        ObjInfo::new(kind, ObjArchitecture::PowerPc, "test".to_string(), symbols, sections)
+    }
+}
@@ -20,8 +20,15 @@ pub enum ObjRelocKind {
     PpcRel24,
     PpcRel14,
     PpcEmbSda21,
+    /// 16-bit offset from the TOC anchor (r2), used by PEF/XCOFF code.
+    PpcToc16,
+    /// 32-bit PC-relative offset, used by GCC relative jump tables.
+    PpcRel32,
 }
 
+/// Not part of the 32-bit PowerPC ELF ABI, but used by binutils for TOC16 references.
+pub const R_PPC_TOC16: u32 = 255;
+
 impl Serialize for ObjRelocKind {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where S: serde::Serializer {
@@ -33,6 +40,8 @@ impl Serialize for ObjRelocKind {
             ObjRelocKind::PpcRel24 => "rel24",
             ObjRelocKind::PpcRel14 => "rel14",
             ObjRelocKind::PpcEmbSda21 => "sda21",
+            ObjRelocKind::PpcToc16 => "toc16",
+            ObjRelocKind::PpcRel32 => "rel32",
         })
     }
 }
@@ -48,8 +57,10 @@ impl<'de> Deserialize<'de> for ObjRelocKind {
             "PpcRel24" | "rel24" => Ok(ObjRelocKind::PpcRel24),
             "PpcRel14" | "rel14" => Ok(ObjRelocKind::PpcRel14),
             "PpcEmbSda21" | "sda21" => Ok(ObjRelocKind::PpcEmbSda21),
+            "PpcToc16" | "toc16" => Ok(ObjRelocKind::PpcToc16),
+            "PpcRel32" | "rel32" => Ok(ObjRelocKind::PpcRel32),
             s => Err(serde::de::Error::unknown_variant(s, &[
-                "abs", "hi", "ha", "l", "rel24", "rel14", "sda21",
+                "abs", "hi", "ha", "l", "rel24", "rel14", "sda21", "toc16", "rel32",
             ])),
         }
     }
@@ -101,6 +112,11 @@ impl ObjReloc {
                 r_offset &= !3;
                 elf::R_PPC_EMB_SDA21
             }
+            ObjRelocKind::PpcToc16 => {
+                r_offset = (r_offset & !3) + 2;
+                R_PPC_TOC16
+            }
+            ObjRelocKind::PpcRel32 => elf::R_PPC_REL32,
         };
         (r_offset, r_type)
     }
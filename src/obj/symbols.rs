@@ -180,6 +180,14 @@ pub enum ObjDataKind {
     String16Table,
     Int,
     Short,
+    /// Words pointing at other symbols, or null.
+    Pointer,
+    /// Pointers to strings.
+    StringPointerTable,
+    /// C++ virtual table. (RTTI pointer, offset and function pointers)
+    Vtable,
+    /// Array of elements with the given stride.
+    Array(u32),
 }
 
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
@@ -602,7 +610,9 @@ impl ObjSymbol {
 
         match self.kind {
             ObjSymbolKind::Unknown => true,
-            ObjSymbolKind::Function => !matches!(reloc_kind, ObjRelocKind::PpcEmbSda21),
+            ObjSymbolKind::Function => {
+                !matches!(reloc_kind, ObjRelocKind::PpcEmbSda21 | ObjRelocKind::PpcToc16)
+            }
             ObjSymbolKind::Object => {
                 // !matches!(reloc_kind, ObjRelocKind::PpcRel14 | ObjRelocKind::PpcRel24)
                 true // SADX has bugged relocations that jump from .text to .bss, how awful
@@ -643,7 +653,9 @@ pub fn best_match_for_reloc(
                     ObjRelocKind::Absolute
                     | ObjRelocKind::PpcRel24
                     | ObjRelocKind::PpcRel14
-                    | ObjRelocKind::PpcEmbSda21 => 2,
+                    | ObjRelocKind::PpcEmbSda21
+                    | ObjRelocKind::PpcToc16
+                    | ObjRelocKind::PpcRel32 => 2,
                 }
             }
             // Label
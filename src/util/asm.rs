@@ -13,7 +13,7 @@ use crate::{
         ObjDataKind, ObjInfo, ObjReloc, ObjRelocKind, ObjSection, ObjSectionKind, ObjSymbol,
         ObjSymbolKind, SymbolIndex,
     },
-    util::nested::NestedVec,
+    util::{config::is_auto_traceback_table, nested::NestedVec, tbtab::TracebackTable},
 };
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -218,12 +218,12 @@ where W: Write + ?Sized {
             }
 
             // Write end of symbols
-            if let Some(entries) = entries.get(&section_end) {
-                for entry in entries {
+            if let Some(end_entries) = entries.get(&section_end) {
+                for entry in end_entries {
                     if entry.kind != SymbolEntryKind::End {
                         continue;
                     }
-                    write_symbol_entry(w, &symbols, entry, section)?;
+                    write_symbol_entry(w, &symbols, entries, entry, section)?;
                 }
             }
 
@@ -281,15 +281,23 @@ where
     if let Some(reloc) = reloc {
         // Zero out relocations
         ins.code = match reloc.kind {
-            ObjRelocKind::Absolute => 0,
+            ObjRelocKind::Absolute | ObjRelocKind::PpcRel32 => 0,
             ObjRelocKind::PpcEmbSda21 => ins.code & !0x1FFFFF,
             ObjRelocKind::PpcRel24 => ins.code & !0x3FFFFFC,
             ObjRelocKind::PpcRel14 => ins.code & !0xFFFC,
             ObjRelocKind::PpcAddr16Hi | ObjRelocKind::PpcAddr16Ha | ObjRelocKind::PpcAddr16Lo => {
                 ins.code & !0xFFFF
             }
+            // Kept as an explicit r2-relative offset
+            ObjRelocKind::PpcToc16 => ins.code,
         };
     }
+    // The assembler has no TOC-relative relocation for 32-bit PowerPC ELF,
+    // so the target of a TOC access is only named in a comment
+    let (reloc, toc_reloc) = match reloc {
+        Some(reloc) if reloc.kind == ObjRelocKind::PpcToc16 => (None, Some(reloc)),
+        reloc => (reloc, None),
+    };
 
     if ins.op == Opcode::Illegal {
         write!(w, ".4byte {:#010X} /* invalid */", ins.code)?;
@@ -336,6 +344,11 @@ where
                 writing_offset = false;
             }
         }
+        if let Some(reloc) = toc_reloc {
+            write!(w, " /* ")?;
+            write_reloc(w, symbols, reloc)?;
+            write!(w, " */")?;
+        }
     }
     writeln!(w)?;
     Ok(())
@@ -360,6 +373,12 @@ where W: Write + ?Sized {
         ObjRelocKind::PpcEmbSda21 => {
             write!(w, "@sda21")?;
         }
+        ObjRelocKind::PpcToc16 => {
+            write!(w, "@toc")?;
+        }
+        ObjRelocKind::PpcRel32 => {
+            write!(w, " - .")?;
+        }
     }
     Ok(())
 }
@@ -367,6 +386,7 @@ where W: Write + ?Sized {
 fn write_symbol_entry<W>(
     w: &mut W,
     symbols: &[ObjSymbol],
+    entries: &BTreeMap<u32, Vec<SymbolEntry>>,
     entry: &SymbolEntry,
     section: &ObjSection,
 ) -> Result<()>
@@ -418,6 +438,13 @@ where
             if let Some(name) = &symbol.demangled_name {
                 writeln!(w, "# {name}")?;
             }
+            if let Some(tb) = find_traceback_table(symbols, entries, symbol, section) {
+                writeln!(w, "/*")?;
+                for line in tb.describe() {
+                    writeln!(w, " * {}", line)?;
+                }
+                writeln!(w, " */")?;
+            }
             write!(w, ".{symbol_kind} ")?;
             write_symbol_name(w, &symbol.name)?;
             writeln!(w, ", {scope}")?;
@@ -455,6 +482,23 @@ where
     Ok(())
 }
 
+/// Decodes the traceback table following a function, if analysis found one.
+fn find_traceback_table(
+    symbols: &[ObjSymbol],
+    entries: &BTreeMap<u32, Vec<SymbolEntry>>,
+    symbol: &ObjSymbol,
+    section: &ObjSection,
+) -> Option<TracebackTable> {
+    if symbol.kind != ObjSymbolKind::Function || symbol.size == 0 {
+        return None;
+    }
+    let end = (symbol.address + symbol.size) as u32;
+    entries.get(&end)?.iter().find(|e| {
+        e.kind == SymbolEntryKind::Start && is_auto_traceback_table(&symbols[e.index as usize])
+    })?;
+    TracebackTable::read(&section.data, (end - section.address as u32) as usize)
+}
+
 fn parse_extab(symbols: &[ObjSymbol], entry: &SymbolEntry, section: &ObjSection) -> Result<String> {
     let symbol = &symbols[entry.index as usize];
     let data = section.symbol_data(symbol)?;
@@ -487,6 +531,8 @@ where
     let mut current_address = start;
     let mut current_symbol_kind = ObjSymbolKind::Unknown;
     let mut current_data_kind = ObjDataKind::Unknown;
+    // Start and stride of the current array, for element markers
+    let mut current_array = None;
     let mut entry = entry_iter.next();
     let mut reloc = reloc_iter.next();
     let mut begin = true;
@@ -501,11 +547,23 @@ where
                     if entry.kind == SymbolEntryKind::End && begin {
                         continue;
                     }
-                    write_symbol_entry(w, symbols, entry, section)?;
+                    write_symbol_entry(w, symbols, entries, entry, section)?;
                 }
                 current_symbol_kind = find_symbol_kind(current_symbol_kind, symbols, vec)?;
                 current_data_kind = find_data_kind(current_data_kind, symbols, vec)
                     .with_context(|| format!("At address {:#010X}", sym_addr))?;
+                current_array = match current_data_kind {
+                    ObjDataKind::Array(stride)
+                        if vec.iter().any(|e| {
+                            e.kind == SymbolEntryKind::Start
+                                && symbols[e.index as usize].data_kind == current_data_kind
+                        }) =>
+                    {
+                        Some((sym_addr, stride))
+                    }
+                    ObjDataKind::Array(_) => current_array,
+                    _ => None,
+                };
                 entry = entry_iter.next();
             } else if current_address > sym_addr {
                 let dbg_symbols = vec.iter().map(|e| &symbols[e.index as usize]).collect_vec();
@@ -518,6 +576,13 @@ where
         }
         begin = false;
 
+        if let Some((array_start, stride)) = current_array {
+            let offset = current_address - array_start;
+            if offset % stride == 0 {
+                writeln!(w, "\t# [{}]", offset / stride)?;
+            }
+        }
+
         let symbol_kind = if current_symbol_kind == ObjSymbolKind::Unknown {
             match section.kind {
                 ObjSectionKind::Code => ObjSymbolKind::Function,
@@ -550,6 +615,13 @@ where
             (Some((addr, _)), None) | (None, Some((addr, _))) => *addr,
             (None, None) => end,
         };
+        // Split data at array element boundaries
+        let until = match current_array {
+            Some((array_start, stride)) => {
+                min(until, array_start + ((current_address - array_start) / stride + 1) * stride)
+            }
+            None => until,
+        };
         ensure!(
             until > current_address,
             "Invalid address range: {}..{}\n\tNext entry: {:?}\n\tNext reloc: {:?}",
@@ -738,7 +810,14 @@ where W: Write + ?Sized {
     }
     let chunk_size = match data_kind {
         ObjDataKind::Byte2 | ObjDataKind::Short => 2,
-        ObjDataKind::Unknown | ObjDataKind::Byte4 | ObjDataKind::Float | ObjDataKind::Int => 4,
+        ObjDataKind::Unknown
+        | ObjDataKind::Byte4
+        | ObjDataKind::Float
+        | ObjDataKind::Int
+        | ObjDataKind::Pointer
+        | ObjDataKind::StringPointerTable
+        | ObjDataKind::Vtable
+        | ObjDataKind::Array(_) => 4,
         ObjDataKind::Byte | ObjDataKind::Byte8 | ObjDataKind::Double => 8,
         ObjDataKind::String
         | ObjDataKind::String16
@@ -773,6 +852,13 @@ where W: Write + ?Sized {
                         writeln!(w, "\t.float {data}")?;
                     }
                 }
+                4 if matches!(
+                    data_kind,
+                    ObjDataKind::Pointer | ObjDataKind::StringPointerTable | ObjDataKind::Vtable
+                ) && chunk == [0; 4] =>
+                {
+                    writeln!(w, "\t.4byte 0")?;
+                }
                 4 if data_kind == ObjDataKind::Int => {
                     let data = i32::from_be_bytes(chunk.try_into().unwrap());
                     writeln!(w, "\t.int {data}")?;
@@ -831,6 +917,12 @@ where
             writeln!(w)?;
             Ok(reloc_address + 4)
         }
+        ObjRelocKind::PpcRel32 => {
+            write!(w, "\t.4byte ")?;
+            write_reloc(w, symbols, reloc)?;
+            writeln!(w)?;
+            Ok(reloc_address + 4)
+        }
         _ => Err(anyhow!(
             "Unsupported data relocation type {:?} @ {:#010X}",
             reloc.kind,
@@ -865,7 +957,7 @@ where
                     if entry.kind == SymbolEntryKind::End && begin {
                         continue;
                     }
-                    write_symbol_entry(w, symbols, entry, section)?;
+                    write_symbol_entry(w, symbols, entries, entry, section)?;
                 }
                 entry = entry_iter.next();
             }
@@ -990,3 +1082,31 @@ where W: Write + ?Sized {
 fn is_illegal_instruction(code: u32) -> bool {
     matches!(code, 0x43000000 /* bc 24, lt, 0x0 */ | 0xB8030000 /* lmw r0, 0(r3) */)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_ins_string(code: u32, symbols: &[ObjSymbol], reloc: Option<&ObjReloc>) -> String {
+        let mut out = vec![];
+        write_ins(&mut out, symbols, 0x80003100, Ins::new(code), reloc, 0x100, None).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_toc16() {
+        let symbols = [ObjSymbol { name: "lbl_80010010".to_string(), ..Default::default() }];
+        let reloc = |kind| ObjReloc { kind, target_symbol: 0, addend: 0, module: None };
+
+        // lwz r3, 0x10(r2)
+        let out = write_ins_string(0x80620010, &symbols, Some(&reloc(ObjRelocKind::PpcToc16)));
+        assert!(out.ends_with("\tlwz r3, 0x10(r2) /* lbl_80010010@toc */\n"), "{}", out);
+        // addi r5, r2, 0x20
+        let out = write_ins_string(0x38A20020, &symbols, Some(&reloc(ObjRelocKind::PpcToc16)));
+        assert!(out.ends_with("\taddi r5, r2, 0x20 /* lbl_80010010@toc */\n"), "{}", out);
+
+        // Other relocations replace the operand
+        let out = write_ins_string(0x38630000, &symbols, Some(&reloc(ObjRelocKind::PpcAddr16Lo)));
+        assert!(out.ends_with("\taddi r3, r3, lbl_80010010@l\n"), "{}", out);
+    }
+}
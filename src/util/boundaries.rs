@@ -0,0 +1,648 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    io::Write,
+};
+
+use anyhow::Result;
+
+use crate::{
+    analysis::{cfa::SectionAddress, read_address},
+    obj::{ObjDataKind, ObjInfo, ObjSection, ObjSectionKind, ObjSplit, ObjSymbol, ObjSymbolKind},
+    util::split::{end_for_section, is_linker_generated_label, is_linker_generated_object},
+};
+
+/// Boundaries below this confidence don't start a new unit.
+const MIN_CONFIDENCE: f32 = 0.5;
+
+/// A signal suggesting that a translation unit starts at an address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BoundarySignal {
+    /// A `.ctors` entry. Each TU with static initializers has exactly one.
+    Ctors,
+    /// The end of a static initializer function, which is emitted last in its TU.
+    StaticInitializer,
+    /// A constant that was already seen since the previous boundary.
+    /// Constants are pooled per TU, so a repeat belongs to another TU.
+    RepeatedConstant,
+    /// The end of a string literal pool, which is emitted at the end of the TU's data.
+    StringPool,
+    /// Zero padding up to an 8-byte or larger alignment.
+    AlignmentPadding,
+    /// The code that first references the data changes to another unit.
+    ReferenceOrder,
+    /// The start or end of a run of functions with exception tables.
+    ExtabGroup,
+}
+
+impl BoundarySignal {
+    pub fn confidence(self) -> f32 {
+        match self {
+            BoundarySignal::Ctors => 0.9,
+            BoundarySignal::StaticInitializer => 0.8,
+            BoundarySignal::RepeatedConstant => 0.7,
+            BoundarySignal::StringPool => 0.6,
+            BoundarySignal::AlignmentPadding => 0.5,
+            BoundarySignal::ReferenceOrder => 0.4,
+            BoundarySignal::ExtabGroup => 0.3,
+        }
+    }
+}
+
+impl fmt::Display for BoundarySignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoundarySignal::Ctors => write!(f, "ctors"),
+            BoundarySignal::StaticInitializer => write!(f, "static-initializer"),
+            BoundarySignal::RepeatedConstant => write!(f, "repeated-constant"),
+            BoundarySignal::StringPool => write!(f, "string-pool"),
+            BoundarySignal::AlignmentPadding => write!(f, "alignment-padding"),
+            BoundarySignal::ReferenceOrder => write!(f, "reference-order"),
+            BoundarySignal::ExtabGroup => write!(f, "extab-group"),
+        }
+    }
+}
+
+/// Combines independent signals into a single confidence.
+fn combined_confidence(signals: &BTreeSet<BoundarySignal>) -> f32 {
+    1.0 - signals.iter().map(|s| 1.0 - s.confidence()).product::<f32>()
+}
+
+/// A range of a section proposed as part of a unit.
+#[derive(Debug, Clone)]
+pub struct RangeSuggestion {
+    pub start: SectionAddress,
+    pub end: u32,
+    /// Confidence of the weaker of the range's two boundaries.
+    pub confidence: f32,
+    pub signals: BTreeSet<BoundarySignal>,
+}
+
+/// A proposed unit, or additional ranges for an existing unit.
+#[derive(Debug, Clone)]
+pub struct UnitSuggestion {
+    pub name: String,
+    /// Whether the unit already exists in the splits.
+    pub existing: bool,
+    pub ranges: Vec<RangeSuggestion>,
+}
+
+/// Sized symbols of a section in address order, one per address.
+fn section_symbols(obj: &ObjInfo, section_index: u32) -> Vec<&ObjSymbol> {
+    let mut symbols: Vec<&ObjSymbol> = vec![];
+    for (_, symbol) in obj.symbols.for_section(section_index) {
+        if !matches!(symbol.kind, ObjSymbolKind::Function | ObjSymbolKind::Object)
+            || !symbol.size_known
+            || symbol.size == 0
+            || is_linker_generated_label(&symbol.name)
+            || is_linker_generated_object(&symbol.name)
+        {
+            continue;
+        }
+        if symbols.last().is_some_and(|last| last.address == symbol.address) {
+            continue;
+        }
+        symbols.push(symbol);
+    }
+    symbols
+}
+
+/// Last user-defined split starting at or before `address`.
+/// Autogenerated splits are only guesses themselves, so they're ignored.
+fn user_split_before(section: &ObjSection, address: u32) -> Option<(u32, &ObjSplit)> {
+    section.splits.for_range(..=address).filter(|(_, split)| !split.autogenerated).next_back()
+}
+
+/// Whether a boundary at `address` would be new, i.e. it's not covered by a
+/// user-defined split and isn't already the start of one.
+fn is_unsplit(section: &ObjSection, address: u32) -> bool {
+    match user_split_before(section, address) {
+        Some((start, split)) => start != address && split.end != 0 && split.end <= address,
+        None => true,
+    }
+}
+
+/// Start of the user-defined split or unsplit gap containing `address`.
+fn known_boundary_before(section: &ObjSection, address: u32) -> u32 {
+    user_split_before(section, address).map_or(section.address as u32, |(start, split)| {
+        if split.end != 0 && split.end <= address {
+            split.end
+        } else {
+            start
+        }
+    })
+}
+
+struct BoundaryFinder<'a> {
+    obj: &'a ObjInfo,
+    boundaries: BTreeMap<SectionAddress, BTreeSet<BoundarySignal>>,
+}
+
+impl BoundaryFinder<'_> {
+    fn add(&mut self, address: SectionAddress, signal: BoundarySignal) {
+        let section = &self.obj.sections[address.section];
+        if address.address <= section.address as u32 || !is_unsplit(section, address.address) {
+            return;
+        }
+        self.boundaries.entry(address).or_default().insert(signal);
+    }
+
+    fn confidence(&self, address: SectionAddress) -> f32 {
+        self.boundaries.get(&address).map_or(0.0, combined_confidence)
+    }
+
+    /// Each `.ctors` entry belongs to a different TU, and the static initializer it
+    /// points to is the last function of that TU.
+    fn find_ctors(&mut self) -> Result<()> {
+        let obj = self.obj;
+        let Some((section_index, section)) = obj.sections.by_name(".ctors")? else {
+            return Ok(());
+        };
+        let end = end_for_section(obj, section_index)?;
+        let mut address = section.address as u32;
+        while address + 4 <= end.address {
+            self.add(SectionAddress::new(section_index, address), BoundarySignal::Ctors);
+            if let Ok(target) = read_address(obj, section, address) {
+                if let Some((_, function)) = obj.symbols.kind_at_section_address(
+                    target.section,
+                    target.address,
+                    ObjSymbolKind::Function,
+                )? {
+                    if function.size_known && function.size > 0 {
+                        self.add(target + function.size as u32, BoundarySignal::StaticInitializer);
+                    }
+                }
+            }
+            address += 4;
+        }
+        Ok(())
+    }
+
+    /// String pools end their TU's data, and constants are deduplicated within a TU.
+    fn find_constant_pools(&mut self) {
+        let obj = self.obj;
+        for (section_index, section) in obj.sections.iter() {
+            if !matches!(section.kind, ObjSectionKind::Data | ObjSectionKind::ReadOnlyData) {
+                continue;
+            }
+            let symbols = section_symbols(obj, section_index);
+            let mut seen = HashSet::<&[u8]>::new();
+            let mut last_known = known_boundary_before(section, section.address as u32);
+            for (i, symbol) in symbols.iter().enumerate() {
+                let address = SectionAddress::new(section_index, symbol.address as u32);
+                let known = known_boundary_before(section, address.address);
+                if known != last_known {
+                    seen.clear();
+                    last_known = known;
+                }
+                let is_string =
+                    matches!(symbol.data_kind, ObjDataKind::String | ObjDataKind::String16);
+                let is_constant = is_string
+                    || matches!(symbol.data_kind, ObjDataKind::Float | ObjDataKind::Double)
+                    || (section.name == ".sdata2" && matches!(symbol.size, 4 | 8));
+                if is_constant {
+                    if let Ok(data) = section.symbol_data(symbol) {
+                        if !seen.insert(data) {
+                            self.add(address, BoundarySignal::RepeatedConstant);
+                            seen.clear();
+                            seen.insert(data);
+                        }
+                    }
+                }
+                if let Some(next) = symbols.get(i + 1) {
+                    let next_is_string =
+                        matches!(next.data_kind, ObjDataKind::String | ObjDataKind::String16);
+                    if is_string && !next_is_string {
+                        self.add(
+                            SectionAddress::new(section_index, next.address as u32),
+                            BoundarySignal::StringPool,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Zero padding up to a larger alignment is usually the start of a TU's section.
+    fn find_alignment_padding(&mut self) {
+        let obj = self.obj;
+        for (section_index, section) in obj.sections.iter() {
+            let symbols = section_symbols(obj, section_index);
+            for (prev, next) in symbols.iter().zip(symbols.iter().skip(1)) {
+                let prev_end = (prev.address + prev.size) as u32;
+                let next_start = next.address as u32;
+                if next_start < prev_end + 4 || next_start % 8 != 0 {
+                    continue;
+                }
+                let is_padding = section.kind == ObjSectionKind::Bss
+                    || section
+                        .data_range(prev_end, next_start)
+                        .is_ok_and(|data| data.iter().all(|&b| b == 0));
+                if is_padding {
+                    self.add(
+                        SectionAddress::new(section_index, next_start),
+                        BoundarySignal::AlignmentPadding,
+                    );
+                }
+            }
+        }
+    }
+
+    /// TUs compiled with exceptions enabled have a run of functions with extabindex entries.
+    fn find_extab_groups(&mut self) -> Result<()> {
+        let obj = self.obj;
+        let Some((extabindex_index, extabindex)) = obj.sections.by_name("extabindex")? else {
+            return Ok(());
+        };
+        let end = end_for_section(obj, extabindex_index)?;
+        let mut functions = BTreeSet::new();
+        let mut address = extabindex.address as u32;
+        while address + 12 <= end.address {
+            if let Ok(function) = read_address(obj, extabindex, address) {
+                functions.insert(function);
+            }
+            address += 12;
+        }
+        for (section_index, _) in obj.sections.by_kind(ObjSectionKind::Code) {
+            let symbols = section_symbols(obj, section_index);
+            for (prev, next) in symbols.iter().zip(symbols.iter().skip(1)) {
+                let prev_addr = SectionAddress::new(section_index, prev.address as u32);
+                let next_addr = SectionAddress::new(section_index, next.address as u32);
+                if functions.contains(&prev_addr) != functions.contains(&next_addr) {
+                    self.add(next_addr, BoundarySignal::ExtabGroup);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Code unit containing `address`: the closest known or confident boundary before it.
+    fn code_unit_start(&self, address: SectionAddress) -> SectionAddress {
+        let section = &self.obj.sections[address.section];
+        let known =
+            SectionAddress::new(address.section, known_boundary_before(section, address.address));
+        let inferred = self
+            .boundaries
+            .range(known..=address)
+            .filter(|(_, signals)| combined_confidence(signals) >= MIN_CONFIDENCE)
+            .map(|(&addr, _)| addr)
+            .next_back();
+        inferred.unwrap_or(known)
+    }
+
+    /// First code reference to each data symbol, by address.
+    fn first_references(&self) -> BTreeMap<SectionAddress, SectionAddress> {
+        let obj = self.obj;
+        let mut references = BTreeMap::<SectionAddress, SectionAddress>::new();
+        for (section_index, section) in obj.sections.by_kind(ObjSectionKind::Code) {
+            for (source, reloc) in section.relocations.iter() {
+                let target = &obj.symbols[reloc.target_symbol];
+                let Some(target_section) = target.section else {
+                    continue;
+                };
+                if obj.sections[target_section].kind == ObjSectionKind::Code {
+                    continue;
+                }
+                references
+                    .entry(SectionAddress::new(target_section, target.address as u32))
+                    .or_insert(SectionAddress::new(section_index, source));
+            }
+        }
+        references
+    }
+
+    /// Data emitted in the same order as the code that uses it changes units where
+    /// the first referencing code changes units.
+    fn find_reference_order(&mut self) -> BTreeMap<SectionAddress, SectionAddress> {
+        let obj = self.obj;
+        let references = self.first_references();
+        let mut data_units = BTreeMap::new();
+        for (section_index, section) in obj.sections.iter() {
+            if section.kind == ObjSectionKind::Code {
+                continue;
+            }
+            let mut prev_unit = None;
+            for symbol in section_symbols(obj, section_index) {
+                let address = SectionAddress::new(section_index, symbol.address as u32);
+                let Some(&source) = references.get(&address) else {
+                    continue;
+                };
+                let unit = self.code_unit_start(source);
+                data_units.insert(address, unit);
+                if prev_unit.is_some_and(|prev| prev != unit) {
+                    self.add(address, BoundarySignal::ReferenceOrder);
+                }
+                prev_unit = Some(unit);
+            }
+        }
+        data_units
+    }
+}
+
+/// Unsplit ranges of a section: gaps between user-defined splits.
+fn unsplit_ranges(obj: &ObjInfo, section_index: u32) -> Result<Vec<(u32, u32)>> {
+    let section = &obj.sections[section_index];
+    let end = end_for_section(obj, section_index)?.address;
+    let mut ranges = vec![];
+    let mut current = section.address as u32;
+    for (start, split) in section.splits.iter().filter(|(_, split)| !split.autogenerated) {
+        if start > current {
+            ranges.push((current, start.min(end)));
+        }
+        current = current.max(if split.end != 0 { split.end } else { end });
+    }
+    if current < end {
+        ranges.push((current, end));
+    }
+    Ok(ranges)
+}
+
+fn unit_name(obj: &ObjInfo, address: SectionAddress) -> String {
+    if obj.module_id == 0 {
+        format!("suggested_{:08X}", address.address)
+    } else {
+        format!(
+            "suggested_{}_{}_{:X}",
+            obj.module_id,
+            obj.sections[address.section].name.trim_start_matches('.'),
+            address.address
+        )
+    }
+}
+
+/// Infers translation unit boundaries within the ranges not covered by user-defined
+/// splits, and proposes units for them.
+///
+/// Code ranges are cut at confident boundaries and become new units. Data ranges are
+/// added to the unit whose code first references them, or become units of their own.
+/// Only ranges delimited by at least one inferred boundary are proposed.
+pub fn suggest_units(obj: &ObjInfo) -> Result<Vec<UnitSuggestion>> {
+    let mut finder = BoundaryFinder { obj, boundaries: BTreeMap::new() };
+    finder.find_ctors()?;
+    finder.find_constant_pools();
+    finder.find_alignment_padding();
+    finder.find_extab_groups()?;
+    let data_units = finder.find_reference_order();
+
+    let mut units = BTreeMap::<String, UnitSuggestion>::new();
+    let mut code_units = BTreeMap::<SectionAddress, String>::new();
+    // Code sections first, so data can refer to the new code units
+    let sections = obj
+        .sections
+        .by_kind(ObjSectionKind::Code)
+        .chain(obj.sections.iter().filter(|(_, s)| s.kind != ObjSectionKind::Code))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    for section_index in sections {
+        let section = &obj.sections[section_index];
+        for (range_start, range_end) in unsplit_ranges(obj, section_index)? {
+            let cuts = finder
+                .boundaries
+                .range(
+                    SectionAddress::new(section_index, range_start + 1)
+                        ..SectionAddress::new(section_index, range_end),
+                )
+                .filter(|(_, signals)| combined_confidence(signals) >= MIN_CONFIDENCE)
+                .map(|(&addr, _)| addr.address)
+                .collect::<Vec<_>>();
+            let starts = std::iter::once(range_start).chain(cuts.iter().copied());
+            let ends = cuts.iter().copied().chain(std::iter::once(range_end));
+            for (start, end) in starts.zip(ends) {
+                let start_addr = SectionAddress::new(section_index, start);
+                let end_addr = SectionAddress::new(section_index, end);
+                let start_confidence =
+                    if start == range_start { 1.0 } else { finder.confidence(start_addr) };
+                let end_confidence =
+                    if end == range_end { 1.0 } else { finder.confidence(end_addr) };
+                if start == range_start && end == range_end {
+                    // Nothing was inferred
+                    continue;
+                }
+                let mut signals = BTreeSet::new();
+                for addr in [start_addr, end_addr] {
+                    if let Some(s) = finder.boundaries.get(&addr) {
+                        signals.extend(s.iter().copied());
+                    }
+                }
+                let range = RangeSuggestion {
+                    start: start_addr,
+                    end,
+                    confidence: start_confidence.min(end_confidence),
+                    signals,
+                };
+
+                let (name, existing) = if section.kind == ObjSectionKind::Code {
+                    let name = unit_name(obj, start_addr);
+                    code_units.insert(start_addr, name.clone());
+                    (name, false)
+                } else {
+                    // Assign to the unit of the code that references the range the most
+                    let mut counts = BTreeMap::<SectionAddress, usize>::new();
+                    for (_, &unit) in data_units.range(start_addr..end_addr) {
+                        *counts.entry(unit).or_default() += 1;
+                    }
+                    let code_unit = counts.into_iter().max_by_key(|&(_, count)| count);
+                    match code_unit {
+                        Some((unit, _)) => match code_units.get(&unit) {
+                            Some(name) => (name.clone(), false),
+                            None => {
+                                match obj.sections[unit.section].splits.for_address(unit.address) {
+                                    Some((_, split)) if !split.autogenerated => {
+                                        (split.unit.clone(), true)
+                                    }
+                                    _ => (unit_name(obj, start_addr), false),
+                                }
+                            }
+                        },
+                        None => (unit_name(obj, start_addr), false),
+                    }
+                };
+                units
+                    .entry(name.clone())
+                    .or_insert_with(|| UnitSuggestion { name, existing, ranges: vec![] })
+                    .ranges
+                    .push(range);
+            }
+        }
+    }
+    let units = units.into_values().collect::<Vec<_>>();
+    if !units.is_empty() {
+        log::info!("Suggested {} units from inferred boundaries", units.len());
+    }
+    Ok(units)
+}
+
+/// Writes the suggestions in the `splits.txt` format, with the confidence and
+/// signals of each range as comments.
+pub fn write_unit_suggestions<W>(w: &mut W, obj: &ObjInfo, units: &[UnitSuggestion]) -> Result<()>
+where W: Write + ?Sized {
+    writeln!(w, "// Suggested translation units, inferred from the data layout.")?;
+    writeln!(w, "// Review each range before copying it into splits.txt.")?;
+    for unit in units {
+        if unit.existing {
+            writeln!(w, "\n// Additional ranges for an existing unit")?;
+            writeln!(w, "{}:", unit.name)?;
+        } else {
+            writeln!(w, "\n{}:", unit.name)?;
+        }
+        for range in &unit.ranges {
+            let signals = range.signals.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            writeln!(w, "\t// confidence {:.2}: {}", range.confidence, signals.join(", "))?;
+            writeln!(
+                w,
+                "\t{:<11} start:{:#010X} end:{:#010X}",
+                obj.sections[range.start.section].name, range.start.address, range.end
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, reloc, section, split, symbol},
+        ObjKind, ObjRelocKind,
+    };
+
+    fn words(words: &[u32]) -> Vec<u8> { words.iter().flat_map(|w| w.to_be_bytes()).collect() }
+
+    /// Suggested units as (name, existing, [(start, end)]).
+    fn summary(units: &[UnitSuggestion]) -> Vec<(&str, bool, Vec<(u32, u32)>)> {
+        units
+            .iter()
+            .map(|unit| {
+                let ranges = unit.ranges.iter().map(|r| (r.start.address, r.end)).collect();
+                (unit.name.as_str(), unit.existing, ranges)
+            })
+            .collect()
+    }
+
+    fn signals(units: &[UnitSuggestion], start: u32) -> Vec<BoundarySignal> {
+        let range = units.iter().flat_map(|u| &u.ranges).find(|r| r.start.address == start);
+        range.unwrap().signals.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_ctors_static_initializer() {
+        let obj = obj(
+            ObjKind::Executable,
+            vec![
+                symbol("__sinit_a", ObjSymbolKind::Function, 0, 0x80003100, 0x10),
+                symbol("fn_b", ObjSymbolKind::Function, 0, 0x80003110, 0x10),
+                symbol("__sinit_b", ObjSymbolKind::Function, 0, 0x80003120, 0x10),
+            ],
+            vec![
+                section(".text", ObjSectionKind::Code, 0x80003100, vec![0; 0x30]),
+                section(
+                    ".ctors",
+                    ObjSectionKind::ReadOnlyData,
+                    0x80005000,
+                    words(&[0x80003100, 0x80003120, 0]),
+                ),
+            ],
+        );
+        let units = suggest_units(&obj).unwrap();
+        assert_eq!(summary(&units), vec![
+            ("suggested_80003100", false, vec![(0x80003100, 0x80003110)]),
+            ("suggested_80003110", false, vec![(0x80003110, 0x80003130)]),
+            ("suggested_80005000", false, vec![(0x80005000, 0x80005004)]),
+            ("suggested_80005004", false, vec![(0x80005004, 0x80005008)]),
+        ]);
+        assert_eq!(signals(&units, 0x80003110), vec![BoundarySignal::StaticInitializer]);
+        assert_eq!(signals(&units, 0x80005004), vec![BoundarySignal::Ctors]);
+    }
+
+    #[test]
+    fn test_repeated_constant() {
+        let obj = obj(
+            ObjKind::Executable,
+            (0..4)
+                .map(|i| symbol(&format!("c{i}"), ObjSymbolKind::Object, 0, 0x80006000 + i * 4, 4))
+                .collect(),
+            vec![section(
+                ".sdata2",
+                ObjSectionKind::ReadOnlyData,
+                0x80006000,
+                // 1.0, 2.0, 1.0, 3.0
+                words(&[0x3F800000, 0x40000000, 0x3F800000, 0x40400000]),
+            )],
+        );
+        let units = suggest_units(&obj).unwrap();
+        assert_eq!(summary(&units), vec![
+            ("suggested_80006000", false, vec![(0x80006000, 0x80006008)]),
+            ("suggested_80006008", false, vec![(0x80006008, 0x80006010)]),
+        ]);
+        assert_eq!(signals(&units, 0x80006008), vec![BoundarySignal::RepeatedConstant]);
+    }
+
+    #[test]
+    fn test_string_pool() {
+        let mut symbols = vec![
+            symbol("s0", ObjSymbolKind::Object, 0, 0x80007000, 4),
+            symbol("s1", ObjSymbolKind::Object, 0, 0x80007004, 4),
+            symbol("data", ObjSymbolKind::Object, 0, 0x80007008, 4),
+        ];
+        symbols[0].data_kind = ObjDataKind::String;
+        symbols[1].data_kind = ObjDataKind::String;
+        let mut data = b"abc\0xy\0\0".to_vec();
+        data.extend([1, 2, 3, 4]);
+        let obj = obj(ObjKind::Executable, symbols, vec![section(
+            ".data",
+            ObjSectionKind::Data,
+            0x80007000,
+            data,
+        )]);
+        let units = suggest_units(&obj).unwrap();
+        assert_eq!(summary(&units), vec![
+            ("suggested_80007000", false, vec![(0x80007000, 0x80007008)]),
+            ("suggested_80007008", false, vec![(0x80007008, 0x8000700C)]),
+        ]);
+        assert_eq!(signals(&units, 0x80007008), vec![BoundarySignal::StringPool]);
+    }
+
+    #[test]
+    fn test_alignment_padding() {
+        let mut data = section(".data", ObjSectionKind::Data, 0x80008000, words(&[1, 0, 2]));
+        // Autogenerated splits don't count as known boundaries
+        data.splits
+            .push(0x80008008, ObjSplit { autogenerated: true, ..split("auto.c", 0x8000800C) });
+        let obj = obj(
+            ObjKind::Executable,
+            vec![
+                symbol("a", ObjSymbolKind::Object, 0, 0x80008000, 4),
+                symbol("b", ObjSymbolKind::Object, 0, 0x80008008, 4),
+            ],
+            vec![data],
+        );
+        let units = suggest_units(&obj).unwrap();
+        assert_eq!(summary(&units), vec![
+            ("suggested_80008000", false, vec![(0x80008000, 0x80008008)]),
+            ("suggested_80008008", false, vec![(0x80008008, 0x8000800C)]),
+        ]);
+        assert_eq!(signals(&units, 0x80008008), vec![BoundarySignal::AlignmentPadding]);
+    }
+
+    #[test]
+    fn test_data_for_existing_unit() {
+        let mut text = section(".text", ObjSectionKind::Code, 0x80003100, vec![0; 0x10]);
+        text.splits.push(0x80003100, split("main.c", 0x80003110));
+        // main references a
+        text.relocations.insert(0x80003100, reloc(ObjRelocKind::PpcAddr16Lo, 1, 0)).unwrap();
+        let obj = obj(
+            ObjKind::Executable,
+            vec![
+                symbol("main", ObjSymbolKind::Function, 0, 0x80003100, 0x10),
+                symbol("a", ObjSymbolKind::Object, 1, 0x80009000, 4),
+                symbol("b", ObjSymbolKind::Object, 1, 0x80009008, 4),
+            ],
+            vec![text, section(".data", ObjSectionKind::Data, 0x80009000, words(&[1, 0, 2]))],
+        );
+        let units = suggest_units(&obj).unwrap();
+        assert_eq!(summary(&units), vec![
+            ("main.c", true, vec![(0x80009000, 0x80009008)]),
+            ("suggested_80009008", false, vec![(0x80009008, 0x8000900C)]),
+        ]);
+    }
+}
@@ -0,0 +1,297 @@
+use std::{collections::BTreeSet, io::Write};
+
+use anyhow::{anyhow, Result};
+use ppc750cl::{Ins, Opcode};
+use serde::Serialize;
+
+use crate::{
+    analysis::{
+        cfa::{AnalyzerState, FunctionInfo, SectionAddress},
+        disassemble,
+        slices::FunctionSlices,
+    },
+    obj::{ObjInfo, ObjSymbolKind},
+    util::graph::dot_escape,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CfgEdgeKind {
+    /// Falls through to the next block, including returns from calls.
+    Fallthrough,
+    /// Unconditional branch within the function.
+    Branch,
+    /// Conditional branch taken.
+    Conditional,
+    /// Jump table entry. (`bctr`)
+    JumpTable,
+    /// Branch to another function.
+    TailCall,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CfgInstruction {
+    pub address: u32,
+    pub code: u32,
+    pub asm: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CfgBlock {
+    pub start: u32,
+    pub end: u32,
+    pub instructions: Vec<CfgInstruction>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CfgEdge {
+    /// Start of the source block.
+    pub from: u32,
+    pub to: u32,
+    pub kind: CfgEdgeKind,
+    /// Address of the branch instruction.
+    pub source: u32,
+    /// Symbol name, for tail calls.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CfgJumpTable {
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Basic blocks and edges of a single function.
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionCfg {
+    pub name: String,
+    pub section: String,
+    pub start: u32,
+    pub end: u32,
+    pub blocks: Vec<CfgBlock>,
+    pub edges: Vec<CfgEdge>,
+    pub jump_tables: Vec<CfgJumpTable>,
+}
+
+/// Analyzes a single function, using the bounds of all known function symbols.
+pub fn analyze_function(obj: &ObjInfo, addr: SectionAddress) -> Result<FunctionSlices> {
+    let mut state = AnalyzerState::default();
+    for (_, symbol) in obj.symbols.by_kind(ObjSymbolKind::Function) {
+        let Some(section_index) = symbol.section else { continue };
+        let start = SectionAddress::new(section_index, symbol.address as u32);
+        state.functions.insert(start, FunctionInfo {
+            end: symbol.size_known.then(|| start + symbol.size as u32),
+            ..Default::default()
+        });
+    }
+    state.process_function_at(obj, addr)?;
+    state
+        .functions
+        .remove(&addr)
+        .and_then(|info| info.slices)
+        .ok_or_else(|| anyhow!("Failed to analyze function @ {:#010X}", addr))
+}
+
+fn symbol_name_at(obj: &ObjInfo, addr: SectionAddress) -> Option<String> {
+    obj.symbols
+        .at_section_address(addr.section, addr.address)
+        .find(|(_, s)| s.kind == ObjSymbolKind::Function)
+        .or_else(|| obj.symbols.at_section_address(addr.section, addr.address).next())
+        .map(|(_, s)| s.name.clone())
+}
+
+fn format_ins(obj: &ObjInfo, addr: SectionAddress, ins: Ins) -> String {
+    let section = &obj.sections[addr.section];
+    let mut asm = ins.simplified().to_string();
+    if let Some(reloc) = section.relocations.at(addr.address) {
+        let target = &obj.symbols[reloc.target_symbol];
+        asm.push_str(&format!(" # {}", target.name));
+        if reloc.addend != 0 {
+            asm.push_str(&format!("{:+}", reloc.addend));
+        }
+    } else if let Some(dest) = ins.branch_dest(addr.address) {
+        asm.push_str(&format!(" # {:#010X}", dest));
+    }
+    asm
+}
+
+/// Builds the control-flow graph of an analyzed function.
+pub fn build_cfg(obj: &ObjInfo, name: &str, slices: &FunctionSlices) -> Result<FunctionCfg> {
+    let start = slices.start().ok_or_else(|| anyhow!("Function {} has no blocks", name))?;
+    let end = slices.end().ok_or_else(|| anyhow!("Function {} has no known end", name))?;
+    let section = &obj.sections[start.section];
+    let block_starts = slices.blocks.keys().cloned().collect::<BTreeSet<SectionAddress>>();
+
+    let mut blocks = Vec::with_capacity(slices.blocks.len());
+    let mut edges = Vec::new();
+    for (&block_start, &block_end) in &slices.blocks {
+        let block_end =
+            block_end.ok_or_else(|| anyhow!("Unfinalized block @ {:#010X}", block_start))?;
+        let mut instructions = Vec::new();
+        let mut addr = block_start;
+        while addr < block_end {
+            let ins = disassemble(section, addr.address)
+                .ok_or_else(|| anyhow!("Failed to disassemble @ {:#010X}", addr))?;
+            instructions.push(CfgInstruction {
+                address: addr.address,
+                code: ins.code,
+                asm: format_ins(obj, addr, ins),
+            });
+            addr += 4;
+        }
+
+        let last_addr = block_end - 4;
+        let Some(last_ins) = disassemble(section, last_addr.address) else { continue };
+        let targets = match slices.branches.get(&last_addr) {
+            Some(targets) => targets.clone(),
+            None => match last_ins.branch_dest(last_addr.address) {
+                // Branches leaving the function aren't recorded in the slices
+                Some(dest) if !last_ins.field_lk() => match obj.sections.at_address(dest) {
+                    Ok((section_index, _)) => vec![SectionAddress::new(section_index, dest)],
+                    Err(_) => vec![],
+                },
+                _ => vec![],
+            },
+        };
+        let is_branch = last_ins.is_branch() && !last_ins.field_lk();
+        // Blocks split at a branch target have no branch of their own, but still
+        // continue into the next block
+        let ends_flow = match last_ins.op {
+            Opcode::B => !last_ins.field_lk(),
+            Opcode::Bclr | Opcode::Bcctr => {
+                !last_ins.field_lk() && last_ins.field_bo() & 0b10100 == 0b10100
+            }
+            Opcode::Rfi => true,
+            _ => false,
+        };
+        if !ends_flow && block_starts.contains(&block_end) && !targets.contains(&block_end) {
+            edges.push(CfgEdge {
+                from: block_start.address,
+                to: block_end.address,
+                kind: CfgEdgeKind::Fallthrough,
+                source: last_addr.address,
+                target: None,
+            });
+        }
+        for target in targets {
+            let kind = if !block_starts.contains(&target) {
+                CfgEdgeKind::TailCall
+            } else if !is_branch || (target == block_end && last_ins.op != Opcode::Bcctr) {
+                CfgEdgeKind::Fallthrough
+            } else if last_ins.op == Opcode::Bcctr {
+                CfgEdgeKind::JumpTable
+            } else if last_ins.op == Opcode::B || last_ins.field_bo() & 0b10100 == 0b10100 {
+                CfgEdgeKind::Branch
+            } else {
+                CfgEdgeKind::Conditional
+            };
+            edges.push(CfgEdge {
+                from: block_start.address,
+                to: target.address,
+                kind,
+                source: last_addr.address,
+                target: if kind == CfgEdgeKind::TailCall {
+                    symbol_name_at(obj, target)
+                } else {
+                    None
+                },
+            });
+        }
+        blocks.push(CfgBlock { start: block_start.address, end: block_end.address, instructions });
+    }
+
+    let jump_tables = slices
+        .jump_table_references
+        .iter()
+        .map(|(addr, &size)| CfgJumpTable { address: addr.address, size })
+        .collect();
+    Ok(FunctionCfg {
+        name: name.to_string(),
+        section: section.name.clone(),
+        start: start.address,
+        end: end.address,
+        blocks,
+        edges,
+        jump_tables,
+    })
+}
+
+/// Writes the graph in Graphviz DOT format, with the disassembly of each block.
+pub fn write_cfg_dot<W>(w: &mut W, cfg: &FunctionCfg) -> Result<()>
+where W: Write + ?Sized {
+    writeln!(w, "digraph \"{}\" {{", dot_escape(&cfg.name))?;
+    writeln!(w, "    node [shape=box, fontname=monospace];")?;
+    for block in &cfg.blocks {
+        let mut label = format!("{:08X}:\\l", block.start);
+        for ins in &block.instructions {
+            label.push_str(&format!("  {}\\l", dot_escape(&ins.asm)));
+        }
+        writeln!(w, "    \"{:08X}\" [label=\"{}\"];", block.start, label)?;
+    }
+    let mut external = BTreeSet::new();
+    for edge in &cfg.edges {
+        let attrs = match edge.kind {
+            CfgEdgeKind::Fallthrough => "style=dashed",
+            CfgEdgeKind::Branch => "",
+            CfgEdgeKind::Conditional => "color=green",
+            CfgEdgeKind::JumpTable => "color=blue",
+            CfgEdgeKind::TailCall => "color=red",
+        };
+        writeln!(w, "    \"{:08X}\" -> \"{:08X}\" [{}];", edge.from, edge.to, attrs)?;
+        if edge.kind == CfgEdgeKind::TailCall && external.insert(edge.to) {
+            let name = edge.target.clone().unwrap_or_else(|| format!("{:#010X}", edge.to));
+            writeln!(
+                w,
+                "    \"{:08X}\" [label=\"{}\", shape=ellipse];",
+                edge.to,
+                dot_escape(&name)
+            )?;
+        }
+    }
+    writeln!(w, "}}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, section, symbol},
+        ObjKind, ObjSectionKind,
+    };
+
+    #[test]
+    fn test_build_cfg_split_block() {
+        let code = [
+            0x38600000u32, // li r3, 0
+            0x38630001,    // addi r3, r3, 1
+            0x2C03000A,    // cmpwi r3, 10
+            0x4180FFF8,    // blt 0x80003104
+            0x4E800020,    // blr
+        ];
+        let data = code.iter().flat_map(|ins| ins.to_be_bytes()).collect::<Vec<_>>();
+        let obj = obj(
+            ObjKind::Executable,
+            vec![symbol("loop", ObjSymbolKind::Function, 0, 0x80003100, 0x14)],
+            vec![section(".text", ObjSectionKind::Code, 0x80003100, data)],
+        );
+        let slices = analyze_function(&obj, SectionAddress::new(0, 0x80003100)).unwrap();
+        let cfg = build_cfg(&obj, "loop", &slices).unwrap();
+
+        let blocks = cfg.blocks.iter().map(|b| (b.start, b.end)).collect::<Vec<_>>();
+        assert_eq!(blocks, vec![
+            (0x80003100, 0x80003104),
+            (0x80003104, 0x80003110),
+            (0x80003110, 0x80003114)
+        ]);
+        let mut edges = cfg.edges.iter().map(|e| (e.from, e.to, e.kind)).collect::<Vec<_>>();
+        edges.sort_by_key(|&(from, to, _)| (from, to));
+        assert_eq!(edges, vec![
+            // Split by the loop's branch target
+            (0x80003100, 0x80003104, CfgEdgeKind::Fallthrough),
+            (0x80003104, 0x80003104, CfgEdgeKind::Conditional),
+            (0x80003104, 0x80003110, CfgEdgeKind::Fallthrough),
+        ]);
+    }
+}
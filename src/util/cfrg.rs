@@ -0,0 +1,368 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use zerocopy::{big_endian::*, FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::{
+    static_assert,
+    util::mac::{decode_mac_roman, fourcc_str, process_resource_fork},
+};
+
+pub const CFRG_RESOURCE_TYPE: [u8; 4] = *b"cfrg";
+pub const CODE_RESOURCE_TYPE: [u8; 4] = *b"CODE";
+pub const ARCH_POWERPC: [u8; 4] = *b"pwpc";
+pub const ARCH_68K: [u8; 4] = *b"m68k";
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct CfrgHeader {
+    _reserved1: U32,
+    _reserved2: U32,
+    /// Format version. (Always 1)
+    version: U32,
+    _reserved3: [U32; 4],
+    /// Number of member entries following the header.
+    member_count: U32,
+}
+
+static_assert!(size_of::<CfrgHeader>() == 0x20);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct CfrgMemberHeader {
+    /// Instruction set architecture. ("pwpc" or "m68k")
+    architecture: [u8; 4],
+    _reserved1: U16,
+    _reserved2: u8,
+    update_level: u8,
+    current_version: U32,
+    old_def_version: U32,
+    /// Application stack size, or 0 for the default.
+    stack_size: U32,
+    /// Application subdirectory ID, or library flags.
+    _app_subdir_id: U16,
+    /// Fragment usage. (import library, application, drop-in, stub library, weak stub library)
+    usage: u8,
+    /// Fragment location. (memory, data fork, resource, byte stream, named fragment)
+    location: u8,
+    /// Offset of the fragment within its container.
+    offset: U32,
+    /// Length of the fragment, or 0 for the rest of the container.
+    length: U32,
+    /// Space ID, or the resource type for resource-based fragments.
+    space_id: U32,
+    /// Fork instance, or the resource ID for resource-based fragments.
+    fork_instance: U16,
+    extension_count: U16,
+    /// Size of the member entry, including the name.
+    member_size: U16,
+}
+
+static_assert!(size_of::<CfrgMemberHeader>() == 0x2A);
+
+pub const CFRG_LOCATION_MEMORY: u8 = 0;
+pub const CFRG_LOCATION_DATA_FORK: u8 = 1;
+pub const CFRG_LOCATION_RESOURCE: u8 = 2;
+
+/// A code fragment described by a `cfrg` resource.
+#[derive(Debug, Clone)]
+pub struct CfrgMember {
+    pub architecture: [u8; 4],
+    pub update_level: u8,
+    pub current_version: u32,
+    pub old_def_version: u32,
+    pub stack_size: u32,
+    pub usage: u8,
+    pub location: u8,
+    pub offset: u32,
+    pub length: u32,
+    pub space_id: u32,
+    pub fork_instance: u16,
+    pub extension_count: u16,
+    pub name: String,
+}
+
+impl CfrgMember {
+    #[inline]
+    pub fn is_powerpc(&self) -> bool { self.architecture == ARCH_POWERPC }
+
+    pub fn usage_str(&self) -> &'static str {
+        match self.usage {
+            0 => "import library",
+            1 => "application",
+            2 => "drop-in",
+            3 => "stub library",
+            4 => "weak stub library",
+            _ => "unknown",
+        }
+    }
+
+    /// Extracts the fragment from the data fork it was described in.
+    pub fn data<'a>(&self, data_fork: &'a [u8]) -> Result<&'a [u8]> {
+        ensure!(
+            self.location == CFRG_LOCATION_DATA_FORK,
+            "Fragment '{}' is not stored in the data fork ({})",
+            self.name,
+            self.location_str()
+        );
+        let start = self.offset as usize;
+        let end = match self.length {
+            0 => data_fork.len(),
+            length => start + length as usize,
+        };
+        data_fork.get(start..end).ok_or_else(|| {
+            anyhow!(
+                "Fragment '{}' out of bounds: {:#X}..{:#X} (data fork is {:#X} bytes)",
+                self.name,
+                start,
+                end,
+                data_fork.len()
+            )
+        })
+    }
+
+    pub fn location_str(&self) -> String {
+        match self.location {
+            CFRG_LOCATION_MEMORY => "memory".to_string(),
+            CFRG_LOCATION_DATA_FORK => "data fork".to_string(),
+            CFRG_LOCATION_RESOURCE => format!(
+                "resource '{}' {}",
+                fourcc_str(&self.space_id.to_be_bytes()),
+                self.fork_instance as i16
+            ),
+            3 => "byte stream".to_string(),
+            4 => "named fragment".to_string(),
+            location => format!("unknown ({})", location),
+        }
+    }
+}
+
+impl Display for CfrgMember {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}, {}) in {}",
+            self.name,
+            fourcc_str(&self.architecture),
+            self.usage_str(),
+            self.location_str()
+        )
+    }
+}
+
+/// Parses the members of a `cfrg` resource.
+pub fn parse_cfrg(data: &[u8]) -> Result<Vec<CfrgMember>> {
+    let (header, mut rest) =
+        CfrgHeader::read_from_prefix(data).map_err(|_| anyhow!("cfrg header too short"))?;
+    ensure!(header.version.get() == 1, "Unsupported cfrg version {}", header.version.get());
+    let max_members = rest.len() / size_of::<CfrgMemberHeader>();
+    let mut members = Vec::with_capacity((header.member_count.get() as usize).min(max_members));
+    for i in 0..header.member_count.get() {
+        let (member, _) = CfrgMemberHeader::read_from_prefix(rest)
+            .map_err(|_| anyhow!("cfrg member {} truncated", i))?;
+        let member_size = member.member_size.get() as usize;
+        ensure!(
+            member_size > size_of::<CfrgMemberHeader>() && member_size <= rest.len(),
+            "cfrg member {} has invalid size {:#X}",
+            i,
+            member_size
+        );
+        let name_data = &rest[size_of::<CfrgMemberHeader>()..member_size];
+        let name_len = (name_data[0] as usize).min(name_data.len() - 1);
+        members.push(CfrgMember {
+            architecture: member.architecture,
+            update_level: member.update_level,
+            current_version: member.current_version.get(),
+            old_def_version: member.old_def_version.get(),
+            stack_size: member.stack_size.get(),
+            usage: member.usage,
+            location: member.location,
+            offset: member.offset.get(),
+            length: member.length.get(),
+            space_id: member.space_id.get(),
+            fork_instance: member.fork_instance.get(),
+            extension_count: member.extension_count.get(),
+            name: decode_mac_roman(&name_data[1..1 + name_len]),
+        });
+        rest = &rest[member_size..];
+    }
+    Ok(members)
+}
+
+/// Code resources found in a resource fork.
+#[derive(Debug, Clone, Default)]
+pub struct CodeResources {
+    /// Members of `cfrg` 0, if present.
+    pub fragments: Option<Vec<CfrgMember>>,
+    /// Number of classic 68k `CODE` resources.
+    pub code_count: usize,
+}
+
+/// Reads `cfrg` 0 and counts the 68k `CODE` resources in a resource fork.
+pub fn read_code_resources(rsrc: &[u8]) -> Result<CodeResources> {
+    let resources = process_resource_fork(rsrc)?;
+    let mut result = CodeResources::default();
+    for resource in &resources {
+        if resource.res_type == CFRG_RESOURCE_TYPE && resource.id == 0 {
+            let data = &rsrc[resource.offset as usize..(resource.offset + resource.len) as usize];
+            result.fragments = Some(parse_cfrg(data)?);
+        } else if resource.res_type == CODE_RESOURCE_TYPE {
+            result.code_count += 1;
+        }
+    }
+    Ok(result)
+}
+
+/// Selects a fragment by index or name. Without a selector, the first PowerPC
+/// fragment in the data fork is used.
+pub fn select_fragment<'a>(
+    fragments: &'a [CfrgMember],
+    selector: Option<&str>,
+) -> Result<(usize, &'a CfrgMember)> {
+    let found = match selector {
+        Some(selector) => match selector.parse::<usize>() {
+            Ok(index) => fragments.get(index).map(|m| (index, m)),
+            Err(_) => fragments.iter().enumerate().find(|(_, m)| m.name == selector),
+        },
+        None => fragments
+            .iter()
+            .enumerate()
+            .find(|(_, m)| m.is_powerpc() && m.location == CFRG_LOCATION_DATA_FORK),
+    };
+    let Some((index, member)) = found else {
+        match selector {
+            Some(selector) => bail!("No fragment '{}' in cfrg", selector),
+            None => bail!("No PowerPC fragment in the data fork"),
+        }
+    };
+    if !member.is_powerpc() {
+        bail!("Fragment {} ({}) is not PowerPC", index, member);
+    }
+    Ok((index, member))
+}
+
+/// Locates the PEF container within a data fork. When the resource fork is
+/// given, its `cfrg` resource describes where each fragment lives.
+pub fn locate_fragment<'a>(
+    data_fork: &'a [u8],
+    rsrc: Option<&[u8]>,
+    selector: Option<&str>,
+) -> Result<&'a [u8]> {
+    let Some(rsrc) = rsrc else {
+        ensure!(selector.is_none(), "Selecting a fragment requires the resource fork");
+        return Ok(data_fork);
+    };
+    let code = read_code_resources(rsrc)?;
+    match code.fragments {
+        Some(fragments) => {
+            let (index, member) = select_fragment(&fragments, selector)?;
+            log::debug!("Using fragment {}: {}", index, member);
+            member.data(data_fork)
+        }
+        None if code.code_count > 0 => bail!(
+            "Classic 68k application ({} CODE resources) without PowerPC fragments",
+            code.code_count
+        ),
+        None => {
+            ensure!(selector.is_none(), "No cfrg resource to select a fragment from");
+            Ok(data_fork)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zerocopy::FromZeros;
+
+    use super::*;
+    use crate::util::mac::tests::resource_fork;
+
+    fn cfrg_member(usage: u8, offset: u32, length: u32, name: &str) -> Vec<u8> {
+        let mut member = CfrgMemberHeader::new_zeroed();
+        member.architecture = ARCH_POWERPC;
+        member.current_version.set(0x100);
+        member.old_def_version.set(0x80);
+        member.usage = usage;
+        member.location = CFRG_LOCATION_DATA_FORK;
+        member.offset.set(offset);
+        member.length.set(length);
+        let member_size = (size_of::<CfrgMemberHeader>() + 1 + name.len() + 3) & !3;
+        member.member_size.set(member_size as u16);
+        let mut out = member.as_bytes().to_vec();
+        out.push(name.len() as u8);
+        out.extend_from_slice(name.as_bytes());
+        out.resize(member_size, 0);
+        out
+    }
+
+    fn cfrg(members: &[Vec<u8>]) -> Vec<u8> {
+        let mut header = CfrgHeader::new_zeroed();
+        header.version.set(1);
+        header.member_count.set(members.len() as u32);
+        let mut out = header.as_bytes().to_vec();
+        for member in members {
+            out.extend_from_slice(member);
+        }
+        out
+    }
+
+    fn two_member_cfrg() -> Vec<u8> {
+        cfrg(&[cfrg_member(1, 0x100, 0x20, "Game"), cfrg_member(0, 0x200, 0, "GameLib")])
+    }
+
+    #[test]
+    fn test_parse_cfrg() {
+        let members = parse_cfrg(&two_member_cfrg()).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].name, "Game");
+        assert_eq!(members[0].usage_str(), "application");
+        assert_eq!(members[0].current_version, 0x100);
+        assert_eq!(members[0].old_def_version, 0x80);
+        assert_eq!((members[0].offset, members[0].length), (0x100, 0x20));
+        assert_eq!(members[1].name, "GameLib");
+        assert_eq!(members[1].usage_str(), "import library");
+        assert_eq!((members[1].offset, members[1].length), (0x200, 0));
+        assert!(members.iter().all(|m| m.is_powerpc()));
+    }
+
+    #[test]
+    fn test_parse_cfrg_invalid() {
+        // A huge member count without member data fails without preallocating
+        let mut data = cfrg(&[]);
+        data[0x1C..0x20].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(parse_cfrg(&data).is_err());
+        // A member size past the end of the resource
+        let mut data = two_member_cfrg();
+        data[0x20 + 0x28..0x20 + 0x2A].copy_from_slice(&0x100u16.to_be_bytes());
+        assert!(parse_cfrg(&data).is_err());
+        // Unsupported version
+        let mut data = two_member_cfrg();
+        data[0x0B] = 2;
+        assert!(parse_cfrg(&data).is_err());
+    }
+
+    #[test]
+    fn test_locate_fragment() {
+        let data_fork = (0..0x240u32).map(|i| i as u8).collect::<Vec<_>>();
+        let rsrc = resource_fork(&[(CFRG_RESOURCE_TYPE, 0, None, &two_member_cfrg())]);
+
+        let fragment = locate_fragment(&data_fork, Some(&rsrc), None).unwrap();
+        assert_eq!(fragment, &data_fork[0x100..0x120]);
+        let fragment = locate_fragment(&data_fork, Some(&rsrc), Some("GameLib")).unwrap();
+        assert_eq!(fragment, &data_fork[0x200..]);
+        let fragment = locate_fragment(&data_fork, Some(&rsrc), Some("1")).unwrap();
+        assert_eq!(fragment, &data_fork[0x200..]);
+        assert!(locate_fragment(&data_fork, Some(&rsrc), Some("Missing")).is_err());
+        assert!(locate_fragment(&data_fork, Some(&rsrc), Some("2")).is_err());
+        // Fragment extends past the data fork
+        assert!(locate_fragment(&data_fork[..0x110], Some(&rsrc), None).is_err());
+
+        // Without a resource fork, the data fork is the container
+        assert_eq!(locate_fragment(&data_fork, None, None).unwrap(), &data_fork[..]);
+        assert!(locate_fragment(&data_fork, None, Some("Game")).is_err());
+
+        // Classic 68k applications have no PowerPC fragment
+        let rsrc = resource_fork(&[(CODE_RESOURCE_TYPE, 0, None, &[0; 4])]);
+        assert!(locate_fragment(&data_fork, Some(&rsrc), None).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     fs,
     io::{BufRead, Write},
     num::ParseIntError,
@@ -23,6 +24,7 @@ use crate::{
     util::{
         file::{buf_writer, FileReadInfo},
         split::default_section_align,
+        tbtab::TracebackTable,
     },
     vfs::open_file,
 };
@@ -133,6 +135,8 @@ pub fn parse_symbol_line(line: &str, obj: &mut ObjInfo) -> Result<Option<ObjSymb
                     "dhash" => {
                         symbol.demangled_name_hash = Some(parse_u32(value)?);
                     }
+                    // Decoded from the traceback table on write
+                    "params" | "gprs" | "fprs" | "alloca" => {}
                     _ => bail!("Unknown symbol attribute '{name}'"),
                 }
             } else {
@@ -164,6 +168,7 @@ pub fn parse_symbol_line(line: &str, obj: &mut ObjInfo) -> Result<Option<ObjSymb
                     "noexport" => {
                         symbol.flags.0 |= ObjSymbolFlags::NoExport;
                     }
+                    "lr_saved" | "cr_saved" => {}
                     _ => bail!("Unknown symbol attribute '{attr}'"),
                 }
             }
@@ -194,12 +199,17 @@ pub fn is_auto_symbol(symbol: &ObjSymbol) -> bool {
         || symbol.name.starts_with("jumptable_")
         || symbol.name.starts_with("gap_")
         || symbol.name.starts_with("pad_")
+        || symbol.name.starts_with("tbtab_")
+        || symbol.name.starts_with("vtable_")
+        || symbol.name.starts_with("vfunc_")
 }
 
 pub fn is_auto_label(symbol: &ObjSymbol) -> bool { symbol.name.starts_with("lbl_") }
 
 pub fn is_auto_jump_table(symbol: &ObjSymbol) -> bool { symbol.name.starts_with("jumptable_") }
 
+pub fn is_auto_traceback_table(symbol: &ObjSymbol) -> bool { symbol.name.starts_with("tbtab_") }
+
 fn write_if_unchanged<Cb>(
     path: &Utf8NativePath,
     cb: Cb,
@@ -304,10 +314,27 @@ where W: Write + ?Sized {
     if symbol.flags.is_no_export() {
         write!(w, " noexport")?;
     }
+    if let Some(tb) = function_traceback_table(obj, symbol) {
+        for attr in tb.attributes() {
+            write!(w, " {attr}")?;
+        }
+    }
     writeln!(w)?;
     Ok(())
 }
 
+/// Decodes the traceback table following a function, if analysis found one.
+fn function_traceback_table(obj: &ObjInfo, symbol: &ObjSymbol) -> Option<TracebackTable> {
+    if symbol.kind != ObjSymbolKind::Function || !symbol.size_known {
+        return None;
+    }
+    let section_index = symbol.section?;
+    let end = (symbol.address + symbol.size) as u32;
+    obj.symbols.at_section_address(section_index, end).find(|(_, s)| is_auto_traceback_table(s))?;
+    let section = &obj.sections[section_index];
+    TracebackTable::read(&section.data, (end - section.address as u32) as usize)
+}
+
 #[inline]
 fn symbol_kind_to_str(kind: ObjSymbolKind) -> &'static str {
     match kind {
@@ -319,22 +346,27 @@ fn symbol_kind_to_str(kind: ObjSymbolKind) -> &'static str {
 }
 
 #[inline]
-fn symbol_data_kind_to_str(kind: ObjDataKind) -> Option<&'static str> {
-    match kind {
-        ObjDataKind::Unknown => None,
-        ObjDataKind::Byte => Some("byte"),
-        ObjDataKind::Byte2 => Some("2byte"),
-        ObjDataKind::Byte4 => Some("4byte"),
-        ObjDataKind::Byte8 => Some("8byte"),
-        ObjDataKind::Float => Some("float"),
-        ObjDataKind::Double => Some("double"),
-        ObjDataKind::String => Some("string"),
-        ObjDataKind::String16 => Some("wstring"),
-        ObjDataKind::StringTable => Some("string_table"),
-        ObjDataKind::String16Table => Some("wstring_table"),
-        ObjDataKind::Int => Some("int"),
-        ObjDataKind::Short => Some("short"),
-    }
+fn symbol_data_kind_to_str(kind: ObjDataKind) -> Option<Cow<'static, str>> {
+    let s = match kind {
+        ObjDataKind::Unknown => return None,
+        ObjDataKind::Array(stride) => return Some(Cow::Owned(format!("array:{:#X}", stride))),
+        ObjDataKind::Byte => "byte",
+        ObjDataKind::Byte2 => "2byte",
+        ObjDataKind::Byte4 => "4byte",
+        ObjDataKind::Byte8 => "8byte",
+        ObjDataKind::Float => "float",
+        ObjDataKind::Double => "double",
+        ObjDataKind::String => "string",
+        ObjDataKind::String16 => "wstring",
+        ObjDataKind::StringTable => "string_table",
+        ObjDataKind::String16Table => "wstring_table",
+        ObjDataKind::Int => "int",
+        ObjDataKind::Short => "short",
+        ObjDataKind::Pointer => "ptr",
+        ObjDataKind::StringPointerTable => "string_ptr_table",
+        ObjDataKind::Vtable => "vtable",
+    };
+    Some(Cow::Borrowed(s))
 }
 
 #[inline]
@@ -387,7 +419,14 @@ fn symbol_data_kind_from_str(s: &str) -> Option<ObjDataKind> {
         "wstring_table" => Some(ObjDataKind::String16Table),
         "int" => Some(ObjDataKind::Int),
         "short" => Some(ObjDataKind::Short),
-        _ => None,
+        "ptr" => Some(ObjDataKind::Pointer),
+        "string_ptr_table" => Some(ObjDataKind::StringPointerTable),
+        "vtable" => Some(ObjDataKind::Vtable),
+        _ => s
+            .strip_prefix("array:")
+            .and_then(|stride| parse_u32(stride).ok())
+            .filter(|&stride| stride > 0)
+            .map(ObjDataKind::Array),
     }
 }
 
@@ -898,3 +937,37 @@ impl serde::Serialize for SectionAddressRef {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::tests::{obj, section};
+
+    #[test]
+    fn test_symbol_line_round_trip() {
+        let data = section(".data", ObjSectionKind::Data, 0x80004000, vec![0; 0x30]);
+        let mut obj = obj(ObjKind::Executable, vec![], vec![data]);
+        for line in [
+            "table = .data:0x80004000; // type:object size:0x24 data:array:0xC\n",
+            "ptr = .data:0x80004024; // type:object size:0x4 data:ptr\n",
+            "__vt__4CFoo = .data:0x80004028; // type:object size:0x8 data:vtable\n",
+        ] {
+            let symbol = parse_symbol_line(line.trim_end(), &mut obj).unwrap().unwrap();
+            let mut out = Vec::new();
+            write_symbol(&mut out, &obj, &symbol).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), line);
+        }
+        let symbol = parse_symbol_line(
+            "table = .data:0x80004000; // type:object size:0x24 data:array:12",
+            &mut obj,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(symbol.data_kind, ObjDataKind::Array(12));
+        assert!(parse_symbol_line(
+            "table = .data:0x80004000; // type:object size:0x24 data:array:0",
+            &mut obj
+        )
+        .is_err());
+    }
+}
@@ -307,6 +307,44 @@ fn to_objdiff_symbol(
     }
 }
 
+/// Check if two symbols' names match, allowing for differences in compiler-generated names,
+/// like @1234 and @5678, or init$1234 and init$5678.
+pub fn symbol_name_fuzzy_eq(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    // Match e.g. @1234 and @5678
+    if a.starts_with('@') && b.starts_with('@') {
+        return true;
+    }
+    // Match e.g. init$1234 and init$5678
+    if let (Some(a_dollar), Some(b_dollar)) = (a.rfind('$'), b.rfind('$')) {
+        if a[..a_dollar] == b[..b_dollar] {
+            if let (Ok(_), Ok(_)) =
+                (a[a_dollar + 1..].parse::<u32>(), b[b_dollar + 1..].parse::<u32>())
+            {
+                return true;
+            }
+        }
+    }
+    // Match e.g. symbol and symbol_80123456 (globalized symbol)
+    if let Some(a_under) = a.rfind('_') {
+        if &a[..a_under] == b && is_hex(&a[a_under + 1..]) {
+            return true;
+        }
+    }
+    if let Some(b_under) = b.rfind('_') {
+        if a == &b[..b_under] && is_hex(&b[b_under + 1..]) {
+            return true;
+        }
+    }
+    false
+}
+
+fn is_hex(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_digit() || matches!(c, 'a'..='f' | 'A'..='F'))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -388,4 +426,17 @@ mod tests {
         ];
         assert_eq!(calc_diff_ranges(&diff, &diff, 3), vec![0..7, 9..15]);
     }
+
+    #[test]
+    fn test_symbol_name_fuzzy_eq() {
+        assert!(symbol_name_fuzzy_eq("symbol", "symbol"));
+        assert!(symbol_name_fuzzy_eq("@1234", "@5678"));
+        assert!(symbol_name_fuzzy_eq("symbol$1234", "symbol$5678"));
+        assert!(symbol_name_fuzzy_eq("symbol", "symbol_80123456"));
+        assert!(symbol_name_fuzzy_eq("symbol_80123456", "symbol"));
+        assert!(!symbol_name_fuzzy_eq("symbol", "symbol2"));
+        assert!(!symbol_name_fuzzy_eq("symbol@1234", "symbol@5678"));
+        assert!(!symbol_name_fuzzy_eq("symbol", "symbol_80123456_"));
+        assert!(!symbol_name_fuzzy_eq("symbol_80123456_", "symbol"));
+    }
 }
@@ -27,7 +27,7 @@ use crate::{
     obj::{
         ObjArchitecture, ObjInfo, ObjKind, ObjReloc, ObjRelocKind, ObjSection, ObjSectionKind,
         ObjSplit, ObjSymbol, ObjSymbolFlagSet, ObjSymbolFlags, ObjSymbolKind, ObjUnit,
-        SectionIndex as ObjSectionIndex, SymbolIndex as ObjSymbolIndex,
+        SectionIndex as ObjSectionIndex, SymbolIndex as ObjSymbolIndex, R_PPC_TOC16,
     },
     util::{
         comment::{CommentSym, MWComment},
@@ -49,7 +49,12 @@ enum BoundaryState {
 
 pub fn process_elf(path: &Utf8NativePath) -> Result<ObjInfo> {
     let mut file = open_file(path, true)?;
-    let obj_file = File::parse(file.map()?)?;
+    process_elf_data(file.map()?)
+}
+
+/// Parses an ELF file that is already in memory, e.g. a static library member.
+pub fn process_elf_data(data: &[u8]) -> Result<ObjInfo> {
+    let obj_file = File::parse(data)?;
     let architecture = match obj_file.architecture() {
         Architecture::PowerPc => ObjArchitecture::PowerPc,
         arch => bail!("Unexpected architecture: {arch:?}"),
@@ -922,6 +927,8 @@ pub fn to_obj_reloc_kind(flags: RelocationFlags) -> Result<ObjRelocKind> {
             elf::R_PPC_REL24 => ObjRelocKind::PpcRel24,
             elf::R_PPC_REL14 => ObjRelocKind::PpcRel14,
             elf::R_PPC_EMB_SDA21 => ObjRelocKind::PpcEmbSda21,
+            R_PPC_TOC16 => ObjRelocKind::PpcToc16,
+            elf::R_PPC_REL32 => ObjRelocKind::PpcRel32,
             kind => bail!("Unhandled ELF relocation type: {kind}"),
         },
         flags => bail!("Unhandled relocation type: {:?}", flags),
@@ -982,10 +989,13 @@ fn write_relocatable_section_data(w: &mut Writer, section: &ObjSection) -> Resul
         w.write(&section.data[current_address..addr as usize]);
         let mut ins = u32::from_be_bytes(*array_ref!(section.data, addr as usize, 4));
         match reloc.kind {
-            ObjRelocKind::Absolute => {
+            ObjRelocKind::Absolute | ObjRelocKind::PpcRel32 => {
                 ins = 0;
             }
-            ObjRelocKind::PpcAddr16Hi | ObjRelocKind::PpcAddr16Ha | ObjRelocKind::PpcAddr16Lo => {
+            ObjRelocKind::PpcAddr16Hi
+            | ObjRelocKind::PpcAddr16Ha
+            | ObjRelocKind::PpcAddr16Lo
+            | ObjRelocKind::PpcToc16 => {
                 ins &= !0xFFFF;
             }
             ObjRelocKind::PpcRel24 => {
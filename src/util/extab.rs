@@ -0,0 +1,339 @@
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::{
+    analysis::{cfa::SectionAddress, read_address, read_u32},
+    obj::{ObjInfo, ObjSection, ObjSymbolKind},
+};
+
+/// A decoded extabindex entry and the extab data it points to.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtabEntry {
+    pub function: Option<String>,
+    pub function_address: u32,
+    pub function_size: u32,
+    pub extabindex_address: u32,
+    pub extab: Option<String>,
+    pub extab_address: u32,
+    /// Split unit of the function, if any.
+    pub unit: Option<String>,
+    /// PC ranges and unwinding actions, as printed by `cwextab`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actions: Option<String>,
+    /// Destructors called while unwinding.
+    pub destructors: Vec<String>,
+    /// Type info referenced by catch blocks.
+    pub catch_types: Vec<String>,
+}
+
+/// A problem found while cross-checking extab, extabindex and splits.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtabIssue {
+    pub extabindex_address: u32,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExtabReport {
+    pub entries: Vec<ExtabEntry>,
+    pub issues: Vec<ExtabIssue>,
+}
+
+fn symbol_at(obj: &ObjInfo, addr: SectionAddress, kind: ObjSymbolKind) -> Option<String> {
+    obj.symbols
+        .at_section_address(addr.section, addr.address)
+        .find(|(_, s)| s.kind == kind)
+        .map(|(_, s)| s.name.clone())
+}
+
+fn split_unit(section: &ObjSection, address: u32) -> Option<String> {
+    section.splits.for_address(address).map(|(_, split)| split.unit.clone())
+}
+
+/// Decodes every extabindex entry and checks that each function, its extabindex
+/// entry and its extab data are split into the same unit.
+///
+/// Unlike the checks performed while splitting, problems are collected into the
+/// report instead of failing on the first one.
+pub fn extab_report(obj: &ObjInfo) -> Result<ExtabReport> {
+    let mut report = ExtabReport::default();
+    let (extabindex_index, extabindex_section) = obj
+        .sections
+        .by_name("extabindex")?
+        .ok_or_else(|| anyhow!("Failed to find extabindex section"))?;
+    let (extab_index, extab_section) =
+        obj.sections.by_name("extab")?.ok_or_else(|| anyhow!("Failed to find extab section"))?;
+    let section_end = match obj.symbols.by_name("_eti_init_info")? {
+        Some((_, symbol)) if symbol.section == Some(extabindex_index) => symbol.address as u32,
+        _ => (extabindex_section.address + extabindex_section.size) as u32,
+    };
+
+    let mut address = extabindex_section.address as u32;
+    while address + 12 <= section_end {
+        let eti_address = address;
+        address += 12;
+        let mut issue = |message: String| {
+            report.issues.push(ExtabIssue { extabindex_address: eti_address, message });
+        };
+
+        let function_addr = match read_address(obj, extabindex_section, eti_address) {
+            Ok(addr) => addr,
+            Err(e) => {
+                issue(format!("Invalid function address: {:#}", e));
+                continue;
+            }
+        };
+        let Some(function_size) = read_u32(extabindex_section, eti_address + 4) else {
+            issue("Failed to read function size".to_string());
+            continue;
+        };
+        let extab_addr = match read_address(obj, extabindex_section, eti_address + 8) {
+            Ok(addr) => addr,
+            Err(e) => {
+                issue(format!("Invalid extab address: {:#}", e));
+                continue;
+            }
+        };
+        if extab_addr.section != extab_index {
+            issue(format!("Invalid extab address {:#010X}", extab_addr.address));
+            continue;
+        }
+        if extab_addr.address % 4 != 0 {
+            issue(format!("Misaligned extab address {:#010X}", extab_addr.address));
+            continue;
+        }
+
+        let function = obj
+            .symbols
+            .kind_at_section_address(
+                function_addr.section,
+                function_addr.address,
+                ObjSymbolKind::Function,
+            )?
+            .map(|(_, s)| s);
+        match function {
+            Some(symbol) if !symbol.size_known || symbol.size != function_size as u64 => {
+                issue(format!(
+                    "Function {} has size {:#X}, extabindex expects {:#X}",
+                    symbol.name, symbol.size, function_size
+                ));
+            }
+            Some(_) => {}
+            None => issue(format!("No function @ {:#010X}", function_addr.address)),
+        }
+        if symbol_at(obj, SectionAddress::new(extabindex_index, eti_address), ObjSymbolKind::Object)
+            .is_none()
+        {
+            issue("No extabindex symbol".to_string());
+        }
+        let extab = obj
+            .symbols
+            .kind_at_section_address(extab_index, extab_addr.address, ObjSymbolKind::Object)?
+            .map(|(_, s)| s)
+            .filter(|s| s.size_known && s.size > 0);
+        if extab.is_none() {
+            issue(format!("No sized extab symbol @ {:#010X}", extab_addr.address));
+        }
+
+        // Cross-check the splits of the function, extabindex and extab entries
+        let text_section = &obj.sections[function_addr.section];
+        let unit = split_unit(text_section, function_addr.address);
+        let eti_unit = split_unit(extabindex_section, eti_address);
+        let extab_unit = split_unit(extab_section, extab_addr.address);
+        match (&unit, &eti_unit, &extab_unit) {
+            (None, _, _) => issue(format!(
+                "Function @ {:#010X} has an extabindex entry but no split",
+                function_addr.address
+            )),
+            (Some(unit), eti_unit, extab_unit) => {
+                for (name, other) in [("extabindex", eti_unit), ("extab", extab_unit)] {
+                    match other {
+                        Some(other) if other != unit => issue(format!(
+                            "Function is split into {}, but its {} entry into {}",
+                            unit, name, other
+                        )),
+                        Some(_) => {}
+                        None => issue(format!(
+                            "Function is split into {}, but its {} entry has no split",
+                            unit, name
+                        )),
+                    }
+                }
+            }
+        }
+
+        let mut actions = None;
+        let mut destructors = vec![];
+        let mut catch_types = vec![];
+        if let Some(extab) = extab {
+            let start = extab.address as u32;
+            let end = (extab.address + extab.size) as u32;
+            let mut names = vec![];
+            for (_, reloc) in extab_section.relocations.range(start..end) {
+                let target = &obj.symbols[reloc.target_symbol];
+                names.push(target.name.clone());
+                if target.kind == ObjSymbolKind::Function {
+                    destructors.push(target.name.clone());
+                } else {
+                    catch_types.push(target.name.clone());
+                }
+            }
+            match extab_section
+                .symbol_data(extab)
+                .and_then(|data| cwextab::decode_extab(data).map_err(|e| anyhow!("{}", e)))
+            {
+                Ok(decoded) => actions = decoded.to_string(names),
+                Err(e) => issue(format!("Failed to decode extab entry {}: {}", extab.name, e)),
+            }
+        }
+
+        report.entries.push(ExtabEntry {
+            function: function.map(|s| s.name.clone()),
+            function_address: function_addr.address,
+            function_size,
+            extabindex_address: eti_address,
+            extab: extab.map(|s| s.name.clone()),
+            extab_address: extab_addr.address,
+            unit,
+            actions,
+            destructors,
+            catch_types,
+        });
+    }
+    Ok(report)
+}
+
+/// Writes the report as text, followed by the issues found.
+pub fn write_extab_report<W>(w: &mut W, report: &ExtabReport) -> Result<()>
+where W: Write + ?Sized {
+    for entry in &report.entries {
+        let function =
+            entry.function.clone().unwrap_or_else(|| format!("{:#010X}", entry.function_address));
+        write!(
+            w,
+            "{} @ {:#010X} (size {:#X})",
+            function, entry.function_address, entry.function_size
+        )?;
+        if let Some(unit) = &entry.unit {
+            write!(w, " in {}", unit)?;
+        }
+        writeln!(w)?;
+        let extab = entry.extab.clone().unwrap_or_else(|| "?".to_string());
+        writeln!(
+            w,
+            "\textabindex {:#010X}, extab {} @ {:#010X}",
+            entry.extabindex_address, extab, entry.extab_address
+        )?;
+        if !entry.destructors.is_empty() {
+            writeln!(w, "\tDestructors: {}", entry.destructors.join(", "))?;
+        }
+        if !entry.catch_types.is_empty() {
+            writeln!(w, "\tCatch types: {}", entry.catch_types.join(", "))?;
+        }
+        if let Some(actions) = &entry.actions {
+            for line in actions.lines().filter(|l| !l.trim().is_empty()) {
+                writeln!(w, "\t\t{}", line)?;
+            }
+        }
+        writeln!(w)?;
+    }
+    if report.issues.is_empty() {
+        writeln!(w, "No issues found in {} extab entries", report.entries.len())?;
+    } else {
+        writeln!(w, "{} issues found:", report.issues.len())?;
+        for issue in &report.issues {
+            writeln!(w, "\textabindex {:#010X}: {}", issue.extabindex_address, issue.message)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, section, split, symbol},
+        ObjKind, ObjSectionKind,
+    };
+
+    fn extab_obj() -> ObjInfo {
+        // fn_a with a valid entry, an entry without a function symbol,
+        // a misaligned extab pointer and a function pointer outside any section
+        let eti_entries: [(u32, u32, u32); 4] = [
+            (0x80003100, 0x10, 0x80005000),
+            (0x80003110, 0x10, 0x80005008),
+            (0x80003100, 0x10, 0x80005002),
+            (0x90000000, 0x10, 0x80005000),
+        ];
+        let mut eti_data = vec![];
+        for (function, size, extab) in eti_entries {
+            eti_data.extend_from_slice(&function.to_be_bytes());
+            eti_data.extend_from_slice(&size.to_be_bytes());
+            eti_data.extend_from_slice(&extab.to_be_bytes());
+        }
+        let extab_data = [0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00].repeat(2);
+        let mut sections = vec![
+            section(".text", ObjSectionKind::Code, 0x80003100, vec![0; 0x20]),
+            section("extab", ObjSectionKind::ReadOnlyData, 0x80005000, extab_data),
+            section("extabindex", ObjSectionKind::ReadOnlyData, 0x80005100, eti_data),
+        ];
+        sections[0].splits.push(0x80003100, split("a.cpp", 0x80003120));
+        sections[1].splits.push(0x80005000, split("a.cpp", 0x80005010));
+        sections[2].splits.push(0x80005100, split("a.cpp", 0x80005130));
+        let mut symbols = vec![
+            symbol("fn_a", ObjSymbolKind::Function, 0, 0x80003100, 0x10),
+            symbol("@etb_80005000", ObjSymbolKind::Object, 1, 0x80005000, 8),
+            symbol("@etb_80005008", ObjSymbolKind::Object, 1, 0x80005008, 8),
+        ];
+        for i in 0..4 {
+            let address = 0x80005100 + i * 12;
+            let name = format!("@eti_{:08X}", address);
+            symbols.push(symbol(&name, ObjSymbolKind::Object, 2, address, 12));
+        }
+        obj(ObjKind::Executable, symbols, sections)
+    }
+
+    fn issues_at(report: &ExtabReport, address: u32) -> Vec<&str> {
+        report
+            .issues
+            .iter()
+            .filter(|i| i.extabindex_address == address)
+            .map(|i| i.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_extab_report() {
+        let report = extab_report(&extab_obj()).unwrap();
+        assert_eq!(report.entries.len(), 2);
+
+        let entry = &report.entries[0];
+        assert_eq!(entry.function.as_deref(), Some("fn_a"));
+        assert_eq!(entry.function_size, 0x10);
+        assert_eq!(entry.extab.as_deref(), Some("@etb_80005000"));
+        assert_eq!(entry.unit.as_deref(), Some("a.cpp"));
+        assert!(issues_at(&report, 0x80005100).is_empty());
+
+        let entry = &report.entries[1];
+        assert_eq!(entry.function, None);
+        assert_eq!(entry.extab.as_deref(), Some("@etb_80005008"));
+        assert_eq!(issues_at(&report, 0x8000510C), ["No function @ 0x80003110"]);
+
+        assert_eq!(issues_at(&report, 0x80005118), ["Misaligned extab address 0x80005002"]);
+        let issues = issues_at(&report, 0x80005124);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].starts_with("Invalid function address"));
+    }
+
+    #[test]
+    fn test_write_extab_report() {
+        let report = extab_report(&extab_obj()).unwrap();
+        let mut out = vec![];
+        write_extab_report(&mut out, &report).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("fn_a @ 0x80003100 (size 0x10) in a.cpp\n"));
+        assert!(text.contains("3 issues found:\n"));
+    }
+}
@@ -0,0 +1,52 @@
+use std::{ffi::OsStr, fmt, str::FromStr};
+
+use argp::FromArgValue;
+use typed_path::Utf8NativePath;
+
+/// Output format for graphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Json,
+    Dot,
+}
+
+impl GraphFormat {
+    /// Picks the format from the output file extension. (`.dot` or `.gv` for DOT)
+    pub fn from_path(path: &Utf8NativePath) -> Self {
+        match path.extension() {
+            Some("dot" | "gv") => Self::Dot,
+            _ => Self::Json,
+        }
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "dot" => Ok(Self::Dot),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for GraphFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            Self::Dot => write!(f, "dot"),
+        }
+    }
+}
+
+impl FromArgValue for GraphFormat {
+    fn from_arg_value(value: &OsStr) -> Result<Self, String> {
+        String::from_arg_value(value)
+            .and_then(|s| Self::from_str(&s).map_err(|_| "Invalid graph format".to_string()))
+    }
+}
+
+/// Escapes a string for use within a quoted DOT ID.
+pub fn dot_escape(s: &str) -> String { s.replace('\\', "\\\\").replace('"', "\\\"") }
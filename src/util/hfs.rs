@@ -0,0 +1,913 @@
+use std::{
+    collections::HashMap,
+    io,
+    io::{Read, Seek, SeekFrom},
+};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use zerocopy::{big_endian::*, FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::{static_assert, util::mac::decode_mac_roman};
+
+pub const HFS_SIGNATURE: [u8; 2] = *b"BD";
+pub const HFS_PLUS_SIGNATURE: [u8; 2] = *b"H+";
+pub const HFSX_SIGNATURE: [u8; 2] = *b"HX";
+const APM_DRIVER_SIGNATURE: [u8; 2] = *b"ER";
+const APM_PARTITION_SIGNATURE: [u8; 2] = *b"PM";
+
+/// Offset of the master directory block / volume header from the start of the volume.
+const VOLUME_HEADER_OFFSET: u64 = 0x400;
+const SECTOR_SIZE: u64 = 0x200;
+
+/// Parent ID of the root folder.
+pub const HFS_ROOT_PARENT_ID: u32 = 1;
+/// Catalog node ID of the root folder.
+pub const HFS_ROOT_FOLDER_ID: u32 = 2;
+const HFS_CATALOG_FILE_ID: u32 = 4;
+
+const FORK_TYPE_DATA: u8 = 0x00;
+const FORK_TYPE_RESOURCE: u8 = 0xFF;
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsExtentDescriptor {
+    start_block: U16,
+    block_count: U16,
+}
+
+type HfsExtentRecord = [HfsExtentDescriptor; 3];
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsMasterDirectoryBlock {
+    /// Volume signature. ("BD")
+    signature: [u8; 2],
+    _create_date: U32,
+    _modify_date: U32,
+    _attributes: U16,
+    _root_file_count: U16,
+    _bitmap_start: U16,
+    _alloc_ptr: U16,
+    /// Number of allocation blocks in the volume.
+    alloc_block_count: U16,
+    /// Size of an allocation block in bytes.
+    alloc_block_size: U32,
+    _clump_size: U32,
+    /// First allocation block, in 512-byte sectors.
+    alloc_block_start: U16,
+    _next_catalog_id: U32,
+    _free_blocks: U16,
+    /// Volume name. (Pascal string)
+    volume_name: [u8; 28],
+    _backup_date: U32,
+    _backup_seq: U16,
+    _write_count: U32,
+    _extents_clump_size: U32,
+    _catalog_clump_size: U32,
+    _root_dir_count: U16,
+    _file_count: U32,
+    _dir_count: U32,
+    _finder_info: [u8; 32],
+    /// Signature of an embedded HFS+ volume. ("H+" if present)
+    embed_signature: [u8; 2],
+    /// Location of an embedded HFS+ volume, in allocation blocks.
+    embed_extent: HfsExtentDescriptor,
+    /// Size of the extents overflow file.
+    extents_file_size: U32,
+    /// First extents of the extents overflow file.
+    extents_file_extents: HfsExtentRecord,
+    /// Size of the catalog file.
+    catalog_file_size: U32,
+    /// First extents of the catalog file.
+    catalog_file_extents: HfsExtentRecord,
+}
+
+static_assert!(size_of::<HfsMasterDirectoryBlock>() == 0xA2);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsPlusExtentDescriptor {
+    start_block: U32,
+    block_count: U32,
+}
+
+type HfsPlusExtentRecord = [HfsPlusExtentDescriptor; 8];
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsPlusForkData {
+    logical_size: U64,
+    _clump_size: U32,
+    _total_blocks: U32,
+    extents: HfsPlusExtentRecord,
+}
+
+static_assert!(size_of::<HfsPlusForkData>() == 0x50);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsPlusVolumeHeader {
+    /// Volume signature. ("H+" or "HX")
+    signature: [u8; 2],
+    /// Format version. (4 for HFS+, 5 for HFSX)
+    version: U16,
+    _attributes: U32,
+    _last_mounted_version: U32,
+    _journal_info_block: U32,
+    _create_date: U32,
+    _modify_date: U32,
+    _backup_date: U32,
+    _checked_date: U32,
+    _file_count: U32,
+    _folder_count: U32,
+    /// Size of an allocation block in bytes.
+    block_size: U32,
+    /// Number of allocation blocks in the volume.
+    total_blocks: U32,
+    _free_blocks: U32,
+    _next_allocation: U32,
+    _rsrc_clump_size: U32,
+    _data_clump_size: U32,
+    _next_catalog_id: U32,
+    _write_count: U32,
+    _encodings_bitmap: U64,
+    _finder_info: [u8; 32],
+    _allocation_file: HfsPlusForkData,
+    /// Extents overflow file.
+    extents_file: HfsPlusForkData,
+    /// Catalog file.
+    catalog_file: HfsPlusForkData,
+    _attributes_file: HfsPlusForkData,
+    _startup_file: HfsPlusForkData,
+}
+
+static_assert!(size_of::<HfsPlusVolumeHeader>() == 0x200);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct BTreeNodeDescriptor {
+    /// Next node of this kind.
+    forward_link: U32,
+    _backward_link: U32,
+    /// Node kind. (-1 = leaf, 0 = index, 1 = header, 2 = map)
+    kind: i8,
+    _height: u8,
+    /// Number of records in the node.
+    record_count: U16,
+    _reserved: U16,
+}
+
+static_assert!(size_of::<BTreeNodeDescriptor>() == 0xE);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct BTreeHeaderRecord {
+    _depth: U16,
+    _root_node: U32,
+    _leaf_records: U32,
+    /// First leaf node.
+    first_leaf_node: U32,
+    _last_leaf_node: U32,
+    /// Size of a node in bytes.
+    node_size: U16,
+    _max_key_length: U16,
+    /// Total number of nodes in the tree.
+    total_nodes: U32,
+    _free_nodes: U32,
+}
+
+static_assert!(size_of::<BTreeHeaderRecord>() == 0x1E);
+
+const BTREE_LEAF_NODE: i8 = -1;
+const BTREE_HEADER_NODE: i8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsCatalogFile {
+    _record_type: u8,
+    _reserved: u8,
+    _flags: u8,
+    _file_type: u8,
+    /// Finder file type.
+    finder_type: [u8; 4],
+    /// Finder file creator.
+    finder_creator: [u8; 4],
+    _finder_info: [u8; 8],
+    /// Catalog node ID.
+    file_id: U32,
+    _data_start_block: U16,
+    /// Logical length of the data fork.
+    data_len: U32,
+    _data_physical_len: U32,
+    _rsrc_start_block: U16,
+    /// Logical length of the resource fork.
+    rsrc_len: U32,
+    _rsrc_physical_len: U32,
+    _create_date: U32,
+    /// Date and time of last modification.
+    modify_date: U32,
+    _backup_date: U32,
+    _extended_finder_info: [u8; 16],
+    _clump_size: U16,
+    /// First extents of the data fork.
+    data_extents: HfsExtentRecord,
+    /// First extents of the resource fork.
+    rsrc_extents: HfsExtentRecord,
+    _reserved2: U32,
+}
+
+static_assert!(size_of::<HfsCatalogFile>() == 0x66);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsCatalogFolder {
+    _record_type: u8,
+    _reserved: u8,
+    _flags: U16,
+    _valence: U16,
+    /// Catalog node ID.
+    folder_id: U32,
+    _create_date: U32,
+    /// Date and time of last modification.
+    modify_date: U32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsPlusCatalogFile {
+    _record_type: U16,
+    _flags: U16,
+    _reserved1: U32,
+    /// Catalog node ID.
+    file_id: U32,
+    _create_date: U32,
+    /// Date and time of last modification.
+    modify_date: U32,
+    _attribute_mod_date: U32,
+    _access_date: U32,
+    _backup_date: U32,
+    _permissions: [u8; 16],
+    /// Finder file type.
+    finder_type: [u8; 4],
+    /// Finder file creator.
+    finder_creator: [u8; 4],
+    _finder_info: [u8; 8],
+    _extended_finder_info: [u8; 16],
+    _text_encoding: U32,
+    _reserved2: U32,
+    /// Data fork.
+    data_fork: HfsPlusForkData,
+    /// Resource fork.
+    rsrc_fork: HfsPlusForkData,
+}
+
+static_assert!(size_of::<HfsPlusCatalogFile>() == 0xF8);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct HfsPlusCatalogFolder {
+    _record_type: U16,
+    _flags: U16,
+    _valence: U32,
+    /// Catalog node ID.
+    folder_id: U32,
+    _create_date: U32,
+    /// Date and time of last modification.
+    modify_date: U32,
+}
+
+const HFS_FOLDER_RECORD: u8 = 1;
+const HFS_FILE_RECORD: u8 = 2;
+const HFS_PLUS_FOLDER_RECORD: u16 = 1;
+const HFS_PLUS_FILE_RECORD: u16 = 2;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HfsKind {
+    Hfs,
+    HfsPlus,
+}
+
+/// A fork's logical length and its extents, in allocation blocks.
+#[derive(Debug, Clone, Default)]
+pub struct HfsFork {
+    pub len: u64,
+    pub extents: Vec<(u32, u32)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum HfsEntryKind {
+    Folder,
+    File { file_type: [u8; 4], creator: [u8; 4], data_fork: HfsFork, rsrc_fork: HfsFork },
+}
+
+#[derive(Debug, Clone)]
+pub struct HfsEntry {
+    pub id: u32,
+    pub parent_id: u32,
+    pub name: String,
+    /// Date and time of last modification, in seconds since 1904-01-01.
+    pub modify_date: u32,
+    pub kind: HfsEntryKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct HfsVolume {
+    pub kind: HfsKind,
+    pub name: String,
+    /// Offset of allocation block 0 within the image.
+    pub blocks_offset: u64,
+    /// Size of an allocation block in bytes.
+    pub block_size: u64,
+    pub entries: Vec<HfsEntry>,
+    /// Catalog node ID to entry index.
+    ids: HashMap<u32, usize>,
+    /// Folder ID to child entry indices.
+    children: HashMap<u32, Vec<usize>>,
+}
+
+impl HfsVolume {
+    pub fn root(&self) -> Option<&HfsEntry> { self.get(HFS_ROOT_FOLDER_ID) }
+
+    pub fn get(&self, id: u32) -> Option<&HfsEntry> { self.ids.get(&id).map(|&i| &self.entries[i]) }
+
+    pub fn children(&self, folder_id: u32) -> impl Iterator<Item = &HfsEntry> + '_ {
+        self.children.get(&folder_id).into_iter().flatten().map(|&i| &self.entries[i])
+    }
+
+    /// Finds an entry by path relative to the root folder. HFS names are
+    /// case-insensitive, so an exact match is preferred before falling
+    /// back to a case-insensitive one.
+    pub fn find(&self, path: &str) -> Option<&HfsEntry> {
+        let mut current = self.root()?;
+        for component in path.split('/').filter(|s| !s.is_empty()) {
+            let HfsEntryKind::Folder = current.kind else {
+                return None;
+            };
+            current = match self.children(current.id).find(|e| e.name == component) {
+                Some(entry) => entry,
+                None => self
+                    .children(current.id)
+                    .find(|e| e.name.to_lowercase() == component.to_lowercase())?,
+            };
+        }
+        Some(current)
+    }
+
+    /// Byte ranges of a fork's contents within the image.
+    pub fn fork_ranges(&self, fork: &HfsFork) -> Vec<(u64, u64)> {
+        let mut ranges = Vec::with_capacity(fork.extents.len());
+        let mut remaining = fork.len;
+        for &(start_block, block_count) in &fork.extents {
+            if remaining == 0 {
+                break;
+            }
+            let len = (block_count as u64 * self.block_size).min(remaining);
+            ranges.push((self.blocks_offset + start_block as u64 * self.block_size, len));
+            remaining -= len;
+        }
+        ranges
+    }
+}
+
+fn read_signature<R>(reader: &mut R, volume_offset: u64) -> io::Result<Option<HfsKind>>
+where R: Read + Seek + ?Sized {
+    let mut buf = [0u8; size_of::<HfsPlusVolumeHeader>()];
+    reader.seek(SeekFrom::Start(volume_offset + VOLUME_HEADER_OFFSET))?;
+    match reader.read_exact(&mut buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let image_len = reader.seek(SeekFrom::End(0))?;
+    match [buf[0], buf[1]] {
+        HFS_SIGNATURE => {
+            let (header, _) = HfsMasterDirectoryBlock::read_from_prefix(&buf).unwrap();
+            let block_size = header.alloc_block_size.get();
+            if block_size == 0 || block_size % SECTOR_SIZE as u32 != 0 {
+                return Ok(None);
+            }
+            if header.alloc_block_count.get() == 0 || header.volume_name[0] > 27 {
+                return Ok(None);
+            }
+            // The allocation blocks must lie within the image
+            let volume_end = volume_offset
+                + header.alloc_block_start.get() as u64 * SECTOR_SIZE
+                + header.alloc_block_count.get() as u64 * block_size as u64;
+            if volume_end > image_len {
+                return Ok(None);
+            }
+            Ok(Some(HfsKind::Hfs))
+        }
+        HFS_PLUS_SIGNATURE | HFSX_SIGNATURE => {
+            let header = HfsPlusVolumeHeader::read_from_bytes(&buf).unwrap();
+            let version = header.version.get();
+            let block_size = header.block_size.get();
+            if !matches!(version, 4 | 5) || block_size < 512 || !block_size.is_power_of_two() {
+                return Ok(None);
+            }
+            let total_blocks = header.total_blocks.get() as u64;
+            if total_blocks == 0 || volume_offset + total_blocks * block_size as u64 > image_len {
+                return Ok(None);
+            }
+            Ok(Some(HfsKind::HfsPlus))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Locates an HFS or HFS+ volume, either at the start of the image or in an
+/// Apple partition map (as used by hybrid ISO9660/HFS CDs).
+pub fn detect_hfs<R>(reader: &mut R) -> io::Result<Option<(u64, HfsKind)>>
+where R: Read + Seek + ?Sized {
+    let result = find_hfs_volume(reader);
+    reader.seek(SeekFrom::Start(0))?;
+    result
+}
+
+fn find_hfs_volume<R>(reader: &mut R) -> io::Result<Option<(u64, HfsKind)>>
+where R: Read + Seek + ?Sized {
+    if let Some(kind) = read_signature(reader, 0)? {
+        return Ok(Some((0, kind)));
+    }
+    let mut block = [0u8; SECTOR_SIZE as usize];
+    reader.seek(SeekFrom::Start(0))?;
+    match reader.read_exact(&mut block) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if block[0..2] != APM_DRIVER_SIGNATURE {
+        return Ok(None);
+    }
+    let mut map_block_count = 1;
+    let mut index = 1;
+    while index <= map_block_count {
+        reader.seek(SeekFrom::Start(index * SECTOR_SIZE))?;
+        reader.read_exact(&mut block)?;
+        if block[0..2] != APM_PARTITION_SIGNATURE {
+            break;
+        }
+        // Partition entry: signature, padding, map block count, start block, block count,
+        // name (32 bytes), type (32 bytes)
+        map_block_count = u32::from_be_bytes(block[4..8].try_into().unwrap()).min(64) as u64;
+        let start = u32::from_be_bytes(block[8..12].try_into().unwrap()) as u64;
+        let partition_type = &block[48..80];
+        if partition_type.starts_with(b"Apple_HFS") {
+            let offset = start * SECTOR_SIZE;
+            if let Some(kind) = read_signature(reader, offset)? {
+                return Ok(Some((offset, kind)));
+            }
+        }
+        index += 1;
+    }
+    Ok(None)
+}
+
+/// Reads the volume catalog, building an in-memory directory tree.
+pub fn process_hfs(data: &[u8]) -> Result<HfsVolume> {
+    let (volume_offset, kind) = detect_hfs(&mut io::Cursor::new(data))?
+        .ok_or_else(|| anyhow!("No HFS or HFS+ volume found"))?;
+    let header = data
+        .get((volume_offset + VOLUME_HEADER_OFFSET) as usize..)
+        .ok_or_else(|| anyhow!("HFS volume header out of bounds"))?;
+    match kind {
+        HfsKind::Hfs => {
+            let (mdb, _) = HfsMasterDirectoryBlock::read_from_prefix(header)
+                .map_err(|_| anyhow!("HFS master directory block truncated"))?;
+            let blocks_offset = volume_offset + mdb.alloc_block_start.get() as u64 * SECTOR_SIZE;
+            let block_size = mdb.alloc_block_size.get() as u64;
+            if mdb.embed_signature == HFS_PLUS_SIGNATURE {
+                // HFS wrapper around an HFS+ volume
+                let embed_offset =
+                    blocks_offset + mdb.embed_extent.start_block.get() as u64 * block_size;
+                return process_hfs_plus(data, embed_offset);
+            }
+            process_hfs_classic(data, &mdb, blocks_offset, block_size)
+        }
+        HfsKind::HfsPlus => process_hfs_plus(data, volume_offset),
+    }
+}
+
+fn process_hfs_classic(
+    data: &[u8],
+    mdb: &HfsMasterDirectoryBlock,
+    blocks_offset: u64,
+    block_size: u64,
+) -> Result<HfsVolume> {
+    let name_len = (mdb.volume_name[0] as usize).min(27);
+    let mut volume = HfsVolume {
+        kind: HfsKind::Hfs,
+        name: decode_mac_roman(&mdb.volume_name[1..1 + name_len]),
+        blocks_offset,
+        block_size,
+        entries: vec![],
+        ids: Default::default(),
+        children: Default::default(),
+    };
+    let hfs_extents = |record: &HfsExtentRecord| -> Vec<(u32, u32)> {
+        record
+            .iter()
+            .filter(|e| e.block_count.get() > 0)
+            .map(|e| (e.start_block.get() as u32, e.block_count.get() as u32))
+            .collect()
+    };
+
+    // Extents overflow file
+    let extents_fork = HfsFork {
+        len: mdb.extents_file_size.get() as u64,
+        extents: hfs_extents(&mdb.extents_file_extents),
+    };
+    let extents_tree = read_fork(data, &volume, &extents_fork)?;
+    let mut overflow = ExtentsOverflow::new();
+    for record in btree_leaf_records(&extents_tree)? {
+        // keyLength, forkType, fileID, startBlock
+        if record.len() < 8 + size_of::<HfsExtentRecord>() || record[0] < 7 {
+            continue;
+        }
+        let fork_type = record[1];
+        let file_id = u32::from_be_bytes(record[2..6].try_into().unwrap());
+        let start_block = u16::from_be_bytes(record[6..8].try_into().unwrap()) as u32;
+        let (extents, _) = HfsExtentRecord::read_from_prefix(&record[8..]).unwrap();
+        overflow
+            .entry((file_id, fork_type))
+            .or_default()
+            .push((start_block, hfs_extents(&extents)));
+    }
+    let resolve = |file_id: u32, fork_type: u8, len: u64, record: &HfsExtentRecord| {
+        let mut fork = HfsFork { len, extents: hfs_extents(record) };
+        append_overflow(&mut fork, &overflow, file_id, fork_type);
+        fork
+    };
+
+    // Catalog file
+    let catalog_fork = resolve(
+        HFS_CATALOG_FILE_ID,
+        FORK_TYPE_DATA,
+        mdb.catalog_file_size.get() as u64,
+        &mdb.catalog_file_extents,
+    );
+    let catalog_tree = read_fork(data, &volume, &catalog_fork)?;
+    for record in btree_leaf_records(&catalog_tree)? {
+        let Some((parent_id, name, body)) = split_hfs_catalog_record(record) else {
+            continue;
+        };
+        match body.first() {
+            Some(&HFS_FOLDER_RECORD) => {
+                let Ok((folder, _)) = HfsCatalogFolder::read_from_prefix(body) else {
+                    continue;
+                };
+                volume.push(HfsEntry {
+                    id: folder.folder_id.get(),
+                    parent_id,
+                    name,
+                    modify_date: folder.modify_date.get(),
+                    kind: HfsEntryKind::Folder,
+                });
+            }
+            Some(&HFS_FILE_RECORD) => {
+                let Ok((file, _)) = HfsCatalogFile::read_from_prefix(body) else {
+                    continue;
+                };
+                let id = file.file_id.get();
+                volume.push(HfsEntry {
+                    id,
+                    parent_id,
+                    name,
+                    modify_date: file.modify_date.get(),
+                    kind: HfsEntryKind::File {
+                        file_type: file.finder_type,
+                        creator: file.finder_creator,
+                        data_fork: resolve(
+                            id,
+                            FORK_TYPE_DATA,
+                            file.data_len.get() as u64,
+                            &file.data_extents,
+                        ),
+                        rsrc_fork: resolve(
+                            id,
+                            FORK_TYPE_RESOURCE,
+                            file.rsrc_len.get() as u64,
+                            &file.rsrc_extents,
+                        ),
+                    },
+                });
+            }
+            _ => {}
+        }
+    }
+    ensure!(volume.root().is_some(), "HFS catalog has no root folder");
+    Ok(volume)
+}
+
+/// Splits a classic HFS catalog leaf record into its parent ID, escaped node name
+/// and record body. Returns `None` for truncated records.
+fn split_hfs_catalog_record(record: &[u8]) -> Option<(u32, String, &[u8])> {
+    // keyLength, reserved, parentID, nodeName (Str31)
+    let key_len = *record.first()? as usize;
+    if key_len < 6 || record.len() < key_len + 1 {
+        return None;
+    }
+    let parent_id = u32::from_be_bytes(record[2..6].try_into().unwrap());
+    let name_len = (record[6] as usize).min(key_len - 6);
+    let name = escape_name(&decode_mac_roman(&record[7..7 + name_len]));
+    // Records are word-aligned after the key
+    let body_start = (key_len + 2) & !1;
+    let body = record.get(body_start..)?;
+    Some((parent_id, name, body))
+}
+
+fn process_hfs_plus(data: &[u8], volume_offset: u64) -> Result<HfsVolume> {
+    let (header, _) = data
+        .get((volume_offset + VOLUME_HEADER_OFFSET) as usize..)
+        .and_then(|d| HfsPlusVolumeHeader::read_from_prefix(d).ok())
+        .ok_or_else(|| anyhow!("HFS+ volume header truncated"))?;
+    ensure!(
+        header.signature == HFS_PLUS_SIGNATURE || header.signature == HFSX_SIGNATURE,
+        "Invalid HFS+ signature"
+    );
+    let mut volume = HfsVolume {
+        kind: HfsKind::HfsPlus,
+        name: String::new(),
+        blocks_offset: volume_offset,
+        block_size: header.block_size.get() as u64,
+        entries: vec![],
+        ids: Default::default(),
+        children: Default::default(),
+    };
+    let hfs_plus_extents = |record: &HfsPlusExtentRecord| -> Vec<(u32, u32)> {
+        record
+            .iter()
+            .filter(|e| e.block_count.get() > 0)
+            .map(|e| (e.start_block.get(), e.block_count.get()))
+            .collect()
+    };
+
+    // Extents overflow file
+    let extents_fork = HfsFork {
+        len: header.extents_file.logical_size.get(),
+        extents: hfs_plus_extents(&header.extents_file.extents),
+    };
+    let extents_tree = read_fork(data, &volume, &extents_fork)?;
+    let mut overflow = ExtentsOverflow::new();
+    for record in btree_leaf_records(&extents_tree)? {
+        // keyLength, forkType, pad, fileID, startBlock
+        if record.len() < 12 + size_of::<HfsPlusExtentRecord>() {
+            continue;
+        }
+        let fork_type = record[2];
+        let file_id = u32::from_be_bytes(record[4..8].try_into().unwrap());
+        let start_block = u32::from_be_bytes(record[8..12].try_into().unwrap());
+        let (extents, _) = HfsPlusExtentRecord::read_from_prefix(&record[12..]).unwrap();
+        overflow
+            .entry((file_id, fork_type))
+            .or_default()
+            .push((start_block, hfs_plus_extents(&extents)));
+    }
+    let resolve = |file_id: u32, fork_type: u8, fork: &HfsPlusForkData| {
+        let mut result =
+            HfsFork { len: fork.logical_size.get(), extents: hfs_plus_extents(&fork.extents) };
+        append_overflow(&mut result, &overflow, file_id, fork_type);
+        result
+    };
+
+    // Catalog file
+    let catalog_fork = resolve(HFS_CATALOG_FILE_ID, FORK_TYPE_DATA, &header.catalog_file);
+    let catalog_tree = read_fork(data, &volume, &catalog_fork)?;
+    for record in btree_leaf_records(&catalog_tree)? {
+        // keyLength, parentID, nodeName (HFSUniStr255)
+        if record.len() < 8 {
+            continue;
+        }
+        let key_len = u16::from_be_bytes(record[0..2].try_into().unwrap()) as usize;
+        if key_len < 6 || record.len() < key_len + 2 {
+            continue;
+        }
+        let parent_id = u32::from_be_bytes(record[2..6].try_into().unwrap());
+        let name_len =
+            (u16::from_be_bytes(record[6..8].try_into().unwrap()) as usize).min((key_len - 6) / 2);
+        let name_units = record[8..8 + name_len * 2]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect::<Vec<u16>>();
+        let name = escape_name(&String::from_utf16_lossy(&name_units));
+        let body = &record[key_len + 2..];
+        let Ok((record_type, _)) = U16::read_from_prefix(body) else {
+            continue;
+        };
+        match record_type.get() {
+            HFS_PLUS_FOLDER_RECORD => {
+                let Ok((folder, _)) = HfsPlusCatalogFolder::read_from_prefix(body) else {
+                    continue;
+                };
+                volume.push(HfsEntry {
+                    id: folder.folder_id.get(),
+                    parent_id,
+                    name,
+                    modify_date: folder.modify_date.get(),
+                    kind: HfsEntryKind::Folder,
+                });
+            }
+            HFS_PLUS_FILE_RECORD => {
+                let Ok((file, _)) = HfsPlusCatalogFile::read_from_prefix(body) else {
+                    continue;
+                };
+                let id = file.file_id.get();
+                volume.push(HfsEntry {
+                    id,
+                    parent_id,
+                    name,
+                    modify_date: file.modify_date.get(),
+                    kind: HfsEntryKind::File {
+                        file_type: file.finder_type,
+                        creator: file.finder_creator,
+                        data_fork: resolve(id, FORK_TYPE_DATA, &file.data_fork),
+                        rsrc_fork: resolve(id, FORK_TYPE_RESOURCE, &file.rsrc_fork),
+                    },
+                });
+            }
+            _ => {}
+        }
+    }
+    ensure!(volume.root().is_some(), "HFS+ catalog has no root folder");
+    Ok(volume)
+}
+
+impl HfsVolume {
+    fn push(&mut self, entry: HfsEntry) {
+        let index = self.entries.len();
+        self.ids.insert(entry.id, index);
+        if entry.parent_id != HFS_ROOT_PARENT_ID {
+            self.children.entry(entry.parent_id).or_default().push(index);
+        } else if entry.id == HFS_ROOT_FOLDER_ID && self.name.is_empty() {
+            self.name = entry.name.clone();
+        }
+        self.entries.push(entry);
+    }
+}
+
+/// Extents overflow records by (file ID, fork type), each with its starting file block.
+type ExtentsOverflow = HashMap<(u32, u8), Vec<(u32, Vec<(u32, u32)>)>>;
+
+/// Appends extents from the extents overflow file, ordered by their starting file block.
+fn append_overflow(fork: &mut HfsFork, overflow: &ExtentsOverflow, file_id: u32, fork_type: u8) {
+    let Some(records) = overflow.get(&(file_id, fork_type)) else {
+        return;
+    };
+    let mut records = records.iter().collect::<Vec<_>>();
+    records.sort_by_key(|(start_block, _)| *start_block);
+    for (_, extents) in records {
+        fork.extents.extend_from_slice(extents);
+    }
+}
+
+/// Reads a fork into memory. Only used for the B-tree files, which are small.
+fn read_fork(data: &[u8], volume: &HfsVolume, fork: &HfsFork) -> Result<Vec<u8>> {
+    ensure!(fork.len <= data.len() as u64, "HFS fork larger than volume: {:#X}", fork.len);
+    let mut out = Vec::with_capacity(fork.len as usize);
+    for (offset, len) in volume.fork_ranges(fork) {
+        let Some(range) = data.get(offset as usize..(offset + len) as usize) else {
+            bail!("HFS extent out of bounds: {:#X}+{:#X}", offset, len);
+        };
+        out.extend_from_slice(range);
+    }
+    ensure!(out.len() as u64 == fork.len, "HFS fork truncated: {:#X}/{:#X}", out.len(), fork.len);
+    Ok(out)
+}
+
+/// Collects all records from the leaf nodes of a B-tree, in key order.
+fn btree_leaf_records(tree: &[u8]) -> Result<Vec<&[u8]>> {
+    if tree.is_empty() {
+        return Ok(vec![]);
+    }
+    let (descriptor, rest) = BTreeNodeDescriptor::read_from_prefix(tree)
+        .map_err(|_| anyhow!("B-tree header node truncated"))?;
+    ensure!(descriptor.kind == BTREE_HEADER_NODE, "Invalid B-tree header node");
+    let (header, _) = BTreeHeaderRecord::read_from_prefix(rest)
+        .map_err(|_| anyhow!("B-tree header record truncated"))?;
+    let node_size = header.node_size.get() as usize;
+    ensure!(node_size >= 512, "Invalid B-tree node size {}", node_size);
+
+    let mut records = Vec::new();
+    let mut node_index = header.first_leaf_node.get();
+    let mut visited = 0;
+    while node_index != 0 {
+        visited += 1;
+        ensure!(visited <= header.total_nodes.get(), "B-tree leaf chain loops");
+        let start = node_index as usize * node_size;
+        let Some(node) = tree.get(start..start + node_size) else {
+            bail!("B-tree node {} out of bounds", node_index);
+        };
+        let (descriptor, _) = BTreeNodeDescriptor::read_from_prefix(node).unwrap();
+        ensure!(descriptor.kind == BTREE_LEAF_NODE, "B-tree node {} is not a leaf", node_index);
+        // Record offsets are stored in reverse at the end of the node,
+        // followed by the offset of the free space.
+        let record_count = descriptor.record_count.get() as usize;
+        let offset_at = |i: usize| -> Option<usize> {
+            let pos = node_size.checked_sub(2 * (i + 1))?;
+            Some(u16::from_be_bytes([node[pos], node[pos + 1]]) as usize)
+        };
+        for i in 0..record_count {
+            let (Some(begin), Some(end)) = (offset_at(i), offset_at(i + 1)) else {
+                bail!("B-tree node {} record table truncated", node_index);
+            };
+            let Some(record) = node.get(begin..end) else {
+                bail!("B-tree node {} record {} out of bounds", node_index, i);
+            };
+            records.push(record);
+        }
+        node_index = descriptor.forward_link.get();
+    }
+    Ok(records)
+}
+
+/// Escapes characters in HFS names that conflict with VFS path separators.
+fn escape_name(name: &str) -> String { name.replace('/', "%2F").replace(':', "%3A") }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog_record(key_len: u8, name: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut record = vec![key_len, 0, 0, 0, 0, 2, name.len() as u8];
+        record.extend_from_slice(name);
+        record.resize((key_len as usize + 2) & !1, 0);
+        record.extend_from_slice(body);
+        record
+    }
+
+    #[test]
+    fn test_split_hfs_catalog_record() {
+        let record = catalog_record(10, b"Game", &[HFS_FOLDER_RECORD, 0]);
+        let (parent_id, name, body) = split_hfs_catalog_record(&record).unwrap();
+        assert_eq!(parent_id, HFS_ROOT_FOLDER_ID);
+        assert_eq!(name, "Game");
+        assert_eq!(body, &[HFS_FOLDER_RECORD, 0]);
+    }
+
+    #[test]
+    fn test_split_hfs_catalog_record_truncated() {
+        // An even key length ends the key one byte before the word-aligned body
+        let record = catalog_record(10, b"Game", &[]);
+        assert!(split_hfs_catalog_record(&record[..11]).is_none());
+        assert!(split_hfs_catalog_record(&record[..5]).is_none());
+        assert!(split_hfs_catalog_record(&[]).is_none());
+        // A key shorter than the parent ID and name length
+        assert!(split_hfs_catalog_record(&[5, 0, 0, 0, 0, 2]).is_none());
+    }
+
+    fn hfs_image(len: usize, block_count: u16) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        let mdb = &mut data[VOLUME_HEADER_OFFSET as usize..];
+        mdb[0..2].copy_from_slice(&HFS_SIGNATURE);
+        mdb[18..20].copy_from_slice(&block_count.to_be_bytes());
+        mdb[20..24].copy_from_slice(&512u32.to_be_bytes());
+        mdb[28..30].copy_from_slice(&3u16.to_be_bytes());
+        data
+    }
+
+    fn hfs_plus_image(len: usize, total_blocks: u32) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        let header = &mut data[VOLUME_HEADER_OFFSET as usize..];
+        header[0..2].copy_from_slice(&HFS_PLUS_SIGNATURE);
+        header[2..4].copy_from_slice(&4u16.to_be_bytes());
+        header[40..44].copy_from_slice(&512u32.to_be_bytes());
+        header[44..48].copy_from_slice(&total_blocks.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_detect_hfs_extent_bounds() {
+        // Allocation blocks start at sector 3 and span 4 * 512 bytes
+        let image = hfs_image(0x1000, 4);
+        let result = detect_hfs(&mut io::Cursor::new(&image)).unwrap();
+        assert_eq!(result, Some((0, HfsKind::Hfs)));
+        let image = hfs_image(0x1000, 6);
+        assert_eq!(detect_hfs(&mut io::Cursor::new(&image)).unwrap(), None);
+        let image = hfs_image(0x1000, 0);
+        assert_eq!(detect_hfs(&mut io::Cursor::new(&image)).unwrap(), None);
+
+        let image = hfs_plus_image(0x1000, 8);
+        let result = detect_hfs(&mut io::Cursor::new(&image)).unwrap();
+        assert_eq!(result, Some((0, HfsKind::HfsPlus)));
+        let image = hfs_plus_image(0x1000, 9);
+        assert_eq!(detect_hfs(&mut io::Cursor::new(&image)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_fork_oversized() {
+        let data = vec![0u8; 0x1000];
+        let volume = HfsVolume {
+            kind: HfsKind::Hfs,
+            name: String::new(),
+            blocks_offset: 0,
+            block_size: 512,
+            entries: vec![],
+            ids: Default::default(),
+            children: Default::default(),
+        };
+        let fork = HfsFork { len: u64::MAX, extents: vec![(0, 8)] };
+        assert!(read_fork(&data, &volume, &fork).is_err());
+        let fork = HfsFork { len: 0x400, extents: vec![(2, 2)] };
+        assert_eq!(read_fork(&data, &volume, &fork).unwrap().len(), 0x400);
+    }
+}
@@ -1,11 +1,48 @@
+use std::collections::BTreeSet;
+
 use anyhow::Result;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use typed_path::{Utf8NativePathBuf, Utf8UnixPath};
 
-use crate::obj::{ObjInfo, ObjKind};
+use crate::obj::{ObjInfo, ObjKind, ObjSection, ObjSectionKind, ObjSymbolKind};
 
 const LCF_TEMPLATE: &str = include_str!("../../assets/ldscript.lcf");
 const LCF_PARTIAL_TEMPLATE: &str = include_str!("../../assets/ldscript_partial.lcf");
+const LD_TEMPLATE: &str = include_str!("../../assets/ldscript.ld");
+const LD_PARTIAL_TEMPLATE: &str = include_str!("../../assets/ldscript_partial.ld");
+
+/// The linker that the generated linker script is for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Linker {
+    /// Metrowerks `mwldeppc`, using an `.lcf` script.
+    #[default]
+    Mwld,
+    /// GNU ld (devkitPPC, Retro68), using an `.ld` script.
+    Gnu,
+}
+
+impl Linker {
+    pub fn ldscript_name(self) -> &'static str {
+        match self {
+            Linker::Mwld => "ldscript.lcf",
+            Linker::Gnu => "ldscript.ld",
+        }
+    }
+
+    pub fn generate_ldscript(
+        self,
+        obj: &ObjInfo,
+        template: Option<&str>,
+        force_active: &[String],
+    ) -> Result<String> {
+        match self {
+            Linker::Mwld => generate_ldscript(obj, template, force_active),
+            Linker::Gnu => generate_gnu_ldscript(obj, template, force_active),
+        }
+    }
+}
 
 pub fn generate_ldscript(
     obj: &ObjInfo,
@@ -34,12 +71,7 @@ pub fn generate_ldscript(
         force_files.push(obj_path.file_name().unwrap().to_string());
     }
 
-    let mut force_active = force_active.to_vec();
-    for (_, symbol) in obj.symbols.iter() {
-        if symbol.flags.is_exported() && symbol.flags.is_global() && !symbol.flags.is_no_write() {
-            force_active.push(symbol.name.clone());
-        }
-    }
+    let force_active = collect_force_active(obj, force_active);
 
     // Hack to handle missing .sbss2 section... what's the proper way?
     let last_section_name = obj.sections.iter().next_back().unwrap().1.name.clone();
@@ -83,20 +115,285 @@ pub fn generate_ldscript_partial(
         force_files.push(obj_path.file_name().unwrap().to_string());
     }
 
+    let force_active = collect_force_active(obj, force_active);
+
+    let out = template
+        .unwrap_or(LCF_PARTIAL_TEMPLATE)
+        .replace("$SECTIONS", &section_defs)
+        .replace("$FORCEACTIVE", &force_active.join("\n    "));
+    Ok(out)
+}
+
+/// Collects the configured force active symbols and all exported globals.
+fn collect_force_active(obj: &ObjInfo, force_active: &[String]) -> Vec<String> {
     let mut force_active = force_active.to_vec();
     for (_, symbol) in obj.symbols.iter() {
         if symbol.flags.is_exported() && symbol.flags.is_global() && !symbol.flags.is_no_write() {
             force_active.push(symbol.name.clone());
         }
     }
+    force_active
+}
+
+pub fn generate_gnu_ldscript(
+    obj: &ObjInfo,
+    template: Option<&str>,
+    force_active: &[String],
+) -> Result<String> {
+    if obj.kind == ObjKind::Relocatable {
+        return generate_gnu_ldscript_partial(obj, template, force_active);
+    }
+
+    let origin = obj.sections.iter().map(|(_, s)| s.address).min().unwrap();
+    let arena_hi = obj.arena_hi.unwrap_or(0x81700000) as u64;
+    let stack_size = match (obj.stack_address, obj.stack_end) {
+        (Some(stack_address), Some(stack_end)) => stack_address - stack_end,
+        _ => 65535, // default
+    };
+
+    let force_active = collect_force_active(obj, force_active);
+    let section_defs = gnu_section_defs(obj, &force_active, false);
+
+    // The entry point is kept by --gc-sections
+    let entry = obj
+        .entry
+        .and_then(|entry| {
+            let (section_index, _) = obj.sections.at_address(entry as u32).ok()?;
+            obj.symbols
+                .kind_at_section_address(section_index, entry as u32, ObjSymbolKind::Function)
+                .ok()
+                .flatten()
+        })
+        .map(|(_, s)| format!("ENTRY({})", s.name))
+        .unwrap_or_default();
+
+    // Keep the original small data bases when known, relative to their section
+    let mut sda_defs = vec![];
+    for (name, section_name, base) in
+        [("_SDA_BASE_", ".sdata", obj.sda_base), ("_SDA2_BASE_", ".sdata2", obj.sda2_base)]
+    {
+        let start = start_label(section_name);
+        match (base, obj.sections.by_name(section_name)?) {
+            (Some(base), Some((_, section))) => {
+                let offset = base as i64 - section.address as i64;
+                if offset < 0 {
+                    sda_defs.push(format!("{} = {} - {:#X};", name, start, -offset));
+                } else {
+                    sda_defs.push(format!("{} = {} + {:#X};", name, start, offset));
+                }
+            }
+            (Some(base), None) => sda_defs.push(format!("{} = {:#010X};", name, base)),
+            (None, Some(_)) => sda_defs.push(format!("{} = {} + 0x8000;", name, start)),
+            (None, None) => {}
+        }
+    }
 
     let out = template
-        .unwrap_or(LCF_PARTIAL_TEMPLATE)
+        .unwrap_or(LD_TEMPLATE)
+        .replace("$ENTRY", &entry)
+        .replace("$EXTERN", &gnu_extern(&force_active))
+        .replace("$ORIGIN", &format!("{:#X}", origin))
+        .replace("$LENGTH", &format!("{:#X}", arena_hi.saturating_sub(origin)))
         .replace("$SECTIONS", &section_defs)
-        .replace("$FORCEACTIVE", &force_active.join("\n    "));
+        .replace("$STACKSIZE", &format!("{:#X}", stack_size))
+        .replace("$ARENAHI", &format!("{:#X}", arena_hi))
+        .replace("$SDA", &sda_defs.join("\n    "));
+    Ok(out)
+}
+
+pub fn generate_gnu_ldscript_partial(
+    obj: &ObjInfo,
+    template: Option<&str>,
+    force_active: &[String],
+) -> Result<String> {
+    let force_active = collect_force_active(obj, force_active);
+    let section_defs = gnu_section_defs(obj, &force_active, true);
+    let out = template
+        .unwrap_or(LD_PARTIAL_TEMPLATE)
+        .replace("$EXTERN", &gnu_extern(&force_active))
+        .replace("$SECTIONS", &section_defs);
     Ok(out)
 }
 
+/// Keeps the force active symbols from being removed by --gc-sections.
+fn gnu_extern(force_active: &[String]) -> String {
+    if force_active.is_empty() {
+        return String::new();
+    }
+    format!("EXTERN(\n    {}\n)", force_active.join("\n    "))
+}
+
+/// Linker generated label for the start of a section, e.g. `_f_text` or `_fextab`.
+fn start_label(section_name: &str) -> String {
+    match section_name.strip_prefix('.') {
+        Some(name) => format!("_f_{}", name),
+        None => format!("_f{}", section_name),
+    }
+}
+
+/// Linker generated label for the end of a section, e.g. `_e_text` or `_eextab`.
+fn end_label(section_name: &str) -> String {
+    match section_name.strip_prefix('.') {
+        Some(name) => format!("_e_{}", name),
+        None => format!("_e{}", section_name),
+    }
+}
+
+/// Input sections of each unit in link order, as (unit, input section name).
+fn gnu_input_sections(obj: &ObjInfo, section: &ObjSection) -> Vec<(String, String)> {
+    let inputs = section
+        .splits
+        .iter()
+        .filter(|(_, split)| !split.common && !split.skip)
+        .map(|(_, split)| {
+            (split.unit.clone(), split.rename.clone().unwrap_or_else(|| section.name.clone()))
+        })
+        .collect::<BTreeSet<_>>();
+    obj.link_order
+        .iter()
+        .flat_map(|unit| inputs.iter().filter(move |(name, _)| *name == unit.name))
+        .cloned()
+        .collect()
+}
+
+/// Input section pattern for a unit's object, e.g. `*/main/foo.o(.text)`.
+///
+/// ld matches file patterns against the whole path an object was given as, which
+/// includes the build directory, so the pattern is anchored at a path separator.
+/// `*` also matches separators, so objects of other units whose path ends with this
+/// unit's path (`*/baz/main/foo.o`) are excluded explicitly.
+fn gnu_input_pattern(unit_paths: &BTreeSet<String>, unit: &str, input: &str) -> String {
+    let path = obj_path_for_unit(unit).with_unix_encoding();
+    let suffix = format!("/{}", path);
+    let excluded = unit_paths
+        .iter()
+        .filter(|other| other.ends_with(&suffix))
+        .map(|other| format!("*/{}", other))
+        .collect::<Vec<_>>();
+    if excluded.is_empty() {
+        format!("*/{}({})", path, input)
+    } else {
+        format!("*/{}(EXCLUDE_FILE({}) {})", path, excluded.join(" "), input)
+    }
+}
+
+/// Generates an output section for each section, placing the input sections of
+/// each unit in link order. Units defining force active symbols are wrapped in
+/// KEEP(), and executables get the same linker generated labels and tables as mwld.
+fn gnu_section_defs(obj: &ObjInfo, force_active: &[String], partial: bool) -> String {
+    let mut keep = BTreeSet::new();
+    for name in force_active {
+        for (_, symbol) in obj.symbols.for_name(name) {
+            let Some(section_index) = symbol.section else {
+                continue;
+            };
+            let section = &obj.sections[section_index];
+            if let Some((_, split)) = section.splits.for_address(symbol.address as u32) {
+                let input = split.rename.clone().unwrap_or_else(|| section.name.clone());
+                keep.insert((split.unit.clone(), input));
+            }
+        }
+    }
+
+    let unit_paths = obj
+        .link_order
+        .iter()
+        .map(|unit| obj_path_for_unit(&unit.name).with_unix_encoding().to_string())
+        .collect::<BTreeSet<_>>();
+    let mut lines = vec![];
+    for (section_index, section) in obj.sections.iter() {
+        let is_bss = section.kind == ObjSectionKind::Bss;
+        // Linker generated tables are reachable from the runtime only
+        let keep_all =
+            matches!(section.name.as_str(), ".init" | "extab" | "extabindex" | ".ctors" | ".dtors");
+        if partial {
+            lines.push(format!("{} :", section.name));
+        } else if is_bss {
+            lines.push(format!("{} ALIGN({:#X}) (NOLOAD) :", section.name, section.align));
+        } else {
+            lines.push(format!("{} ALIGN({:#X}) :", section.name, section.align));
+        }
+        lines.push("{".to_string());
+        let start = start_label(&section.name);
+        if !partial {
+            lines.push(format!("    {} = .;", start));
+            lines.push(format!("    {}_rom = .;", start));
+            match section.name.as_str() {
+                ".ctors" => lines.push("    _ctors = .;".to_string()),
+                ".dtors" => lines.push("    _dtors = .;".to_string()),
+                _ => {}
+            }
+        }
+        for (unit, input) in gnu_input_sections(obj, section) {
+            let pattern = gnu_input_pattern(&unit_paths, &unit, &input);
+            if keep_all || keep.contains(&(unit, input)) {
+                lines.push(format!("    KEEP({})", pattern));
+            } else {
+                lines.push(format!("    {}", pattern));
+            }
+        }
+        if keep_all {
+            lines.push(format!("    KEEP(*({}))", section.name));
+        } else {
+            lines.push(format!("    *({})", section.name));
+        }
+        if section.name == ".bss" {
+            lines.push("    *(COMMON)".to_string());
+        }
+        if !partial {
+            if matches!(section.name.as_str(), ".ctors" | ".dtors") {
+                lines.push("    LONG(0)".to_string());
+            }
+            lines.push(format!("    {} = .;", end_label(&section.name)));
+            for (_, symbol) in obj.symbols.for_section(section_index) {
+                lines.extend(gnu_linker_table(obj, &symbol.name));
+            }
+        }
+        lines.push(if partial { "}" } else { "} > text" }.to_string());
+        lines.push(String::new());
+    }
+    lines.pop();
+    lines.join("\n    ")
+}
+
+/// Generates the tables that mwld creates for the runtime, if `name` is one of them.
+fn gnu_linker_table(obj: &ObjInfo, name: &str) -> Vec<String> {
+    let mut lines = vec![];
+    match name {
+        "_rom_copy_info" => {
+            lines.push("    _rom_copy_info = .;".to_string());
+            for (_, section) in obj.sections.iter().filter(|(_, s)| s.kind != ObjSectionKind::Bss) {
+                let (start, end) = (start_label(&section.name), end_label(&section.name));
+                lines.push(format!(
+                    "    LONG({}_rom) LONG({}) LONG({} - {})",
+                    start, start, end, start
+                ));
+            }
+            lines.push("    LONG(0) LONG(0) LONG(0)".to_string());
+        }
+        "_bss_init_info" => {
+            lines.push("    _bss_init_info = .;".to_string());
+            for (_, section) in obj.sections.by_kind(ObjSectionKind::Bss) {
+                let (start, end) = (start_label(&section.name), end_label(&section.name));
+                lines.push(format!("    LONG({}) LONG({} - {})", start, end, start));
+            }
+            lines.push("    LONG(0) LONG(0)".to_string());
+        }
+        "_eti_init_info" => {
+            lines.push("    _eti_init_info = .;".to_string());
+            if obj.sections.iter().any(|(_, s)| s.name == ".text") {
+                lines.push(
+                    "    LONG(_fextabindex) LONG(_eextabindex) LONG(_f_text) LONG(_e_text - _f_text)"
+                        .to_string(),
+                );
+            }
+            lines.push("    LONG(0) LONG(0) LONG(0) LONG(0)".to_string());
+        }
+        _ => {}
+    }
+    lines
+}
+
 pub fn obj_path_for_unit(unit: &str) -> Utf8NativePathBuf {
     Utf8UnixPath::new(unit).with_encoding().with_extension("o")
 }
@@ -104,3 +401,100 @@ pub fn obj_path_for_unit(unit: &str) -> Utf8NativePathBuf {
 pub fn asm_path_for_unit(unit: &str) -> Utf8NativePathBuf {
     Utf8UnixPath::new(unit).with_encoding().with_extension("s")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, section, split, symbol},
+        ObjUnit,
+    };
+
+    fn unit(name: &str) -> ObjUnit {
+        ObjUnit { name: name.to_string(), autogenerated: false, comment_version: None, order: None }
+    }
+
+    #[test]
+    fn test_gnu_input_pattern() {
+        let unit_paths: BTreeSet<String> =
+            ["main.o", "game/main.o", "game/foo.o"].map(String::from).into_iter().collect();
+        assert_eq!(
+            gnu_input_pattern(&unit_paths, "main.c", ".text"),
+            "*/main.o(EXCLUDE_FILE(*/game/main.o) .text)"
+        );
+        assert_eq!(gnu_input_pattern(&unit_paths, "game/main.c", ".text"), "*/game/main.o(.text)");
+        assert_eq!(gnu_input_pattern(&unit_paths, "game/foo.cpp", ".data"), "*/game/foo.o(.data)");
+    }
+
+    #[test]
+    fn test_generate_gnu_ldscript() {
+        let mut sections = vec![
+            section(".text", ObjSectionKind::Code, 0x80003100, vec![0; 0x10]),
+            section(".sdata", ObjSectionKind::Data, 0x80004000, vec![0; 8]),
+            section(".bss", ObjSectionKind::Bss, 0x80004100, vec![0; 8]),
+        ];
+        sections[0].splits.push(0x80003100, split("main.c", 0x80003108));
+        sections[0].splits.push(0x80003108, split("game/main.c", 0x80003110));
+        sections[1].splits.push(0x80004000, split("game/main.c", 0x80004008));
+        sections[2].splits.push(0x80004100, split("main.c", 0x80004108));
+        let symbols = vec![
+            symbol("main", ObjSymbolKind::Function, 0, 0x80003100, 8),
+            symbol("game_init", ObjSymbolKind::Function, 0, 0x80003108, 8),
+        ];
+        let mut obj = obj(ObjKind::Executable, symbols, sections);
+        obj.entry = Some(0x80003100);
+        obj.sda_base = Some(0x8000BF00);
+        obj.sda2_base = Some(0x80006000);
+        obj.link_order = vec![unit("main.c"), unit("game/main.c")];
+
+        let template = "$ENTRY\n$EXTERN\nSECTIONS\n{\n    $SECTIONS\n    $SDA\n}\n";
+        let force_active = ["game_init".to_string()];
+        let out = generate_gnu_ldscript(&obj, Some(template), &force_active).unwrap();
+        let expected = concat!(
+            "ENTRY(main)\n",
+            "EXTERN(\n",
+            "    game_init\n",
+            ")\n",
+            "SECTIONS\n",
+            "{\n",
+            "    .text ALIGN(0x4) :\n",
+            "    {\n",
+            "        _f_text = .;\n",
+            "        _f_text_rom = .;\n",
+            "        */main.o(EXCLUDE_FILE(*/game/main.o) .text)\n",
+            "        KEEP(*/game/main.o(.text))\n",
+            "        *(.text)\n",
+            "        _e_text = .;\n",
+            "    } > text\n",
+            "    \n",
+            "    .sdata ALIGN(0x4) :\n",
+            "    {\n",
+            "        _f_sdata = .;\n",
+            "        _f_sdata_rom = .;\n",
+            "        */game/main.o(.sdata)\n",
+            "        *(.sdata)\n",
+            "        _e_sdata = .;\n",
+            "    } > text\n",
+            "    \n",
+            "    .bss ALIGN(0x4) (NOLOAD) :\n",
+            "    {\n",
+            "        _f_bss = .;\n",
+            "        _f_bss_rom = .;\n",
+            "        */main.o(EXCLUDE_FILE(*/game/main.o) .bss)\n",
+            "        *(.bss)\n",
+            "        *(COMMON)\n",
+            "        _e_bss = .;\n",
+            "    } > text\n",
+            "    _SDA_BASE_ = _f_sdata + 0x7F00;\n",
+            "    _SDA2_BASE_ = 0x80006000;\n",
+            "}\n",
+        );
+        assert_eq!(out, expected);
+
+        // Without known bases, they are placed 0x8000 into the small data sections
+        obj.sda_base = None;
+        obj.sda2_base = None;
+        let out = generate_gnu_ldscript(&obj, Some("$SDA"), &force_active).unwrap();
+        assert_eq!(out, "_SDA_BASE_ = _f_sdata + 0x8000;");
+    }
+}
@@ -0,0 +1,668 @@
+use std::{
+    io,
+    io::{Read, Seek, SeekFrom},
+};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use zerocopy::{big_endian::*, FromBytes, FromZeros, Immutable, IntoBytes, KnownLayout};
+
+use crate::static_assert;
+
+pub const APPLE_SINGLE_MAGIC: [u8; 4] = [0x00, 0x05, 0x16, 0x00];
+pub const APPLE_DOUBLE_MAGIC: [u8; 4] = [0x00, 0x05, 0x16, 0x07];
+pub const MACBINARY_III_SIGNATURE: [u8; 4] = *b"mBIN";
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct MacBinaryHeader {
+    /// Old version number. (Always 0)
+    old_version: u8,
+    /// Length of the file name.
+    name_len: u8,
+    /// File name. (Pascal string contents)
+    name: [u8; 63],
+    /// Finder file type.
+    file_type: [u8; 4],
+    /// Finder file creator.
+    creator: [u8; 4],
+    _finder_flags: u8,
+    /// Always 0
+    zero1: u8,
+    _vertical: U16,
+    _horizontal: U16,
+    _folder_id: U16,
+    _protected: u8,
+    /// Always 0
+    zero2: u8,
+    /// Length of the data fork.
+    data_len: U32,
+    /// Length of the resource fork.
+    rsrc_len: U32,
+    _created: U32,
+    _modified: U32,
+    /// Length of the Get Info comment. (MacBinary II)
+    _comment_len: U16,
+    _finder_flags2: u8,
+    /// Signature. ("mBIN" for MacBinary III)
+    signature: [u8; 4],
+    _script: u8,
+    _extended_finder_flags: u8,
+    _unused: [u8; 8],
+    _total_unpacked_len: U32,
+    /// Length of the secondary header. (MacBinary II)
+    secondary_header_len: U16,
+    /// Version of MacBinary used to write the file. (MacBinary II)
+    _version: u8,
+    /// Minimum version of MacBinary needed to read the file. (MacBinary II)
+    _min_version: u8,
+    /// CRC-16/XMODEM of the previous 124 bytes. (MacBinary II)
+    crc: U16,
+    _reserved: [u8; 2],
+}
+
+static_assert!(size_of::<MacBinaryHeader>() == 0x80);
+
+impl MacBinaryHeader {
+    /// Checks the header for validity against the total file length.
+    fn is_valid(&self, file_len: u64) -> bool {
+        if self.old_version != 0
+            || self.zero1 != 0
+            || self.name_len == 0
+            || self.name_len as usize > self.name.len()
+        {
+            return false;
+        }
+        let bytes = self.as_bytes();
+        let has_crc = crc16_xmodem(&bytes[..0x7C]) == self.crc.get();
+        if !has_crc && self.signature != MACBINARY_III_SIGNATURE {
+            // MacBinary I: everything after the fork lengths must be zero
+            if self.zero2 != 0 || bytes[0x63..].iter().any(|&b| b != 0) {
+                return false;
+            }
+        }
+        let data_len = self.data_len.get() as u64;
+        let rsrc_len = self.rsrc_len.get() as u64;
+        let end = self.data_offset() + align_up(data_len, 0x80) + rsrc_len;
+        end <= file_len && data_len + rsrc_len > 0
+    }
+
+    /// Offset of the data fork, after the secondary header.
+    pub fn data_offset(&self) -> u64 {
+        0x80 + align_up(self.secondary_header_len.get() as u64, 0x80)
+    }
+
+    /// Offset of the resource fork, after the data fork.
+    pub fn rsrc_offset(&self) -> u64 {
+        self.data_offset() + align_up(self.data_len.get() as u64, 0x80)
+    }
+
+    pub fn name(&self) -> String { decode_mac_roman(&self.name[..self.name_len as usize]) }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct AppleSingleHeader {
+    /// Magic identifier. (AppleSingle or AppleDouble)
+    magic: [u8; 4],
+    /// Format version. (0x00010000 or 0x00020000)
+    _version: U32,
+    _filler: [u8; 16],
+    /// Number of entries following the header.
+    entry_count: U16,
+}
+
+static_assert!(size_of::<AppleSingleHeader>() == 0x1A);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct AppleSingleEntry {
+    /// Entry ID. (1 = data fork, 2 = resource fork, 3 = real name, 9 = Finder info)
+    id: U32,
+    /// Offset of the entry data from the start of the file.
+    offset: U32,
+    /// Length of the entry data.
+    length: U32,
+}
+
+static_assert!(size_of::<AppleSingleEntry>() == 0xC);
+
+const APPLE_SINGLE_DATA_FORK: u32 = 1;
+const APPLE_SINGLE_RESOURCE_FORK: u32 = 2;
+const APPLE_SINGLE_REAL_NAME: u32 = 3;
+const APPLE_SINGLE_FINDER_INFO: u32 = 9;
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct ResourceForkHeader {
+    /// Offset of the resource data from the start of the fork.
+    data_offset: U32,
+    /// Offset of the resource map from the start of the fork.
+    map_offset: U32,
+    /// Length of the resource data.
+    data_len: U32,
+    /// Length of the resource map.
+    map_len: U32,
+}
+
+static_assert!(size_of::<ResourceForkHeader>() == 0x10);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct ResourceMapHeader {
+    /// Copy of the fork header. (Often zeroed)
+    _header: [u8; 16],
+    _next_map: U32,
+    _file_ref: U16,
+    _attributes: U16,
+    /// Offset of the type list from the start of the map.
+    type_list_offset: U16,
+    /// Offset of the name list from the start of the map.
+    name_list_offset: U16,
+}
+
+static_assert!(size_of::<ResourceMapHeader>() == 0x1C);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct ResourceTypeEntry {
+    /// Resource type. (e.g. "CODE")
+    res_type: [u8; 4],
+    /// Number of resources of this type, minus one.
+    count_minus_one: U16,
+    /// Offset of the reference list from the start of the type list.
+    ref_list_offset: U16,
+}
+
+static_assert!(size_of::<ResourceTypeEntry>() == 0x8);
+
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct ResourceRefEntry {
+    /// Resource ID.
+    id: I16,
+    /// Offset of the name from the start of the name list. (0xFFFF if none)
+    name_offset: U16,
+    /// Resource attributes.
+    attributes: u8,
+    /// Offset of the resource data (24-bit), relative to the start of the resource data.
+    data_offset: [u8; 3],
+    _handle: U32,
+}
+
+static_assert!(size_of::<ResourceRefEntry>() == 0xC);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MacContainerKind {
+    MacBinary,
+    AppleSingle,
+    AppleDouble,
+    ResourceFork,
+}
+
+/// Location of a fork within a container.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MacFork {
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MacFile {
+    pub kind: MacContainerKind,
+    pub name: Option<String>,
+    pub file_type: Option<[u8; 4]>,
+    pub creator: Option<[u8; 4]>,
+    pub data_fork: Option<MacFork>,
+    pub resource_fork: Option<MacFork>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MacResource {
+    pub res_type: [u8; 4],
+    pub id: i16,
+    pub name: Option<String>,
+    pub attributes: u8,
+    /// Offset of the resource contents from the start of the fork.
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[inline]
+fn align_up(value: u64, alignment: u64) -> u64 { (value + alignment - 1) & !(alignment - 1) }
+
+/// CRC-16/XMODEM, as used by MacBinary II headers.
+fn crc16_xmodem(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Detects containers without a fixed magic (MacBinary and bare resource forks).
+/// AppleSingle and AppleDouble are detected by their magic in [`crate::vfs::detect`].
+pub fn detect_mac_file<R>(reader: &mut R) -> io::Result<Option<MacContainerKind>>
+where R: Read + Seek + ?Sized {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 0x80];
+    let read = if file_len >= 0x80 {
+        reader.read_exact(&mut header)?;
+        0x80
+    } else if file_len >= 0x10 {
+        reader.read_exact(&mut header[..0x10])?;
+        0x10
+    } else {
+        reader.seek(SeekFrom::Start(0))?;
+        return Ok(None);
+    };
+    let mut result = None;
+    if read == 0x80 && MacBinaryHeader::read_from_bytes(&header).unwrap().is_valid(file_len) {
+        result = Some(MacContainerKind::MacBinary);
+    } else {
+        let (fork_header, _) = ResourceForkHeader::read_from_prefix(&header).unwrap();
+        if is_resource_fork_header(&fork_header, file_len) {
+            let mut map_header = ResourceMapHeader::new_zeroed();
+            reader.seek(SeekFrom::Start(fork_header.map_offset.get() as u64))?;
+            reader.read_exact(map_header.as_mut_bytes())?;
+            let map_len = fork_header.map_len.get();
+            if (map_header.type_list_offset.get() as u32) < map_len
+                && (map_header.name_list_offset.get() as u32) <= map_len
+            {
+                result = Some(MacContainerKind::ResourceFork);
+            }
+        }
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(result)
+}
+
+fn is_resource_fork_header(header: &ResourceForkHeader, fork_len: u64) -> bool {
+    let data_offset = header.data_offset.get() as u64;
+    let map_offset = header.map_offset.get() as u64;
+    let data_len = header.data_len.get() as u64;
+    let map_len = header.map_len.get() as u64;
+    data_offset >= 0x10
+        && map_offset >= data_offset + data_len
+        && map_len >= size_of::<ResourceMapHeader>() as u64 + 2
+        && map_offset + map_len == fork_len
+}
+
+/// Parses the container structure, locating the data and resource forks.
+pub fn process_mac_file(data: &[u8], kind: MacContainerKind) -> Result<MacFile> {
+    match kind {
+        MacContainerKind::MacBinary => {
+            let (header, _) = MacBinaryHeader::read_from_prefix(data)
+                .map_err(|_| anyhow!("MacBinary header too short"))?;
+            ensure!(header.is_valid(data.len() as u64), "Invalid MacBinary header");
+            let data_len = header.data_len.get() as u64;
+            let rsrc_len = header.rsrc_len.get() as u64;
+            Ok(MacFile {
+                kind,
+                name: Some(header.name()),
+                file_type: Some(header.file_type),
+                creator: Some(header.creator),
+                data_fork: (data_len > 0)
+                    .then(|| MacFork { offset: header.data_offset(), len: data_len }),
+                resource_fork: (rsrc_len > 0)
+                    .then(|| MacFork { offset: header.rsrc_offset(), len: rsrc_len }),
+            })
+        }
+        MacContainerKind::AppleSingle | MacContainerKind::AppleDouble => {
+            let (header, mut entries) = AppleSingleHeader::read_from_prefix(data)
+                .map_err(|_| anyhow!("AppleSingle header too short"))?;
+            ensure!(
+                header.magic == APPLE_SINGLE_MAGIC || header.magic == APPLE_DOUBLE_MAGIC,
+                "Invalid AppleSingle magic"
+            );
+            let mut result = MacFile {
+                kind,
+                name: None,
+                file_type: None,
+                creator: None,
+                data_fork: None,
+                resource_fork: None,
+            };
+            for _ in 0..header.entry_count.get() {
+                let (entry, rest) = AppleSingleEntry::read_from_prefix(entries)
+                    .map_err(|_| anyhow!("AppleSingle entry table truncated"))?;
+                entries = rest;
+                let offset = entry.offset.get() as u64;
+                let len = entry.length.get() as u64;
+                ensure!(
+                    offset + len <= data.len() as u64,
+                    "AppleSingle entry {} out of bounds: {:#X}+{:#X}",
+                    entry.id.get(),
+                    offset,
+                    len
+                );
+                let entry_data = &data[offset as usize..(offset + len) as usize];
+                match entry.id.get() {
+                    APPLE_SINGLE_DATA_FORK => result.data_fork = Some(MacFork { offset, len }),
+                    APPLE_SINGLE_RESOURCE_FORK => {
+                        result.resource_fork = Some(MacFork { offset, len })
+                    }
+                    APPLE_SINGLE_REAL_NAME => {
+                        result.name = Some(decode_mac_roman(entry_data))
+                    }
+                    APPLE_SINGLE_FINDER_INFO if len >= 8 => {
+                        result.file_type = Some(entry_data[0..4].try_into().unwrap());
+                        result.creator = Some(entry_data[4..8].try_into().unwrap());
+                    }
+                    _ => {}
+                }
+            }
+            Ok(result)
+        }
+        MacContainerKind::ResourceFork => Ok(MacFile {
+            kind,
+            name: None,
+            file_type: None,
+            creator: None,
+            data_fork: None,
+            resource_fork: Some(MacFork { offset: 0, len: data.len() as u64 }),
+        }),
+    }
+}
+
+/// Parses the resource map of a resource fork.
+pub fn process_resource_fork(data: &[u8]) -> Result<Vec<MacResource>> {
+    let (header, _) = ResourceForkHeader::read_from_prefix(data)
+        .map_err(|_| anyhow!("Resource fork header too short"))?;
+    let fork_len = data.len() as u64;
+    let data_offset = header.data_offset.get() as u64;
+    let map_offset = header.map_offset.get() as u64;
+    let map_len = header.map_len.get() as u64;
+    ensure!(
+        map_offset + map_len <= fork_len && data_offset + header.data_len.get() as u64 <= fork_len,
+        "Resource fork header out of bounds"
+    );
+    let map = &data[map_offset as usize..(map_offset + map_len) as usize];
+    let (map_header, _) = ResourceMapHeader::read_from_prefix(map)
+        .map_err(|_| anyhow!("Resource map header too short"))?;
+    let type_list = map
+        .get(map_header.type_list_offset.get() as usize..)
+        .ok_or_else(|| anyhow!("Resource type list out of bounds"))?;
+    let name_list = map.get(map_header.name_list_offset.get() as usize..).unwrap_or_default();
+    let (type_count, mut types) =
+        U16::read_from_prefix(type_list).map_err(|_| anyhow!("Resource type list truncated"))?;
+    // The count is stored minus one; an empty map stores 0xFFFF.
+    let type_count = type_count.get().wrapping_add(1);
+
+    let mut resources = Vec::new();
+    for _ in 0..type_count {
+        let (type_entry, rest) = ResourceTypeEntry::read_from_prefix(types)
+            .map_err(|_| anyhow!("Resource type list truncated"))?;
+        types = rest;
+        let mut refs = type_list
+            .get(type_entry.ref_list_offset.get() as usize..)
+            .ok_or_else(|| anyhow!("Resource reference list out of bounds"))?;
+        for _ in 0..=type_entry.count_minus_one.get() {
+            let (entry, rest) = ResourceRefEntry::read_from_prefix(refs)
+                .map_err(|_| anyhow!("Resource reference list truncated"))?;
+            refs = rest;
+            let [b0, b1, b2] = entry.data_offset;
+            let offset =
+                data_offset + u32::from_be_bytes([0, b0, b1, b2]) as u64 + size_of::<u32>() as u64;
+            let Some(len_bytes) = data.get(offset as usize - 4..offset as usize) else {
+                bail!(
+                    "Resource '{}' {} data out of bounds",
+                    fourcc_str(&type_entry.res_type),
+                    entry.id.get()
+                );
+            };
+            let len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as u64;
+            ensure!(
+                offset + len <= fork_len,
+                "Resource '{}' {} data out of bounds",
+                fourcc_str(&type_entry.res_type),
+                entry.id.get()
+            );
+            let name = match entry.name_offset.get() {
+                0xFFFF => None,
+                name_offset => name_list.get(name_offset as usize..).and_then(|s| {
+                    let len = *s.first()? as usize;
+                    Some(decode_mac_roman(s.get(1..1 + len)?))
+                }),
+            };
+            resources.push(MacResource {
+                res_type: type_entry.res_type,
+                id: entry.id.get(),
+                name,
+                attributes: entry.attributes,
+                offset,
+                len,
+            });
+        }
+    }
+    Ok(resources)
+}
+
+/// Upper half of the Mac OS Roman character set.
+const MAC_ROMAN_HIGH: [char; 128] = [
+    'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á', 'à', 'â', 'ä', 'ã', 'å', 'ç', 'é', 'è', 'ê', 'ë', 'í',
+    'ì', 'î', 'ï', 'ñ', 'ó', 'ò', 'ô', 'ö', 'õ', 'ú', 'ù', 'û', 'ü', '†', '°', '¢', '£', '§', '•',
+    '¶', 'ß', '®', '©', '™', '´', '¨', '≠', 'Æ', 'Ø', '∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑', '∏',
+    'π', '∫', 'ª', 'º', 'Ω', 'æ', 'ø', '¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«', '»', '…', '\u{A0}',
+    'À', 'Ã', 'Õ', 'Œ', 'œ', '–', '—', '“', '”', '‘', '’', '÷', '◊', 'ÿ', 'Ÿ', '⁄', '€', '‹', '›',
+    'ﬁ', 'ﬂ', '‡', '·', '‚', '„', '‰', 'Â', 'Ê', 'Á', 'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô',
+    '\u{F8FF}', 'Ò', 'Ú', 'Û', 'Ù', 'ı', 'ˆ', '˜', '¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ',
+];
+
+/// Decodes a Mac OS Roman string, as used by file and resource names.
+pub fn decode_mac_roman(data: &[u8]) -> String {
+    data.iter()
+        .map(|&b| if b < 0x80 { b as char } else { MAC_ROMAN_HIGH[(b - 0x80) as usize] })
+        .collect()
+}
+
+/// Formats a four-character code for use in a path, escaping characters
+/// that aren't printable or conflict with path separators.
+pub fn fourcc_str(code: &[u8; 4]) -> String {
+    let mut out = String::with_capacity(4);
+    for &b in code {
+        if b.is_ascii_graphic() && !matches!(b, b'/' | b':' | b'%' | b'\\') || b == b' ' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    pub(crate) fn macbinary(name: &str, data: &[u8], rsrc: &[u8]) -> Vec<u8> {
+        let mut header = MacBinaryHeader::new_zeroed();
+        header.name_len = name.len() as u8;
+        header.name[..name.len()].copy_from_slice(name.as_bytes());
+        header.file_type = *b"APPL";
+        header.creator = *b"TEST";
+        header.data_len.set(data.len() as u32);
+        header.rsrc_len.set(rsrc.len() as u32);
+        header.crc.set(crc16_xmodem(&header.as_bytes()[..0x7C]));
+        let mut out = header.as_bytes().to_vec();
+        out.extend_from_slice(data);
+        out.resize(align_up(out.len() as u64, 0x80) as usize, 0);
+        out.extend_from_slice(rsrc);
+        out
+    }
+
+    /// Builds a resource fork from (type, ID, name, data) tuples.
+    pub(crate) fn resource_fork(resources: &[([u8; 4], i16, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut types = Vec::<[u8; 4]>::new();
+        for (res_type, ..) in resources {
+            if !types.contains(res_type) {
+                types.push(*res_type);
+            }
+        }
+        let mut data = Vec::new();
+        let mut names = Vec::new();
+        let mut type_list = ((types.len() as u16).wrapping_sub(1)).to_be_bytes().to_vec();
+        let mut ref_lists = Vec::new();
+        let ref_lists_offset = 2 + types.len() * size_of::<ResourceTypeEntry>();
+        for res_type in &types {
+            let refs = resources.iter().filter(|(t, ..)| t == res_type).collect::<Vec<_>>();
+            type_list.extend_from_slice(res_type);
+            type_list.extend_from_slice(&(refs.len() as u16 - 1).to_be_bytes());
+            type_list
+                .extend_from_slice(&((ref_lists_offset + ref_lists.len()) as u16).to_be_bytes());
+            for (_, id, name, contents) in refs {
+                let name_offset = match name {
+                    Some(name) => {
+                        let offset = names.len() as u16;
+                        names.push(name.len() as u8);
+                        names.extend_from_slice(name.as_bytes());
+                        offset
+                    }
+                    None => 0xFFFF,
+                };
+                ref_lists.extend_from_slice(&id.to_be_bytes());
+                ref_lists.extend_from_slice(&name_offset.to_be_bytes());
+                ref_lists.push(0);
+                ref_lists.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+                ref_lists.extend_from_slice(&[0; 4]);
+                data.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+                data.extend_from_slice(contents);
+            }
+        }
+        type_list.extend(ref_lists);
+
+        let map_offset = 0x100 + data.len();
+        let mut map = vec![0; size_of::<ResourceMapHeader>()];
+        map[0x18..0x1A].copy_from_slice(&(size_of::<ResourceMapHeader>() as u16).to_be_bytes());
+        let name_list_offset = (map.len() + type_list.len()) as u16;
+        map[0x1A..0x1C].copy_from_slice(&name_list_offset.to_be_bytes());
+        map.extend(type_list);
+        map.extend(names);
+
+        let mut out = vec![0; 0x100];
+        out[0..4].copy_from_slice(&0x100u32.to_be_bytes());
+        out[4..8].copy_from_slice(&(map_offset as u32).to_be_bytes());
+        out[8..12].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        out[12..16].copy_from_slice(&(map.len() as u32).to_be_bytes());
+        out.extend(data);
+        out.extend(map);
+        out
+    }
+
+    fn apple_double(name: &str, rsrc: &[u8]) -> Vec<u8> {
+        let mut out = APPLE_DOUBLE_MAGIC.to_vec();
+        out.extend_from_slice(&0x00020000u32.to_be_bytes());
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&3u16.to_be_bytes());
+        let mut offset =
+            (size_of::<AppleSingleHeader>() + 3 * size_of::<AppleSingleEntry>()) as u32;
+        let finder_info = b"APPLTEST";
+        for (id, len) in [
+            (APPLE_SINGLE_REAL_NAME, name.len()),
+            (APPLE_SINGLE_FINDER_INFO, finder_info.len()),
+            (APPLE_SINGLE_RESOURCE_FORK, rsrc.len()),
+        ] {
+            out.extend_from_slice(&id.to_be_bytes());
+            out.extend_from_slice(&offset.to_be_bytes());
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+            offset += len as u32;
+        }
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(finder_info);
+        out.extend_from_slice(rsrc);
+        out
+    }
+
+    fn detect(data: &[u8]) -> Option<MacContainerKind> {
+        detect_mac_file(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn test_macbinary() {
+        let file = macbinary("Game", b"Joy!peffpwpc", b"resources");
+        assert_eq!(detect(&file), Some(MacContainerKind::MacBinary));
+        let mac = process_mac_file(&file, MacContainerKind::MacBinary).unwrap();
+        assert_eq!(mac.name.as_deref(), Some("Game"));
+        assert_eq!(mac.file_type, Some(*b"APPL"));
+        assert_eq!(mac.creator, Some(*b"TEST"));
+        assert_eq!(mac.data_fork, Some(MacFork { offset: 0x80, len: 12 }));
+        assert_eq!(mac.resource_fork, Some(MacFork { offset: 0x100, len: 9 }));
+    }
+
+    #[test]
+    fn test_macbinary_truncated() {
+        let file = macbinary("Game", b"Joy!peffpwpc", b"resources");
+        assert_eq!(detect(&file[..file.len() - 1]), None);
+        assert!(process_mac_file(&file[..file.len() - 1], MacContainerKind::MacBinary).is_err());
+        assert!(process_mac_file(&file[..0x40], MacContainerKind::MacBinary).is_err());
+    }
+
+    #[test]
+    fn test_apple_double() {
+        let rsrc = resource_fork(&[(*b"cfrg", 0, None, b"fragments")]);
+        let file = apple_double("Game", &rsrc);
+        let mac = process_mac_file(&file, MacContainerKind::AppleDouble).unwrap();
+        assert_eq!(mac.name.as_deref(), Some("Game"));
+        assert_eq!(mac.file_type, Some(*b"APPL"));
+        assert_eq!(mac.creator, Some(*b"TEST"));
+        assert_eq!(mac.data_fork, None);
+        let fork = mac.resource_fork.unwrap();
+        assert_eq!(fork.len, rsrc.len() as u64);
+        assert_eq!(&file[fork.offset as usize..], rsrc.as_slice());
+        // Entries pointing past the end of the file
+        assert!(process_mac_file(&file[..file.len() - 1], MacContainerKind::AppleDouble).is_err());
+        // Entry table cut short
+        assert!(process_mac_file(&file[..0x20], MacContainerKind::AppleDouble).is_err());
+    }
+
+    #[test]
+    fn test_resource_fork() {
+        let rsrc = resource_fork(&[
+            (*b"cfrg", 0, None, b"fragments"),
+            (*b"CODE", 0, Some("jump table"), b"jt"),
+            (*b"CODE", 1, Some("Main"), b"code"),
+        ]);
+        assert_eq!(detect(&rsrc), Some(MacContainerKind::ResourceFork));
+        let resources = process_resource_fork(&rsrc).unwrap();
+        let summary = resources
+            .iter()
+            .map(|r| {
+                let contents = &rsrc[r.offset as usize..(r.offset + r.len) as usize];
+                (fourcc_str(&r.res_type), r.id, r.name.as_deref(), contents)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            ("cfrg".to_string(), 0, None, b"fragments".as_slice()),
+            ("CODE".to_string(), 0, Some("jump table"), b"jt".as_slice()),
+            ("CODE".to_string(), 1, Some("Main"), b"code".as_slice()),
+        ]);
+    }
+
+    #[test]
+    fn test_resource_fork_truncated() {
+        let rsrc = resource_fork(&[(*b"cfrg", 0, None, b"fragments")]);
+        assert_eq!(detect(&rsrc[..rsrc.len() - 1]), None);
+        assert!(process_resource_fork(&rsrc[..rsrc.len() - 1]).is_err());
+        assert!(process_resource_fork(&rsrc[..8]).is_err());
+    }
+
+    #[test]
+    fn test_not_mac_file() {
+        assert_eq!(detect(&[]), None);
+        assert_eq!(detect(b"too short"), None);
+        // DOL header: text section offsets, addresses and sizes
+        let mut dol = vec![0u8; 0x200];
+        dol[0..4].copy_from_slice(&0x100u32.to_be_bytes());
+        dol[0x48..0x4C].copy_from_slice(&0x80003100u32.to_be_bytes());
+        dol[0x90..0x94].copy_from_slice(&0x100u32.to_be_bytes());
+        assert_eq!(detect(&dol), None);
+        // Text that happens to start with a zero byte
+        let mut text = vec![0u8];
+        text.extend_from_slice(&[b'a'; 0x100]);
+        assert_eq!(detect(&text), None);
+    }
+}
@@ -934,6 +934,7 @@ pub fn create_obj(result: &MapInfo) -> Result<ObjInfo> {
         split_meta: None,
         sda2_base: None,
         sda_base: None,
+        toc_base: None,
         stack_address: None,
         stack_end: None,
         db_stack_addr: None,
@@ -3,6 +3,9 @@ use std::{borrow::Cow, ops::Deref};
 pub mod alf;
 pub mod asm;
 pub mod bin2c;
+pub mod boundaries;
+pub mod cfg;
+pub mod cfrg;
 pub mod comment;
 pub mod config;
 pub mod dep;
@@ -10,18 +13,24 @@ pub mod diff;
 pub mod dol;
 pub mod dwarf;
 pub mod elf;
+pub mod extab;
 pub mod file;
+pub mod graph;
+pub mod hfs;
 pub mod lcf;
+pub mod mac;
 pub mod map;
 pub mod ncompress;
 pub mod nested;
 pub mod nlzss;
 pub mod path;
 pub mod pef;
+pub mod progress;
 pub mod rarc;
 pub mod read;
 pub mod reader;
 pub mod rel;
+pub mod roundtrip;
 pub mod rso;
 pub mod signatures;
 pub mod split;
@@ -30,6 +39,7 @@ pub mod tbtab;
 pub mod toposort;
 pub mod u8_arc;
 pub mod wad;
+pub mod xrefs;
 
 #[inline]
 pub const fn align_up(value: u32, align: u32) -> u32 { (value + (align - 1)) & !(align - 1) }
@@ -1,60 +1,2179 @@
 use std::{
-    collections::BTreeMap, error::Error, io::{self, Cursor, Read, Seek}
+    borrow::Cow,
+    io,
+    io::{BufRead, Cursor, Read, Seek, SeekFrom, Write},
 };
 
-use anyhow::{anyhow, bail, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use itertools::Itertools;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 use crate::{
-    analysis::cfa::{locate_bss_memsets, locate_sda_bases, SectionAddress},
-    array_ref,
+    analysis::{cfa::SectionAddress, read_u32},
     obj::{
-        ObjArchitecture, ObjInfo, ObjKind, ObjSection, ObjSectionKind, ObjSymbol, ObjSymbolFlagSet,
-        ObjSymbolFlags, ObjSymbolKind, SectionIndex,
+        ObjArchitecture, ObjInfo, ObjKind, ObjReloc, ObjRelocKind, ObjSection, ObjSectionKind,
+        ObjSymbol, ObjSymbolFlagSet, ObjSymbolFlags, ObjSymbolKind, ObjSymbolScope, SectionIndex,
+        SymbolIndex,
     },
     util::{
-        alf::{AlfFile, AlfSymbol, ALF_MAGIC},
         align_up,
-        reader::{skip_bytes, Endian, FromReader},
+        config::parse_u32,
+        file::read_c_string,
+        reader::{read_vec, struct_size, write_vec, Endian, FromReader, ToWriter},
     },
 };
 
-use object::{Object, ObjectSection, SectionKind};
-
-pub fn process_pef(buf: &[u8], name: &str) -> Result<ObjInfo> {
-    let pef = object::read::pef::PefFile::parse(&*buf)?;
-
-    let mut sections = vec![];
-    for pef_section in pef.sections() {
-        
-        let (name_str, kind) = match pef_section.kind() {
-            SectionKind::Text => Ok((pef_section.name()?, ObjSectionKind::Code)),
-            SectionKind::Data => Ok((pef_section.name()?, ObjSectionKind::Data)),
-            SectionKind::Metadata => Ok((pef_section.name()?, ObjSectionKind::ReadOnlyData)),
-            _ => Err(anyhow!("unknown section type")),
-        }?;
-    
-        let name = name_str.to_string();
+pub const PEF_MAGIC: [u8; 8] = *b"Joy!peff";
+pub const PEF_ARCH_PPC: [u8; 4] = *b"pwpc";
+pub const PEF_ARCH_68K: [u8; 4] = *b"m68k";
+
+/// PEF sections are position-independent and usually have a default address of 0.
+/// Such sections are instantiated consecutively, starting at this address.
+pub const PEF_IMAGE_BASE: u32 = 0x10000000;
+
+/// Section index used by exports with an absolute value.
+pub const PEF_SECTION_ABS: i16 = -2;
+/// Section index used by exports that re-export an import.
+pub const PEF_SECTION_REEXPORT: i16 = -3;
+
+/// Whether the buffer starts with a PEF container header.
+pub fn is_pef(buf: &[u8]) -> bool { buf.len() >= 8 && buf[..8] == PEF_MAGIC }
+
+#[derive(Debug, Clone)]
+pub struct PefContainerHeader {
+    /// Target architecture, `pwpc` or `m68k`.
+    pub architecture: [u8; 4],
+    /// Container format version. Always 1.
+    pub format_version: u32,
+    /// Creation date, in seconds since 1904-01-01.
+    pub date_time_stamp: u32,
+    /// Oldest version this fragment's definitions are compatible with.
+    pub old_def_version: u32,
+    /// Oldest version this fragment's implementation is compatible with.
+    pub old_imp_version: u32,
+    /// Current fragment version.
+    pub current_version: u32,
+    /// Total number of sections.
+    pub section_count: u16,
+    /// Number of instantiated sections. These always come first.
+    pub inst_section_count: u16,
+}
+
+impl FromReader for PefContainerHeader {
+    type Args = ();
+
+    const STATIC_SIZE: usize = struct_size([
+        8,                // magic
+        4,                // architecture
+        u32::STATIC_SIZE, // format_version
+        u32::STATIC_SIZE, // date_time_stamp
+        u32::STATIC_SIZE, // old_def_version
+        u32::STATIC_SIZE, // old_imp_version
+        u32::STATIC_SIZE, // current_version
+        u16::STATIC_SIZE, // section_count
+        u16::STATIC_SIZE, // inst_section_count
+        u32::STATIC_SIZE, // reserved
+    ]);
+
+    fn from_reader_args<R>(reader: &mut R, e: Endian, _args: Self::Args) -> io::Result<Self>
+    where R: Read + Seek + ?Sized {
+        if <[u8; 8]>::from_reader(reader, e)? != PEF_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid PEF magic"));
+        }
+        let result = Self {
+            architecture: <_>::from_reader(reader, e)?,
+            format_version: <_>::from_reader(reader, e)?,
+            date_time_stamp: <_>::from_reader(reader, e)?,
+            old_def_version: <_>::from_reader(reader, e)?,
+            old_imp_version: <_>::from_reader(reader, e)?,
+            current_version: <_>::from_reader(reader, e)?,
+            section_count: <_>::from_reader(reader, e)?,
+            inst_section_count: <_>::from_reader(reader, e)?,
+        };
+        u32::from_reader(reader, e)?; // reserved
+        Ok(result)
+    }
+}
+
+impl ToWriter for PefContainerHeader {
+    fn to_writer<W>(&self, writer: &mut W, e: Endian) -> io::Result<()>
+    where W: Write + ?Sized {
+        PEF_MAGIC.to_writer(writer, e)?;
+        self.architecture.to_writer(writer, e)?;
+        self.format_version.to_writer(writer, e)?;
+        self.date_time_stamp.to_writer(writer, e)?;
+        self.old_def_version.to_writer(writer, e)?;
+        self.old_imp_version.to_writer(writer, e)?;
+        self.current_version.to_writer(writer, e)?;
+        self.section_count.to_writer(writer, e)?;
+        self.inst_section_count.to_writer(writer, e)?;
+        0u32.to_writer(writer, e)?; // reserved
+        Ok(())
+    }
+
+    fn write_size(&self) -> usize { Self::STATIC_SIZE }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+pub enum PefSectionKind {
+    Code = 0,
+    UnpackedData = 1,
+    PatternInitData = 2,
+    Constant = 3,
+    Loader = 4,
+    Debug = 5,
+    ExecutableData = 6,
+    Exception = 7,
+    Traceback = 8,
+}
+
+#[derive(Debug, Clone)]
+pub struct PefSectionHeader {
+    /// Offset into the section name table, or -1 if unnamed.
+    pub name_offset: i32,
+    /// Preferred address of the section. Usually 0.
+    pub default_address: u32,
+    /// Size of the section in memory, including zero-initialized data.
+    pub total_length: u32,
+    /// Size of the initialized portion of the section in memory.
+    pub unpacked_length: u32,
+    /// Size of the section contents in the container.
+    pub container_length: u32,
+    /// Offset of the section contents in the container.
+    pub container_offset: u32,
+    pub kind: PefSectionKind,
+    pub share_kind: u8,
+    /// Section alignment as a power of 2.
+    pub alignment: u8,
+    /// Resolved from the section name table.
+    pub name: Option<String>,
+}
+
+impl FromReader for PefSectionHeader {
+    type Args = ();
+
+    const STATIC_SIZE: usize = struct_size([
+        i32::STATIC_SIZE, // name_offset
+        u32::STATIC_SIZE, // default_address
+        u32::STATIC_SIZE, // total_length
+        u32::STATIC_SIZE, // unpacked_length
+        u32::STATIC_SIZE, // container_length
+        u32::STATIC_SIZE, // container_offset
+        u8::STATIC_SIZE,  // kind
+        u8::STATIC_SIZE,  // share_kind
+        u8::STATIC_SIZE,  // alignment
+        u8::STATIC_SIZE,  // reserved
+    ]);
+
+    fn from_reader_args<R>(reader: &mut R, e: Endian, _args: Self::Args) -> io::Result<Self>
+    where R: Read + Seek + ?Sized {
+        let name_offset = <_>::from_reader(reader, e)?;
+        let default_address = <_>::from_reader(reader, e)?;
+        let total_length = <_>::from_reader(reader, e)?;
+        let unpacked_length = <_>::from_reader(reader, e)?;
+        let container_length = <_>::from_reader(reader, e)?;
+        let container_offset = <_>::from_reader(reader, e)?;
+        let kind = u8::from_reader(reader, e)?;
+        let kind = PefSectionKind::try_from(kind).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid PEF section kind {kind}"))
+        })?;
+        let share_kind = <_>::from_reader(reader, e)?;
+        let alignment = <_>::from_reader(reader, e)?;
+        u8::from_reader(reader, e)?; // reserved
+        Ok(Self {
+            name_offset,
+            default_address,
+            total_length,
+            unpacked_length,
+            container_length,
+            container_offset,
+            kind,
+            share_kind,
+            alignment,
+            name: None,
+        })
+    }
+}
+
+impl ToWriter for PefSectionHeader {
+    fn to_writer<W>(&self, writer: &mut W, e: Endian) -> io::Result<()>
+    where W: Write + ?Sized {
+        self.name_offset.to_writer(writer, e)?;
+        self.default_address.to_writer(writer, e)?;
+        self.total_length.to_writer(writer, e)?;
+        self.unpacked_length.to_writer(writer, e)?;
+        self.container_length.to_writer(writer, e)?;
+        self.container_offset.to_writer(writer, e)?;
+        u8::from(self.kind).to_writer(writer, e)?;
+        self.share_kind.to_writer(writer, e)?;
+        self.alignment.to_writer(writer, e)?;
+        0u8.to_writer(writer, e)?; // reserved
+        Ok(())
+    }
+
+    fn write_size(&self) -> usize { Self::STATIC_SIZE }
+}
+
+impl PefSectionHeader {
+    /// Whether the section is instantiated in memory by the Code Fragment Manager.
+    pub fn is_instantiated(&self) -> bool {
+        matches!(
+            self.kind,
+            PefSectionKind::Code
+                | PefSectionKind::UnpackedData
+                | PefSectionKind::PatternInitData
+                | PefSectionKind::Constant
+                | PefSectionKind::ExecutableData
+        )
+    }
+
+    /// Whether the section contents are stored pattern-initialized and must be
+    /// expanded with [`unpack_pidata`] before use.
+    pub fn is_packed(&self) -> bool { self.kind == PefSectionKind::PatternInitData }
+}
+
+#[derive(Debug, Clone)]
+pub struct PefLoaderHeader {
+    /// Section containing the main symbol, or -1.
+    pub main_section: i32,
+    pub main_offset: u32,
+    /// Section containing the initialization routine, or -1.
+    pub init_section: i32,
+    pub init_offset: u32,
+    /// Section containing the termination routine, or -1.
+    pub term_section: i32,
+    pub term_offset: u32,
+    pub imported_library_count: u32,
+    pub total_imported_symbol_count: u32,
+    pub reloc_section_count: u32,
+    /// Offset of the relocation instructions from the start of the loader section.
+    pub reloc_instr_offset: u32,
+    /// Offset of the string table from the start of the loader section.
+    pub loader_strings_offset: u32,
+    /// Offset of the export hash table from the start of the loader section.
+    pub export_hash_offset: u32,
+    /// Number of export hash table entries as a power of 2.
+    pub export_hash_table_power: u32,
+    pub exported_symbol_count: u32,
+}
+
+impl FromReader for PefLoaderHeader {
+    type Args = ();
+
+    const STATIC_SIZE: usize = struct_size([
+        i32::STATIC_SIZE, // main_section
+        u32::STATIC_SIZE, // main_offset
+        i32::STATIC_SIZE, // init_section
+        u32::STATIC_SIZE, // init_offset
+        i32::STATIC_SIZE, // term_section
+        u32::STATIC_SIZE, // term_offset
+        u32::STATIC_SIZE, // imported_library_count
+        u32::STATIC_SIZE, // total_imported_symbol_count
+        u32::STATIC_SIZE, // reloc_section_count
+        u32::STATIC_SIZE, // reloc_instr_offset
+        u32::STATIC_SIZE, // loader_strings_offset
+        u32::STATIC_SIZE, // export_hash_offset
+        u32::STATIC_SIZE, // export_hash_table_power
+        u32::STATIC_SIZE, // exported_symbol_count
+    ]);
+
+    fn from_reader_args<R>(reader: &mut R, e: Endian, _args: Self::Args) -> io::Result<Self>
+    where R: Read + Seek + ?Sized {
+        Ok(Self {
+            main_section: <_>::from_reader(reader, e)?,
+            main_offset: <_>::from_reader(reader, e)?,
+            init_section: <_>::from_reader(reader, e)?,
+            init_offset: <_>::from_reader(reader, e)?,
+            term_section: <_>::from_reader(reader, e)?,
+            term_offset: <_>::from_reader(reader, e)?,
+            imported_library_count: <_>::from_reader(reader, e)?,
+            total_imported_symbol_count: <_>::from_reader(reader, e)?,
+            reloc_section_count: <_>::from_reader(reader, e)?,
+            reloc_instr_offset: <_>::from_reader(reader, e)?,
+            loader_strings_offset: <_>::from_reader(reader, e)?,
+            export_hash_offset: <_>::from_reader(reader, e)?,
+            export_hash_table_power: <_>::from_reader(reader, e)?,
+            exported_symbol_count: <_>::from_reader(reader, e)?,
+        })
+    }
+}
+
+impl ToWriter for PefLoaderHeader {
+    fn to_writer<W>(&self, writer: &mut W, e: Endian) -> io::Result<()>
+    where W: Write + ?Sized {
+        self.main_section.to_writer(writer, e)?;
+        self.main_offset.to_writer(writer, e)?;
+        self.init_section.to_writer(writer, e)?;
+        self.init_offset.to_writer(writer, e)?;
+        self.term_section.to_writer(writer, e)?;
+        self.term_offset.to_writer(writer, e)?;
+        self.imported_library_count.to_writer(writer, e)?;
+        self.total_imported_symbol_count.to_writer(writer, e)?;
+        self.reloc_section_count.to_writer(writer, e)?;
+        self.reloc_instr_offset.to_writer(writer, e)?;
+        self.loader_strings_offset.to_writer(writer, e)?;
+        self.export_hash_offset.to_writer(writer, e)?;
+        self.export_hash_table_power.to_writer(writer, e)?;
+        self.exported_symbol_count.to_writer(writer, e)?;
+        Ok(())
+    }
+
+    fn write_size(&self) -> usize { Self::STATIC_SIZE }
+}
+
+#[derive(Debug, Clone)]
+pub struct PefImportedLibrary {
+    /// Offset into the loader string table.
+    pub name_offset: u32,
+    pub old_imp_version: u32,
+    pub current_version: u32,
+    pub imported_symbol_count: u32,
+    /// Index of the first symbol in the imported symbol table.
+    pub first_imported_symbol: u32,
+    pub options: u8,
+    /// Resolved from the loader string table.
+    pub name: String,
+}
+
+impl FromReader for PefImportedLibrary {
+    type Args = ();
+
+    const STATIC_SIZE: usize = struct_size([
+        u32::STATIC_SIZE, // name_offset
+        u32::STATIC_SIZE, // old_imp_version
+        u32::STATIC_SIZE, // current_version
+        u32::STATIC_SIZE, // imported_symbol_count
+        u32::STATIC_SIZE, // first_imported_symbol
+        u8::STATIC_SIZE,  // options
+        u8::STATIC_SIZE,  // reserved_a
+        u16::STATIC_SIZE, // reserved_b
+    ]);
+
+    fn from_reader_args<R>(reader: &mut R, e: Endian, _args: Self::Args) -> io::Result<Self>
+    where R: Read + Seek + ?Sized {
+        let result = Self {
+            name_offset: <_>::from_reader(reader, e)?,
+            old_imp_version: <_>::from_reader(reader, e)?,
+            current_version: <_>::from_reader(reader, e)?,
+            imported_symbol_count: <_>::from_reader(reader, e)?,
+            first_imported_symbol: <_>::from_reader(reader, e)?,
+            options: <_>::from_reader(reader, e)?,
+            name: String::new(),
+        };
+        u8::from_reader(reader, e)?; // reserved_a
+        u16::from_reader(reader, e)?; // reserved_b
+        Ok(result)
+    }
+}
+
+impl ToWriter for PefImportedLibrary {
+    fn to_writer<W>(&self, writer: &mut W, e: Endian) -> io::Result<()>
+    where W: Write + ?Sized {
+        self.name_offset.to_writer(writer, e)?;
+        self.old_imp_version.to_writer(writer, e)?;
+        self.current_version.to_writer(writer, e)?;
+        self.imported_symbol_count.to_writer(writer, e)?;
+        self.first_imported_symbol.to_writer(writer, e)?;
+        self.options.to_writer(writer, e)?;
+        0u8.to_writer(writer, e)?; // reserved_a
+        0u16.to_writer(writer, e)?; // reserved_b
+        Ok(())
+    }
+
+    fn write_size(&self) -> usize { Self::STATIC_SIZE }
+}
+
+impl PefImportedLibrary {
+    /// Library must be initialized before the importing fragment.
+    pub const INIT_BEFORE: u8 = 0x80;
+    /// Library is optional, its imports are weak.
+    pub const WEAK_IMPORT: u8 = 0x40;
+
+    pub fn is_weak(&self) -> bool { self.options & Self::WEAK_IMPORT != 0 }
+
+    pub fn init_before(&self) -> bool { self.options & Self::INIT_BEFORE != 0 }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+pub enum PefSymbolClass {
+    Code = 0,
+    Data = 1,
+    TVector = 2,
+    Toc = 3,
+    Glue = 4,
+}
+
+impl PefSymbolClass {
+    const MASK: u8 = 0x0F;
+    pub const WEAK: u8 = 0x80;
+
+    fn parse(class: u8) -> io::Result<Self> {
+        Self::try_from(class & Self::MASK).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid PEF symbol class {class}"))
+        })
+    }
+
+    pub fn symbol_kind(self) -> ObjSymbolKind {
+        match self {
+            PefSymbolClass::Code | PefSymbolClass::Glue => ObjSymbolKind::Function,
+            PefSymbolClass::Data | PefSymbolClass::TVector | PefSymbolClass::Toc => {
+                ObjSymbolKind::Object
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PefImportedSymbol {
+    pub name: String,
+    pub class: PefSymbolClass,
+    pub weak: bool,
+    /// Index of the library this symbol is imported from.
+    pub library: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PefExportedSymbol {
+    pub name: String,
+    pub class: PefSymbolClass,
+    /// Section-relative offset, absolute value or import index,
+    /// depending on `section_index`.
+    pub value: u32,
+    /// PEF section index, [`PEF_SECTION_ABS`] or [`PEF_SECTION_REEXPORT`].
+    pub section_index: i16,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PefRelocTarget {
+    /// Adds the address of a section (by PEF section index).
+    Section(u16),
+    /// Adds the address of an imported symbol (by import index).
+    Import(u32),
+}
+
+/// A single relocated word, decoded from the loader relocation instructions.
+#[derive(Debug, Clone)]
+pub struct PefReloc {
+    /// PEF section index of the relocated word.
+    pub section: u16,
+    /// Offset of the relocated word within the section.
+    pub offset: u32,
+    pub target: PefRelocTarget,
+}
+
+#[derive(Debug, Clone)]
+pub struct PefLoader {
+    pub header: PefLoaderHeader,
+    pub libraries: Vec<PefImportedLibrary>,
+    pub imports: Vec<PefImportedSymbol>,
+    pub exports: Vec<PefExportedSymbol>,
+    pub relocations: Vec<PefReloc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PefFile {
+    pub header: PefContainerHeader,
+    pub sections: Vec<PefSectionHeader>,
+    pub loader: Option<PefLoader>,
+}
+
+impl PefFile {
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(buf);
+        let header = PefContainerHeader::from_reader(&mut reader, Endian::Big)?;
+        let mut sections: Vec<PefSectionHeader> =
+            read_vec(&mut reader, header.section_count as usize, Endian::Big)?;
+        let names_offset = reader.stream_position()?;
+        for section in &mut sections {
+            if section.name_offset >= 0 {
+                section.name =
+                    Some(read_c_string(&mut reader, names_offset + section.name_offset as u64)?);
+            }
+            let end = section.container_offset as u64 + section.container_length as u64;
+            ensure!(
+                end <= buf.len() as u64,
+                "PEF section {:?} data {:#X}-{:#X} out of bounds",
+                section.kind,
+                section.container_offset,
+                end
+            );
+        }
+        let loader = match sections.iter().find(|s| s.kind == PefSectionKind::Loader) {
+            Some(section) => Some(
+                parse_loader(&mut reader, section, header.inst_section_count)
+                    .context("Failed to parse PEF loader section")?,
+            ),
+            None => None,
+        };
+        Ok(Self { header, sections, loader })
+    }
+
+    /// Raw contents of a section, as stored in the container.
+    pub fn section_data<'a>(&self, buf: &'a [u8], index: usize) -> Result<&'a [u8]> {
+        let section =
+            self.sections.get(index).ok_or_else(|| anyhow!("Invalid PEF section {index}"))?;
+        let start = section.container_offset as usize;
+        Ok(&buf[start..start + section.container_length as usize])
+    }
+}
+
+fn parse_loader<R>(
+    reader: &mut R,
+    section: &PefSectionHeader,
+    inst_section_count: u16,
+) -> Result<PefLoader>
+where
+    R: Read + Seek + ?Sized,
+{
+    let base = section.container_offset as u64;
+    reader.seek(SeekFrom::Start(base))?;
+    let header = PefLoaderHeader::from_reader(reader, Endian::Big)?;
+    let strings = base + header.loader_strings_offset as u64;
+
+    // Counts and offsets are untrusted, check that each table fits in the section
+    // before allocating for it. Returns the end offset of the table.
+    let table_end = |name: &str, offset: u64, count: u32, size: usize| -> Result<u64> {
+        let end = (count as u64)
+            .checked_mul(size as u64)
+            .and_then(|length| length.checked_add(offset))
+            .with_context(|| format!("PEF loader {name} table size overflows"))?;
+        ensure!(
+            end <= section.container_length as u64,
+            "PEF loader {} table {:#X}-{:#X} out of bounds",
+            name,
+            offset,
+            end
+        );
+        Ok(end)
+    };
+    let imports_start = table_end(
+        "library",
+        PefLoaderHeader::STATIC_SIZE as u64,
+        header.imported_library_count,
+        PefImportedLibrary::STATIC_SIZE,
+    )?;
+    let reloc_headers_start =
+        table_end("import", imports_start, header.total_imported_symbol_count, u32::STATIC_SIZE)?;
+    table_end("relocation header", reloc_headers_start, header.reloc_section_count, 12)?;
+
+    let mut libraries: Vec<PefImportedLibrary> =
+        read_vec(reader, header.imported_library_count as usize, Endian::Big)?;
+    let mut imports = Vec::with_capacity(header.total_imported_symbol_count as usize);
+    for _ in 0..header.total_imported_symbol_count {
+        let class_and_name = u32::from_reader(reader, Endian::Big)?;
+        let class = (class_and_name >> 24) as u8;
+        imports.push(PefImportedSymbol {
+            name: read_c_string(reader, strings + (class_and_name & 0xFFFFFF) as u64)?,
+            class: PefSymbolClass::parse(class)?,
+            weak: class & PefSymbolClass::WEAK != 0,
+            library: u32::MAX,
+        });
+    }
+    for (index, library) in libraries.iter_mut().enumerate() {
+        library.name = read_c_string(reader, strings + library.name_offset as u64)?;
+        let start = library.first_imported_symbol as usize;
+        let end = start.saturating_add(library.imported_symbol_count as usize);
+        ensure!(
+            end <= imports.len(),
+            "Library {} imports {}-{} out of bounds",
+            library.name,
+            start,
+            end
+        );
+        for import in &mut imports[start..end] {
+            import.library = index as u32;
+            import.weak |= library.is_weak();
+        }
+    }
+
+    let mut relocations = Vec::new();
+    let mut reloc_headers = Vec::with_capacity(header.reloc_section_count as usize);
+    for _ in 0..header.reloc_section_count {
+        let section_index = u16::from_reader(reader, Endian::Big)?;
+        u16::from_reader(reader, Endian::Big)?; // reserved
+        let reloc_count = u32::from_reader(reader, Endian::Big)?;
+        let first_reloc_offset = u32::from_reader(reader, Endian::Big)?;
+        reloc_headers.push((section_index, reloc_count, first_reloc_offset));
+    }
+    for (section_index, reloc_count, first_reloc_offset) in reloc_headers {
+        let offset = header.reloc_instr_offset as u64 + first_reloc_offset as u64;
+        table_end("relocation", offset, reloc_count, u16::STATIC_SIZE)?;
+        reader.seek(SeekFrom::Start(base + offset))?;
+        let instructions: Vec<u16> = read_vec(reader, reloc_count as usize, Endian::Big)?;
+        decode_relocations(section_index, &instructions, inst_section_count, &mut relocations)
+            .with_context(|| format!("Failed to decode relocations for section {section_index}"))?;
+    }
+
+    // The export hash table is only needed for lookups, skip straight to the key table
+    let mut exports = Vec::with_capacity(header.exported_symbol_count as usize);
+    let key_table = 1u64
+        .checked_shl(header.export_hash_table_power)
+        .and_then(|count| count.checked_mul(u32::STATIC_SIZE as u64))
+        .and_then(|length| length.checked_add(header.export_hash_offset as u64))
+        .context("PEF loader export hash table size overflows")?;
+    let symbol_table = table_end("export key", key_table, header.exported_symbol_count, 4)?;
+    table_end("export symbol", symbol_table, header.exported_symbol_count, 10)?;
+    reader.seek(SeekFrom::Start(base + key_table))?;
+    let keys: Vec<u32> = read_vec(reader, header.exported_symbol_count as usize, Endian::Big)?;
+    for key in keys {
+        let class_and_name = u32::from_reader(reader, Endian::Big)?;
+        let value = u32::from_reader(reader, Endian::Big)?;
+        let section_index = i16::from_reader(reader, Endian::Big)?;
+        // Export names are not null-terminated, the length is stored in the hash key
+        let name_length = (key >> 16) as usize;
+        let position = reader.stream_position()?;
+        reader.seek(SeekFrom::Start(strings + (class_and_name & 0xFFFFFF) as u64))?;
+        let mut name = vec![0u8; name_length];
+        reader.read_exact(&mut name)?;
+        reader.seek(SeekFrom::Start(position))?;
+        exports.push(PefExportedSymbol {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            class: PefSymbolClass::parse((class_and_name >> 24) as u8)?,
+            value,
+            section_index,
+        });
+    }
+
+    Ok(PefLoader { header, libraries, imports, exports, relocations })
+}
+
+/// Decodes the packed relocation instruction stream of a single section.
+fn decode_relocations(
+    section_index: u16,
+    instructions: &[u16],
+    inst_section_count: u16,
+    out: &mut Vec<PefReloc>,
+) -> Result<()> {
+    let mut offset = 0u32;
+    let mut import_index = 0u32;
+    // sectionC and sectionD default to the first two instantiated sections
+    let mut sect_c = (inst_section_count > 0).then_some(0u16);
+    let mut sect_d = (inst_section_count > 1).then_some(1u16);
+    // Active repeats: (instruction index, remaining repetitions)
+    let mut repeats: Vec<(usize, u32)> = Vec::new();
+
+    let mut push = |offset: u32, target: PefRelocTarget| {
+        out.push(PefReloc { section: section_index, offset, target });
+    };
+    let section_c = |sect: Option<u16>| {
+        sect.map(PefRelocTarget::Section).ok_or_else(|| anyhow!("sectionC is not set"))
+    };
+    let section_d = |sect: Option<u16>| {
+        sect.map(PefRelocTarget::Section).ok_or_else(|| anyhow!("sectionD is not set"))
+    };
+
+    let mut i = 0;
+    while i < instructions.len() {
+        let op = instructions[i];
+        let mut len = 1;
+        match op >> 12 {
+            // RelocBySectDWithSkip
+            0x0..=0x3 => {
+                let skip_count = ((op >> 6) & 0xFF) as u32;
+                let reloc_count = (op & 0x3F) as u32;
+                offset += skip_count * 4;
+                for _ in 0..reloc_count {
+                    push(offset, section_d(sect_d)?);
+                    offset += 4;
+                }
+            }
+            // RelocRun group
+            0x4 | 0x5 => {
+                let run_length = (op & 0x1FF) as u32 + 1;
+                for _ in 0..run_length {
+                    match (op >> 9) & 0xF {
+                        // RelocBySectC
+                        0 => {
+                            push(offset, section_c(sect_c)?);
+                            offset += 4;
+                        }
+                        // RelocBySectD
+                        1 => {
+                            push(offset, section_d(sect_d)?);
+                            offset += 4;
+                        }
+                        // RelocTVector12
+                        2 => {
+                            push(offset, section_c(sect_c)?);
+                            push(offset + 4, section_d(sect_d)?);
+                            offset += 12;
+                        }
+                        // RelocTVector8
+                        3 => {
+                            push(offset, section_c(sect_c)?);
+                            push(offset + 4, section_d(sect_d)?);
+                            offset += 8;
+                        }
+                        // RelocVTable8
+                        4 => {
+                            push(offset, section_d(sect_d)?);
+                            offset += 8;
+                        }
+                        // RelocImportRun
+                        5 => {
+                            push(offset, PefRelocTarget::Import(import_index));
+                            import_index += 1;
+                            offset += 4;
+                        }
+                        subop => bail!("Unknown RelocRun subopcode {subop} ({op:#06X})"),
+                    }
+                }
+            }
+            // RelocSmIndex group
+            0x6 | 0x7 => {
+                let index = op & 0x1FF;
+                match (op >> 9) & 0xF {
+                    // RelocSmByImport
+                    0 => {
+                        push(offset, PefRelocTarget::Import(index as u32));
+                        import_index = index as u32 + 1;
+                        offset += 4;
+                    }
+                    // RelocSmSetSectC
+                    1 => sect_c = Some(index),
+                    // RelocSmSetSectD
+                    2 => sect_d = Some(index),
+                    // RelocSmBySection
+                    3 => {
+                        push(offset, PefRelocTarget::Section(index));
+                        offset += 4;
+                    }
+                    subop => bail!("Unknown RelocSmIndex subopcode {subop} ({op:#06X})"),
+                }
+            }
+            // RelocIncrPosition
+            0x8 => offset += (op & 0xFFF) as u32 + 1,
+            // RelocSmRepeat
+            0x9 => {
+                let block_count = ((op >> 8) & 0xF) as usize + 1;
+                let repeat_count = (op & 0xFF) as u32 + 1;
+                if let Some(next) = repeat(&mut repeats, i, block_count, repeat_count)? {
+                    i = next;
+                    continue;
+                }
+            }
+            // Large opcodes, followed by a second halfword
+            0xA | 0xB => {
+                let next = *instructions
+                    .get(i + 1)
+                    .ok_or_else(|| anyhow!("Truncated relocation instruction {op:#06X}"))?;
+                len = 2;
+                match op >> 10 {
+                    // RelocSetPosition
+                    0x28 => offset = ((op as u32 & 0x3FF) << 16) | next as u32,
+                    // RelocLgByImport
+                    0x29 => {
+                        let index = ((op as u32 & 0x3FF) << 16) | next as u32;
+                        push(offset, PefRelocTarget::Import(index));
+                        import_index = index + 1;
+                        offset += 4;
+                    }
+                    // RelocLgRepeat
+                    0x2C => {
+                        let block_count = ((op >> 6) & 0xF) as usize + 1;
+                        let repeat_count = ((op as u32 & 0x3F) << 16) | next as u32;
+                        if let Some(next) = repeat(&mut repeats, i, block_count, repeat_count)? {
+                            i = next;
+                            continue;
+                        }
+                    }
+                    // RelocLgSetOrBySection
+                    0x2D => {
+                        let index = ((op as u32 & 0x3F) << 16) | next as u32;
+                        let index = u16::try_from(index)
+                            .map_err(|_| anyhow!("Invalid section index {index}"))?;
+                        match (op >> 6) & 0xF {
+                            0 => {
+                                push(offset, PefRelocTarget::Section(index));
+                                offset += 4;
+                            }
+                            1 => sect_c = Some(index),
+                            2 => sect_d = Some(index),
+                            subop => bail!("Unknown RelocLgSetOrBySection subopcode {subop}"),
+                        }
+                    }
+                    _ => bail!("Unknown relocation opcode {op:#06X}"),
+                }
+            }
+            _ => bail!("Unknown relocation opcode {op:#06X}"),
+        }
+        i += len;
+    }
+    Ok(())
+}
+
+/// Handles RelocSmRepeat and RelocLgRepeat. The preceding `block_count` instructions
+/// are executed `repeat_count` additional times. Returns the instruction index to jump to,
+/// or `None` once all repetitions are done.
+fn repeat(
+    repeats: &mut Vec<(usize, u32)>,
+    index: usize,
+    block_count: usize,
+    repeat_count: u32,
+) -> Result<Option<usize>> {
+    ensure!(block_count <= index, "Repeat block before start of relocations");
+    let remaining = match repeats.last() {
+        Some(&(at, remaining)) if at == index => {
+            repeats.pop();
+            remaining
+        }
+        _ => repeat_count,
+    };
+    if remaining == 0 {
+        return Ok(None);
+    }
+    repeats.push((index, remaining - 1));
+    Ok(Some(index - block_count))
+}
+
+/// Reads a pattern-initialized data argument: big-endian groups of 7 bits,
+/// where the high bit of each byte signals that another byte follows.
+fn read_pidata_count(data: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut value = 0usize;
+    loop {
+        let byte = *data.get(*pos).ok_or_else(|| anyhow!("Truncated pidata argument"))?;
+        *pos += 1;
+        ensure!(value >> (usize::BITS - 7) == 0, "Pidata argument overflow");
+        value = (value << 7) | (byte & 0x7F) as usize;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn read_pidata_bytes<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let bytes = data
+        .get(*pos..*pos + len)
+        .ok_or_else(|| anyhow!("Pidata block {:#X}-{:#X} out of bounds", *pos, *pos + len))?;
+    *pos += len;
+    Ok(bytes)
+}
+
+/// Expands the contents of a pattern-initialized data section.
+///
+/// The result is the initialized part of the section (`unpacked_length` bytes);
+/// the zero-initialized remainder up to `total_length` is not included.
+pub fn unpack_pidata(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let op = data[pos];
+        pos += 1;
+        let mut count = (op & 0x1F) as usize;
+        if count == 0 {
+            count = read_pidata_count(data, &mut pos)?;
+        }
+        match op >> 5 {
+            // Zero
+            0 => out.resize(out.len() + count, 0),
+            // BlockCopy
+            1 => out.extend_from_slice(read_pidata_bytes(data, &mut pos, count)?),
+            // RepeatedBlock
+            2 => {
+                let repeat_count = read_pidata_count(data, &mut pos)? + 1;
+                let block = read_pidata_bytes(data, &mut pos, count)?;
+                for _ in 0..repeat_count {
+                    out.extend_from_slice(block);
+                }
+            }
+            // InterleaveRepeatBlockWithBlockCopy
+            3 => {
+                let custom_size = read_pidata_count(data, &mut pos)?;
+                let repeat_count = read_pidata_count(data, &mut pos)?;
+                let common = read_pidata_bytes(data, &mut pos, count)?;
+                for _ in 0..repeat_count {
+                    out.extend_from_slice(common);
+                    out.extend_from_slice(read_pidata_bytes(data, &mut pos, custom_size)?);
+                }
+                out.extend_from_slice(common);
+            }
+            // InterleaveRepeatZero
+            4 => {
+                let custom_size = read_pidata_count(data, &mut pos)?;
+                let repeat_count = read_pidata_count(data, &mut pos)?;
+                for _ in 0..repeat_count {
+                    out.resize(out.len() + count, 0);
+                    out.extend_from_slice(read_pidata_bytes(data, &mut pos, custom_size)?);
+                }
+                out.resize(out.len() + count, 0);
+            }
+            opcode => bail!("Unknown pidata opcode {opcode} at {:#X}", pos - 1),
+        }
+    }
+    Ok(out)
+}
+
+fn default_section_name(kind: PefSectionKind) -> &'static str {
+    match kind {
+        PefSectionKind::Code => ".text",
+        PefSectionKind::Constant => ".rodata",
+        _ => ".data",
+    }
+}
+
+/// Object section names of the instantiated sections, by PEF section index.
+/// Unnamed sections are named after their kind, duplicates after their index.
+fn instantiated_section_names(pef: &PefFile) -> Vec<(usize, String)> {
+    let mut names: Vec<(usize, String)> = Vec::new();
+    for (index, section) in pef.sections.iter().enumerate().filter(|(_, s)| s.is_instantiated()) {
+        let mut name =
+            section.name.clone().unwrap_or_else(|| default_section_name(section.kind).to_string());
+        if names.iter().any(|(_, n)| *n == name) {
+            name = format!(".section{}", index);
+        }
+        names.push((index, name));
+    }
+    names
+}
+
+pub fn process_pef(buf: &[u8], name: &str) -> Result<(PefFile, ObjInfo)> {
+    let pef = PefFile::parse(buf)?;
+    ensure!(
+        pef.header.architecture == PEF_ARCH_PPC,
+        "Unsupported PEF architecture '{}'",
+        String::from_utf8_lossy(&pef.header.architecture)
+    );
+
+    let mut sections: Vec<ObjSection> = Vec::new();
+    let mut next_address = PEF_IMAGE_BASE;
+    for (index, name) in instantiated_section_names(&pef) {
+        let section = &pef.sections[index];
+        let kind = match section.kind {
+            PefSectionKind::Code => ObjSectionKind::Code,
+            PefSectionKind::UnpackedData
+            | PefSectionKind::PatternInitData
+            | PefSectionKind::ExecutableData => ObjSectionKind::Data,
+            PefSectionKind::Constant => ObjSectionKind::ReadOnlyData,
+            _ => continue,
+        };
+        let align = 1u32 << section.alignment;
+        let address = if section.default_address != 0 {
+            section.default_address
+        } else {
+            align_up(next_address, align.max(32))
+        };
+        next_address = address + section.total_length;
+
+        let mut data = pef.section_data(buf, index)?.to_vec();
+        if section.is_packed() {
+            data = unpack_pidata(&data)
+                .with_context(|| format!("Failed to unpack PEF section {index}"))?;
+            ensure!(
+                data.len() == section.unpacked_length as usize,
+                "PEF section {} unpacked to {:#X} bytes, expected {:#X}",
+                index,
+                data.len(),
+                section.unpacked_length
+            );
+        }
+        // Zero-initialized data follows the initialized data
+        data.resize(section.total_length as usize, 0);
+        // Unpacked data no longer corresponds to the container bytes,
+        // so treat it like a section without file contents.
+        let file_offset = if section.is_packed() { 0 } else { section.container_offset };
+
         sections.push(ObjSection {
             name,
             kind,
-            address: pef_section.address() as u64,
-            size: pef_section.size() as u64,
-            data: pef_section.data().expect("Could not get section data").to_vec(),
-            align: 0,
-            elf_index: 0,
+            address: address as u64,
+            size: section.total_length as u64,
+            data,
+            align: align as u64,
+            elf_index: index as SectionIndex,
             relocations: Default::default(),
-            virtual_address: Some(pef_section.address() as u64),
-            file_offset: pef_section.address() as u64,
+            virtual_address: Some(address as u64),
+            file_offset: file_offset as u64,
             section_known: true,
             splits: Default::default(),
         });
     }
-    let obj = ObjInfo::new(
+
+    let mut obj = ObjInfo::new(
         ObjKind::Executable,
         ObjArchitecture::PowerPc,
         name.to_string(),
         vec![],
         sections,
     );
-    Ok(obj)
+    if let Some(loader) = &pef.loader {
+        apply_loader(&mut obj, &pef, loader)?;
+    }
+    Ok((pef, obj))
+}
+
+enum LoaderRelocTarget {
+    Address(SectionAddress),
+    Import(SymbolIndex, i64),
+}
+
+fn pef_section_index(obj: &ObjInfo, index: u16) -> Result<SectionIndex> {
+    obj.sections
+        .get_elf_index(index as SectionIndex)
+        .map(|(section_index, _)| section_index)
+        .ok_or_else(|| anyhow!("PEF section {} is not instantiated", index))
+}
+
+/// Creates symbols for the loader imports, exports and entry points,
+/// and applies the loader relocations to the section data.
+fn apply_loader(obj: &mut ObjInfo, pef: &PefFile, loader: &PefLoader) -> Result<()> {
+    let mut import_symbols = Vec::with_capacity(loader.imports.len());
+    for import in &loader.imports {
+        let mut flags = ObjSymbolFlagSet(ObjSymbolFlags::Global.into());
+        if import.weak {
+            flags.set_scope(ObjSymbolScope::Weak);
+        }
+        import_symbols.push(obj.add_symbol(
+            ObjSymbol {
+                name: import.name.clone(),
+                flags,
+                kind: import.class.symbol_kind(),
+                ..Default::default()
+            },
+            false,
+        )?);
+    }
+
+    // Relocate section data to the instantiated addresses
+    let mut targets = Vec::with_capacity(loader.relocations.len());
+    for reloc in &loader.relocations {
+        let section_index = pef_section_index(obj, reloc.section)?;
+        let source = SectionAddress::new(
+            section_index,
+            obj.sections[section_index].address as u32 + reloc.offset,
+        );
+        let value = read_u32(&obj.sections[section_index], source.address).ok_or_else(|| {
+            anyhow!("Relocation @ {:#010X} out of bounds of section", source.address)
+        })?;
+        let target = match reloc.target {
+            PefRelocTarget::Section(index) => {
+                let target_index = pef_section_index(obj, index)?;
+                let target_section = &obj.sections[target_index];
+                let address = value
+                    .wrapping_add(target_section.address as u32)
+                    .wrapping_sub(pef.sections[index as usize].default_address);
+                let offset = reloc.offset as usize;
+                obj.sections[section_index].data[offset..offset + 4]
+                    .copy_from_slice(&address.to_be_bytes());
+                LoaderRelocTarget::Address(SectionAddress::new(target_index, address))
+            }
+            PefRelocTarget::Import(index) => {
+                let symbol = *import_symbols
+                    .get(index as usize)
+                    .ok_or_else(|| anyhow!("Invalid import index {}", index))?;
+                LoaderRelocTarget::Import(symbol, value as i32 as i64)
+            }
+        };
+        targets.push((source, target));
+    }
+
+    for export in &loader.exports {
+        let mut flags = ObjSymbolFlagSet(ObjSymbolFlags::Global.into());
+        flags.set_force_active(true);
+        let (section, address) = match export.section_index {
+            PEF_SECTION_ABS => (None, export.value),
+            PEF_SECTION_REEXPORT => {
+                log::debug!("Skipping re-exported import {}", export.name);
+                continue;
+            }
+            index if index < 0 => bail!("Invalid section {} for export {}", index, export.name),
+            index => {
+                let section_index = pef_section_index(obj, index as u16)?;
+                let address = obj.sections[section_index].address as u32 + export.value;
+                (Some(section_index), address)
+            }
+        };
+        obj.add_symbol(
+            ObjSymbol {
+                name: export.name.clone(),
+                address: address as u64,
+                section,
+                flags,
+                kind: export.class.symbol_kind(),
+                ..Default::default()
+            },
+            false,
+        )?;
+        if export.class == PefSymbolClass::TVector {
+            if let Some(section_index) = section {
+                add_tvector_function(
+                    obj,
+                    SectionAddress::new(section_index, address),
+                    &export.name,
+                )?;
+            }
+        }
+    }
+
+    let header = &loader.header;
+    for (section, offset, name) in [
+        (header.main_section, header.main_offset, "__start"),
+        (header.init_section, header.init_offset, "__initialize"),
+        (header.term_section, header.term_offset, "__terminate"),
+    ] {
+        if section < 0 {
+            continue;
+        }
+        let section_index = pef_section_index(obj, section as u16)?;
+        let section = &obj.sections[section_index];
+        let addr = SectionAddress::new(section_index, section.address as u32 + offset);
+        let function = if section.kind == ObjSectionKind::Code {
+            obj.add_symbol(
+                ObjSymbol {
+                    name: name.to_string(),
+                    address: addr.address as u64,
+                    section: Some(section_index),
+                    flags: ObjSymbolFlagSet(ObjSymbolFlags::Global.into()),
+                    kind: ObjSymbolKind::Function,
+                    ..Default::default()
+                },
+                false,
+            )?;
+            Some(addr)
+        } else {
+            // Entry points in data sections are transition vectors,
+            // the second word being the fragment's TOC anchor
+            if obj.toc_base.is_none() {
+                obj.toc_base = read_u32(section, addr.address + 4);
+            }
+            obj.add_symbol(
+                ObjSymbol {
+                    name: name.to_string(),
+                    address: addr.address as u64,
+                    section: Some(section_index),
+                    size: 8,
+                    size_known: true,
+                    flags: ObjSymbolFlagSet(ObjSymbolFlags::Global.into()),
+                    kind: ObjSymbolKind::Object,
+                    ..Default::default()
+                },
+                false,
+            )?;
+            add_tvector_function(obj, addr, name)?
+        };
+        if name == "__start" {
+            obj.entry = function.map(|addr| addr.address as u64);
+        }
+    }
+
+    // Transition vectors: a code pointer immediately followed by a TOC pointer
+    let mut tvector_code = Vec::new();
+    for window in targets.windows(2) {
+        let [(source, LoaderRelocTarget::Address(code)), (next, LoaderRelocTarget::Address(toc))] =
+            window
+        else {
+            continue;
+        };
+        if *next == *source + 4
+            && obj.sections[code.section].kind == ObjSectionKind::Code
+            && obj.sections[toc.section].kind != ObjSectionKind::Code
+        {
+            tvector_code.push(*code);
+            if obj.toc_base.is_none() {
+                obj.toc_base = Some(toc.address);
+            }
+        }
+    }
+    for addr in tvector_code {
+        obj.add_symbol(
+            ObjSymbol {
+                name: format!("fn_{:08X}", addr.address),
+                address: addr.address as u64,
+                section: Some(addr.section),
+                kind: ObjSymbolKind::Function,
+                ..Default::default()
+            },
+            false,
+        )?;
+    }
+
+    for (source, target) in targets {
+        let (target_symbol, addend) = match target {
+            LoaderRelocTarget::Address(target) => {
+                if let Some((symbol_idx, symbol)) =
+                    obj.symbols.for_relocation(target, ObjRelocKind::Absolute)?
+                {
+                    (symbol_idx, target.address as i64 - symbol.address as i64)
+                } else {
+                    let symbol_idx = obj.symbols.add_direct(ObjSymbol {
+                        name: format!("lbl_{:08X}", target.address),
+                        address: target.address as u64,
+                        section: Some(target.section),
+                        ..Default::default()
+                    })?;
+                    (symbol_idx, 0)
+                }
+            }
+            LoaderRelocTarget::Import(symbol_idx, addend) => (symbol_idx, addend),
+        };
+        obj.sections[source.section].relocations.insert(source.address, ObjReloc {
+            kind: ObjRelocKind::Absolute,
+            target_symbol,
+            addend,
+            module: None,
+        })?;
+    }
+    Ok(())
+}
+
+/// Names the function referenced by the transition vector at `addr`,
+/// following the XCOFF convention of prefixing the code symbol with a dot.
+fn add_tvector_function(
+    obj: &mut ObjInfo,
+    addr: SectionAddress,
+    name: &str,
+) -> Result<Option<SectionAddress>> {
+    let Some(code) = read_u32(&obj.sections[addr.section], addr.address) else {
+        return Ok(None);
+    };
+    let Ok((section_index, section)) = obj.sections.at_address(code) else {
+        return Ok(None);
+    };
+    if section.kind != ObjSectionKind::Code {
+        return Ok(None);
+    }
+    obj.add_symbol(
+        ObjSymbol {
+            name: format!(".{}", name),
+            address: code as u64,
+            section: Some(section_index),
+            flags: ObjSymbolFlagSet(ObjSymbolFlags::Global.into()),
+            kind: ObjSymbolKind::Function,
+            ..Default::default()
+        },
+        false,
+    )?;
+    Ok(Some(SectionAddress::new(section_index, code)))
+}
+
+/// Writes a pattern-initialized data argument, the inverse of [`read_pidata_count`].
+fn write_pidata_count(out: &mut Vec<u8>, value: usize) {
+    let mut shift = 0;
+    while shift + 7 < usize::BITS && value >> (shift + 7) != 0 {
+        shift += 7;
+    }
+    while shift > 0 {
+        out.push(((value >> shift) & 0x7F) as u8 | 0x80);
+        shift -= 7;
+    }
+    out.push((value & 0x7F) as u8);
+}
+
+fn write_pidata_op(out: &mut Vec<u8>, opcode: u8, count: usize) {
+    if count > 0 && count <= 0x1F {
+        out.push((opcode << 5) | count as u8);
+    } else {
+        out.push(opcode << 5);
+        write_pidata_count(out, count);
+    }
+}
+
+/// Compresses section contents into pattern-initialized data, the inverse of [`unpack_pidata`].
+/// Runs of zeros and repeated bytes are encoded, everything else is copied.
+pub fn pack_pidata(data: &[u8]) -> Vec<u8> {
+    const MIN_RUN: usize = 4;
+    let mut out = Vec::new();
+    let flush = |out: &mut Vec<u8>, literal: &[u8]| {
+        if !literal.is_empty() {
+            // BlockCopy
+            write_pidata_op(out, 1, literal.len());
+            out.extend_from_slice(literal);
+        }
+    };
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos < data.len() {
+        let byte = data[pos];
+        let run = data[pos..].iter().take_while(|&&b| b == byte).count();
+        if run < MIN_RUN {
+            pos += run;
+            continue;
+        }
+        flush(&mut out, &data[literal_start..pos]);
+        if byte == 0 {
+            // Zero
+            write_pidata_op(&mut out, 0, run);
+        } else {
+            // RepeatedBlock of a single byte
+            write_pidata_op(&mut out, 2, 1);
+            write_pidata_count(&mut out, run - 1);
+            out.push(byte);
+        }
+        pos += run;
+        literal_start = pos;
+    }
+    flush(&mut out, &data[literal_start..]);
+    out
+}
+
+/// Encodes the relocations of a single section into relocation instructions,
+/// the inverse of [`decode_relocations`]. Relocations must be sorted by offset.
+fn encode_relocations(relocs: &[PefReloc], inst_section_count: u16) -> Result<Vec<u16>> {
+    let sect_c = (inst_section_count > 0).then_some(0u16);
+    let sect_d = (inst_section_count > 1).then_some(1u16);
+    let mut out = Vec::new();
+    let mut offset = 0u32;
+    let mut import_index = 0u32;
+    // Number of relocations starting at `i` that relocate consecutive words
+    let run_length = |i: usize, max: usize, target: &dyn Fn(usize) -> PefRelocTarget| {
+        relocs[i..]
+            .iter()
+            .enumerate()
+            .take(max)
+            .take_while(|(n, r)| {
+                r.offset == relocs[i].offset + *n as u32 * 4 && r.target == target(*n)
+            })
+            .count()
+    };
+
+    let mut i = 0;
+    while i < relocs.len() {
+        let reloc = &relocs[i];
+        ensure!(
+            reloc.offset >= offset,
+            "Relocation @ {:#X} overlaps or is out of order (expected >= {:#X})",
+            reloc.offset,
+            offset
+        );
+        let skip = reloc.offset - offset;
+        if sect_d.is_some_and(|d| reloc.target == PefRelocTarget::Section(d))
+            && skip % 4 == 0
+            && skip / 4 <= 0xFF
+        {
+            // RelocBySectDWithSkip
+            let count = run_length(i, 0x3F, &|_| reloc.target);
+            out.push(((skip / 4) << 6) as u16 | count as u16);
+            offset = reloc.offset + count as u32 * 4;
+            i += count;
+            continue;
+        }
+        if skip > 0 && skip <= 0x1000 {
+            // RelocIncrPosition
+            out.push(0x8000 | (skip - 1) as u16);
+        } else if skip > 0 {
+            // RelocSetPosition
+            ensure!(reloc.offset < 1 << 26, "Relocation offset {:#X} too large", reloc.offset);
+            out.push(0xA000 | (reloc.offset >> 16) as u16);
+            out.push(reloc.offset as u16);
+        }
+        offset = reloc.offset;
+
+        let count = match reloc.target {
+            PefRelocTarget::Section(index) if Some(index) == sect_c => {
+                // RelocBySectC
+                let count = run_length(i, 0x200, &|_| reloc.target);
+                out.push(0x4000 | (count - 1) as u16);
+                count
+            }
+            PefRelocTarget::Section(index) if index < 0x200 => {
+                // RelocSmBySection
+                out.push(0x6600 | index);
+                1
+            }
+            PefRelocTarget::Section(index) => {
+                // RelocLgSetOrBySection
+                out.push(0xB400);
+                out.push(index);
+                1
+            }
+            PefRelocTarget::Import(index) if index == import_index => {
+                // RelocImportRun
+                let count = run_length(i, 0x200, &|n| PefRelocTarget::Import(index + n as u32));
+                out.push(0x4A00 | (count - 1) as u16);
+                import_index += count as u32;
+                count
+            }
+            PefRelocTarget::Import(index) => {
+                if index < 0x200 {
+                    // RelocSmByImport
+                    out.push(0x6000 | index as u16);
+                } else {
+                    // RelocLgByImport
+                    ensure!(index < 1 << 26, "Import index {} too large", index);
+                    out.push(0xA400 | (index >> 16) as u16);
+                    out.push(index as u16);
+                }
+                import_index = index + 1;
+                1
+            }
+        };
+        offset += count as u32 * 4;
+        i += count;
+    }
+    Ok(out)
+}
+
+/// Computes the export hash word of a name: the name length in the upper 16 bits
+/// and a hash of the name in the lower 16 bits.
+pub fn pef_hash_word(name: &[u8]) -> u32 {
+    let mut hash = 0i32;
+    for &c in name {
+        hash = (hash << 1).wrapping_sub(hash >> 16) ^ c as i32;
+    }
+    ((name.len() as u32) << 16) | ((hash ^ (hash >> 16)) as u32 & 0xFFFF)
+}
+
+fn pef_hash_slot(hash_word: u32, power: u32) -> u32 {
+    (hash_word ^ (hash_word >> power)) & ((1 << power) - 1)
+}
+
+/// Size of the export hash table as a power of 2, keeping the average chain short.
+fn pef_hash_table_power(export_count: usize) -> u32 {
+    (0..16).find(|&power| export_count >> power < 10).unwrap_or(16)
+}
+
+/// An instantiated section to be written by [`write_pef`].
+#[derive(Debug, Clone)]
+pub struct PefSectionData {
+    pub name: Option<String>,
+    pub kind: PefSectionKind,
+    pub share_kind: u8,
+    /// Section alignment as a power of 2.
+    pub alignment: u8,
+    /// Initialized contents. Pattern-initialized sections are packed on write.
+    pub data: Vec<u8>,
+    /// Size of the section in memory. The remainder after `data` is zero-initialized.
+    pub total_length: u32,
+}
+
+/// Writes a PEF container, followed by a loader section built from `loader`.
+///
+/// Only the entry points are used from the loader header, and only the names, versions
+/// and options from the imported libraries; everything else is computed.
+/// Imports must be grouped by library.
+pub fn write_pef<W>(
+    w: &mut W,
+    header: &PefContainerHeader,
+    sections: &[PefSectionData],
+    loader: &PefLoader,
+) -> Result<()>
+where
+    W: Write + ?Sized,
+{
+    let inst_section_count = u16::try_from(sections.len())?;
+    let loader_data = build_loader(loader, inst_section_count)?;
+
+    let mut section_headers = Vec::with_capacity(sections.len() + 1);
+    let mut names = Vec::new();
+    let mut offset = (PefContainerHeader::STATIC_SIZE
+        + (sections.len() + 1) * PefSectionHeader::STATIC_SIZE) as u32;
+    for section in sections {
+        let name_offset = match &section.name {
+            Some(name) => {
+                let name_offset = names.len() as i32;
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+                name_offset
+            }
+            None => -1,
+        };
+        ensure!(
+            section.total_length as usize >= section.data.len(),
+            "PEF section {:?} data {:#X} exceeds total length {:#X}",
+            section.name,
+            section.data.len(),
+            section.total_length
+        );
+        section_headers.push(PefSectionHeader {
+            name_offset,
+            default_address: 0,
+            total_length: section.total_length,
+            unpacked_length: section.data.len() as u32,
+            container_length: 0, // Calculated below
+            container_offset: 0, // Calculated below
+            kind: section.kind,
+            share_kind: section.share_kind,
+            alignment: section.alignment,
+            name: section.name.clone(),
+        });
+    }
+    section_headers.push(PefSectionHeader {
+        name_offset: -1,
+        default_address: 0,
+        total_length: 0,
+        unpacked_length: 0,
+        container_length: loader_data.len() as u32,
+        container_offset: 0, // Calculated below
+        kind: PefSectionKind::Loader,
+        share_kind: 0,
+        alignment: 4,
+        name: None,
+    });
+    offset += names.len() as u32;
+
+    // Section contents are aligned to 16 bytes in the container
+    let mut contents = Vec::with_capacity(section_headers.len());
+    for (index, section_header) in section_headers.iter_mut().enumerate() {
+        let data = match sections.get(index) {
+            Some(section) if section_header.is_packed() => Cow::Owned(pack_pidata(&section.data)),
+            Some(section) => Cow::Borrowed(section.data.as_slice()),
+            None => Cow::Borrowed(loader_data.as_slice()),
+        };
+        offset = align_up(offset, 16);
+        section_header.container_offset = offset;
+        section_header.container_length = data.len() as u32;
+        offset += data.len() as u32;
+        contents.push(data);
+    }
+
+    let header = PefContainerHeader {
+        section_count: section_headers.len() as u16,
+        inst_section_count,
+        ..header.clone()
+    };
+    let mut out = Vec::with_capacity(offset as usize);
+    header.to_writer(&mut out, Endian::Big)?;
+    write_vec(&mut out, &section_headers, Endian::Big)?;
+    out.extend_from_slice(&names);
+    for (section_header, data) in section_headers.iter().zip(contents) {
+        out.resize(section_header.container_offset as usize, 0);
+        out.extend_from_slice(&data);
+    }
+    w.write_all(&out)?;
+    Ok(())
+}
+
+fn build_loader(loader: &PefLoader, inst_section_count: u16) -> Result<Vec<u8>> {
+    let mut strings = Vec::new();
+    let mut add_string = |s: &str, terminate: bool| {
+        let offset = strings.len() as u32;
+        strings.extend_from_slice(s.as_bytes());
+        if terminate {
+            strings.push(0);
+        }
+        offset
+    };
+
+    let mut libraries = loader.libraries.clone();
+    for (index, library) in libraries.iter_mut().enumerate() {
+        library.name_offset = add_string(&library.name, true);
+        let first = loader.imports.iter().position(|i| i.library == index as u32);
+        let count = loader.imports.iter().filter(|i| i.library == index as u32).count();
+        library.first_imported_symbol = first.unwrap_or(loader.imports.len()) as u32;
+        library.imported_symbol_count = count as u32;
+        ensure!(
+            loader.imports[library.first_imported_symbol as usize..][..count]
+                .iter()
+                .all(|i| i.library == index as u32),
+            "Imports from library {} are not contiguous",
+            library.name
+        );
+    }
+    let mut imports = Vec::with_capacity(loader.imports.len());
+    for import in &loader.imports {
+        let mut class = u8::from(import.class);
+        if import.weak {
+            class |= PefSymbolClass::WEAK;
+        }
+        imports.push(((class as u32) << 24) | add_string(&import.name, true));
+    }
+
+    // Exports are ordered by hash table slot
+    let power = pef_hash_table_power(loader.exports.len());
+    let mut exports = loader
+        .exports
+        .iter()
+        .map(|export| (pef_hash_word(export.name.as_bytes()), export))
+        .collect::<Vec<_>>();
+    exports.sort_by_key(|&(hash_word, _)| pef_hash_slot(hash_word, power));
+    let mut hash_table = vec![0u32; 1 << power];
+    for (index, &(hash_word, _)) in exports.iter().enumerate().rev() {
+        let slot = &mut hash_table[pef_hash_slot(hash_word, power) as usize];
+        // Chain count in the upper 14 bits, first index in the lower 18 bits
+        *slot = (((*slot >> 18) + 1) << 18) | index as u32;
+    }
+    let export_names =
+        exports.iter().map(|(_, export)| add_string(&export.name, false)).collect::<Vec<_>>();
+
+    // Relocation instructions, per section
+    let mut relocations = loader.relocations.clone();
+    relocations.sort_by_key(|r| (r.section, r.offset));
+    let mut reloc_headers = Vec::new();
+    let mut instructions = Vec::new();
+    for (section, relocs) in &relocations.iter().chunk_by(|r| r.section) {
+        let relocs = relocs.cloned().collect::<Vec<_>>();
+        let encoded = encode_relocations(&relocs, inst_section_count)
+            .with_context(|| format!("Failed to encode relocations for section {section}"))?;
+        reloc_headers.push((section, encoded.len() as u32, instructions.len() as u32 * 2));
+        instructions.extend(encoded);
+    }
+
+    let mut header = PefLoaderHeader {
+        imported_library_count: libraries.len() as u32,
+        total_imported_symbol_count: imports.len() as u32,
+        reloc_section_count: reloc_headers.len() as u32,
+        export_hash_table_power: power,
+        exported_symbol_count: exports.len() as u32,
+        ..loader.header.clone()
+    };
+    header.reloc_instr_offset = (PefLoaderHeader::STATIC_SIZE
+        + libraries.len() * PefImportedLibrary::STATIC_SIZE
+        + imports.len() * u32::STATIC_SIZE
+        + reloc_headers.len() * 12) as u32;
+    header.loader_strings_offset =
+        align_up(header.reloc_instr_offset + instructions.len() as u32 * 2, 4);
+    header.export_hash_offset = align_up(header.loader_strings_offset + strings.len() as u32, 4);
+
+    let mut out = Vec::new();
+    header.to_writer(&mut out, Endian::Big)?;
+    write_vec(&mut out, &libraries, Endian::Big)?;
+    write_vec(&mut out, &imports, Endian::Big)?;
+    for (section, count, first_offset) in reloc_headers {
+        section.to_writer(&mut out, Endian::Big)?;
+        0u16.to_writer(&mut out, Endian::Big)?; // reserved
+        count.to_writer(&mut out, Endian::Big)?;
+        first_offset.to_writer(&mut out, Endian::Big)?;
+    }
+    write_vec(&mut out, &instructions, Endian::Big)?;
+    out.resize(header.loader_strings_offset as usize, 0);
+    out.extend_from_slice(&strings);
+    out.resize(header.export_hash_offset as usize, 0);
+    write_vec(&mut out, &hash_table, Endian::Big)?;
+    for &(hash_word, _) in &exports {
+        hash_word.to_writer(&mut out, Endian::Big)?;
+    }
+    for (&(_, export), name_offset) in exports.iter().zip(export_names) {
+        (((u8::from(export.class) as u32) << 24) | name_offset).to_writer(&mut out, Endian::Big)?;
+        export.value.to_writer(&mut out, Endian::Big)?;
+        export.section_index.to_writer(&mut out, Endian::Big)?;
+    }
+    Ok(out)
+}
+
+/// Attributes of a PEF that a linked ELF does not carry: container versions,
+/// section kinds, imported libraries and exports.
+///
+/// `pef config` writes them from the original fragment, `elf2pef` reads them back.
+#[derive(Debug, Clone, Default)]
+pub struct PefLayout {
+    pub date_time_stamp: u32,
+    pub old_def_version: u32,
+    pub old_imp_version: u32,
+    pub current_version: u32,
+    pub sections: Vec<PefLayoutSection>,
+    pub libraries: Vec<PefImportedLibrary>,
+    pub imports: Vec<PefImportedSymbol>,
+    pub exports: Vec<(String, PefSymbolClass)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PefLayoutSection {
+    /// Section name, as assigned by [`process_pef`].
+    pub name: String,
+    pub kind: PefSectionKind,
+    pub share_kind: u8,
+    /// Section alignment as a power of 2.
+    pub alignment: u8,
+    /// Whether the name is stored in the container.
+    pub named: bool,
+}
+
+impl PefLayout {
+    pub fn from_pef(pef: &PefFile) -> Result<Self> {
+        let sections = instantiated_section_names(pef)
+            .into_iter()
+            .map(|(index, name)| {
+                let section = &pef.sections[index];
+                PefLayoutSection {
+                    name,
+                    kind: section.kind,
+                    share_kind: section.share_kind,
+                    alignment: section.alignment,
+                    named: section.name.is_some(),
+                }
+            })
+            .collect();
+        let (libraries, imports, exports) = match &pef.loader {
+            Some(loader) => (
+                loader.libraries.clone(),
+                loader
+                    .imports
+                    .iter()
+                    .map(|import| {
+                        let library =
+                            loader.libraries.get(import.library as usize).with_context(|| {
+                                format!(
+                                    "Import {} has invalid library index {}",
+                                    import.name, import.library
+                                )
+                            })?;
+                        Ok(PefImportedSymbol {
+                            // Weak libraries imply weak imports, don't repeat it for each import
+                            weak: import.weak && !library.is_weak(),
+                            ..import.clone()
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                loader.exports.iter().map(|e| (e.name.clone(), e.class)).collect(),
+            ),
+            None => Default::default(),
+        };
+        Ok(Self {
+            date_time_stamp: pef.header.date_time_stamp,
+            old_def_version: pef.header.old_def_version,
+            old_imp_version: pef.header.old_imp_version,
+            current_version: pef.header.current_version,
+            sections,
+            libraries,
+            imports,
+            exports,
+        })
+    }
+}
+
+fn section_kind_to_str(kind: PefSectionKind) -> &'static str {
+    match kind {
+        PefSectionKind::Code => "code",
+        PefSectionKind::UnpackedData => "data",
+        PefSectionKind::PatternInitData => "pidata",
+        PefSectionKind::Constant => "rodata",
+        PefSectionKind::Loader => "loader",
+        PefSectionKind::Debug => "debug",
+        PefSectionKind::ExecutableData => "execdata",
+        PefSectionKind::Exception => "exception",
+        PefSectionKind::Traceback => "traceback",
+    }
+}
+
+fn section_kind_from_str(s: &str) -> Option<PefSectionKind> {
+    Some(match s {
+        "code" => PefSectionKind::Code,
+        "data" => PefSectionKind::UnpackedData,
+        "pidata" => PefSectionKind::PatternInitData,
+        "rodata" => PefSectionKind::Constant,
+        "execdata" => PefSectionKind::ExecutableData,
+        _ => return None,
+    })
+}
+
+fn symbol_class_to_str(class: PefSymbolClass) -> &'static str {
+    match class {
+        PefSymbolClass::Code => "code",
+        PefSymbolClass::Data => "data",
+        PefSymbolClass::TVector => "tvector",
+        PefSymbolClass::Toc => "toc",
+        PefSymbolClass::Glue => "glue",
+    }
+}
+
+fn symbol_class_from_str(s: &str) -> Option<PefSymbolClass> {
+    Some(match s {
+        "code" => PefSymbolClass::Code,
+        "data" => PefSymbolClass::Data,
+        "tvector" => PefSymbolClass::TVector,
+        "toc" => PefSymbolClass::Toc,
+        "glue" => PefSymbolClass::Glue,
+        _ => return None,
+    })
+}
+
+/// Quotes a name for the layout file if it contains whitespace or quotes.
+fn layout_name(name: &str) -> Cow<str> {
+    if !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return Cow::Borrowed(name);
+    }
+    Cow::Owned(format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+/// Splits a layout line into whitespace-separated tokens, unquoting quoted names.
+fn layout_tokens(line: &str) -> Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        token.push(chars.next().ok_or_else(|| anyhow!("Unterminated escape"))?)
+                    }
+                    Some(c) => token.push(c),
+                    None => bail!("Unterminated quoted name"),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+pub fn write_pef_layout<W>(w: &mut W, layout: &PefLayout) -> Result<()>
+where W: Write + ?Sized {
+    writeln!(
+        w,
+        "container date:{:#010X} old_def:{:#X} old_imp:{:#X} current:{:#X}",
+        layout.date_time_stamp,
+        layout.old_def_version,
+        layout.old_imp_version,
+        layout.current_version
+    )?;
+    for section in &layout.sections {
+        write!(
+            w,
+            "section {} kind:{} share:{} align:{}",
+            layout_name(&section.name),
+            section_kind_to_str(section.kind),
+            section.share_kind,
+            section.alignment
+        )?;
+        if section.named {
+            write!(w, " named")?;
+        }
+        writeln!(w)?;
+    }
+    for (index, library) in layout.libraries.iter().enumerate() {
+        write!(
+            w,
+            "library {} old_imp:{:#X} current:{:#X}",
+            layout_name(&library.name),
+            library.old_imp_version,
+            library.current_version
+        )?;
+        if library.is_weak() {
+            write!(w, " weak")?;
+        }
+        if library.init_before() {
+            write!(w, " init_before")?;
+        }
+        writeln!(w)?;
+        for import in layout.imports.iter().filter(|i| i.library == index as u32) {
+            write!(
+                w,
+                "\timport {} class:{}",
+                layout_name(&import.name),
+                symbol_class_to_str(import.class)
+            )?;
+            if import.weak {
+                write!(w, " weak")?;
+            }
+            writeln!(w)?;
+        }
+    }
+    for (name, class) in &layout.exports {
+        writeln!(w, "export {} class:{}", layout_name(name), symbol_class_to_str(*class))?;
+    }
+    Ok(())
+}
+
+pub fn read_pef_layout<R>(reader: R) -> Result<PefLayout>
+where R: BufRead {
+    let mut layout = PefLayout::default();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        parse_pef_layout_line(&line, &mut layout)
+            .with_context(|| format!("Failed to parse line {}: '{}'", line_number + 1, line))?;
+    }
+    Ok(layout)
+}
+
+fn parse_pef_layout_line(line: &str, layout: &mut PefLayout) -> Result<()> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+        return Ok(());
+    }
+    let mut tokens = layout_tokens(line)?.into_iter();
+    let keyword = tokens.next().unwrap_or_default();
+    let name = if keyword == "container" { None } else { tokens.next() };
+    let rest = tokens.collect::<Vec<_>>();
+    let attrs = rest
+        .iter()
+        .map(|token| token.split_once(':').map_or((token.as_str(), None), |(k, v)| (k, Some(v))))
+        .collect::<Vec<_>>();
+    let value = |key: &str| attrs.iter().find(|(k, _)| *k == key).and_then(|(_, v)| *v);
+    let flag = |key: &str| attrs.iter().any(|&(k, v)| k == key && v.is_none());
+    let number = |key: &str| value(key).map(parse_u32).transpose().map(Option::unwrap_or_default);
+    match keyword.as_str() {
+        "container" => {
+            layout.date_time_stamp = number("date")?;
+            layout.old_def_version = number("old_def")?;
+            layout.old_imp_version = number("old_imp")?;
+            layout.current_version = number("current")?;
+        }
+        "section" => {
+            let name = name.ok_or_else(|| anyhow!("Missing section name"))?;
+            let kind = value("kind").unwrap_or("data");
+            layout.sections.push(PefLayoutSection {
+                name,
+                kind: section_kind_from_str(kind)
+                    .ok_or_else(|| anyhow!("Unknown section kind '{}'", kind))?,
+                share_kind: number("share")? as u8,
+                alignment: number("align")? as u8,
+                named: flag("named"),
+            });
+        }
+        "library" => {
+            let name = name.ok_or_else(|| anyhow!("Missing library name"))?;
+            let mut options = 0;
+            if flag("weak") {
+                options |= PefImportedLibrary::WEAK_IMPORT;
+            }
+            if flag("init_before") {
+                options |= PefImportedLibrary::INIT_BEFORE;
+            }
+            layout.libraries.push(PefImportedLibrary {
+                name_offset: 0,
+                old_imp_version: number("old_imp")?,
+                current_version: number("current")?,
+                imported_symbol_count: 0,
+                first_imported_symbol: 0,
+                options,
+                name,
+            });
+        }
+        "import" => {
+            let name = name.ok_or_else(|| anyhow!("Missing import name"))?;
+            ensure!(!layout.libraries.is_empty(), "Import {} before any library", name);
+            let class = value("class").unwrap_or("tvector");
+            layout.imports.push(PefImportedSymbol {
+                name,
+                class: symbol_class_from_str(class)
+                    .ok_or_else(|| anyhow!("Unknown symbol class '{}'", class))?,
+                weak: flag("weak"),
+                library: layout.libraries.len() as u32 - 1,
+            });
+        }
+        "export" => {
+            let name = name.ok_or_else(|| anyhow!("Missing export name"))?;
+            let class = value("class").unwrap_or("tvector");
+            layout.exports.push((
+                name,
+                symbol_class_from_str(class)
+                    .ok_or_else(|| anyhow!("Unknown symbol class '{}'", class))?,
+            ));
+        }
+        _ => bail!("Unknown keyword '{}'", keyword),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(instructions: &[u16]) -> Vec<(u32, PefRelocTarget)> {
+        let mut out = Vec::new();
+        decode_relocations(1, instructions, 3, &mut out).unwrap();
+        out.into_iter().map(|r| (r.offset, r.target)).collect()
+    }
+
+    #[test]
+    fn test_decode_relocations() {
+        // RelocBySectDWithSkip: skip 1, relocate 2
+        assert_eq!(decode(&[0x0042]), vec![
+            (4, PefRelocTarget::Section(1)),
+            (8, PefRelocTarget::Section(1)),
+        ]);
+        // RelocTVector12 x2
+        assert_eq!(decode(&[0x4401]), vec![
+            (0, PefRelocTarget::Section(0)),
+            (4, PefRelocTarget::Section(1)),
+            (12, PefRelocTarget::Section(0)),
+            (16, PefRelocTarget::Section(1)),
+        ]);
+        // RelocImportRun x2, RelocSmByImport 5, RelocImportRun x1
+        assert_eq!(decode(&[0x4A01, 0x6005, 0x4A00]), vec![
+            (0, PefRelocTarget::Import(0)),
+            (4, PefRelocTarget::Import(1)),
+            (8, PefRelocTarget::Import(5)),
+            (12, PefRelocTarget::Import(6)),
+        ]);
+        // RelocSetPosition 0x10000, RelocSmSetSectD 2, RelocBySectD x1
+        assert_eq!(decode(&[0xA001, 0x0000, 0x6402, 0x4200]), vec![(
+            0x10000,
+            PefRelocTarget::Section(2)
+        )]);
+        // RelocBySectC x1, RelocIncrPosition 4, RelocSmRepeat 2 blocks x2
+        assert_eq!(decode(&[0x4000, 0x8003, 0x9101]), vec![
+            (0, PefRelocTarget::Section(0)),
+            (8, PefRelocTarget::Section(0)),
+            (16, PefRelocTarget::Section(0)),
+        ]);
+    }
+
+    #[test]
+    fn test_unpack_pidata() {
+        #[rustfmt::skip]
+        let data = [
+            0x03, // Zero 3
+            0x22, 0xAA, 0xBB, // BlockCopy 2
+            0x41, 0x02, 0xCC, // RepeatedBlock 1 x3
+            0x61, 0x01, 0x02, 0x11, 0x21, 0x22, // InterleaveRepeatBlockWithBlockCopy
+            0x82, 0x01, 0x01, 0x33, // InterleaveRepeatZero
+        ];
+        assert_eq!(unpack_pidata(&data).unwrap(), vec![
+            0x00, 0x00, 0x00, 0xAA, 0xBB, 0xCC, 0xCC, 0xCC, 0x11, 0x21, 0x11, 0x22, 0x11, 0x00,
+            0x00, 0x33, 0x00, 0x00,
+        ]);
+        // Zero with an extended count of 0x80
+        assert_eq!(unpack_pidata(&[0x00, 0x81, 0x00]).unwrap(), vec![0; 0x80]);
+    }
+
+    #[test]
+    fn test_pack_pidata() {
+        let mut data = vec![0x12, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x56];
+        data.extend([0xFF; 0x90]);
+        data.extend([0x00; 0x20]);
+        let packed = pack_pidata(&data);
+        assert!(packed.len() < data.len());
+        assert_eq!(unpack_pidata(&packed).unwrap(), data);
+        assert_eq!(unpack_pidata(&pack_pidata(&[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_pef_layout_round_trip() {
+        let layout = PefLayout {
+            date_time_stamp: 0xB3C1D2E4,
+            current_version: 0x1008000,
+            sections: vec![PefLayoutSection {
+                name: "code section".to_string(),
+                kind: PefSectionKind::Code,
+                share_kind: 4,
+                alignment: 4,
+                named: true,
+            }],
+            libraries: vec![PefImportedLibrary {
+                name_offset: 0,
+                old_imp_version: 0,
+                current_version: 0x1008000,
+                imported_symbol_count: 0,
+                first_imported_symbol: 0,
+                options: PefImportedLibrary::WEAK_IMPORT,
+                name: "Sound Manager Lib".to_string(),
+            }],
+            imports: vec![
+                PefImportedSymbol {
+                    name: "SndPlay".to_string(),
+                    class: PefSymbolClass::TVector,
+                    weak: false,
+                    library: 0,
+                },
+                PefImportedSymbol {
+                    name: "say \"hi\"\\".to_string(),
+                    class: PefSymbolClass::Data,
+                    weak: true,
+                    library: 0,
+                },
+            ],
+            exports: vec![("main entry".to_string(), PefSymbolClass::Code)],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_pef_layout(&mut out, &layout).unwrap();
+        let read = read_pef_layout(out.as_slice()).unwrap();
+        assert_eq!(read.date_time_stamp, layout.date_time_stamp);
+        assert_eq!(read.current_version, layout.current_version);
+        assert_eq!(read.sections.len(), 1);
+        assert_eq!(read.sections[0].name, "code section");
+        assert_eq!(read.sections[0].kind, PefSectionKind::Code);
+        assert_eq!(read.sections[0].share_kind, 4);
+        assert!(read.sections[0].named);
+        assert_eq!(read.libraries.len(), 1);
+        assert_eq!(read.libraries[0].name, "Sound Manager Lib");
+        assert!(read.libraries[0].is_weak());
+        let imports = read.imports.iter().map(|i| (i.name.as_str(), i.weak)).collect::<Vec<_>>();
+        assert_eq!(imports, vec![("SndPlay", false), ("say \"hi\"\\", true)]);
+        assert_eq!(read.exports.len(), 1);
+        assert_eq!(read.exports[0].0, "main entry");
+        assert_eq!(read.exports[0].1, PefSymbolClass::Code);
+        assert!(read_pef_layout(&b"library \"Unterminated"[..]).is_err());
+    }
+
+    #[test]
+    fn test_encode_relocations() {
+        let relocs = [
+            (0, PefRelocTarget::Section(1)),
+            (4, PefRelocTarget::Section(1)),
+            (8, PefRelocTarget::Section(0)),
+            (12, PefRelocTarget::Import(0)),
+            (16, PefRelocTarget::Import(1)),
+            (24, PefRelocTarget::Import(7)),
+            (0x2000, PefRelocTarget::Section(2)),
+            (0x40000, PefRelocTarget::Section(1)),
+        ]
+        .map(|(offset, target)| PefReloc { section: 1, offset, target });
+        let encoded = encode_relocations(&relocs, 3).unwrap();
+        assert_eq!(
+            decode(&encoded),
+            relocs.iter().map(|r| (r.offset, r.target)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_loader_bounds() {
+        let header = PefContainerHeader {
+            architecture: PEF_ARCH_PPC,
+            format_version: 1,
+            date_time_stamp: 0,
+            old_def_version: 0,
+            old_imp_version: 0,
+            current_version: 0,
+            section_count: 0,
+            inst_section_count: 0,
+        };
+        let section = PefSectionData {
+            name: None,
+            kind: PefSectionKind::Code,
+            share_kind: 4,
+            alignment: 4,
+            data: vec![0x4E, 0x80, 0x00, 0x20],
+            total_length: 4,
+        };
+        let loader = PefLoader {
+            header: PefLoaderHeader {
+                main_section: -1,
+                main_offset: 0,
+                init_section: -1,
+                init_offset: 0,
+                term_section: -1,
+                term_offset: 0,
+                imported_library_count: 0,
+                total_imported_symbol_count: 0,
+                reloc_section_count: 0,
+                reloc_instr_offset: 0,
+                loader_strings_offset: 0,
+                export_hash_offset: 0,
+                export_hash_table_power: 0,
+                exported_symbol_count: 0,
+            },
+            libraries: vec![],
+            imports: vec![],
+            exports: vec![],
+            relocations: vec![],
+        };
+        let mut buf = Vec::new();
+        write_pef(&mut buf, &header, &[section], &loader).unwrap();
+        let pef = PefFile::parse(&buf).unwrap();
+        let loader_offset = pef
+            .sections
+            .iter()
+            .find(|s| s.kind == PefSectionKind::Loader)
+            .unwrap()
+            .container_offset as usize;
+
+        // Corrupt a loader header field, by index
+        let corrupt = |field: usize, value: u32| {
+            let mut buf = buf.clone();
+            let offset = loader_offset + field * 4;
+            buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+            PefFile::parse(&buf)
+        };
+        // export_hash_table_power
+        assert!(corrupt(12, 64).is_err());
+        assert!(corrupt(12, 62).is_err());
+        // exported_symbol_count
+        assert!(corrupt(13, u32::MAX).is_err());
+        // total_imported_symbol_count
+        assert!(corrupt(7, u32::MAX).is_err());
+    }
 }
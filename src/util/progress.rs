@@ -0,0 +1,471 @@
+use std::{collections::BTreeMap, io::Write};
+
+use anyhow::Result;
+use itertools::{EitherOrBoth, Itertools};
+use objdiff_core::{
+    diff::{code::diff_code, DiffObjConfig},
+    obj::SymbolRef,
+};
+use serde::Serialize;
+
+use crate::{
+    obj::{ObjInfo, ObjSection, ObjSectionKind, ObjSymbol, ObjSymbolKind},
+    util::diff::{process_code, symbol_name_fuzzy_eq},
+};
+
+/// Version of the progress report schema. Fields are only ever added.
+pub const PROGRESS_REPORT_VERSION: u32 = 1;
+
+/// Matched sizes and counts, for a unit, section, module or the whole project.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProgressMeasures {
+    pub code_size: u64,
+    pub matched_code: u64,
+    pub matched_code_percent: f32,
+    /// Code size weighted by the match percentage of each function.
+    pub fuzzy_match_percent: f32,
+    pub data_size: u64,
+    pub matched_data: u64,
+    pub matched_data_percent: f32,
+    pub functions: u32,
+    pub matched_functions: u32,
+    pub matched_functions_percent: f32,
+    #[serde(skip)]
+    fuzzy_code: f64,
+}
+
+impl ProgressMeasures {
+    pub fn add(&mut self, other: &ProgressMeasures) {
+        self.code_size += other.code_size;
+        self.matched_code += other.matched_code;
+        self.fuzzy_code += other.fuzzy_code;
+        self.data_size += other.data_size;
+        self.matched_data += other.matched_data;
+        self.functions += other.functions;
+        self.matched_functions += other.matched_functions;
+        self.calc_percents();
+    }
+
+    fn calc_percents(&mut self) {
+        let percent =
+            |n: f64, total: f64| if total == 0.0 { 100.0 } else { (n / total * 100.0) as f32 };
+        self.matched_code_percent = percent(self.matched_code as f64, self.code_size as f64);
+        self.fuzzy_match_percent = percent(self.fuzzy_code, self.code_size as f64);
+        self.matched_data_percent = percent(self.matched_data as f64, self.data_size as f64);
+        self.matched_functions_percent =
+            percent(self.matched_functions as f64, self.functions as f64);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UnitProgress {
+    pub name: String,
+    pub autogenerated: bool,
+    /// Whether a built object was found for the unit.
+    pub built: bool,
+    pub measures: ProgressMeasures,
+    pub sections: BTreeMap<String, ProgressMeasures>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ModuleProgress {
+    pub name: String,
+    pub module_id: u32,
+    pub measures: ProgressMeasures,
+    pub sections: BTreeMap<String, ProgressMeasures>,
+    pub units: Vec<UnitProgress>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressReport {
+    pub version: u32,
+    pub measures: ProgressMeasures,
+    pub modules: Vec<ModuleProgress>,
+}
+
+impl Default for ProgressReport {
+    fn default() -> Self {
+        Self {
+            version: PROGRESS_REPORT_VERSION,
+            measures: Default::default(),
+            modules: Default::default(),
+        }
+    }
+}
+
+impl ModuleProgress {
+    pub fn add_unit(&mut self, unit: UnitProgress) {
+        self.measures.add(&unit.measures);
+        for (name, measures) in &unit.sections {
+            self.sections.entry(name.clone()).or_default().add(measures);
+        }
+        self.units.push(unit);
+    }
+}
+
+impl ProgressReport {
+    pub fn add_module(&mut self, module: ModuleProgress) {
+        self.measures.add(&module.measures);
+        self.modules.push(module);
+    }
+}
+
+/// Finds the built counterpart of a target symbol by name. Failing that, a symbol of
+/// the same kind at the same position in the same section matches if its name is a
+/// compiler-generated variant (e.g. `@1234` and `@5678`).
+fn find_symbol<'a>(
+    obj: &'a ObjInfo,
+    target_obj: &ObjInfo,
+    target: &ObjSymbol,
+) -> Option<(&'a ObjSymbol, &'a ObjSection)> {
+    let by_name = obj
+        .symbols
+        .for_name(&target.name)
+        .map(|(_, s)| s)
+        .find(|s| s.kind == target.kind)
+        .and_then(|s| Some((s, &obj.sections[s.section?])));
+    if by_name.is_some() {
+        return by_name;
+    }
+    let target_section = &target_obj.sections[target.section?];
+    let offset = target.address - target_section.address;
+    let (section_index, section) = obj.sections.by_name(&target_section.name).ok()??;
+    let address = (section.address + offset) as u32;
+    obj.symbols
+        .for_section_range(section_index, address..=address)
+        .map(|(_, s)| s)
+        .find(|s| s.kind == target.kind && symbol_name_fuzzy_eq(&target.name, &s.name))
+        .map(|s| (s, section))
+}
+
+/// Whether the data of two symbols matches, including relocations.
+fn data_matches(
+    target_obj: &ObjInfo,
+    target: &ObjSymbol,
+    target_section: &ObjSection,
+    built_obj: &ObjInfo,
+    built: &ObjSymbol,
+    built_section: &ObjSection,
+) -> Result<bool> {
+    if target.size != built.size || target_section.kind != built_section.kind {
+        return Ok(false);
+    }
+    if target_section.kind == ObjSectionKind::Bss {
+        return Ok(true);
+    }
+    let target_start = target.address as u32;
+    let built_start = built.address as u32;
+    if target_section.data_range(target_start, target_start + target.size as u32)?
+        != built_section.data_range(built_start, built_start + built.size as u32)?
+    {
+        return Ok(false);
+    }
+    let target_relocs =
+        target_section.relocations.range(target_start..target_start + target.size as u32);
+    let built_relocs =
+        built_section.relocations.range(built_start..built_start + built.size as u32);
+    for pair in target_relocs.zip_longest(built_relocs) {
+        let ((target_addr, target_reloc), (built_addr, built_reloc)) = match pair {
+            EitherOrBoth::Both(a, b) => (a, b),
+            _ => return Ok(false),
+        };
+        if target_addr - target_start != built_addr - built_start
+            || target_reloc.kind != built_reloc.kind
+            || target_reloc.addend != built_reloc.addend
+            || !symbol_name_fuzzy_eq(
+                &target_obj.symbols[target_reloc.target_symbol].name,
+                &built_obj.symbols[built_reloc.target_symbol].name,
+            )
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Diffs a built object against its split target, symbol by symbol.
+///
+/// Functions are diffed with objdiff-core, and count as matched when they match
+/// completely. Data symbols match when their contents and relocations are identical.
+/// Without a built object, nothing in the unit is matched.
+///
+/// Sizes are the sum of the sized function and object symbols, so alignment padding
+/// between symbols is not counted.
+pub fn unit_progress(
+    name: &str,
+    autogenerated: bool,
+    target_obj: &ObjInfo,
+    built_obj: Option<&ObjInfo>,
+) -> Result<UnitProgress> {
+    let mut sections = target_obj
+        .sections
+        .iter()
+        .map(|(_, s)| (s.name.clone(), ProgressMeasures::default()))
+        .collect::<BTreeMap<_, _>>();
+
+    let config = DiffObjConfig::default();
+    for (_, symbol) in target_obj.symbols.iter() {
+        let Some(section_index) = symbol.section else {
+            continue;
+        };
+        if symbol.size == 0
+            || !matches!(symbol.kind, ObjSymbolKind::Function | ObjSymbolKind::Object)
+        {
+            continue;
+        }
+        let section = &target_obj.sections[section_index];
+        let measures = sections.get_mut(&section.name).unwrap();
+        let built = built_obj.and_then(|obj| Some((obj, find_symbol(obj, target_obj, symbol)?)));
+        if section.kind == ObjSectionKind::Code && symbol.kind == ObjSymbolKind::Function {
+            measures.code_size += symbol.size;
+            measures.functions += 1;
+            let Some((built_obj, (built, built_section))) = built else {
+                continue;
+            };
+            if built_section.kind != ObjSectionKind::Code {
+                continue;
+            }
+            let target_code = process_code(target_obj, symbol, section, &config)?;
+            let built_code = process_code(built_obj, built, built_section, &config)?;
+            let (target_diff, _) = diff_code(
+                &target_code,
+                &built_code,
+                SymbolRef::default(),
+                SymbolRef::default(),
+                &config,
+            )?;
+            let match_percent = target_diff.match_percent.unwrap_or(0.0);
+            measures.fuzzy_code += symbol.size as f64 * match_percent as f64 / 100.0;
+            if match_percent == 100.0 {
+                measures.matched_code += symbol.size;
+                measures.matched_functions += 1;
+            }
+        } else if section.kind != ObjSectionKind::Code {
+            measures.data_size += symbol.size;
+            let Some((built_obj, (built, built_section))) = built else {
+                continue;
+            };
+            if data_matches(target_obj, symbol, section, built_obj, built, built_section)? {
+                measures.matched_data += symbol.size;
+            }
+        }
+    }
+
+    let mut unit = UnitProgress {
+        name: name.to_string(),
+        autogenerated,
+        built: built_obj.is_some(),
+        ..Default::default()
+    };
+    for (name, mut measures) in sections {
+        measures.calc_percents();
+        unit.measures.add(&measures);
+        unit.sections.insert(name, measures);
+    }
+    unit.measures.calc_percents();
+    Ok(unit)
+}
+
+fn write_measures_row<W>(w: &mut W, name: &str, m: &ProgressMeasures) -> Result<()>
+where W: Write + ?Sized {
+    writeln!(
+        w,
+        "| {} | {:.2}% ({} / {}) | {:.2}% | {:.2}% ({} / {}) | {:.2}% ({} / {}) |",
+        name,
+        m.matched_code_percent,
+        m.matched_code,
+        m.code_size,
+        m.fuzzy_match_percent,
+        m.matched_data_percent,
+        m.matched_data,
+        m.data_size,
+        m.matched_functions_percent,
+        m.matched_functions,
+        m.functions
+    )?;
+    Ok(())
+}
+
+fn write_table_header<W>(w: &mut W, first: &str) -> Result<()>
+where W: Write + ?Sized {
+    writeln!(w, "| {} | Code | Fuzzy | Data | Functions |", first)?;
+    writeln!(w, "| --- | --- | --- | --- | --- |")?;
+    Ok(())
+}
+
+/// Writes the report as Markdown tables: modules, then sections and units of each module.
+pub fn write_progress_markdown<W>(w: &mut W, report: &ProgressReport) -> Result<()>
+where W: Write + ?Sized {
+    writeln!(w, "# Progress\n")?;
+    write_table_header(w, "Module")?;
+    for module in &report.modules {
+        write_measures_row(w, &module.name, &module.measures)?;
+    }
+    write_measures_row(w, "**Total**", &report.measures)?;
+    for module in &report.modules {
+        writeln!(w, "\n## {}\n", module.name)?;
+        write_table_header(w, "Section")?;
+        for (name, measures) in &module.sections {
+            write_measures_row(w, &format!("`{}`", name), measures)?;
+        }
+        writeln!(w)?;
+        write_table_header(w, "Unit")?;
+        for unit in &module.units {
+            let mut name = format!("`{}`", unit.name);
+            if unit.autogenerated {
+                name.push_str(" (auto)");
+            } else if !unit.built {
+                name.push_str(" (not built)");
+            }
+            write_measures_row(w, &name, &unit.measures)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{
+        tests::{obj, reloc, section, symbol},
+        ObjKind, ObjRelocKind,
+    };
+
+    /// Object with the functions `main` and `helper` in .text, and `table` in .data,
+    /// pointing to `main` plus `table_addend`.
+    fn unit_obj(main: &[u32], helper: &[u32], table: &str, table_addend: i64) -> ObjInfo {
+        let code = main.iter().chain(helper).flat_map(|ins| ins.to_be_bytes()).collect();
+        let mut data = section(".data", ObjSectionKind::Data, 0, vec![0; 4]);
+        data.relocations.insert(0, reloc(ObjRelocKind::Absolute, 0, table_addend)).unwrap();
+        let main_size = main.len() as u64 * 4;
+        obj(
+            ObjKind::Relocatable,
+            vec![
+                symbol("main", ObjSymbolKind::Function, 0, 0, main_size),
+                symbol("helper", ObjSymbolKind::Function, 0, main_size, helper.len() as u64 * 4),
+                symbol(table, ObjSymbolKind::Object, 1, 0, 4),
+            ],
+            vec![section(".text", ObjSectionKind::Code, 0, code), data],
+        )
+    }
+
+    const LI_R3_0: u32 = 0x38600000;
+    const LI_R3_1: u32 = 0x38600001;
+    const BLR: u32 = 0x4E800020;
+
+    #[test]
+    fn test_unit_progress() {
+        let target = unit_obj(&[LI_R3_0, BLR], &[BLR], "table", 0);
+        let built = unit_obj(&[LI_R3_1, BLR], &[BLR], "table", 0);
+        let unit = unit_progress("main.c", false, &target, Some(&built)).unwrap();
+        assert!(unit.built);
+        let m = &unit.measures;
+        assert_eq!((m.code_size, m.matched_code), (12, 4));
+        assert_eq!((m.functions, m.matched_functions), (2, 1));
+        assert!(m.fuzzy_match_percent > m.matched_code_percent && m.fuzzy_match_percent < 100.0);
+        assert_eq!((m.data_size, m.matched_data), (4, 4));
+        assert_eq!(unit.sections[".text"].code_size, 12);
+        assert_eq!(unit.sections[".data"].matched_data, 4);
+    }
+
+    #[test]
+    fn test_unit_progress_data_relocations() {
+        let target = unit_obj(&[BLR], &[BLR], "@10", 0);
+        // Compiler-generated names match by position
+        let built = unit_obj(&[BLR], &[BLR], "@20", 0);
+        let unit = unit_progress("main.c", false, &target, Some(&built)).unwrap();
+        assert_eq!(unit.measures.matched_data, 4);
+        // Same bytes, but the relocation points elsewhere
+        let built = unit_obj(&[BLR], &[BLR], "@20", 4);
+        let unit = unit_progress("main.c", false, &target, Some(&built)).unwrap();
+        assert_eq!(unit.measures.matched_data, 0);
+        // Other names don't
+        let built = unit_obj(&[BLR], &[BLR], "other", 0);
+        let unit = unit_progress("main.c", false, &target, Some(&built)).unwrap();
+        assert_eq!(unit.measures.matched_data, 0);
+    }
+
+    #[test]
+    fn test_unit_progress_not_built() {
+        let target = unit_obj(&[LI_R3_0, BLR], &[BLR], "table", 0);
+        let unit = unit_progress("main.c", true, &target, None).unwrap();
+        assert!(!unit.built);
+        let m = &unit.measures;
+        assert_eq!((m.code_size, m.matched_code, m.matched_code_percent), (12, 0, 0.0));
+        assert_eq!((m.data_size, m.matched_data), (4, 0));
+        assert_eq!(m.fuzzy_match_percent, 0.0);
+    }
+
+    #[test]
+    fn test_measures_add() {
+        let mut total = ProgressMeasures::default();
+        total.calc_percents();
+        // Nothing to match counts as complete
+        assert_eq!(total.matched_code_percent, 100.0);
+        assert_eq!(total.matched_data_percent, 100.0);
+        total.add(&ProgressMeasures {
+            code_size: 100,
+            matched_code: 50,
+            fuzzy_code: 75.0,
+            functions: 4,
+            matched_functions: 1,
+            ..Default::default()
+        });
+        total.add(&ProgressMeasures {
+            code_size: 100,
+            matched_code: 100,
+            fuzzy_code: 100.0,
+            data_size: 10,
+            matched_data: 5,
+            functions: 1,
+            matched_functions: 1,
+            ..Default::default()
+        });
+        assert_eq!((total.code_size, total.matched_code), (200, 150));
+        assert_eq!(total.matched_code_percent, 75.0);
+        assert_eq!(total.fuzzy_match_percent, 87.5);
+        assert_eq!(total.matched_data_percent, 50.0);
+        assert_eq!(total.matched_functions_percent, 40.0);
+    }
+
+    #[test]
+    fn test_report_json() {
+        let mut measures = ProgressMeasures {
+            code_size: 8,
+            matched_code: 4,
+            fuzzy_code: 6.0,
+            data_size: 4,
+            matched_data: 4,
+            functions: 2,
+            matched_functions: 1,
+            ..Default::default()
+        };
+        measures.calc_percents();
+        let mut module = ModuleProgress { name: "main".to_string(), ..Default::default() };
+        module.add_unit(UnitProgress {
+            name: "main.c".to_string(),
+            autogenerated: false,
+            built: true,
+            measures,
+            sections: Default::default(),
+        });
+        let mut report = ProgressReport::default();
+        report.add_module(module);
+
+        let measures = concat!(
+            r#"{"code_size":8,"matched_code":4,"matched_code_percent":50.0,"#,
+            r#""fuzzy_match_percent":75.0,"data_size":4,"matched_data":4,"#,
+            r#""matched_data_percent":100.0,"functions":2,"matched_functions":1,"#,
+            r#""matched_functions_percent":50.0}"#
+        );
+        let expected = format!(
+            concat!(
+                r#"{{"version":1,"measures":{m},"modules":[{{"name":"main","module_id":0,"#,
+                r#""measures":{m},"sections":{{}},"units":[{{"name":"main.c","#,
+                r#""autogenerated":false,"built":true,"measures":{m},"sections":{{}}}}]}}]}}"#
+            ),
+            m = measures
+        );
+        assert_eq!(serde_json::to_string(&report).unwrap(), expected);
+    }
+}
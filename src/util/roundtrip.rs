@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use crate::{
+    obj::{ObjInfo, ObjKind, ObjReloc, ObjRelocKind, ObjSection, ObjSectionKind},
+    util::{diff::symbol_name_fuzzy_eq, elf::process_elf_data},
+};
+
+/// A difference between a written split object and the original module.
+#[derive(Debug, Clone)]
+pub struct RoundTripIssue {
+    pub unit: String,
+    pub section: String,
+    /// Address in the original module.
+    pub address: u32,
+    pub message: String,
+}
+
+/// Collects the addresses of the global symbols defined by the split objects,
+/// which is how the linker resolves references between units.
+pub fn split_symbol_addresses(split_objs: &[ObjInfo]) -> BTreeMap<String, u32> {
+    let mut addresses = BTreeMap::new();
+    for split_obj in split_objs {
+        for (_, symbol) in split_obj.symbols.iter() {
+            let Some(section_index) = symbol.section else {
+                continue;
+            };
+            if symbol.flags.is_local() {
+                continue;
+            }
+            if let Some(base) = split_obj.sections[section_index].virtual_address {
+                addresses.insert(symbol.name.clone(), (base + symbol.address) as u32);
+            }
+        }
+    }
+    addresses
+}
+
+/// Bits of the word that a relocation writes.
+fn reloc_mask(kind: ObjRelocKind) -> u32 {
+    match kind {
+        ObjRelocKind::Absolute | ObjRelocKind::PpcRel32 => 0xFFFFFFFF,
+        ObjRelocKind::PpcAddr16Hi
+        | ObjRelocKind::PpcAddr16Ha
+        | ObjRelocKind::PpcAddr16Lo
+        | ObjRelocKind::PpcToc16 => 0xFFFF,
+        ObjRelocKind::PpcRel24 => 0x3FFFFFC,
+        ObjRelocKind::PpcRel14 => 0xFFFC,
+        ObjRelocKind::PpcEmbSda21 => 0x1FFFFF,
+    }
+}
+
+/// Applies a relocation the way the linker would. For SDA21 relocations, the
+/// base register is taken from the original instruction.
+fn apply_reloc(
+    obj: &ObjInfo,
+    kind: ObjRelocKind,
+    ins: u32,
+    original: u32,
+    source: u32,
+    target: u32,
+) -> u32 {
+    let value = match kind {
+        ObjRelocKind::Absolute => target,
+        ObjRelocKind::PpcRel32 | ObjRelocKind::PpcRel24 | ObjRelocKind::PpcRel14 => {
+            target.wrapping_sub(source)
+        }
+        ObjRelocKind::PpcAddr16Hi => target >> 16,
+        ObjRelocKind::PpcAddr16Ha => target.wrapping_add(0x8000) >> 16,
+        ObjRelocKind::PpcAddr16Lo => target,
+        ObjRelocKind::PpcToc16 => target.wrapping_sub(obj.toc_base.unwrap_or(0)),
+        ObjRelocKind::PpcEmbSda21 => {
+            let reg = (original >> 16) & 0x1F;
+            let base = match reg {
+                13 => obj.sda_base.unwrap_or(0),
+                2 => obj.sda2_base.unwrap_or(0),
+                _ => 0,
+            };
+            (reg << 16) | (target.wrapping_sub(base) & 0xFFFF)
+        }
+    };
+    let mask = reloc_mask(kind);
+    (ins & !mask) | (value & mask)
+}
+
+/// Resolves a relocation target in the written object to an address in the module.
+fn resolve_target(
+    obj: &ObjInfo,
+    written: &ObjInfo,
+    reloc: &ObjReloc,
+    globals: &BTreeMap<String, u32>,
+) -> Option<u32> {
+    let symbol = &written.symbols[reloc.target_symbol];
+    let address = match symbol.section {
+        Some(section_index) => written.sections[section_index].virtual_address? + symbol.address,
+        None => match globals.get(&symbol.name) {
+            Some(&address) => address as u64,
+            None => {
+                obj.symbols.for_name(&symbol.name).find(|(_, s)| s.section.is_some())?.1.address
+            }
+        },
+    };
+    Some((address as i64 + reloc.addend) as u32)
+}
+
+fn find_original_section(obj: &ObjInfo, section: &ObjSection, address: u64) -> Option<&ObjSection> {
+    obj.sections
+        .iter()
+        .find(|(_, s)| {
+            s.kind == section.kind
+                && s.address <= address
+                && address + section.size <= s.address + s.size
+        })
+        .map(|(_, s)| s)
+}
+
+/// Reads back a written split object and checks it against the original module.
+///
+/// For executables, relocations are applied at the original addresses and the result
+/// is compared with the original bytes. For relocatable modules, the relocated fields
+/// are ignored instead. In both cases, the relocations themselves must match the
+/// original module's relocations in kind and target.
+pub fn verify_split_obj(
+    obj: &ObjInfo,
+    unit: &str,
+    data: &[u8],
+    globals: &BTreeMap<String, u32>,
+) -> Result<Vec<RoundTripIssue>> {
+    let written = process_elf_data(data)?;
+    let mut issues = vec![];
+    for (_, section) in written.sections.iter() {
+        let mut issue = |address: u32, message: String| {
+            issues.push(RoundTripIssue {
+                unit: unit.to_string(),
+                section: section.name.clone(),
+                address,
+                message,
+            });
+        };
+        let Some(virtual_address) = section.virtual_address else {
+            issue(0, "Missing original address".to_string());
+            continue;
+        };
+        let Some(original_section) = find_original_section(obj, section, virtual_address) else {
+            issue(virtual_address as u32, "No original section contains this section".to_string());
+            continue;
+        };
+        let start = virtual_address as u32;
+        let end = (virtual_address + section.size) as u32;
+
+        // Compare relocations by kind and resolved target
+        let mut expected =
+            original_section.relocations.range(start..end).collect::<BTreeMap<_, _>>();
+        let mut targets = BTreeMap::new();
+        for (offset, reloc) in section.relocations.iter() {
+            let address = start + offset;
+            let name = &written.symbols[reloc.target_symbol].name;
+            let target = resolve_target(obj, &written, reloc, globals);
+            targets.insert(offset, target);
+            let Some(original) = expected.remove(&address) else {
+                issue(address, format!("Extra {:?} relocation to {}", reloc.kind, name));
+                continue;
+            };
+            let original_symbol = &obj.symbols[original.target_symbol];
+            if original.kind != reloc.kind {
+                issue(
+                    address,
+                    format!(
+                        "{:?} relocation to {}, expected {:?}",
+                        reloc.kind, name, original.kind
+                    ),
+                );
+                continue;
+            }
+            let original_target = original_symbol
+                .section
+                .map(|_| (original_symbol.address as i64 + original.addend) as u32);
+            match (original_target, target) {
+                (Some(original_target), Some(target)) if original_target != target => issue(
+                    address,
+                    format!(
+                        "Relocation to {} resolves to {:#010X}, expected {:#010X} ({})",
+                        name, target, original_target, original_symbol.name
+                    ),
+                ),
+                (Some(_), None) => issue(address, format!("Unresolved relocation target {}", name)),
+                // Target in another module, compare by name
+                (None, _)
+                    if !symbol_name_fuzzy_eq(name, &original_symbol.name)
+                        || original.addend != reloc.addend =>
+                {
+                    issue(
+                        address,
+                        format!(
+                            "Relocation to {}{:+#X}, expected {}{:+#X}",
+                            name, reloc.addend, original_symbol.name, original.addend
+                        ),
+                    )
+                }
+                _ => {}
+            }
+        }
+        for (address, original) in expected {
+            issue(
+                address,
+                format!(
+                    "Missing {:?} relocation to {}",
+                    original.kind, obj.symbols[original.target_symbol].name
+                ),
+            );
+        }
+
+        if section.kind == ObjSectionKind::Bss {
+            continue;
+        }
+        let original_data = original_section.data_range(start, end)?;
+        let mut linked = section.data.clone();
+        for (offset, reloc) in section.relocations.iter() {
+            let offset = offset as usize;
+            if offset + 4 > linked.len() || offset + 4 > original_data.len() {
+                continue;
+            }
+            let ins = u32::from_be_bytes(linked[offset..offset + 4].try_into().unwrap());
+            let original =
+                u32::from_be_bytes(original_data[offset..offset + 4].try_into().unwrap());
+            let value = match targets.get(&(offset as u32)).copied().flatten() {
+                Some(target) if obj.kind == ObjKind::Executable => {
+                    apply_reloc(obj, reloc.kind, ins, original, start + offset as u32, target)
+                }
+                // Ignore the relocated field
+                _ => {
+                    let mask = reloc_mask(reloc.kind);
+                    (ins & !mask) | (original & mask)
+                }
+            };
+            linked[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+        if linked.len() != original_data.len() {
+            issue(start, format!("Size {:#X}, expected {:#X}", linked.len(), original_data.len()));
+        } else if let Some(pos) = linked.iter().zip(original_data).position(|(a, b)| a != b) {
+            let count = linked.iter().zip(original_data).filter(|(a, b)| a != b).count();
+            issue(
+                start + pos as u32,
+                format!(
+                    "{} bytes differ, first {:#04X} expected {:#04X}",
+                    count, linked[pos], original_data[pos]
+                ),
+            );
+        }
+    }
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use objdiff_core::obj::split_meta::SplitMeta;
+
+    use super::*;
+    use crate::{
+        obj::{
+            tests::{obj, reloc, section, symbol},
+            ObjSymbol, ObjSymbolKind, SymbolIndex,
+        },
+        util::{comment::MWComment, elf::write_elf},
+    };
+
+    #[test]
+    fn test_reloc_mask() {
+        assert_eq!(reloc_mask(ObjRelocKind::Absolute), 0xFFFFFFFF);
+        assert_eq!(reloc_mask(ObjRelocKind::PpcRel32), 0xFFFFFFFF);
+        assert_eq!(reloc_mask(ObjRelocKind::PpcAddr16Hi), 0xFFFF);
+        assert_eq!(reloc_mask(ObjRelocKind::PpcAddr16Ha), 0xFFFF);
+        assert_eq!(reloc_mask(ObjRelocKind::PpcAddr16Lo), 0xFFFF);
+        assert_eq!(reloc_mask(ObjRelocKind::PpcToc16), 0xFFFF);
+        assert_eq!(reloc_mask(ObjRelocKind::PpcEmbSda21), 0x1FFFFF);
+        // Branch displacements leave the opcode, AA and LK bits alone
+        for (kind, bits) in [(ObjRelocKind::PpcRel24, 24), (ObjRelocKind::PpcRel14, 14)] {
+            let mask = reloc_mask(kind);
+            assert_eq!(mask.count_ones(), bits, "{:?}", kind);
+            assert_eq!(mask.trailing_zeros(), 2, "{:?}", kind);
+            assert_eq!(mask.leading_zeros(), 30 - bits, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_apply_reloc() {
+        let mut obj = obj(ObjKind::Executable, vec![], vec![]);
+        obj.toc_base = Some(0x80010000);
+        obj.sda_base = Some(0x80100000);
+        obj.sda2_base = Some(0x80110000);
+        let apply = |kind, ins, original, source, target| {
+            apply_reloc(&obj, kind, ins, original, source, target)
+        };
+
+        assert_eq!(apply(ObjRelocKind::Absolute, 0, 0, 0x80004000, 0x80001234), 0x80001234);
+        assert_eq!(apply(ObjRelocKind::PpcRel32, 0, 0, 0x80004000, 0x80003FF0), 0xFFFFFFF0);
+        // lis r3 / addi r3, r3
+        assert_eq!(apply(ObjRelocKind::PpcAddr16Hi, 0x3C600000, 0, 0, 0x80018000), 0x3C608001);
+        assert_eq!(apply(ObjRelocKind::PpcAddr16Ha, 0x3C600000, 0, 0, 0x80018000), 0x3C608002);
+        assert_eq!(apply(ObjRelocKind::PpcAddr16Lo, 0x38630000, 0, 0, 0x80018000), 0x38638000);
+        // bl, backwards and forwards
+        assert_eq!(
+            apply(ObjRelocKind::PpcRel24, 0x48000001, 0, 0x80003100, 0x80003000),
+            0x4BFFFF01
+        );
+        assert_eq!(
+            apply(ObjRelocKind::PpcRel24, 0x48000001, 0, 0x80003100, 0x80003120),
+            0x48000021
+        );
+        // b to the limit of the 24-bit displacement
+        assert_eq!(
+            apply(ObjRelocKind::PpcRel24, 0x48000000, 0, 0x80000000, 0x81FFFFFC),
+            0x49FFFFFC
+        );
+        // bne and bnel, backwards and forwards
+        assert_eq!(
+            apply(ObjRelocKind::PpcRel14, 0x40820000, 0, 0x80003100, 0x800030F0),
+            0x4082FFF0
+        );
+        assert_eq!(
+            apply(ObjRelocKind::PpcRel14, 0x40820001, 0, 0x80003100, 0x80003140),
+            0x40820041
+        );
+        // lwz r3, x(r2)
+        assert_eq!(apply(ObjRelocKind::PpcToc16, 0x80620000, 0, 0, 0x80010010), 0x80620010);
+        assert_eq!(apply(ObjRelocKind::PpcToc16, 0x80620000, 0, 0, 0x8000FFF0), 0x8062FFF0);
+        // The SDA21 base register comes from the original instruction
+        assert_eq!(
+            apply(ObjRelocKind::PpcEmbSda21, 0x80600000, 0x806D8010, 0, 0x800F8010),
+            0x806D8010
+        );
+        assert_eq!(
+            apply(ObjRelocKind::PpcEmbSda21, 0xC0200000, 0xC0228000, 0, 0x80108000),
+            0xC0228000
+        );
+        assert_eq!(apply(ObjRelocKind::PpcEmbSda21, 0x80600000, 0x80600000, 0, 0x1234), 0x80601234);
+    }
+
+    fn symbols(text: u64, data: u64) -> Vec<ObjSymbol> {
+        vec![
+            symbol("fn_a", ObjSymbolKind::Function, 0, text, 8),
+            symbol("fn_b", ObjSymbolKind::Function, 0, text + 8, 0xC),
+            symbol("lbl_80004000", ObjSymbolKind::Object, 1, data, 4),
+        ]
+    }
+
+    /// fn_a calls fn_b, which loads the address of lbl_80004000, a pointer to fn_a.
+    fn original_obj(text_data: [u32; 5], data_target: SymbolIndex) -> ObjInfo {
+        let text_data = text_data.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let data = pointer(&symbols(0x80003000, 0x80004000)[data_target as usize]);
+        let mut sections = vec![
+            section(".text", ObjSectionKind::Code, 0x80003000, text_data),
+            section(".data", ObjSectionKind::Data, 0x80004000, data),
+        ];
+        add_relocs(&mut sections, 0x80003000, 0x80004000, data_target);
+        obj(ObjKind::Executable, symbols(0x80003000, 0x80004000), sections)
+    }
+
+    fn pointer(symbol: &ObjSymbol) -> Vec<u8> { (symbol.address as u32).to_be_bytes().to_vec() }
+
+    fn add_relocs(sections: &mut [ObjSection], text: u32, data: u32, data_target: SymbolIndex) {
+        let text_relocs = &mut sections[0].relocations;
+        text_relocs.insert(text, reloc(ObjRelocKind::PpcRel24, 1, 0)).unwrap();
+        text_relocs.insert(text + 8, reloc(ObjRelocKind::PpcAddr16Ha, 2, 0)).unwrap();
+        text_relocs.insert(text + 0xC, reloc(ObjRelocKind::PpcAddr16Lo, 2, 0)).unwrap();
+        let data_relocs = &mut sections[1].relocations;
+        data_relocs.insert(data, reloc(ObjRelocKind::Absolute, data_target, 0)).unwrap();
+    }
+
+    /// The object written for unit a.c, with relocated fields cleared.
+    fn written_obj(data_address: u64) -> Vec<u8> {
+        let text_data = [0x48000001u32, 0x4E800020, 0x3C600000, 0x38630000, 0x4E800020]
+            .iter()
+            .flat_map(|i| i.to_be_bytes())
+            .collect();
+        let mut sections = vec![
+            section(".text", ObjSectionKind::Code, 0, text_data),
+            section(".data", ObjSectionKind::Data, 0, vec![0; 4]),
+        ];
+        sections[0].virtual_address = Some(0x80003000);
+        sections[1].virtual_address = Some(data_address);
+        add_relocs(&mut sections, 0, 0, 0);
+        let mut obj = obj(ObjKind::Relocatable, symbols(0, 0), sections);
+        obj.mw_comment = Some(MWComment::new(8).unwrap());
+        obj.split_meta = Some(SplitMeta {
+            generator: None,
+            module_name: None,
+            module_id: None,
+            virtual_addresses: None,
+        });
+        write_elf(&obj, false).unwrap()
+    }
+
+    const TEXT: [u32; 5] = [0x48000009, 0x4E800020, 0x3C608000, 0x38634000, 0x4E800020];
+
+    #[test]
+    fn test_verify_split_obj() {
+        let obj = original_obj(TEXT, 0);
+        let issues =
+            verify_split_obj(&obj, "a.c", &written_obj(0x80004000), &BTreeMap::new()).unwrap();
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    #[test]
+    fn test_verify_split_obj_mismatch() {
+        // The original ends with blrl, and its pointer targets fn_b
+        let mut text = TEXT;
+        text[4] = 0x4E800021;
+        let obj = original_obj(text, 1);
+        let issues =
+            verify_split_obj(&obj, "a.c", &written_obj(0x80004000), &BTreeMap::new()).unwrap();
+        let issues = issues
+            .iter()
+            .map(|i| (i.unit.as_str(), i.section.as_str(), i.address, i.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(issues, [
+            ("a.c", ".text", 0x80003013, "1 bytes differ, first 0x20 expected 0x21"),
+            (
+                "a.c",
+                ".data",
+                0x80004000,
+                "Relocation to fn_a resolves to 0x80003000, expected 0x80003008 (fn_b)"
+            ),
+            ("a.c", ".data", 0x80004003, "1 bytes differ, first 0x00 expected 0x08"),
+        ]);
+
+        // A section placed outside of the original sections
+        let obj = original_obj(TEXT, 0);
+        let issues =
+            verify_split_obj(&obj, "a.c", &written_obj(0x80005000), &BTreeMap::new()).unwrap();
+        let issues = issues.iter().filter(|i| i.section == ".data").collect::<Vec<_>>();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].address, 0x80005000);
+        assert_eq!(issues[0].message, "No original section contains this section");
+    }
+}
@@ -1,8 +1,12 @@
-use std::collections::{btree_map, BTreeMap};
+use std::{
+    collections::{btree_map, BTreeMap, HashMap, HashSet},
+    io::Read,
+};
 
-use anyhow::{anyhow, bail, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use base64::{engine::general_purpose::STANDARD, Engine};
 use cwdemangle::{demangle, DemangleOptions};
+use object::elf;
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use typed_path::Utf8NativePath;
@@ -15,10 +19,11 @@ use crate::{
     },
     array_ref,
     obj::{
-        ObjInfo, ObjKind, ObjReloc, ObjRelocKind, ObjSection, ObjSymbol, ObjSymbolFlagSet,
-        ObjSymbolKind, SectionIndex, SymbolIndex,
+        ObjInfo, ObjKind, ObjReloc, ObjRelocKind, ObjSection, ObjSectionKind, ObjSymbol,
+        ObjSymbolFlagSet, ObjSymbolKind, SectionIndex, SymbolIndex,
     },
-    util::elf::process_elf,
+    util::elf::{process_elf, process_elf_data},
+    vfs::open_file,
 };
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
@@ -45,6 +50,34 @@ pub struct FunctionSignature {
     pub signature: String,
     pub symbols: Vec<OutSymbol>,
     pub relocations: Vec<OutReloc>,
+    /// Object the signature was generated from, e.g. `os.a:OSInit.o`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl FunctionSignature {
+    #[inline]
+    pub fn name(&self) -> &str { &self.symbols[self.symbol as usize].name }
+
+    #[inline]
+    pub fn size(&self) -> u32 { self.symbols[self.symbol as usize].size }
+
+    /// The static library (or object) the signature was generated from.
+    pub fn library(&self) -> Option<&str> {
+        self.source.as_deref().map(|s| s.split_once(':').map_or(s, |(lib, _)| lib))
+    }
+
+    /// Decodes the signature into (instruction, mask) pairs.
+    pub fn decode(&self) -> Result<Vec<(u32, u32)>> {
+        let sig_data = STANDARD.decode(&self.signature)?;
+        ensure!(sig_data.len() % 8 == 0, "Invalid signature length for {}", self.name());
+        Ok(sig_data
+            .chunks_exact(8)
+            .map(|c| {
+                (u32::from_be_bytes(*array_ref!(c, 0, 4)), u32::from_be_bytes(*array_ref!(c, 4, 4)))
+            })
+            .collect())
+    }
 }
 
 pub fn check_signature(mut data: &[u8], sig: &FunctionSignature) -> Result<bool> {
@@ -73,6 +106,12 @@ pub fn parse_signatures(sig_str: &str) -> Result<Vec<FunctionSignature>> {
     Ok(serde_yaml::from_str(sig_str)?)
 }
 
+pub fn read_signatures(path: &Utf8NativePath) -> Result<Vec<FunctionSignature>> {
+    let mut file = open_file(path, true)?;
+    let sig_str = std::str::from_utf8(file.map()?).with_context(|| format!("Reading {}", path))?;
+    parse_signatures(sig_str).with_context(|| format!("Parsing {}", path))
+}
+
 pub fn check_signatures_str(
     section: &ObjSection,
     addr: u32,
@@ -175,7 +214,8 @@ pub fn apply_signature(
             | (&Relocation::Lo(RelocationTarget::Address(addr)), ObjRelocKind::PpcAddr16Lo)
             | (&Relocation::Rel24(RelocationTarget::Address(addr)), ObjRelocKind::PpcRel24)
             | (&Relocation::Rel14(RelocationTarget::Address(addr)), ObjRelocKind::PpcRel14)
-            | (&Relocation::Sda21(RelocationTarget::Address(addr)), ObjRelocKind::PpcEmbSda21) => {
+            | (&Relocation::Sda21(RelocationTarget::Address(addr)), ObjRelocKind::PpcEmbSda21)
+            | (&Relocation::Toc16(RelocationTarget::Address(addr)), ObjRelocKind::PpcToc16) => {
                 SectionAddress::new(
                     addr.section,
                     (addr.address as i64 - sig_reloc.addend as i64) as u32,
@@ -244,14 +284,38 @@ pub fn compare_signature(existing: &mut FunctionSignature, new: &FunctionSignatu
     Ok(())
 }
 
+/// Groups signatures by function name, keeping every revision (distinct hash) of
+/// each function in the order they were first seen.
+pub fn merge_signatures(
+    signatures: impl IntoIterator<Item = FunctionSignature>,
+) -> BTreeMap<String, Vec<FunctionSignature>> {
+    let mut functions: BTreeMap<String, Vec<FunctionSignature>> = BTreeMap::new();
+    for signature in signatures {
+        let revisions = match functions.entry(signature.name().to_string()) {
+            btree_map::Entry::Vacant(e) => {
+                e.insert(vec![signature]);
+                continue;
+            }
+            btree_map::Entry::Occupied(e) => e.into_mut(),
+        };
+        let Some(existing) = revisions.iter_mut().find(|s| s.hash == signature.hash) else {
+            revisions.push(signature);
+            continue;
+        };
+        if let Err(e) = compare_signature(existing, &signature) {
+            log::warn!("Keeping first signature for {}: {:#}", signature.name(), e);
+        }
+        if existing.source.is_none() {
+            existing.source = signature.source;
+        }
+    }
+    functions
+}
+
 pub fn generate_signature(
     path: &Utf8NativePath,
     symbol_name: &str,
 ) -> Result<Option<FunctionSignature>> {
-    let mut out_symbols: Vec<OutSymbol> = Vec::new();
-    let mut out_relocs: Vec<OutReloc> = Vec::new();
-    let mut symbol_map: BTreeMap<SymbolIndex, u32> = BTreeMap::new();
-
     let mut obj = process_elf(path)?;
     if obj.kind == ObjKind::Executable
         && (obj.sda2_base.is_none()
@@ -285,106 +349,339 @@ pub fn generate_signature(
         if symbol.name != symbol_name && symbol.name != symbol_name.replace("TRK", "TRK_") {
             continue;
         }
-        let section_idx = symbol.section.unwrap();
-        let section = &obj.sections[section_idx];
-        // let out_symbol_idx = out_symbols.len();
-        out_symbols.push(OutSymbol {
-            kind: symbol.kind,
-            name: symbol.name.clone(),
-            size: symbol.size as u32,
-            flags: symbol.flags,
-            section: Some(section.name.clone()),
-        });
-        // println!(
-        //     "Building signature for {} ({:#010X}-{:#010X})",
-        //     symbol.name,
-        //     symbol.address,
-        //     symbol.address + symbol.size
-        // );
-        let mut instructions = section.data[(symbol.address - section.address) as usize
-            ..(symbol.address - section.address + symbol.size) as usize]
-            .chunks_exact(4)
-            .map(|c| (u32::from_be_bytes(c.try_into().unwrap()), !0u32))
-            .collect::<Vec<(u32, u32)>>();
-        for (idx, (ins, pat)) in instructions.iter_mut().enumerate() {
-            let addr = (symbol.address as usize + idx * 4) as u32;
-            if let Some(reloc) = section.relocations.at(addr) {
-                let symbol_idx = match symbol_map.entry(reloc.target_symbol) {
-                    btree_map::Entry::Vacant(e) => {
-                        let target = &obj.symbols[reloc.target_symbol];
-                        let symbol_idx = out_symbols.len() as u32;
-                        e.insert(symbol_idx);
-                        out_symbols.push(OutSymbol {
-                            kind: target.kind,
-                            name: target.name.clone(),
-                            size: if target.kind == ObjSymbolKind::Function {
-                                0
-                            } else {
-                                target.size as u32
-                            },
-                            flags: target.flags,
-                            section: target
-                                .section
-                                .and_then(|idx| obj.sections.get(idx))
-                                .map(|section| section.name.clone()),
-                        });
-                        symbol_idx
-                    }
-                    btree_map::Entry::Occupied(e) => *e.get(),
-                };
-                match reloc.kind {
-                    ObjRelocKind::Absolute => {
-                        *ins = 0;
-                        *pat = 0;
-                    }
-                    ObjRelocKind::PpcAddr16Hi
-                    | ObjRelocKind::PpcAddr16Ha
-                    | ObjRelocKind::PpcAddr16Lo => {
-                        *ins &= !0xFFFF;
-                        *pat = !0xFFFF;
-                    }
-                    ObjRelocKind::PpcRel24 => {
-                        *ins &= !0x3FFFFFC;
-                        *pat = !0x3FFFFFC;
-                    }
-                    ObjRelocKind::PpcRel14 => {
-                        *ins &= !0xFFFC;
-                        *pat = !0xFFFC;
-                    }
-                    ObjRelocKind::PpcEmbSda21 => {
-                        *ins &= !0x1FFFFF;
-                        *pat = !0x1FFFFF;
-                    }
+        return build_signature(&obj, symbol).map(Some);
+    }
+    Ok(None)
+}
+
+/// Builds a relocation-masked signature for a function symbol.
+pub fn build_signature(obj: &ObjInfo, symbol: &ObjSymbol) -> Result<FunctionSignature> {
+    let mut out_symbols: Vec<OutSymbol> = Vec::new();
+    let mut out_relocs: Vec<OutReloc> = Vec::new();
+    let mut symbol_map: BTreeMap<SymbolIndex, u32> = BTreeMap::new();
+
+    let section_idx =
+        symbol.section.ok_or_else(|| anyhow!("Symbol {} has no section", symbol.name))?;
+    let section = &obj.sections[section_idx];
+    out_symbols.push(OutSymbol {
+        kind: symbol.kind,
+        name: symbol.name.clone(),
+        size: symbol.size as u32,
+        flags: symbol.flags,
+        section: Some(section.name.clone()),
+    });
+    let mut instructions = section.data[(symbol.address - section.address) as usize
+        ..(symbol.address - section.address + symbol.size) as usize]
+        .chunks_exact(4)
+        .map(|c| (u32::from_be_bytes(c.try_into().unwrap()), !0u32))
+        .collect::<Vec<(u32, u32)>>();
+    for (idx, (ins, pat)) in instructions.iter_mut().enumerate() {
+        let addr = (symbol.address as usize + idx * 4) as u32;
+        if let Some(reloc) = section.relocations.at(addr) {
+            let symbol_idx = match symbol_map.entry(reloc.target_symbol) {
+                btree_map::Entry::Vacant(e) => {
+                    let target = &obj.symbols[reloc.target_symbol];
+                    let symbol_idx = out_symbols.len() as u32;
+                    e.insert(symbol_idx);
+                    out_symbols.push(OutSymbol {
+                        kind: target.kind,
+                        name: target.name.clone(),
+                        size: if target.kind == ObjSymbolKind::Function {
+                            0
+                        } else {
+                            target.size as u32
+                        },
+                        flags: target.flags,
+                        section: target
+                            .section
+                            .and_then(|idx| obj.sections.get(idx))
+                            .map(|section| section.name.clone()),
+                    });
+                    symbol_idx
+                }
+                btree_map::Entry::Occupied(e) => *e.get(),
+            };
+            match reloc.kind {
+                ObjRelocKind::Absolute | ObjRelocKind::PpcRel32 => {
+                    *ins = 0;
+                    *pat = 0;
                 }
-                out_relocs.push(OutReloc {
-                    offset: addr - (symbol.address as u32),
-                    kind: reloc.kind,
-                    symbol: symbol_idx,
-                    addend: reloc.addend as i32,
-                });
+                ObjRelocKind::PpcAddr16Hi
+                | ObjRelocKind::PpcAddr16Ha
+                | ObjRelocKind::PpcAddr16Lo
+                | ObjRelocKind::PpcToc16 => {
+                    *ins &= !0xFFFF;
+                    *pat = !0xFFFF;
+                }
+                ObjRelocKind::PpcRel24 => {
+                    *ins &= !0x3FFFFFC;
+                    *pat = !0x3FFFFFC;
+                }
+                ObjRelocKind::PpcRel14 => {
+                    *ins &= !0xFFFC;
+                    *pat = !0xFFFC;
+                }
+                ObjRelocKind::PpcEmbSda21 => {
+                    *ins &= !0x1FFFFF;
+                    *pat = !0x1FFFFF;
+                }
+            }
+            out_relocs.push(OutReloc {
+                offset: addr - (symbol.address as u32),
+                kind: reloc.kind,
+                symbol: symbol_idx,
+                addend: reloc.addend as i32,
+            });
+        }
+    }
+
+    let mut data = vec![0u8; instructions.len() * 8];
+    for (idx, &(ins, pat)) in instructions.iter().enumerate() {
+        data[idx * 8..idx * 8 + 4].copy_from_slice(&ins.to_be_bytes());
+        data[idx * 8 + 4..idx * 8 + 8].copy_from_slice(&pat.to_be_bytes());
+    }
+
+    let encoded = STANDARD.encode(&data);
+    let mut hasher = Sha1::new();
+    hasher.update(&data);
+    let hash = hasher.finalize();
+    let mut hash_buf = [0u8; 40];
+    let hash_str = base16ct::lower::encode_str(&hash, &mut hash_buf)
+        .map_err(|e| anyhow!("Failed to encode hash: {e}"))?;
+    Ok(FunctionSignature {
+        symbol: 0,
+        hash: hash_str.to_string(),
+        signature: encoded,
+        symbols: out_symbols,
+        relocations: out_relocs,
+        source: None,
+    })
+}
+
+/// Builds signatures for every function in a relocatable object.
+pub fn generate_signatures(obj: &ObjInfo, source: &str) -> Result<Vec<FunctionSignature>> {
+    ensure!(obj.kind == ObjKind::Relocatable, "{} is not a relocatable object", source);
+    let mut out = Vec::new();
+    for (_, symbol) in obj.symbols.by_kind(ObjSymbolKind::Function) {
+        let Some(section_index) = symbol.section else {
+            continue;
+        };
+        if symbol.size == 0 || obj.sections[section_index].kind != ObjSectionKind::Code {
+            continue;
+        }
+        let mut signature = build_signature(obj, symbol)
+            .with_context(|| format!("Building signature for {} in {}", symbol.name, source))?;
+        signature.source = Some(source.to_string());
+        out.push(signature);
+    }
+    Ok(out)
+}
+
+/// Builds signatures for every function in an object file or static library.
+/// Static library members are processed individually and recorded as `lib.a:member.o`.
+pub fn generate_signatures_from_file(path: &Utf8NativePath) -> Result<Vec<FunctionSignature>> {
+    let mut file = open_file(path, true)?;
+    let data = file.map()?;
+    let file_name = path.file_name().unwrap_or(path.as_str());
+    if !data.starts_with(b"!<arch>\n") {
+        let obj = process_elf_data(data).with_context(|| format!("Loading {}", path))?;
+        return generate_signatures(&obj, file_name);
+    }
+    let mut out = Vec::new();
+    let mut archive = ar::Archive::new(data);
+    while let Some(entry) = archive.next_entry() {
+        let mut entry = entry.with_context(|| format!("Processing entry in {}", path))?;
+        let member_name = String::from_utf8_lossy(entry.header().identifier()).into_owned();
+        let mut member_data = Vec::with_capacity(entry.header().size() as usize);
+        entry.read_to_end(&mut member_data)?;
+        if !member_data.starts_with(&elf::ELFMAG) {
+            log::debug!("Skipping non-ELF member {} in {}", member_name, path);
+            continue;
+        }
+        let source = format!("{}:{}", file_name, member_name);
+        let obj = process_elf_data(&member_data).with_context(|| format!("Loading {}", source))?;
+        out.extend(generate_signatures(&obj, &source)?);
+    }
+    Ok(out)
+}
+
+/// Number of leading instructions used to index signatures.
+const INDEX_PREFIX_LEN: usize = 4;
+
+type IndexKey = [u32; INDEX_PREFIX_LEN];
+
+fn index_key(words: impl Iterator<Item = u32>) -> IndexKey {
+    let mut key = [0u32; INDEX_PREFIX_LEN];
+    for (out, word) in key.iter_mut().zip(words) {
+        *out = word;
+    }
+    key
+}
+
+/// A set of function signatures indexed by their masked instruction prefix,
+/// for matching many functions against many signatures at once.
+pub struct SignatureDatabase {
+    pub signatures: Vec<FunctionSignature>,
+    patterns: Vec<Vec<(u32, u32)>>,
+    /// Signatures grouped by the relocation mask of their prefix,
+    /// then keyed by the masked prefix itself.
+    index: Vec<(IndexKey, HashMap<IndexKey, Vec<usize>>)>,
+}
+
+impl SignatureDatabase {
+    pub fn new(signatures: Vec<FunctionSignature>) -> Result<Self> {
+        let mut seen = HashSet::new();
+        let mut db = Self { signatures: Vec::new(), patterns: Vec::new(), index: Vec::new() };
+        for signature in signatures {
+            // The same object is often linked into several libraries
+            if !seen.insert((signature.hash.clone(), signature.name().to_string())) {
+                continue;
             }
+            let pattern = signature.decode()?;
+            let mask = index_key(pattern.iter().map(|&(_, pat)| pat));
+            let key = index_key(pattern.iter().map(|&(ins, _)| ins));
+            let sig_index = db.signatures.len();
+            let group = match db.index.iter_mut().position(|(m, _)| *m == mask) {
+                Some(idx) => &mut db.index[idx].1,
+                None => {
+                    db.index.push((mask, HashMap::new()));
+                    &mut db.index.last_mut().unwrap().1
+                }
+            };
+            group.entry(key).or_default().push(sig_index);
+            db.signatures.push(signature);
+            db.patterns.push(pattern);
         }
+        Ok(db)
+    }
 
-        let mut data = vec![0u8; instructions.len() * 8];
-        for (idx, &(ins, pat)) in instructions.iter().enumerate() {
-            data[idx * 8..idx * 8 + 4].copy_from_slice(&ins.to_be_bytes());
-            data[idx * 8 + 4..idx * 8 + 8].copy_from_slice(&pat.to_be_bytes());
+    /// Loads and merges one or more signature files.
+    pub fn load<P: AsRef<Utf8NativePath>>(paths: &[P]) -> Result<Self> {
+        let mut signatures = Vec::new();
+        for path in paths {
+            signatures.extend(read_signatures(path.as_ref())?);
         }
+        Self::new(signatures)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize { self.signatures.len() }
 
-        let encoded = STANDARD.encode(&data);
-        let mut hasher = Sha1::new();
-        hasher.update(&data);
-        let hash = hasher.finalize();
-        let mut hash_buf = [0u8; 40];
-        let hash_str = base16ct::lower::encode_str(&hash, &mut hash_buf)
-            .map_err(|e| anyhow!("Failed to encode hash: {e}"))?;
-        return Ok(Some(FunctionSignature {
-            symbol: 0,
-            hash: hash_str.to_string(),
-            signature: encoded,
-            symbols: out_symbols,
-            relocations: out_relocs,
-        }));
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.signatures.is_empty() }
+
+    /// Finds all signatures matching the function data exactly.
+    pub fn find(&self, data: &[u8]) -> Vec<usize> {
+        let words = data
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes(*array_ref!(c, 0, 4)))
+            .collect::<Vec<u32>>();
+        let prefix = index_key(words.iter().cloned());
+        let mut out = Vec::new();
+        for (mask, group) in &self.index {
+            let mut key = prefix;
+            for (word, pat) in key.iter_mut().zip(mask) {
+                *word &= pat;
+            }
+            let Some(candidates) = group.get(&key) else {
+                continue;
+            };
+            for &idx in candidates {
+                let pattern = &self.patterns[idx];
+                if pattern.len() == words.len()
+                    && pattern.iter().zip(&words).all(|(&(ins, pat), &word)| word & pat == ins)
+                {
+                    out.push(idx);
+                }
+            }
+        }
+        out.sort_unstable();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::tests::{obj, reloc, section, symbol};
+
+    /// A relocatable object with `OSInit` calling `OSReport`, and `__OSLeaf`.
+    fn library_obj(leaf_code: u32) -> ObjInfo {
+        let code = [0x9421FFF0u32, 0x48000001, 0x38210010, 0x4E800020, leaf_code, 0x4E800020];
+        let mut report = symbol("OSReport", ObjSymbolKind::Unknown, 0, 0, 0);
+        report.section = None;
+        let mut obj = obj(
+            ObjKind::Relocatable,
+            vec![
+                symbol("OSInit", ObjSymbolKind::Function, 0, 0, 0x10),
+                symbol("__OSLeaf", ObjSymbolKind::Function, 0, 0x10, 8),
+                report,
+            ],
+            vec![section(
+                ".text",
+                ObjSectionKind::Code,
+                0,
+                code.iter().flat_map(|i| i.to_be_bytes()).collect(),
+            )],
+        );
+        obj.sections[0].relocations.insert(4, reloc(ObjRelocKind::PpcRel24, 2, 0)).unwrap();
+        obj
+    }
+
+    #[test]
+    fn test_generate_signatures() {
+        let signatures = generate_signatures(&library_obj(0x38600000), "os.a:OSInit.o").unwrap();
+        assert_eq!(signatures.len(), 2);
+        let signature = &signatures[0];
+        assert_eq!(signature.name(), "OSInit");
+        assert_eq!(signature.size(), 0x10);
+        assert_eq!(signature.library(), Some("os.a"));
+        assert_eq!(signature.decode().unwrap(), vec![
+            (0x9421FFF0, !0),
+            (0x48000001, !0x3FFFFFC),
+            (0x38210010, !0),
+            (0x4E800020, !0),
+        ]);
+        assert_eq!(signature.relocations.len(), 1);
+        assert_eq!(signature.relocations[0].offset, 4);
+        assert_eq!(signature.symbols[signature.relocations[0].symbol as usize].name, "OSReport");
+
+        // The signature matches the same code with any branch displacement
+        let mut data = signature
+            .decode()
+            .unwrap()
+            .iter()
+            .flat_map(|(i, _)| i.to_be_bytes())
+            .collect::<Vec<_>>();
+        data[4..8].copy_from_slice(&0x4BFFF001u32.to_be_bytes());
+        assert!(check_signature(&data, signature).unwrap());
+        data[8] = 0;
+        assert!(!check_signature(&data, signature).unwrap());
+
+        // Signatures round trip through YAML
+        let yaml = serde_yaml::to_string(&signatures).unwrap();
+        assert_eq!(parse_signatures(&yaml).unwrap(), signatures);
+    }
+
+    #[test]
+    fn test_merge_signatures() {
+        let first = generate_signatures(&library_obj(0x38600000), "os.a:OSInit.o").unwrap();
+        let mut second = generate_signatures(&library_obj(0x38600001), "os_d.a:OSInit.o").unwrap();
+        // Identical revisions keep the first source, unless it has none
+        let mut unsourced = first.clone();
+        unsourced[0].source = None;
+        second[0].source = None;
+
+        let merged = merge_signatures(unsourced.into_iter().chain(second).chain(first));
+        assert_eq!(merged.keys().collect::<Vec<_>>(), vec!["OSInit", "__OSLeaf"]);
+        let init = &merged["OSInit"];
+        assert_eq!(init.len(), 1);
+        assert_eq!(init[0].source.as_deref(), Some("os.a:OSInit.o"));
+        // __OSLeaf differs between the libraries, so both revisions are kept in order
+        let leaf = &merged["__OSLeaf"];
+        assert_eq!(leaf.len(), 2);
+        assert_eq!(leaf[0].source.as_deref(), Some("os.a:OSInit.o"));
+        assert_eq!(leaf[1].source.as_deref(), Some("os_d.a:OSInit.o"));
+        assert_ne!(leaf[0].hash, leaf[1].hash);
     }
-    Ok(None)
 }
@@ -873,19 +873,102 @@ pub fn update_splits(obj: &mut ObjInfo, common_start: Option<u32>, fill_gaps: bo
     Ok(())
 }
 
+/// Why one unit must be linked before another.
+#[derive(Debug, Clone)]
+enum LinkOrderReason {
+    /// A split of the first unit is directly followed by a split of the second.
+    Split { section: String, from_addr: u32, to_addr: u32, to_end: u32 },
+    /// The units' `order` attributes.
+    Order { from_order: i32, to_order: i32 },
+}
+
+fn describe_link_order_edge(from: &str, to: &str, reason: &LinkOrderReason) -> String {
+    match reason {
+        LinkOrderReason::Split { section, from_addr, to_addr, .. } => format!(
+            "{} precedes {} in {} ({} @ {:#010X}, {} @ {:#010X})",
+            from, to, section, from, from_addr, to, to_addr
+        ),
+        LinkOrderReason::Order { from_order, to_order } => {
+            format!("{} precedes {} by order ({} < {})", from, to, from_order, to_order)
+        }
+    }
+}
+
+fn suggest_link_order_fix(from: &str, to: &str, reason: &LinkOrderReason) -> String {
+    match reason {
+        LinkOrderReason::Split { section, from_addr, to_end, .. } => format!(
+            "assign {}:{:#010X}-{:#010X} to a single unit ({} or {})",
+            section, from_addr, to_end, from, to
+        ),
+        LinkOrderReason::Order { from_order, to_order } => format!(
+            "swap the orders of {} and {} (order:{} and order:{})",
+            from, to, to_order, from_order
+        ),
+    }
+}
+
+/// Explains a cycle in the link order graph, listing the constraints that form it and
+/// the smallest edits that would break it.
+fn explain_link_order_cycle(
+    cycle: &[usize],
+    index_to_unit: &[&str],
+    reasons: &BTreeMap<(usize, usize), Vec<LinkOrderReason>>,
+) -> String {
+    let edges = cycle
+        .iter()
+        .enumerate()
+        .map(|(i, &from)| (from, cycle[(i + 1) % cycle.len()]))
+        .map(|edge| (edge, reasons.get(&edge).map(Vec::as_slice).unwrap_or_default()))
+        .collect_vec();
+    let mut out = format!(
+        "Cyclic dependency encountered while resolving link order: {} -> {}",
+        cycle.iter().map(|&idx| index_to_unit[idx]).join(" -> "),
+        index_to_unit[cycle[0]]
+    );
+    let split_section = |edge_reasons: &[LinkOrderReason]| {
+        edge_reasons.iter().find_map(|reason| match reason {
+            LinkOrderReason::Split { section, .. } => Some(section.clone()),
+            LinkOrderReason::Order { .. } => None,
+        })
+    };
+    if let [((a, b), ab), (_, ba)] = edges.as_slice() {
+        if let (Some(ab), Some(ba)) = (split_section(ab), split_section(ba)) {
+            out.push_str(&format!(
+                "\n{} precedes {} in {} but {} precedes {} in {}",
+                index_to_unit[*a], index_to_unit[*b], ab, index_to_unit[*b], index_to_unit[*a], ba
+            ));
+        }
+    }
+    for &((from, to), edge_reasons) in &edges {
+        for reason in edge_reasons {
+            out.push_str(&format!(
+                "\n\t{}",
+                describe_link_order_edge(index_to_unit[from], index_to_unit[to], reason)
+            ));
+        }
+    }
+    // Removing any one edge breaks the cycle, so start with the least constrained ones
+    out.push_str("\nTo break the cycle, do one of the following in splits.txt:");
+    for &((from, to), edge_reasons) in
+        edges.iter().sorted_by_key(|(_, edge_reasons)| edge_reasons.len())
+    {
+        let fixes = edge_reasons
+            .iter()
+            .map(|reason| suggest_link_order_fix(index_to_unit[from], index_to_unit[to], reason))
+            .join(", and ");
+        out.push_str(&format!("\n\t{}", fixes));
+    }
+    out
+}
+
 /// The ordering of TUs inside of each section represents a directed edge in a DAG.
 /// We can use a topological sort to determine a valid global TU order.
 /// There can be ambiguities, but any solution that satisfies the link order
-/// constraints is considered valid.
+/// constraints is considered valid. Since the splits of each section form a chain,
+/// units that share a section are always ordered, and the remaining choices only
+/// affect units that never share a section, which doesn't change the output.
 #[instrument(level = "debug", skip(obj))]
 fn resolve_link_order(obj: &ObjInfo) -> Result<Vec<ObjUnit>> {
-    #[allow(dead_code)]
-    #[derive(Debug, Copy, Clone)]
-    struct SplitEdge {
-        from: i64,
-        to: i64,
-    }
-
     let mut unit_to_index_map = BTreeMap::<&str, usize>::new();
     let mut index_to_unit = vec![];
     for (_, _, _, split) in obj.sections.all_splits() {
@@ -896,6 +979,7 @@ fn resolve_link_order(obj: &ObjInfo) -> Result<Vec<ObjUnit>> {
         });
     }
     let mut graph = vec![vec![]; index_to_unit.len()];
+    let mut reasons = BTreeMap::<(usize, usize), Vec<LinkOrderReason>>::new();
 
     for (_section_index, section) in obj.sections.iter() {
         let mut iter = section.splits.iter().peekable();
@@ -910,6 +994,8 @@ fn resolve_link_order(obj: &ObjInfo) -> Result<Vec<ObjUnit>> {
                 continue;
             }
 
+            let a_index = *unit_to_index_map.get(a.unit.as_str()).unwrap();
+            let b_index = *unit_to_index_map.get(b.unit.as_str()).unwrap();
             if a.unit != b.unit {
                 log::debug!(
                     "Adding dependency {} ({:#010X}) -> {} ({:#010X})",
@@ -918,9 +1004,13 @@ fn resolve_link_order(obj: &ObjInfo) -> Result<Vec<ObjUnit>> {
                     b.unit,
                     b_addr
                 );
-                let a_index = *unit_to_index_map.get(a.unit.as_str()).unwrap();
-                let b_index = *unit_to_index_map.get(b.unit.as_str()).unwrap();
                 graph[a_index].push(b_index);
+                reasons.entry((a_index, b_index)).or_default().push(LinkOrderReason::Split {
+                    section: section.name.clone(),
+                    from_addr: a_addr,
+                    to_addr: b_addr,
+                    to_end: b.end,
+                });
             }
         }
     }
@@ -939,34 +1029,39 @@ fn resolve_link_order(obj: &ObjInfo) -> Result<Vec<ObjUnit>> {
             }
         }
     }
-    let mut iter =
-        ordered_units.values().filter_map(|unit| unit_to_index_map.get(unit.as_str())).peekable();
-    while let (Some(&a_index), Some(&&b_index)) = (iter.next(), iter.peek()) {
+    let mut iter = ordered_units
+        .iter()
+        .filter_map(|(&order, unit)| Some((order, *unit_to_index_map.get(unit.as_str())?)))
+        .peekable();
+    while let (Some((a_order, a_index)), Some(&(b_order, b_index))) = (iter.next(), iter.peek()) {
         graph[a_index].push(b_index);
+        reasons
+            .entry((a_index, b_index))
+            .or_default()
+            .push(LinkOrderReason::Order { from_order: a_order, to_order: b_order });
     }
 
-    match toposort(&graph) {
-        Ok(vec) => Ok(vec
-            .iter()
-            .map(|&idx| {
-                let name = index_to_unit[idx];
-                if let Some(existing) = obj.link_order.iter().find(|u| u.name == name) {
-                    existing.clone()
-                } else {
-                    ObjUnit {
-                        name: name.to_string(),
-                        autogenerated: obj.is_unit_autogenerated(name),
-                        comment_version: None,
-                        order: None,
-                    }
+    let order = match toposort(&graph) {
+        Ok(order) => order,
+        Err(cycle) => bail!(explain_link_order_cycle(&cycle, &index_to_unit, &reasons)),
+    };
+
+    Ok(order
+        .iter()
+        .map(|&idx| {
+            let name = index_to_unit[idx];
+            if let Some(existing) = obj.link_order.iter().find(|u| u.name == name) {
+                existing.clone()
+            } else {
+                ObjUnit {
+                    name: name.to_string(),
+                    autogenerated: obj.is_unit_autogenerated(name),
+                    comment_version: None,
+                    order: None,
                 }
-            })
-            .collect_vec()),
-        Err(e) => Err(anyhow!(
-            "Cyclic dependency encountered while resolving link order: {}",
-            e.iter().map(|&idx| index_to_unit[idx]).join(" -> ")
-        )),
-    }
+            }
+        })
+        .collect_vec())
 }
 
 /// Split an object into multiple relocatable objects.
@@ -1487,3 +1582,78 @@ fn unit_exists(
             .any(|(_, split)| split.unit.eq_ignore_ascii_case(unit_name))
         || new_splits.values().any(|split| split.unit.eq_ignore_ascii_case(unit_name))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::tests::{self as fixtures, split};
+
+    fn section(name: &str, kind: ObjSectionKind, splits: &[(&str, u32, u32)]) -> ObjSection {
+        let size = splits.last().map_or(0, |&(_, _, end)| end);
+        let mut section = fixtures::section(name, kind, 0, vec![0; size as usize]);
+        for &(unit, start, end) in splits {
+            section.splits.push(start, split(unit, end));
+        }
+        section
+    }
+
+    fn obj(sections: Vec<ObjSection>) -> ObjInfo {
+        fixtures::obj(ObjKind::Relocatable, vec![], sections)
+    }
+
+    #[test]
+    fn test_resolve_link_order() {
+        let obj = obj(vec![
+            section(".text", ObjSectionKind::Code, &[
+                ("a.c", 0, 0x10),
+                ("b.c", 0x10, 0x20),
+                ("c.c", 0x20, 0x30),
+            ]),
+            section(".data", ObjSectionKind::Data, &[("a.c", 0, 0x8), ("c.c", 0x8, 0x10)]),
+            section(".bss", ObjSectionKind::Bss, &[("d.c", 0, 0x8)]),
+        ]);
+        let order = resolve_link_order(&obj).unwrap();
+        let names = order.iter().map(|u| u.name.as_str()).collect_vec();
+        assert_eq!(names.len(), 4);
+        // Units sharing a section are always ordered by their splits, only d.c is free
+        let position = |name: &str| names.iter().position(|&n| n == name).unwrap();
+        assert!(position("a.c") < position("b.c"));
+        assert!(position("b.c") < position("c.c"));
+    }
+
+    #[test]
+    fn test_resolve_link_order_cycle() {
+        let obj = obj(vec![
+            section(".text", ObjSectionKind::Code, &[("a.c", 0, 0x10), ("b.c", 0x10, 0x20)]),
+            section(".data", ObjSectionKind::Data, &[("b.c", 0, 0x8), ("a.c", 0x8, 0x10)]),
+        ]);
+        let message = resolve_link_order(&obj).unwrap_err().to_string();
+        assert!(
+            message.contains("a.c precedes b.c in .text but b.c precedes a.c in .data")
+                || message.contains("b.c precedes a.c in .data but a.c precedes b.c in .text"),
+            "{}",
+            message
+        );
+        assert!(message.contains("assign .text:0x00000000-0x00000020 to a single unit"));
+        assert!(message.contains("assign .data:0x00000000-0x00000010 to a single unit"));
+    }
+
+    #[test]
+    fn test_resolve_link_order_order_cycle() {
+        let mut obj = obj(vec![section(".text", ObjSectionKind::Code, &[
+            ("a.c", 0, 0x10),
+            ("b.c", 0x10, 0x20),
+        ])]);
+        for (name, order) in [("a.c", 1), ("b.c", 0)] {
+            obj.link_order.push(ObjUnit {
+                name: name.to_string(),
+                autogenerated: false,
+                comment_version: None,
+                order: Some(order),
+            });
+        }
+        let message = resolve_link_order(&obj).unwrap_err().to_string();
+        assert!(message.contains("b.c precedes a.c by order (0 < 1)"), "{}", message);
+        assert!(message.contains("swap the orders of b.c and a.c (order:1 and order:0)"));
+    }
+}
@@ -153,7 +153,7 @@ impl TracebackTableShort {
     pub fn get_number_of_fixed_parms(&self) -> u8 { self.number_of_fixed_parms }
 
     // flags5
-    pub fn get_number_of_fp_parms(&self) -> u8 { self.flags5 & Self::FP_PARMS_MASK >> 1 }
+    pub fn get_number_of_fp_parms(&self) -> u8 { (self.flags5 & Self::FP_PARMS_MASK) >> 1 }
     pub fn params_on_stack(&self) -> bool { self.flags5 & Self::PARMS_ON_STACK != 0 }
 
 }
@@ -300,4 +300,104 @@ impl TracebackTable {
     pub fn size(&self) -> usize {
         (std::mem::size_of::<TracebackTableShort>() + self.ext_size + 3) & !3 // 4 byte aligned 
     }
+
+    /// Decodes `parm_info` into one character per parameter:
+    /// `i` for fixed point, `f` for single and `d` for double precision.
+    /// Parameters past the 32 encoded bits are not included.
+    pub fn param_types(&self) -> Option<String> {
+        let parm_info = self.parm_info?;
+        let count = self.short.get_number_of_fixed_parms() as u32
+            + self.short.get_number_of_fp_parms() as u32;
+        let mut out = String::new();
+        let mut bit = 0;
+        for _ in 0..count {
+            if bit >= 32 {
+                break;
+            }
+            if parm_info & (0x8000_0000 >> bit) == 0 {
+                out.push('i');
+                bit += 1;
+            } else if bit + 1 < 32 {
+                out.push(if parm_info & (0x8000_0000 >> (bit + 1)) == 0 { 'f' } else { 'd' });
+                bit += 2;
+            } else {
+                break;
+            }
+        }
+        Some(out)
+    }
+
+    /// Calling-convention attributes, as written to symbols.txt.
+    pub fn attributes(&self) -> Vec<String> {
+        let mut attrs = Vec::new();
+        if let Some(params) = self.param_types().filter(|p| !p.is_empty()) {
+            attrs.push(format!("params:{}", params));
+        }
+        if self.short.gpr_regs_saved() > 0 {
+            attrs.push(format!("gprs:{}", self.short.gpr_regs_saved()));
+        }
+        if self.short.fp_regs_saved() > 0 {
+            attrs.push(format!("fprs:{}", self.short.fp_regs_saved()));
+        }
+        if self.short.lr_saved() {
+            attrs.push("lr_saved".to_string());
+        }
+        if self.short.cr_saved() {
+            attrs.push("cr_saved".to_string());
+        }
+        if let Some(reg) = self.alloca_reg {
+            attrs.push(format!("alloca:r{}", reg));
+        }
+        attrs
+    }
+
+    /// Human-readable summary, as written to the asm output.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(name) = &self.name {
+            lines.push(format!("Name: {}", name));
+        }
+        match self.short.language() {
+            Ok(language) => lines.push(format!("Language: {:?}", language)),
+            Err(e) => lines.push(e.to_string()),
+        }
+        if let Some(params) = self.param_types() {
+            let params = params
+                .chars()
+                .map(|c| match c {
+                    'i' => "int",
+                    'f' => "float",
+                    _ => "double",
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let on_stack = if self.short.params_on_stack() { " (on stack)" } else { "" };
+            lines.push(format!("Parameters: {}{}", params, on_stack));
+        }
+        lines.push(format!(
+            "Saved GPRs: {}, saved FPRs: {}",
+            self.short.gpr_regs_saved(),
+            self.short.fp_regs_saved()
+        ));
+        let mut saves = Vec::new();
+        if self.short.lr_saved() {
+            saves.push("LR");
+        }
+        if self.short.cr_saved() {
+            saves.push("CR");
+        }
+        if self.short.stores_bc() {
+            saves.push("back chain");
+        }
+        if !saves.is_empty() {
+            lines.push(format!("Saves: {}", saves.join(", ")));
+        }
+        if let Some(reg) = self.alloca_reg {
+            lines.push(format!("alloca register: r{}", reg));
+        }
+        if let Some(fnc_size) = self.fnc_size {
+            lines.push(format!("Function size: {:#X}", fnc_size));
+        }
+        lines
+    }
 }
@@ -0,0 +1,416 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use typed_path::Utf8NativePath;
+
+use crate::{
+    analysis::{
+        cfa::AnalyzerState,
+        pass::{
+            AnalysisPass, FindCfmGlueStubs, FindRelCtorsDtors, FindRelRodataData,
+            FindSaveRestSleds, FindTRKInterruptVectorTable,
+        },
+        signatures::{apply_signatures, apply_signatures_post},
+        tracker::Tracker,
+    },
+    obj::{
+        ObjInfo, ObjKind, ObjRelocKind, ObjSectionKind, ObjSymbolKind, SectionIndex, SymbolIndex,
+    },
+    util::{
+        config::apply_symbols_file,
+        file::buf_writer,
+        graph::{dot_escape, GraphFormat},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XrefKind {
+    /// Branch to a function. (`bl`, or `b` for tail calls)
+    Call,
+    /// Any other reference, e.g. taking a function's address or accessing data.
+    Reference,
+}
+
+/// A function or data symbol in the cross-reference graph.
+#[derive(Debug, Clone, Serialize)]
+pub struct XrefNode {
+    pub name: String,
+    /// Module ID, for targets of unresolved relocations into other modules.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub demangled_name: Option<String>,
+    /// Section name, or `None` for absolute symbols.
+    pub section: Option<String>,
+    pub address: u32,
+    pub size: u32,
+    pub function: bool,
+    /// Number of distinct functions referencing this symbol.
+    pub fan_in: usize,
+    /// Number of distinct symbols referenced from this symbol.
+    pub fan_out: usize,
+}
+
+/// A relocation from one symbol to another.
+#[derive(Debug, Clone, Serialize)]
+pub struct XrefEdge {
+    pub from: String,
+    pub from_address: u32,
+    pub to: String,
+    pub to_address: u32,
+    /// Module ID, for unresolved relocations into other modules.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_module: Option<u32>,
+    pub kind: XrefKind,
+    pub reloc_kind: ObjRelocKind,
+    /// Address of the relocated instruction or data.
+    pub source: u32,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Xrefs {
+    pub nodes: Vec<XrefNode>,
+    pub edges: Vec<XrefEdge>,
+}
+
+/// Finds the sized function or object containing an address.
+fn containing_symbol(
+    obj: &ObjInfo,
+    section_index: SectionIndex,
+    address: u32,
+) -> Option<SymbolIndex> {
+    obj.symbols
+        .for_section_range(section_index, ..=address)
+        .rev()
+        .find(|(_, s)| {
+            matches!(s.kind, ObjSymbolKind::Function | ObjSymbolKind::Object)
+                && s.size_known
+                && address < (s.address + s.size) as u32
+        })
+        .map(|(idx, _)| idx)
+}
+
+/// Target of a relocation: a symbol, or an address in another module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum XrefTarget {
+    Symbol(SymbolIndex),
+    /// Module ID, section index and address. Module 0 (the DOL) uses absolute addresses.
+    External(u32, u8, u32),
+}
+
+impl XrefTarget {
+    fn name(self, obj: &ObjInfo) -> String {
+        match self {
+            XrefTarget::Symbol(idx) => obj.symbols[idx].name.clone(),
+            XrefTarget::External(0, _, address) => format!("lbl_{:08X}", address),
+            XrefTarget::External(module_id, section, address) => {
+                format!("lbl_{}_{}_{:X}", module_id, section, address)
+            }
+        }
+    }
+
+    fn address(self, obj: &ObjInfo) -> u32 {
+        match self {
+            XrefTarget::Symbol(idx) => obj.symbols[idx].address as u32,
+            XrefTarget::External(_, _, address) => address,
+        }
+    }
+
+    fn module(self) -> Option<u32> {
+        match self {
+            XrefTarget::Symbol(_) => None,
+            XrefTarget::External(module_id, _, _) => Some(module_id),
+        }
+    }
+}
+
+/// Collects every relocation between functions and data symbols, including unresolved
+/// relocations into other modules. Relocations in code or data outside of a sized symbol
+/// are skipped.
+pub fn build_xrefs(obj: &ObjInfo) -> Xrefs {
+    struct Edge {
+        from: SymbolIndex,
+        to: XrefTarget,
+        kind: XrefKind,
+        reloc_kind: ObjRelocKind,
+        source: u32,
+        addend: i64,
+    }
+
+    let is_branch = |kind| matches!(kind, ObjRelocKind::PpcRel24 | ObjRelocKind::PpcRel14);
+    let mut edges = Vec::new();
+    for (section_index, section) in obj.sections.iter() {
+        for (address, reloc) in section.relocations.iter() {
+            let Some(from) = containing_symbol(obj, section_index, address) else {
+                continue;
+            };
+            let target = &obj.symbols[reloc.target_symbol];
+            let is_call = section.kind == ObjSectionKind::Code
+                && target.kind == ObjSymbolKind::Function
+                && is_branch(reloc.kind);
+            edges.push(Edge {
+                from,
+                to: XrefTarget::Symbol(reloc.target_symbol),
+                kind: if is_call { XrefKind::Call } else { XrefKind::Reference },
+                reloc_kind: reloc.kind,
+                source: address,
+                addend: reloc.addend,
+            });
+        }
+    }
+    // Relocations within the module are rebuilt by analysis, only keep the other modules'
+    for rel_reloc in obj.unresolved_relocations.iter().filter(|r| r.module_id != obj.module_id) {
+        let Some((section_index, section)) =
+            obj.sections.get_elf_index(rel_reloc.section as SectionIndex)
+        else {
+            continue;
+        };
+        let Some(from) = containing_symbol(obj, section_index, rel_reloc.address) else {
+            continue;
+        };
+        let is_call = section.kind == ObjSectionKind::Code && is_branch(rel_reloc.kind);
+        edges.push(Edge {
+            from,
+            to: XrefTarget::External(
+                rel_reloc.module_id,
+                rel_reloc.target_section,
+                rel_reloc.addend,
+            ),
+            kind: if is_call { XrefKind::Call } else { XrefKind::Reference },
+            reloc_kind: rel_reloc.kind,
+            source: rel_reloc.address,
+            addend: 0,
+        });
+    }
+
+    // Every function is a node, along with any referenced symbol
+    let mut node_indices = obj
+        .symbols
+        .iter()
+        .filter(|(_, s)| s.kind == ObjSymbolKind::Function && s.section.is_some())
+        .map(|(idx, _)| XrefTarget::Symbol(idx))
+        .collect::<BTreeSet<XrefTarget>>();
+    let mut callers = BTreeMap::<XrefTarget, BTreeSet<SymbolIndex>>::new();
+    let mut callees = BTreeMap::<SymbolIndex, BTreeSet<XrefTarget>>::new();
+    for edge in &edges {
+        node_indices.insert(XrefTarget::Symbol(edge.from));
+        node_indices.insert(edge.to);
+        if edge.to == XrefTarget::Symbol(edge.from) {
+            continue;
+        }
+        if obj.symbols[edge.from].kind == ObjSymbolKind::Function {
+            callers.entry(edge.to).or_default().insert(edge.from);
+        }
+        callees.entry(edge.from).or_default().insert(edge.to);
+    }
+
+    let mut nodes = node_indices
+        .into_iter()
+        .map(|target| {
+            let fan_in = callers.get(&target).map_or(0, |s| s.len());
+            match target {
+                XrefTarget::Symbol(idx) => {
+                    let symbol = &obj.symbols[idx];
+                    XrefNode {
+                        name: symbol.name.clone(),
+                        module: None,
+                        demangled_name: symbol.demangled_name.clone(),
+                        section: symbol.section.map(|s| obj.sections[s].name.clone()),
+                        address: symbol.address as u32,
+                        size: symbol.size as u32,
+                        function: symbol.kind == ObjSymbolKind::Function,
+                        fan_in,
+                        fan_out: callees.get(&idx).map_or(0, |s| s.len()),
+                    }
+                }
+                XrefTarget::External(module_id, _, address) => XrefNode {
+                    name: target.name(obj),
+                    module: Some(module_id),
+                    demangled_name: None,
+                    section: None,
+                    address,
+                    size: 0,
+                    function: edges
+                        .iter()
+                        .any(|edge| edge.to == target && edge.kind == XrefKind::Call),
+                    fan_in,
+                    fan_out: 0,
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|n| (n.module.is_some(), n.section.is_none(), n.address));
+
+    let edges = edges
+        .into_iter()
+        .map(|edge| {
+            let from = &obj.symbols[edge.from];
+            XrefEdge {
+                from: from.name.clone(),
+                from_address: from.address as u32,
+                to: edge.to.name(obj),
+                to_address: edge.to.address(obj),
+                to_module: edge.to.module(),
+                kind: edge.kind,
+                reloc_kind: edge.reloc_kind,
+                source: edge.source,
+                addend: edge.addend,
+            }
+        })
+        .collect();
+    Xrefs { nodes, edges }
+}
+
+fn dot_id(name: &str, address: u32) -> String {
+    format!("\"{}@{:08X}\"", dot_escape(name), address)
+}
+
+/// Writes the graph in Graphviz DOT format. References are drawn dashed.
+pub fn write_xrefs_dot<W>(w: &mut W, name: &str, xrefs: &Xrefs) -> Result<()>
+where W: Write + ?Sized {
+    writeln!(w, "digraph \"{}\" {{", dot_escape(name))?;
+    writeln!(w, "    node [shape=box, fontname=monospace];")?;
+    for node in &xrefs.nodes {
+        let shape = if node.function { "" } else { ", shape=ellipse" };
+        writeln!(
+            w,
+            "    {} [label=\"{}\", tooltip=\"{:#010X} (fan-in {})\"{}];",
+            dot_id(&node.name, node.address),
+            dot_escape(node.demangled_name.as_deref().unwrap_or(&node.name)),
+            node.address,
+            node.fan_in,
+            shape
+        )?;
+    }
+    for edge in &xrefs.edges {
+        let style = match edge.kind {
+            XrefKind::Call => "",
+            XrefKind::Reference => ", style=dashed",
+        };
+        writeln!(
+            w,
+            "    {} -> {} [label=\"{:?}\", tooltip=\"{:#010X}\"{}];",
+            dot_id(&edge.from, edge.from_address),
+            dot_id(&edge.to, edge.to_address),
+            edge.reloc_kind,
+            edge.source,
+            style
+        )?;
+    }
+    writeln!(w, "}}")?;
+    Ok(())
+}
+
+/// Builds and writes the cross-reference graph of an analyzed object.
+pub fn write_xrefs(obj: &ObjInfo, out_path: &Utf8NativePath, format: GraphFormat) -> Result<()> {
+    let xrefs = build_xrefs(obj);
+    log::info!(
+        "Writing {} nodes and {} edges to {} ({})",
+        xrefs.nodes.len(),
+        xrefs.edges.len(),
+        out_path,
+        format
+    );
+    let mut out = buf_writer(out_path)?;
+    match format {
+        GraphFormat::Json => serde_json::to_writer_pretty(&mut out, &xrefs)?,
+        GraphFormat::Dot => write_xrefs_dot(&mut out, &obj.name, &xrefs)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Analyzes a freshly loaded DOL, REL or PEF like `split` does, then writes its
+/// cross-reference graph. The format defaults to the output file's extension.
+pub fn analyze_and_write_xrefs(
+    mut obj: ObjInfo,
+    symbols: Option<&Utf8NativePath>,
+    out_path: &Utf8NativePath,
+    format: Option<GraphFormat>,
+) -> Result<()> {
+    if let Some(symbols_path) = symbols {
+        apply_symbols_file(symbols_path, &mut obj)?;
+    }
+
+    let mut state = AnalyzerState::default();
+    FindSaveRestSleds::execute(&mut state, &obj)?;
+    if obj.kind == ObjKind::Relocatable {
+        state.detect_functions(&obj)?;
+        FindRelCtorsDtors::execute(&mut state, &obj)?;
+        FindRelRodataData::execute(&mut state, &obj)?;
+        state.apply(&mut obj)?;
+        apply_signatures(&mut obj)?;
+    } else {
+        apply_signatures(&mut obj)?;
+        FindCfmGlueStubs::execute(&mut state, &obj)?;
+        state.detect_functions(&obj)?;
+        FindTRKInterruptVectorTable::execute(&mut state, &obj)?;
+        state.apply(&mut obj)?;
+    }
+    apply_signatures_post(&mut obj)?;
+
+    let mut tracker = Tracker::new(&obj);
+    tracker.process(&obj)?;
+    tracker.apply(&mut obj, false)?;
+
+    let format = format.unwrap_or_else(|| GraphFormat::from_path(out_path));
+    write_xrefs(&obj, out_path, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        obj::tests::{obj, section, symbol},
+        util::rel::RelReloc,
+    };
+
+    fn rel_reloc(kind: ObjRelocKind, address: u32, module_id: u32, addend: u32) -> RelReloc {
+        RelReloc {
+            kind,
+            section: 1,
+            address,
+            module_id,
+            target_section: 1,
+            addend,
+            original_section: 1,
+            original_target_section: 1,
+        }
+    }
+
+    #[test]
+    fn test_unresolved_relocations() {
+        let mut text = section(".text", ObjSectionKind::Code, 0, vec![0; 0xC]);
+        text.elf_index = 1;
+        let caller = symbol("caller", ObjSymbolKind::Function, 0, 0, 0xC);
+        let mut obj = obj(ObjKind::Relocatable, vec![caller], vec![text]);
+        obj.module_id = 2;
+        obj.unresolved_relocations = vec![
+            rel_reloc(ObjRelocKind::PpcRel24, 0, 0, 0x80003100),
+            rel_reloc(ObjRelocKind::PpcAddr16Lo, 4, 3, 0x20),
+            // Within the module, left to analysis
+            rel_reloc(ObjRelocKind::PpcRel24, 8, 2, 0x40),
+        ];
+
+        let xrefs = build_xrefs(&obj);
+        let edges = xrefs
+            .edges
+            .iter()
+            .map(|e| (e.from.as_str(), e.to.as_str(), e.to_module, e.to_address, e.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(edges, vec![
+            ("caller", "lbl_80003100", Some(0), 0x80003100, XrefKind::Call),
+            ("caller", "lbl_3_1_20", Some(3), 0x20, XrefKind::Reference),
+        ]);
+        let caller = xrefs.nodes.iter().find(|n| n.name == "caller").unwrap();
+        assert_eq!(caller.fan_out, 2);
+        let external = xrefs.nodes.iter().find(|n| n.name == "lbl_80003100").unwrap();
+        assert_eq!((external.module, external.function, external.fan_in), (Some(0), true, 1));
+    }
+}
@@ -0,0 +1,116 @@
+use std::{io, sync::Arc};
+
+use filetime::FileTime;
+use typed_path::Utf8UnixPath;
+
+use super::{
+    StaticFile, Vfs, VfsError, VfsFile, VfsFileType, VfsMetadata, VfsResult, WindowedFile,
+};
+use crate::util::hfs::{process_hfs, HfsEntry, HfsEntryKind, HfsFork, HfsVolume};
+
+/// Seconds between the Mac OS epoch (1904-01-01) and the Unix epoch.
+const MAC_EPOCH_OFFSET: i64 = 2082844800;
+
+/// Read-only view of an HFS or HFS+ volume. Files open their data fork;
+/// resource forks are available as `File/..namedfork/rsrc`.
+#[derive(Clone)]
+pub struct HfsFs {
+    file: Box<dyn VfsFile>,
+    volume: Arc<HfsVolume>,
+}
+
+enum HfsFindResult<'a> {
+    Folder(&'a HfsEntry),
+    Fork(&'a HfsEntry, &'a HfsFork),
+}
+
+impl HfsFs {
+    pub fn new(mut file: Box<dyn VfsFile>) -> io::Result<Self> {
+        let volume = process_hfs(file.map()?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self { file, volume: Arc::new(volume) })
+    }
+
+    fn find(&self, path: &Utf8UnixPath) -> Option<HfsFindResult> {
+        let path = path.as_str();
+        let (path, fork_name) = match path.split_once("/..namedfork/") {
+            Some((path, fork_name)) => (path, Some(fork_name)),
+            None => (path, None),
+        };
+        let entry = self.volume.find(path)?;
+        match (&entry.kind, fork_name) {
+            (HfsEntryKind::Folder, None) => Some(HfsFindResult::Folder(entry)),
+            (HfsEntryKind::File { data_fork, .. }, None | Some("data")) => {
+                Some(HfsFindResult::Fork(entry, data_fork))
+            }
+            (HfsEntryKind::File { rsrc_fork, .. }, Some("rsrc")) => {
+                Some(HfsFindResult::Fork(entry, rsrc_fork))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn entry_mtime(entry: &HfsEntry) -> Option<FileTime> {
+    if entry.modify_date == 0 {
+        return None;
+    }
+    Some(FileTime::from_unix_time(entry.modify_date as i64 - MAC_EPOCH_OFFSET, 0))
+}
+
+impl Vfs for HfsFs {
+    fn open(&mut self, path: &Utf8UnixPath) -> VfsResult<Box<dyn VfsFile>> {
+        let (entry, fork) = match self.find(path) {
+            Some(HfsFindResult::Fork(entry, fork)) => (entry, fork),
+            Some(HfsFindResult::Folder(_)) => return Err(VfsError::IsADirectory),
+            None => return Err(VfsError::NotFound),
+        };
+        let mtime = entry_mtime(entry);
+        let ranges = self.volume.fork_ranges(fork);
+        if let [(offset, len)] = ranges.as_slice() {
+            return Ok(Box::new(WindowedFile::new(self.file.clone(), *offset, *len)?));
+        }
+        // Fragmented (or empty) forks are read into memory
+        let mut file = self.file.clone();
+        let data = file.map()?;
+        if fork.len > data.len() as u64 {
+            return Err(VfsError::from("HFS: fork larger than image"));
+        }
+        let mut out = Vec::with_capacity(fork.len as usize);
+        for (offset, len) in ranges {
+            let range = data
+                .get(offset as usize..(offset + len) as usize)
+                .ok_or_else(|| VfsError::from("HFS: extent out of bounds"))?;
+            out.extend_from_slice(range);
+        }
+        Ok(Box::new(StaticFile::new(Arc::from(out), mtime)))
+    }
+
+    fn exists(&mut self, path: &Utf8UnixPath) -> VfsResult<bool> { Ok(self.find(path).is_some()) }
+
+    fn read_dir(&mut self, path: &Utf8UnixPath) -> VfsResult<Vec<String>> {
+        match self.find(path) {
+            Some(HfsFindResult::Folder(entry)) => {
+                Ok(self.volume.children(entry.id).map(|e| e.name.clone()).collect())
+            }
+            Some(HfsFindResult::Fork(_, _)) => Err(VfsError::NotADirectory),
+            None => Err(VfsError::NotFound),
+        }
+    }
+
+    fn metadata(&mut self, path: &Utf8UnixPath) -> VfsResult<VfsMetadata> {
+        match self.find(path) {
+            Some(HfsFindResult::Folder(entry)) => Ok(VfsMetadata {
+                file_type: VfsFileType::Directory,
+                len: 0,
+                mtime: entry_mtime(entry),
+            }),
+            Some(HfsFindResult::Fork(entry, fork)) => Ok(VfsMetadata {
+                file_type: VfsFileType::File,
+                len: fork.len,
+                mtime: entry_mtime(entry),
+            }),
+            None => Err(VfsError::NotFound),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::io;
+
+use filetime::FileTime;
+use typed_path::Utf8UnixPath;
+
+use super::{Vfs, VfsError, VfsFile, VfsFileType, VfsMetadata, VfsResult, WindowedFile};
+use crate::util::mac::{
+    fourcc_str, process_mac_file, process_resource_fork, MacContainerKind, MacFile, MacFork,
+    MacResource,
+};
+
+/// Exposes the forks of a MacBinary, AppleSingle or AppleDouble file
+/// as `data` and `rsrc`.
+#[derive(Clone)]
+pub struct MacFs {
+    file: Box<dyn VfsFile>,
+    mac: MacFile,
+    mtime: Option<FileTime>,
+}
+
+impl MacFs {
+    pub fn new(mut file: Box<dyn VfsFile>, kind: MacContainerKind) -> io::Result<Self> {
+        let mtime = file.metadata()?.mtime;
+        let mac = process_mac_file(file.map()?, kind)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self { file, mac, mtime })
+    }
+
+    fn find(&self, path: &Utf8UnixPath) -> VfsResult<Option<MacFork>> {
+        match path.as_str().trim_matches('/') {
+            "" => Ok(None),
+            "data" => self.mac.data_fork.map(Some).ok_or(VfsError::NotFound),
+            "rsrc" => self.mac.resource_fork.map(Some).ok_or(VfsError::NotFound),
+            _ => Err(VfsError::NotFound),
+        }
+    }
+}
+
+impl Vfs for MacFs {
+    fn open(&mut self, path: &Utf8UnixPath) -> VfsResult<Box<dyn VfsFile>> {
+        match self.find(path)? {
+            Some(fork) => {
+                Ok(Box::new(WindowedFile::new(self.file.clone(), fork.offset, fork.len)?))
+            }
+            None => Err(VfsError::IsADirectory),
+        }
+    }
+
+    fn exists(&mut self, path: &Utf8UnixPath) -> VfsResult<bool> {
+        match self.find(path) {
+            Ok(_) => Ok(true),
+            Err(VfsError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_dir(&mut self, path: &Utf8UnixPath) -> VfsResult<Vec<String>> {
+        if self.find(path)?.is_some() {
+            return Err(VfsError::NotADirectory);
+        }
+        let mut entries = Vec::new();
+        if self.mac.data_fork.is_some() {
+            entries.push("data".to_string());
+        }
+        if self.mac.resource_fork.is_some() {
+            entries.push("rsrc".to_string());
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&mut self, path: &Utf8UnixPath) -> VfsResult<VfsMetadata> {
+        match self.find(path)? {
+            Some(fork) => {
+                Ok(VfsMetadata { file_type: VfsFileType::File, len: fork.len, mtime: self.mtime })
+            }
+            None => {
+                Ok(VfsMetadata { file_type: VfsFileType::Directory, len: 0, mtime: self.mtime })
+            }
+        }
+    }
+}
+
+/// Exposes the resources of a resource fork as `TYPE/ID`, e.g. `cfrg/0` or `CODE/1`.
+#[derive(Clone)]
+pub struct ResourceForkFs {
+    file: Box<dyn VfsFile>,
+    resources: Vec<MacResource>,
+    mtime: Option<FileTime>,
+}
+
+enum ResourceFindResult<'a> {
+    Root,
+    Type([u8; 4]),
+    Resource(&'a MacResource),
+}
+
+impl ResourceForkFs {
+    pub fn new(mut file: Box<dyn VfsFile>) -> io::Result<Self> {
+        let mtime = file.metadata()?.mtime;
+        let resources = process_resource_fork(file.map()?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self { file, resources, mtime })
+    }
+
+    fn find(&self, path: &Utf8UnixPath) -> Option<ResourceFindResult> {
+        let path = path.as_str().trim_matches('/');
+        if path.is_empty() {
+            return Some(ResourceFindResult::Root);
+        }
+        let (type_str, id_str) = match path.split_once('/') {
+            Some((type_str, id_str)) => (type_str, Some(id_str)),
+            None => (path, None),
+        };
+        let res_type = self
+            .resources
+            .iter()
+            .map(|r| r.res_type)
+            .find(|res_type| fourcc_str(res_type) == type_str)?;
+        match id_str {
+            None => Some(ResourceFindResult::Type(res_type)),
+            Some(id_str) => {
+                let id = id_str.parse::<i16>().ok()?;
+                self.resources
+                    .iter()
+                    .find(|r| r.res_type == res_type && r.id == id)
+                    .map(ResourceFindResult::Resource)
+            }
+        }
+    }
+}
+
+impl Vfs for ResourceForkFs {
+    fn open(&mut self, path: &Utf8UnixPath) -> VfsResult<Box<dyn VfsFile>> {
+        match self.find(path) {
+            Some(ResourceFindResult::Resource(resource)) => {
+                let file = WindowedFile::new(self.file.clone(), resource.offset, resource.len)?;
+                Ok(Box::new(file))
+            }
+            Some(_) => Err(VfsError::IsADirectory),
+            None => Err(VfsError::NotFound),
+        }
+    }
+
+    fn exists(&mut self, path: &Utf8UnixPath) -> VfsResult<bool> { Ok(self.find(path).is_some()) }
+
+    fn read_dir(&mut self, path: &Utf8UnixPath) -> VfsResult<Vec<String>> {
+        match self.find(path) {
+            Some(ResourceFindResult::Root) => {
+                let mut entries = Vec::<String>::new();
+                for resource in &self.resources {
+                    let name = fourcc_str(&resource.res_type);
+                    if !entries.contains(&name) {
+                        entries.push(name);
+                    }
+                }
+                Ok(entries)
+            }
+            Some(ResourceFindResult::Type(res_type)) => Ok(self
+                .resources
+                .iter()
+                .filter(|r| r.res_type == res_type)
+                .map(|r| r.id.to_string())
+                .collect()),
+            Some(ResourceFindResult::Resource(_)) => Err(VfsError::NotADirectory),
+            None => Err(VfsError::NotFound),
+        }
+    }
+
+    fn metadata(&mut self, path: &Utf8UnixPath) -> VfsResult<VfsMetadata> {
+        match self.find(path) {
+            Some(ResourceFindResult::Resource(resource)) => Ok(VfsMetadata {
+                file_type: VfsFileType::File,
+                len: resource.len,
+                mtime: self.mtime,
+            }),
+            Some(_) => {
+                Ok(VfsMetadata { file_type: VfsFileType::Directory, len: 0, mtime: self.mtime })
+            }
+            None => Err(VfsError::NotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Arc};
+
+    use super::*;
+    use crate::{
+        util::mac::tests::{macbinary, resource_fork},
+        vfs::{common::StaticFile, detect, ArchiveKind, FileFormat},
+    };
+
+    fn static_file(data: &[u8]) -> Box<dyn VfsFile> {
+        Box::new(StaticFile::new(Arc::from(data), None))
+    }
+
+    fn read(fs: &mut dyn Vfs, path: &str) -> Vec<u8> {
+        fs.open(Utf8UnixPath::new(path)).unwrap().map().unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_detect() {
+        let rsrc = resource_fork(&[(*b"cfrg", 0, None, b"fragments")]);
+        let file = macbinary("Game", b"Joy!peffpwpc", &rsrc);
+        assert_eq!(
+            detect(&mut Cursor::new(&file)).unwrap(),
+            FileFormat::Archive(ArchiveKind::Mac(MacContainerKind::MacBinary))
+        );
+        assert_eq!(
+            detect(&mut Cursor::new(&rsrc)).unwrap(),
+            FileFormat::Archive(ArchiveKind::Mac(MacContainerKind::ResourceFork))
+        );
+        // Truncated containers and other files are opened as regular files
+        assert_eq!(detect(&mut Cursor::new(&file[..file.len() - 1])).unwrap(), FileFormat::Regular);
+        assert_eq!(detect(&mut Cursor::new(&rsrc[..rsrc.len() - 1])).unwrap(), FileFormat::Regular);
+        assert_eq!(detect(&mut Cursor::new(vec![0u8; 0x1000])).unwrap(), FileFormat::Regular);
+    }
+
+    #[test]
+    fn test_mac_fs() {
+        let rsrc = resource_fork(&[(*b"cfrg", 0, None, b"fragments")]);
+        let file = macbinary("Game", b"Joy!peffpwpc", &rsrc);
+        let mut fs = MacFs::new(static_file(&file), MacContainerKind::MacBinary).unwrap();
+        assert_eq!(fs.read_dir(Utf8UnixPath::new("")).unwrap(), vec!["data", "rsrc"]);
+        assert_eq!(read(&mut fs, "data"), b"Joy!peffpwpc");
+        assert_eq!(read(&mut fs, "rsrc"), rsrc);
+        assert!(matches!(fs.open(Utf8UnixPath::new("other")), Err(VfsError::NotFound)));
+        assert!(MacFs::new(static_file(&file[..0x80]), MacContainerKind::MacBinary).is_err());
+    }
+
+    #[test]
+    fn test_resource_fork_fs() {
+        let rsrc = resource_fork(&[
+            (*b"cfrg", 0, None, b"fragments"),
+            (*b"CODE", 0, None, b"jt"),
+            (*b"CODE", 1, Some("Main"), b"code"),
+        ]);
+        let mut fs = ResourceForkFs::new(static_file(&rsrc)).unwrap();
+        assert_eq!(fs.read_dir(Utf8UnixPath::new("")).unwrap(), vec!["cfrg", "CODE"]);
+        assert_eq!(fs.read_dir(Utf8UnixPath::new("CODE")).unwrap(), vec!["0", "1"]);
+        assert_eq!(read(&mut fs, "cfrg/0"), b"fragments");
+        assert_eq!(read(&mut fs, "CODE/1"), b"code");
+        assert_eq!(fs.metadata(Utf8UnixPath::new("CODE/0")).unwrap().len, 2);
+        assert!(!fs.exists(Utf8UnixPath::new("CODE/2")).unwrap());
+        assert!(ResourceForkFs::new(static_file(&rsrc[..rsrc.len() - 4])).is_err());
+    }
+}
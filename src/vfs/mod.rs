@@ -1,5 +1,7 @@
 mod common;
 mod disc;
+mod hfs;
+mod mac;
 mod rarc;
 mod std_fs;
 mod u8_arc;
@@ -18,6 +20,8 @@ use common::{StaticFile, WindowedFile};
 use disc::{nod_to_io_error, DiscFs};
 use dyn_clone::DynClone;
 use filetime::FileTime;
+use hfs::HfsFs;
+use mac::{MacFs, ResourceForkFs};
 use nodtool::{nod, nod::DiscStream};
 use rarc::RarcFs;
 pub use std_fs::StdFs;
@@ -26,6 +30,8 @@ use u8_arc::U8Fs;
 use wad::WadFs;
 
 use crate::util::{
+    hfs::{detect_hfs, HfsKind},
+    mac::{detect_mac_file, MacContainerKind, APPLE_DOUBLE_MAGIC, APPLE_SINGLE_MAGIC},
     ncompress::{YAY0_MAGIC, YAZ0_MAGIC},
     nlzss,
     rarc::RARC_MAGIC,
@@ -158,6 +164,8 @@ pub enum ArchiveKind {
     U8,
     Disc(nod::Format),
     Wad,
+    Mac(MacContainerKind),
+    Hfs(HfsKind),
 }
 
 impl Display for ArchiveKind {
@@ -167,6 +175,12 @@ impl Display for ArchiveKind {
             ArchiveKind::U8 => write!(f, "U8"),
             ArchiveKind::Disc(format) => write!(f, "Disc ({})", format),
             ArchiveKind::Wad => write!(f, "WAD"),
+            ArchiveKind::Mac(MacContainerKind::MacBinary) => write!(f, "MacBinary"),
+            ArchiveKind::Mac(MacContainerKind::AppleSingle) => write!(f, "AppleSingle"),
+            ArchiveKind::Mac(MacContainerKind::AppleDouble) => write!(f, "AppleDouble"),
+            ArchiveKind::Mac(MacContainerKind::ResourceFork) => write!(f, "Resource fork"),
+            ArchiveKind::Hfs(HfsKind::Hfs) => write!(f, "HFS"),
+            ArchiveKind::Hfs(HfsKind::HfsPlus) => write!(f, "HFS+"),
         }
     }
 }
@@ -187,11 +201,23 @@ where R: Read + Seek + ?Sized {
         _ if magic.starts_with(&RARC_MAGIC) => Ok(FileFormat::Archive(ArchiveKind::Rarc)),
         _ if magic.starts_with(&U8_MAGIC) => Ok(FileFormat::Archive(ArchiveKind::U8)),
         WAD_MAGIC => Ok(FileFormat::Archive(ArchiveKind::Wad)),
+        _ if magic.starts_with(&APPLE_SINGLE_MAGIC) => {
+            Ok(FileFormat::Archive(ArchiveKind::Mac(MacContainerKind::AppleSingle)))
+        }
+        _ if magic.starts_with(&APPLE_DOUBLE_MAGIC) => {
+            Ok(FileFormat::Archive(ArchiveKind::Mac(MacContainerKind::AppleDouble)))
+        }
         _ => {
             let format = nod::Disc::detect(file)?;
             file.seek(SeekFrom::Start(0))?;
-            match format {
-                Some(format) => Ok(FileFormat::Archive(ArchiveKind::Disc(format))),
+            if let Some(format) = format {
+                return Ok(FileFormat::Archive(ArchiveKind::Disc(format)));
+            }
+            if let Some((_, kind)) = detect_hfs(file)? {
+                return Ok(FileFormat::Archive(ArchiveKind::Hfs(kind)));
+            }
+            match detect_mac_file(file)? {
+                Some(kind) => Ok(FileFormat::Archive(ArchiveKind::Mac(kind))),
                 None => Ok(FileFormat::Regular),
             }
         }
@@ -339,6 +365,11 @@ pub fn open_fs(mut file: Box<dyn VfsFile>, kind: ArchiveKind) -> io::Result<Box<
             Ok(Box::new(DiscFs::new(disc, partition, metadata.mtime)?))
         }
         ArchiveKind::Wad => Ok(Box::new(WadFs::new(file)?)),
+        ArchiveKind::Mac(MacContainerKind::ResourceFork) => {
+            Ok(Box::new(ResourceForkFs::new(file)?))
+        }
+        ArchiveKind::Mac(kind) => Ok(Box::new(MacFs::new(file, kind)?)),
+        ArchiveKind::Hfs(_) => Ok(Box::new(HfsFs::new(file)?)),
     }
 }
 